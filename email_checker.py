@@ -7,6 +7,8 @@ Purpose: AI agent (OpenClaw) can read and process emails automatically
 
 Usage:
     python3 email_checker.py [--once]
+    python3 email_checker.py --shadow   # print what would be forwarded, as JSON lines
+    python3 email_checker.py --once --json-results   # also print a JSON line per forwarded email
 
 Environment Variables:
     MAILCOW_IMAP_HOST: IMAP server (default: localhost)
@@ -92,8 +94,8 @@ class EmailChecker:
         except Exception as e:
             print(f"Warning: Could not save last check time: {e}")
     
-    def check_imap_emails(self) -> List[Dict]:
-        """Check IMAP server for new emails"""
+    def check_imap_emails(self, peek: bool = False) -> List[Dict]:
+        """Check IMAP server for new emails (without marking them seen if peek)"""
         host = MAILCOW_IMAP_HOST
         port = MAILCOW_IMAP_PORT
         username = MAILCOW_USERNAME
@@ -131,7 +133,7 @@ class EmailChecker:
             if status == 'OK' and messages[0]:
                 for msg_id in messages[0].split():
                     # Fetch email
-                    status, msg_data = mail.fetch(msg_id, '(RFC822)')
+                    status, msg_data = mail.fetch(msg_id, '(BODY.PEEK[])' if peek else '(RFC822)')
                     if status == 'OK' and msg_data:
                         for response_part in msg_data:
                             if isinstance(response_part, tuple):
@@ -147,6 +149,7 @@ class EmailChecker:
                                 body = self._get_email_body(email_msg)
                                 
                                 emails.append({
+                                    'message_id': email_msg['message-id'],
                                     'subject': subject,
                                     'from': from_addr,
                                     'date': date_str,
@@ -181,6 +184,14 @@ class EmailChecker:
                     body = str(msg.get_payload())
         return body
     
+    def _format_message(self, email_data: Dict) -> str:
+        """Message text sent to the OpenClaw channel"""
+        return (f"📧 New Email Received\n\n"
+                f"From: {email_data['from']}\n"
+                f"Subject: {email_data['subject']}\n"
+                f"Date: {email_data['date']}\n\n"
+                f"Preview:\n{email_data['body'][:500]}...")
+
     def forward_via_openclaw(self, email_data: Dict):
         """Forward email to OpenClaw channel for AI agent to process"""
         gateway = OPENCLAW_GATEWAY
@@ -191,11 +202,7 @@ class EmailChecker:
         
         payload = {
             'channel': 'openclaw',  # Send to OpenClaw channel (AI can read)
-            'message': f"📧 New Email Received\n\n"
-                     f"From: {email_data['from']}\n"
-                     f"Subject: {email_data['subject']}\n"
-                     f"Date: {email_data['date']}\n\n"
-                     f"Preview:\n{email_data['body'][:500]}...",
+            'message': self._format_message(email_data),
         }
         
         try:
@@ -205,11 +212,26 @@ class EmailChecker:
             
             with urllib.request.urlopen(req, timeout=10) as response:
                 print(f"✓ Sent to OpenClaw channel: {email_data['subject']}")
+            self._report(email_data, None)
         
         except urllib.error.URLError as e:
             print(f"✗ Failed to send to OpenClaw: {e}")
+            self._report(email_data, str(e))
         except Exception as e:
             print(f"✗ Error: {e}")
+            self._report(email_data, str(e))
+
+    def _report(self, email_data: Dict, error: Optional[str]):
+        """With --json-results, print one JSON line per forwarding attempt"""
+        if '--json-results' not in sys.argv:
+            return
+        print(json.dumps({'result': {
+            'message_id': email_data['message_id'] and str(email_data['message_id']),
+            'subject': str(email_data['subject']),
+            'from': str(email_data['from']),
+            'sent': error is None,
+            'error': error,
+        }}, ensure_ascii=False), flush=True)
     
     def forward_via_smtp(self, email_data: Dict):
         """Forward email via local SMTP (mailcow) -备用方法"""
@@ -234,6 +256,18 @@ class EmailChecker:
         
         self._save_last_checked()
     
+    def shadow(self):
+        """Print what would be forwarded, one JSON line per email, without
+        forwarding, marking mail seen or saving the last check time"""
+        for email_data in self.check_imap_emails(peek=True):
+            print(json.dumps({'shadow': {
+                'message_id': email_data['message_id'] and str(email_data['message_id']),
+                'subject': str(email_data['subject']),
+                'from': str(email_data['from']),
+                'date': str(email_data['date']),
+                'message': self._format_message(email_data),
+            }}, ensure_ascii=False))
+
     def run_forever(self):
         """Continuously check for new emails"""
         interval = CHECK_INTERVAL
@@ -279,7 +313,9 @@ def main():
     
     checker = EmailChecker()
     
-    if run_once:
+    if '--shadow' in sys.argv:
+        checker.shadow()
+    elif run_once:
         checker.run_once()
     else:
         checker.run_forever()
@@ -0,0 +1,12 @@
+#![no_main]
+
+use email_checker::mime;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = mime::decode_base64(data);
+    let _ = mime::decode_quoted_printable(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = mime::decode_encoded_words(text);
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use email_checker::mime::{self, ContentType, Part};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for (name, value) in mime::parse_headers(data) {
+        assert!(!name.contains(':'));
+        let content_type = ContentType::parse(&value);
+        let _ = content_type.param("boundary");
+    }
+    let part = Part::parse(data);
+    let _ = part.decoded_header("Subject");
+    let _ = part.content_type();
+});
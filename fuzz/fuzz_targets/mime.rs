@@ -0,0 +1,15 @@
+#![no_main]
+
+use email_checker::mime::{self, Part};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let message = Part::parse(data);
+    for part in message.walk() {
+        let _ = part.text();
+        if let Some(boundary) = part.content_type().param("boundary") {
+            let _ = mime::split_multipart(&part.body, boundary);
+        }
+    }
+    let _ = message.find("text/plain");
+});
@@ -0,0 +1,239 @@
+//! Health alerts about the checker itself, separate from forwarded mail.
+//!
+//! Each alert fires once when its condition starts and sends a
+//! `resolved` event when it clears, to the sink named in `alerts.sink`:
+//!
+//! - `auth_failure`: the IMAP server rejected the credentials.
+//! - `sink_down`: deliveries to a sink have failed for longer than
+//!   `sink_down_minutes`.
+//! - `queue_depth`: at least `queue_depth` messages are waiting for a
+//!   retry after failed deliveries.
+//! - `quota`: the mailbox is at least `quota_percent` full; a full
+//!   mailbox rejects new mail, so nothing would reach the checker.
+//! - `growth`: a folder gets far more or less mail than usual, see
+//!   [`crate::growth`].
+//! - `watchdog`: mail expected regularly is overdue, see
+//!   [`crate::watchdog`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::email::OPENCLAW_CHANNEL;
+use crate::imap::Quota;
+
+pub const DEFAULT_SINK_DOWN_MINUTES: u64 = 10;
+pub const DEFAULT_QUEUE_DEPTH: usize = 20;
+pub const DEFAULT_QUOTA_PERCENT: u64 = 90;
+
+/// `alerts` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// Sink receiving alerts; alerting is off without one.
+    pub sink: Option<String>,
+    pub sink_down_minutes: u64,
+    pub queue_depth: usize,
+    /// How full the mailbox may get before `quota` fires; 0 turns it off.
+    pub quota_percent: u64,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            sink_down_minutes: DEFAULT_SINK_DOWN_MINUTES,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            quota_percent: DEFAULT_QUOTA_PERCENT,
+        }
+    }
+}
+
+/// What a cycle reported about the checker's health.
+#[derive(Debug, Default)]
+pub struct Health {
+    /// The login was rejected, with the server's message.
+    pub auth_failure: Option<String>,
+    /// Messages left undelivered for a later retry.
+    pub pending: usize,
+    /// The mailbox's quota as last read.
+    pub quota: Option<Quota>,
+    /// What is unusual about each folder's arrivals, by folder.
+    pub growth: BTreeMap<String, Option<String>>,
+    /// What is overdue per watchdog, by name.
+    pub watchdogs: BTreeMap<String, Option<String>>,
+}
+
+pub struct Alerts {
+    config: AlertConfig,
+    account: String,
+    firing: BTreeSet<String>,
+    failing_since: BTreeMap<String, Instant>,
+}
+
+impl Alerts {
+    pub fn new(config: &AlertConfig, account: &str) -> Self {
+        Self {
+            config: config.clone(),
+            account: account.to_string(),
+            firing: BTreeSet::new(),
+            failing_since: BTreeMap::new(),
+        }
+    }
+
+    pub fn sink(&self) -> Option<&str> {
+        self.config.sink.as_deref()
+    }
+
+    /// Note the outcome of a delivery to `sink`.
+    pub fn delivery(&mut self, sink: &str, ok: bool) {
+        if ok {
+            self.failing_since.remove(sink);
+        } else {
+            self.failing_since.entry(sink.to_string()).or_insert_with(Instant::now);
+        }
+    }
+
+    /// Events for alerts that started or cleared with this cycle.
+    pub fn evaluate(&mut self, health: &Health) -> Vec<Value> {
+        let mut events = Vec::new();
+        let auth = health.auth_failure.as_ref().map(|msg| format!("IMAP login failed: {}", msg));
+        events.extend(self.set("auth_failure", auth));
+
+        let down_after = Duration::from_secs(self.config.sink_down_minutes * 60);
+        let down: Vec<(String, u64)> = self
+            .failing_since
+            .iter()
+            .filter(|(_, since)| since.elapsed() >= down_after)
+            .map(|(sink, since)| (sink.clone(), since.elapsed().as_secs() / 60))
+            .collect();
+        let mut sinks: BTreeSet<String> = self.failing_since.keys().cloned().collect();
+        sinks.extend(self.firing.iter().filter_map(|a| a.strip_prefix("sink_down:")).map(str::to_string));
+        for sink in sinks {
+            let message = down
+                .iter()
+                .find(|(name, _)| *name == sink)
+                .map(|(_, minutes)| format!("Deliveries to '{}' have failed for {} minutes", sink, minutes));
+            events.extend(self.set(&format!("sink_down:{}", sink), message));
+        }
+
+        let queued = (health.pending >= self.config.queue_depth.max(1))
+            .then(|| format!("{} messages are waiting for a retry", health.pending));
+        events.extend(self.set("queue_depth", queued));
+
+        let percent = self.config.quota_percent;
+        let full = health.quota.filter(|quota| percent > 0 && quota.limit > 0 && quota.percent() >= percent);
+        let full = full.map(|quota| {
+            let mb = |bytes: u64| bytes as f64 / 1e6;
+            format!("The mailbox is {}% full ({:.1} of {:.1} MB)", quota.percent(), mb(quota.used), mb(quota.limit))
+        });
+        events.extend(self.set("quota", full));
+
+        events.extend(self.set_each("growth", &health.growth));
+        events.extend(self.set_each("watchdog", &health.watchdogs));
+        events
+    }
+
+    /// [`Alerts::set`] for each subject of `alert`, resolving those that
+    /// fired before and are gone now.
+    fn set_each(&mut self, alert: &str, messages: &BTreeMap<String, Option<String>>) -> Vec<Value> {
+        let prefix = format!("{}:", alert);
+        let mut subjects: BTreeSet<String> = messages.keys().cloned().collect();
+        subjects.extend(self.firing.iter().filter_map(|a| a.strip_prefix(&prefix)).map(str::to_string));
+        subjects
+            .into_iter()
+            .filter_map(|subject| {
+                let message = messages.get(&subject).cloned().flatten();
+                self.set(&format!("{}{}", prefix, subject), message)
+            })
+            .collect()
+    }
+
+    /// Fire `alert` with `message`, or resolve it with `None`; returns an
+    /// event only when that changes its state.
+    fn set(&mut self, alert: &str, message: Option<String>) -> Option<Value> {
+        let (name, subject) = alert.split_once(':').unwrap_or((alert, ""));
+        let (status, message) = match message {
+            Some(message) if self.firing.insert(alert.to_string()) => ("firing", message),
+            None if self.firing.remove(alert) => ("resolved", format!("{} resolved", alert)),
+            _ => return None,
+        };
+        let mut event = json!({
+            "channel": OPENCLAW_CHANNEL,
+            "event_type": "health",
+            "alert": name,
+            "status": status,
+            "account": self.account,
+            "message": message,
+        });
+        if !subject.is_empty() {
+            let field = match name {
+                "growth" => "folder",
+                "watchdog" => "watchdog",
+                _ => "sink",
+            };
+            event[field] = json!(subject);
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alerts_fire_once_and_resolve() {
+        let config = AlertConfig {
+            sink: Some("ops".to_string()),
+            sink_down_minutes: 0,
+            queue_depth: 3,
+            quota_percent: 90,
+        };
+        let mut alerts = Alerts::new(&config, "bot@example.com");
+        let failing = Health {
+            auth_failure: Some("Invalid credentials".to_string()),
+            pending: 0,
+            quota: None,
+            growth: BTreeMap::new(),
+            watchdogs: BTreeMap::new(),
+        };
+        let events = alerts.evaluate(&failing);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["alert"], "auth_failure");
+        assert_eq!(events[0]["status"], "firing");
+        assert!(alerts.evaluate(&failing).is_empty());
+
+        alerts.delivery("openclaw", false);
+        let events = alerts.evaluate(&Health {
+            auth_failure: None,
+            pending: 5,
+            quota: None,
+            growth: BTreeMap::new(),
+            watchdogs: BTreeMap::new(),
+        });
+        let names: Vec<_> = events.iter().map(|e| (e["alert"].as_str().unwrap(), e["status"].as_str().unwrap())).collect();
+        assert_eq!(names, [("auth_failure", "resolved"), ("sink_down", "firing"), ("queue_depth", "firing")]);
+        assert_eq!(events[1]["sink"], "openclaw");
+
+        alerts.delivery("openclaw", true);
+        let events = alerts.evaluate(&Health::default());
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e["status"] == "resolved"));
+
+        let quota = |used| Some(Quota { used, limit: 1_000_000_000 });
+        assert!(alerts.evaluate(&Health { quota: quota(899_000_000), ..Health::default() }).is_empty());
+        let events = alerts.evaluate(&Health { quota: quota(950_000_000), ..Health::default() });
+        assert_eq!(events[0]["alert"], "quota");
+        assert_eq!(events[0]["message"], "The mailbox is 95% full (950.0 of 1000.0 MB)");
+
+        let growth = BTreeMap::from([("INBOX".to_string(), Some("INBOX got no mail".to_string()))]);
+        let events = alerts.evaluate(&Health { growth, ..Health::default() });
+        assert_eq!((&events[0]["alert"], &events[0]["status"]), (&json!("quota"), &json!("resolved")));
+        assert_eq!((&events[1]["alert"], &events[1]["status"]), (&json!("growth"), &json!("firing")));
+        assert_eq!(events[1]["folder"], "INBOX");
+        assert_eq!(alerts.evaluate(&Health::default())[0]["status"], "resolved");
+    }
+}
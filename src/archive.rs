@@ -0,0 +1,256 @@
+//! Local copy of every processed message.
+//!
+//! Messages are written to a Maildir, which `mu index` picks up as is,
+//! and/or handed to `notmuch insert` so they land in a notmuch
+//! database tagged with their event type, and/or uploaded with their
+//! payload to S3-compatible storage. [`search`] scans the Maildir
+//! so operators can look up what was forwarded without the mailbox.
+
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::email::EmailData;
+use crate::process;
+use crate::s3::{self, S3Config};
+
+/// Variables notmuch reads to find its config and database.
+const NOTMUCH_ENV: &[&str] = &["NOTMUCH_*", "MAILDIR"];
+
+static DELIVERY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// `archive` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Maildir to store messages in; created if missing.
+    pub maildir: Option<String>,
+    /// Also run `notmuch insert` for each message.
+    pub notmuch: bool,
+    /// Folder below the notmuch mail root, e.g. `checker`.
+    pub notmuch_folder: Option<String>,
+    /// Tags added to every inserted message, besides the event type.
+    pub notmuch_tags: Vec<String>,
+    /// Bucket to upload messages and payloads to; see [`crate::s3`].
+    pub s3: Option<S3Config>,
+}
+
+impl ArchiveConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.maildir.is_some() || self.notmuch || self.s3.is_some()
+    }
+}
+
+/// Store one message, and its `payload` where that is kept too,
+/// according to `config`.
+pub fn store(config: &ArchiveConfig, raw: &[u8], event_type: &str, payload: &Value) -> Result<(), Box<dyn Error>> {
+    if let Some(maildir) = &config.maildir {
+        write_maildir(Path::new(maildir), raw)?;
+    }
+    if config.notmuch {
+        notmuch_insert(config, raw, event_type)?;
+    }
+    if let Some(s3) = &config.s3 {
+        s3::store(s3, raw, payload)?;
+    }
+    Ok(())
+}
+
+/// Deliver into `maildir` the Maildir way: write to `tmp/`, then rename
+/// into `cur/` flagged as seen (the checker has already handled it).
+pub fn write_maildir(maildir: &Path, raw: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+    for sub in ["tmp", "new", "cur"] {
+        fs::create_dir_all(maildir.join(sub))?;
+    }
+    let name = unique_name();
+    let tmp = maildir.join("tmp").join(&name);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(raw)?;
+    file.sync_all()?;
+    let target = maildir.join("cur").join(format!("{}:2,S", name));
+    fs::rename(&tmp, &target)?;
+    Ok(target)
+}
+
+/// A message found by [`search`].
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub path: PathBuf,
+    pub date: String,
+    pub from: String,
+    pub subject: String,
+}
+
+/// Messages in `maildir`, newest first (by their delivery-time name).
+pub fn messages(maildir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    for sub in ["new", "cur"] {
+        let dir = maildir.join(sub);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort_by_key(|p| std::cmp::Reverse(p.file_name().map(|n| n.to_os_string())));
+    Ok(paths)
+}
+
+/// Archived messages whose subject, sender, headers or text contain every
+/// term (case-insensitive), newest first, at most `limit` of them.
+pub fn search(maildir: &Path, terms: &[String], limit: usize) -> Result<Vec<Hit>, Box<dyn Error>> {
+    let terms: Vec<String> = terms.iter().map(|t| t.to_lowercase()).collect();
+    let mut hits = Vec::new();
+    for path in messages(maildir)? {
+        if hits.len() >= limit {
+            break;
+        }
+        let email = EmailData::from_raw(&fs::read(&path)?);
+        let mut haystack = format!("{}\n{}\n{}", email.subject, email.from, email.body);
+        for (name, value) in &email.headers {
+            haystack.push_str(&format!("\n{}: {}", name, value));
+        }
+        let haystack = haystack.to_lowercase();
+        if terms.iter().all(|t| haystack.contains(t.as_str())) {
+            hits.push(Hit {
+                path,
+                date: email.date,
+                from: email.from,
+                subject: email.subject,
+            });
+        }
+    }
+    Ok(hits)
+}
+
+/// Search a notmuch-only archive with `notmuch search`, printing its output.
+pub fn notmuch_search(config: &ArchiveConfig, terms: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut command = process::command("notmuch", NOTMUCH_ENV);
+    command.arg("search");
+    if let Some(folder) = &config.notmuch_folder {
+        command.arg(format!("folder:{}", folder));
+    }
+    let status = command
+        .args(terms)
+        .status()
+        .map_err(|e| format!("failed to run notmuch: {}", e))?;
+    if !status.success() {
+        return Err("notmuch search failed".into());
+    }
+    Ok(())
+}
+
+/// Files of the messages matching a notmuch `query`, including ones
+/// notmuch would normally exclude (e.g. tagged `deleted` or `spam`).
+pub fn notmuch_files(config: &ArchiveConfig, query: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut command = process::command("notmuch", NOTMUCH_ENV);
+    command.args(["search", "--output=files", "--exclude=false"]);
+    if let Some(folder) = &config.notmuch_folder {
+        command.arg(format!("folder:{}", folder)).arg("and");
+    }
+    let output = command
+        .arg(query)
+        .output()
+        .map_err(|e| format!("failed to run notmuch: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("notmuch search failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(PathBuf::from).collect())
+}
+
+/// Let notmuch drop deleted files from its database.
+pub fn notmuch_reindex() -> Result<(), Box<dyn Error>> {
+    let output = process::command("notmuch", NOTMUCH_ENV)
+        .args(["new", "--quiet"])
+        .output()
+        .map_err(|e| format!("failed to run notmuch: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("notmuch new failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(())
+}
+
+fn unique_name() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let host = fs::read_to_string("/etc/hostname")
+        .map(|h| h.trim().replace(['/', ':'], "_"))
+        .unwrap_or_else(|_| "localhost".to_string());
+    format!(
+        "{}.M{:06}P{}Q{}.{}",
+        now.as_secs(),
+        now.subsec_micros(),
+        std::process::id(),
+        DELIVERY_COUNTER.fetch_add(1, Ordering::Relaxed),
+        host
+    )
+}
+
+fn notmuch_insert(config: &ArchiveConfig, raw: &[u8], event_type: &str) -> Result<(), Box<dyn Error>> {
+    let mut command = process::command("notmuch", NOTMUCH_ENV);
+    command.arg("insert").arg("--create-folder");
+    if let Some(folder) = &config.notmuch_folder {
+        command.arg(format!("--folder={}", folder));
+    }
+    command.arg(format!("+{}", event_type)).arg("-unread");
+    for tag in &config.notmuch_tags {
+        command.arg(format!("+{}", tag));
+    }
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run notmuch: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(raw)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!("notmuch insert failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_maildir() {
+        let dir = std::env::temp_dir().join(format!("email_checker_maildir_{}", std::process::id()));
+        let first = write_maildir(&dir, b"Subject: one\r\n\r\n").unwrap();
+        let second = write_maildir(&dir, b"Subject: two\r\n\r\n").unwrap();
+        assert_ne!(first, second);
+        assert!(first.to_string_lossy().ends_with(":2,S"));
+        assert_eq!(fs::read(&second).unwrap(), b"Subject: two\r\n\r\n");
+        assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_search() {
+        let dir = std::env::temp_dir().join(format!("email_checker_search_{}", std::process::id()));
+        write_maildir(&dir, b"Subject: Invoice 4711\r\nFrom: billing@example.com\r\n\r\nPlease pay.\r\n").unwrap();
+        write_maildir(&dir, b"Subject: Lunch\r\nFrom: bob@example.com\r\n\r\nInvoice attached? No.\r\n").unwrap();
+
+        let terms = |t: &[&str]| t.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let hits = search(&dir, &terms(&["invoice", "4711"]), 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].subject, "Invoice 4711");
+        assert_eq!(search(&dir, &terms(&["invoice"]), 10).unwrap().len(), 2);
+        assert_eq!(search(&dir, &terms(&["invoice"]), 1).unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
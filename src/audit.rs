@@ -0,0 +1,182 @@
+//! Append-only, hash-chained log of every payload sent to a sink.
+//!
+//! Each delivery attempt appends one JSON line with the time, the sink,
+//! the SHA-256 of the payload, its correlation ID and the outcome. Every
+//! line also carries the hash of the line before it and its own hash over
+//! both, so editing or deleting an entry breaks the chain from that point
+//! on, which `audit verify` reports. Cutting entries off the end cannot
+//! be seen from the file alone; compare the last hash it prints with a
+//! copy kept elsewhere.
+
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::digest::{hex, sha256};
+use crate::{log, redact};
+use crate::sink::Sink;
+
+/// `prev` of the first entry.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub time: String,
+    pub sink: String,
+    pub payload_sha256: String,
+    /// The payload's `correlation_id`, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// `ok`, or the error the sink returned.
+    pub response: String,
+    pub prev: String,
+    /// SHA-256 over this entry with `hash` empty.
+    pub hash: String,
+}
+
+impl Entry {
+    fn compute_hash(&self) -> String {
+        let unhashed = Entry { hash: String::new(), ..self.clone() };
+        let line = serde_json::to_string(&unhashed).unwrap_or_default();
+        hex(&sha256(line.as_bytes()))
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Open `path` for appending, continuing the chain already in it. An
+    /// unreadable last entry starts a new chain, which `verify` flags.
+    pub fn open(path: &Path) -> Self {
+        let mut audit = Self {
+            path: path.to_path_buf(),
+            seq: 0,
+            last_hash: GENESIS.to_string(),
+        };
+        let text = fs::read_to_string(path).unwrap_or_default();
+        if let Some(line) = text.lines().rev().find(|l| !l.trim().is_empty()) {
+            match serde_json::from_str::<Entry>(line) {
+                Ok(last) => (audit.seq, audit.last_hash) = (last.seq, last.hash),
+                Err(e) => log::error(&format!("{}: unreadable last entry ({}); the chain restarts", path.display(), e)),
+            }
+        }
+        audit
+    }
+
+    pub fn append(&mut self, sink: &str, payload: &Value, result: &Result<(), String>) -> io::Result<()> {
+        let mut entry = Entry {
+            seq: self.seq + 1,
+            time: chrono::Utc::now().to_rfc3339(),
+            sink: sink.to_string(),
+            payload_sha256: hex(&sha256(payload.to_string().as_bytes())),
+            correlation_id: payload["correlation_id"].as_str().map(str::to_string),
+            response: match result {
+                Ok(()) => "ok".to_string(),
+                Err(e) => redact::redact(e),
+            },
+            prev: self.last_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        let mut file = OpenOptions::new().create(true).append(true).mode(0o600).open(&self.path)?;
+        file.write_all(format!("{}\n", serde_json::to_string(&entry)?).as_bytes())?;
+        file.sync_data()?;
+        self.seq = entry.seq;
+        self.last_hash = entry.hash;
+        Ok(())
+    }
+}
+
+/// Check the whole chain in `path`. Returns the number of entries and
+/// the last hash, or where the chain first breaks.
+pub fn verify(path: &Path) -> Result<(u64, String), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (mut seq, mut prev) = (0, GENESIS.to_string());
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let broken = |why: &str| format!("line {}: {}", i + 1, why);
+        let entry: Entry = serde_json::from_str(line).map_err(|e| broken(&e.to_string()))?;
+        if entry.seq != seq + 1 {
+            return Err(broken(&format!("expected entry {}, found {}", seq + 1, entry.seq)));
+        }
+        if entry.prev != prev {
+            return Err(broken("does not follow the previous entry"));
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(broken("entry was modified"));
+        }
+        (seq, prev) = (entry.seq, entry.hash);
+    }
+    Ok((seq, prev))
+}
+
+/// A sink whose deliveries are recorded in an [`AuditLog`].
+pub struct Audited {
+    pub name: String,
+    pub inner: Box<dyn Sink>,
+    pub log: Arc<Mutex<AuditLog>>,
+}
+
+impl Audited {
+    fn audit<T>(&self, payload: &Value, result: Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        let outcome = result.as_ref().map(|_| ()).map_err(|e| e.to_string());
+        let mut audit = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = audit.append(&self.name, payload, &outcome) {
+            log::error(&format!("could not write audit log {}: {}", audit.path.display(), e));
+        }
+        result
+    }
+}
+
+impl Sink for Audited {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.audit(payload, self.inner.deliver(payload))
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.audit(payload, self.inner.deliver_keyed(payload, key))
+    }
+
+    fn tick(&self) -> Result<(), Box<dyn Error>> {
+        self.inner.tick()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_chain_detects_tampering() {
+        let path = std::env::temp_dir().join(format!("email_checker_audit_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut log = AuditLog::open(&path);
+        log.append("openclaw", &json!({"message": "one", "correlation_id": "5f1c"}), &Ok(())).unwrap();
+        log.append("ci", &json!({"message": "two"}), &Err("HTTP 502".to_string())).unwrap();
+        // A reopened log continues the chain.
+        let mut log = AuditLog::open(&path);
+        log.append("openclaw", &json!({"message": "three"}), &Ok(())).unwrap();
+        let (entries, last) = verify(&path).unwrap();
+        assert_eq!((entries, last), (3, log.last_hash.clone()));
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.lines().next().unwrap().contains(r#""correlation_id":"5f1c""#));
+        fs::write(&path, text.replace("HTTP 502", "ok")).unwrap();
+        assert_eq!(verify(&path).unwrap_err(), "line 2: entry was modified");
+        let lines: Vec<&str> = text.lines().collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify(&path).unwrap_err().starts_with("line 2: expected entry 2"));
+        fs::remove_file(path).unwrap();
+    }
+}
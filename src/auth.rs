@@ -0,0 +1,220 @@
+//! IMAP authentication mechanisms.
+//!
+//! `LOGIN` is the plain IMAP command; the others are SASL mechanisms run
+//! over `AUTHENTICATE`. Without an explicit choice the strongest one the
+//! server advertises is used. For `XOAUTH2` the password is the OAuth
+//! access token, typically fetched from a secret provider.
+
+use std::fs::File;
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest::{hex, hmac_md5, md4};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Mechanism {
+    #[serde(rename = "login", alias = "LOGIN")]
+    Login,
+    #[serde(rename = "plain", alias = "PLAIN")]
+    Plain,
+    #[serde(rename = "cram-md5", alias = "CRAM-MD5")]
+    CramMd5,
+    #[serde(rename = "ntlm", alias = "NTLM")]
+    Ntlm,
+    #[serde(rename = "xoauth2", alias = "XOAUTH2")]
+    Xoauth2,
+}
+
+impl Mechanism {
+    /// SASL name, as in the `AUTH=` capability.
+    pub fn name(self) -> &'static str {
+        match self {
+            Mechanism::Login => "LOGIN",
+            Mechanism::Plain => "PLAIN",
+            Mechanism::CramMd5 => "CRAM-MD5",
+            Mechanism::Ntlm => "NTLM",
+            Mechanism::Xoauth2 => "XOAUTH2",
+        }
+    }
+
+    /// Pick a mechanism from the server's capabilities: CRAM-MD5 keeps the
+    /// password off the wire, then PLAIN, then the LOGIN command unless
+    /// the server disabled it.
+    pub fn negotiate(capabilities: &[String]) -> Result<Self, String> {
+        let has = |cap: &str| capabilities.iter().any(|c| c.eq_ignore_ascii_case(cap));
+        if has("AUTH=CRAM-MD5") {
+            Ok(Mechanism::CramMd5)
+        } else if has("AUTH=PLAIN") {
+            Ok(Mechanism::Plain)
+        } else if !has("LOGINDISABLED") {
+            Ok(Mechanism::Login)
+        } else {
+            Err(format!("no supported authentication mechanism in: {}", capabilities.join(" ")))
+        }
+    }
+}
+
+/// Client side of one SASL exchange: answers the server's challenges.
+pub struct Exchange<'a> {
+    mechanism: Mechanism,
+    username: &'a str,
+    password: &'a str,
+    step: usize,
+}
+
+impl<'a> Exchange<'a> {
+    pub fn new(mechanism: Mechanism, username: &'a str, password: &'a str) -> Self {
+        Self {
+            mechanism,
+            username,
+            password,
+            step: 0,
+        }
+    }
+
+    /// Response to the server's (decoded) challenge.
+    pub fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, String> {
+        self.step += 1;
+        match (self.mechanism, self.step) {
+            (Mechanism::Plain, 1) => Ok(format!("\0{}\0{}", self.username, self.password).into_bytes()),
+            (Mechanism::CramMd5, 1) => Ok(cram_md5(self.username, self.password, challenge).into_bytes()),
+            (Mechanism::Xoauth2, 1) => {
+                Ok(format!("user={}\x01auth=Bearer {}\x01\x01", self.username, self.password).into_bytes())
+            }
+            // A second XOAUTH2 challenge carries a JSON error; an empty
+            // response lets the server finish with NO.
+            (Mechanism::Xoauth2, _) => Ok(Vec::new()),
+            (Mechanism::Ntlm, 1) => Ok(ntlm_negotiate()),
+            (Mechanism::Ntlm, 2) => ntlm_authenticate(self.username, self.password, challenge),
+            _ => Err(format!("unexpected {} challenge", self.mechanism.name())),
+        }
+    }
+}
+
+/// RFC 2195: `user hex(HMAC-MD5(password, challenge))`.
+pub fn cram_md5(username: &str, password: &str, challenge: &[u8]) -> String {
+    format!("{} {}", username, hex(&hmac_md5(password.as_bytes(), challenge)))
+}
+
+const NTLM_SIGNATURE: &[u8] = b"NTLMSSP\0";
+/// UNICODE | OEM | REQUEST_TARGET | NTLM | ALWAYS_SIGN | EXTENDED_SESSIONSECURITY
+const NTLM_FLAGS: u32 = 0x0008_8207;
+
+fn ntlm_negotiate() -> Vec<u8> {
+    let mut message = NTLM_SIGNATURE.to_vec();
+    message.extend_from_slice(&1u32.to_le_bytes());
+    message.extend_from_slice(&NTLM_FLAGS.to_le_bytes());
+    message.extend_from_slice(&[0; 16]); // empty domain and workstation
+    message
+}
+
+fn utf16le(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// NTOWFv2 (MS-NLMP 3.3.2).
+pub fn ntowf_v2(username: &str, password: &str, domain: &str) -> [u8; 16] {
+    let nt_hash = md4(&utf16le(password));
+    hmac_md5(&nt_hash, &utf16le(&format!("{}{}", username.to_uppercase(), domain)))
+}
+
+/// NTLMv2 AUTHENTICATE message answering a CHALLENGE. The username may be
+/// given as `DOMAIN\user`.
+fn ntlm_authenticate(username: &str, password: &str, challenge: &[u8]) -> Result<Vec<u8>, String> {
+    if challenge.len() < 48 || !challenge.starts_with(NTLM_SIGNATURE) {
+        return Err("malformed NTLM challenge".to_string());
+    }
+    let server_challenge = &challenge[24..32];
+    let target_info = security_buffer(challenge, 40).unwrap_or(&[]);
+    let (domain, user) = username.split_once('\\').unwrap_or(("", username));
+
+    let mut client_challenge = [0u8; 8];
+    if File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut client_challenge)).is_err() {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        client_challenge = (nanos as u64).to_le_bytes();
+    }
+    // Windows FILETIME: 100ns ticks since 1601.
+    let unix = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    let timestamp = (unix.as_secs() + 11_644_473_600) * 10_000_000 + unix.subsec_nanos() as u64 / 100;
+
+    let key = ntowf_v2(user, password, domain);
+    let mut blob = vec![1, 1, 0, 0, 0, 0, 0, 0];
+    blob.extend_from_slice(&timestamp.to_le_bytes());
+    blob.extend_from_slice(&client_challenge);
+    blob.extend_from_slice(&[0; 4]);
+    blob.extend_from_slice(target_info);
+    blob.extend_from_slice(&[0; 4]);
+
+    let mut nt_response = hmac_md5(&key, &[server_challenge, &blob].concat()).to_vec();
+    nt_response.extend_from_slice(&blob);
+    let mut lm_response = hmac_md5(&key, &[server_challenge, &client_challenge[..]].concat()).to_vec();
+    lm_response.extend_from_slice(&client_challenge);
+
+    let fields = [lm_response, nt_response, utf16le(domain), utf16le(user), Vec::new(), Vec::new()];
+    let mut header = NTLM_SIGNATURE.to_vec();
+    header.extend_from_slice(&3u32.to_le_bytes());
+    let mut payload = Vec::new();
+    let base = 64;
+    for field in &fields {
+        let len = field.len() as u16;
+        header.extend_from_slice(&len.to_le_bytes());
+        header.extend_from_slice(&len.to_le_bytes());
+        header.extend_from_slice(&((base + payload.len()) as u32).to_le_bytes());
+        payload.extend_from_slice(field);
+    }
+    header.extend_from_slice(&NTLM_FLAGS.to_le_bytes());
+    header.extend_from_slice(&payload);
+    Ok(header)
+}
+
+/// Contents of the `(len, maxlen, offset)` buffer described at `at`.
+fn security_buffer(message: &[u8], at: usize) -> Option<&[u8]> {
+    let len = u16::from_le_bytes([*message.get(at)?, *message.get(at + 1)?]) as usize;
+    let offset = u32::from_le_bytes(message.get(at + 4..at + 8)?.try_into().ok()?) as usize;
+    message.get(offset..offset + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cram_md5_and_ntowf() {
+        // RFC 2195 and MS-NLMP 4.2.4.1.1 examples.
+        assert_eq!(
+            cram_md5("tim", "tanstaaftanstaaf", b"<1896.697170952@postoffice.reston.mci.net>"),
+            "tim b913a602c7eda7a495b4e6e7334d3890"
+        );
+        assert_eq!(hex(&ntowf_v2("User", "Password", "Domain")), "0c868a403bfd7a93a3001ef22ef02e3f");
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let caps = |c: &str| c.split(' ').map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(Mechanism::negotiate(&caps("IMAP4rev1 AUTH=PLAIN AUTH=CRAM-MD5")), Ok(Mechanism::CramMd5));
+        assert_eq!(Mechanism::negotiate(&caps("IMAP4rev1 AUTH=PLAIN LOGINDISABLED")), Ok(Mechanism::Plain));
+        assert_eq!(Mechanism::negotiate(&caps("IMAP4rev1")), Ok(Mechanism::Login));
+        assert!(Mechanism::negotiate(&caps("IMAP4rev1 LOGINDISABLED")).is_err());
+    }
+
+    #[test]
+    fn test_ntlm_messages() {
+        let mut exchange = Exchange::new(Mechanism::Ntlm, "CORP\\bot", "secret");
+        assert!(exchange.respond(b"").unwrap().starts_with(NTLM_SIGNATURE));
+
+        let mut challenge = NTLM_SIGNATURE.to_vec();
+        challenge.extend_from_slice(&2u32.to_le_bytes());
+        challenge.extend_from_slice(&[0; 12]);
+        challenge.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        challenge.extend_from_slice(&[0; 8]);
+        challenge.extend_from_slice(&[4, 0, 4, 0, 48, 0, 0, 0]);
+        challenge.extend_from_slice(&[0, 0, 0, 0]);
+        let message = exchange.respond(&challenge).unwrap();
+        assert_eq!(&message[8..12], &3u32.to_le_bytes());
+        assert_eq!(security_buffer(&message, 28), Some(&utf16le("CORP")[..]));
+        assert_eq!(security_buffer(&message, 36), Some(&utf16le("bot")[..]));
+        // NTProofStr (16) + blob header (28) + target info (4) + trailer (4).
+        assert_eq!(security_buffer(&message, 20).unwrap().len(), 16 + 28 + 4 + 4);
+    }
+}
@@ -0,0 +1,127 @@
+//! Delivery status notification (RFC 3464) parsing.
+//!
+//! A bounce is a `multipart/report; report-type=delivery-status` message
+//! whose `message/delivery-status` part holds one per-message block
+//! followed by a block per recipient.
+
+use serde::Serialize;
+
+use crate::mime::{self, Part};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Bounce {
+    pub reporting_mta: Option<String>,
+    pub recipients: Vec<FailedRecipient>,
+    /// `Message-ID` of the returned original, when it was included.
+    pub original_message_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FailedRecipient {
+    pub recipient: String,
+    /// Enhanced status code, e.g. `5.1.1`.
+    pub status: Option<String>,
+    pub action: Option<String>,
+    pub diagnostic: Option<String>,
+}
+
+pub fn from_message(message: &Part) -> Option<Bounce> {
+    let content_type = message.content_type();
+    let is_report = content_type.mime_type == "multipart/report"
+        && content_type
+            .param("report-type")
+            .is_none_or(|t| t.eq_ignore_ascii_case("delivery-status"));
+    if !is_report {
+        return None;
+    }
+
+    let status = message.find("message/delivery-status")?;
+    let mut bounce = parse(&status.text());
+    bounce.original_message_id = message
+        .walk()
+        .into_iter()
+        .find(|p| matches!(p.content_type().mime_type.as_str(), "message/rfc822" | "text/rfc822-headers"))
+        .and_then(|p| {
+            let original = p.children.first().cloned().unwrap_or_else(|| Part::parse(&p.decoded_body()));
+            original.header("Message-ID").map(str::to_string)
+        });
+    Some(bounce)
+}
+
+/// Parse the body of a `message/delivery-status` part.
+pub fn parse(text: &str) -> Bounce {
+    let normalized = text.replace("\r\n", "\n");
+    let mut blocks = normalized
+        .split("\n\n")
+        .map(|block| mime::parse_headers(block.as_bytes()))
+        .filter(|fields| !fields.is_empty());
+
+    let mut bounce = Bounce::default();
+    if let Some(per_message) = blocks.next() {
+        bounce.reporting_mta = field(&per_message, "Reporting-MTA").map(strip_type);
+    }
+    for fields in blocks {
+        let Some(recipient) = field(&fields, "Final-Recipient").or_else(|| field(&fields, "Original-Recipient")) else {
+            continue;
+        };
+        bounce.recipients.push(FailedRecipient {
+            recipient: strip_type(recipient),
+            status: field(&fields, "Status").map(|s| s.split_whitespace().next().unwrap_or("").to_string()),
+            action: field(&fields, "Action").map(str::to_ascii_lowercase),
+            diagnostic: field(&fields, "Diagnostic-Code").map(strip_type),
+        });
+    }
+    bounce
+}
+
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Drop the `rfc822;` / `dns;` / `smtp;` type prefix of a DSN field.
+fn strip_type(value: &str) -> String {
+    value
+        .split_once(';')
+        .map_or(value, |(_, rest)| rest)
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DSN: &str = "From: MAILER-DAEMON@mx.example.com\r\n\
+Subject: Undelivered Mail Returned to Sender\r\n\
+Content-Type: multipart/report; report-type=delivery-status; boundary=\"B\"\r\n\r\n\
+--B\r\nContent-Type: text/plain\r\n\r\nThis is the mail system.\r\n\
+--B\r\nContent-Type: message/delivery-status\r\n\r\n\
+Reporting-MTA: dns; mx.example.com\r\n\r\n\
+Final-Recipient: rfc822; nobody@example.org\r\nOriginal-Recipient: rfc822;nobody@example.org\r\n\
+Action: failed\r\nStatus: 5.1.1\r\nDiagnostic-Code: smtp; 550 5.1.1 User unknown\r\n\r\n\
+--B\r\nContent-Type: message/rfc822\r\n\r\nMessage-ID: <orig@example.com>\r\nSubject: hi\r\n\r\nbody\r\n\
+--B--\r\n";
+
+    #[test]
+    fn test_parse_dsn() {
+        let bounce = from_message(&Part::parse(DSN.as_bytes())).unwrap();
+        assert_eq!(bounce.reporting_mta.as_deref(), Some("mx.example.com"));
+        assert_eq!(bounce.original_message_id.as_deref(), Some("<orig@example.com>"));
+        assert_eq!(bounce.recipients.len(), 1);
+        let recipient = &bounce.recipients[0];
+        assert_eq!(recipient.recipient, "nobody@example.org");
+        assert_eq!(recipient.status.as_deref(), Some("5.1.1"));
+        assert_eq!(recipient.action.as_deref(), Some("failed"));
+        assert_eq!(recipient.diagnostic.as_deref(), Some("550 5.1.1 User unknown"));
+    }
+
+    #[test]
+    fn test_non_report_is_not_bounce() {
+        let message = Part::parse(b"Content-Type: multipart/report; report-type=disposition-notification; boundary=x\n\n--x--\n");
+        assert!(from_message(&message).is_none());
+        assert!(from_message(&Part::parse(b"Subject: hi\n\nbody")).is_none());
+    }
+}
@@ -0,0 +1,203 @@
+//! iCalendar (RFC 5545) invite parsing.
+//!
+//! Only the first `VEVENT` of a `text/calendar` part is extracted; that
+//! is what invitation mail carries in practice.
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::mime::Part;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CalendarEvent {
+    /// iTIP method of the enclosing calendar (`REQUEST`, `CANCEL`, ...).
+    pub method: Option<String>,
+    pub summary: Option<String>,
+    /// ISO 8601 start; UTC times carry a `Z`, floating/TZID times do not.
+    pub start: Option<String>,
+    pub end: Option<String>,
+    /// `TZID` of the start time, when given.
+    pub timezone: Option<String>,
+    pub organizer: Option<String>,
+    pub organizer_name: Option<String>,
+    pub location: Option<String>,
+    pub uid: Option<String>,
+}
+
+/// A single content line: `NAME;PARAM=x:VALUE`.
+struct Property<'a> {
+    name: String,
+    params: Vec<(String, String)>,
+    value: &'a str,
+}
+
+impl Property<'_> {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Find the first `text/calendar` part of a message and parse it.
+pub fn from_message(message: &Part) -> Option<CalendarEvent> {
+    message.find("text/calendar").and_then(|part| {
+        let mut event = parse(&part.text())?;
+        if event.method.is_none() {
+            event.method = part.content_type().param("method").map(str::to_ascii_uppercase);
+        }
+        Some(event)
+    })
+}
+
+pub fn parse(ics: &str) -> Option<CalendarEvent> {
+    let lines = unfold(ics);
+    let mut event = CalendarEvent::default();
+    let mut in_event = false;
+    let mut found = false;
+
+    for line in &lines {
+        let Some(prop) = parse_line(line) else { continue };
+        match (prop.name.as_str(), in_event) {
+            ("BEGIN", false) if prop.value.eq_ignore_ascii_case("VEVENT") && !found => {
+                in_event = true;
+                found = true;
+            }
+            ("END", true) if prop.value.eq_ignore_ascii_case("VEVENT") => in_event = false,
+            ("METHOD", false) => event.method = Some(prop.value.to_ascii_uppercase()),
+            ("SUMMARY", true) => event.summary = Some(unescape(prop.value)),
+            ("LOCATION", true) => event.location = Some(unescape(prop.value)),
+            ("UID", true) => event.uid = Some(prop.value.to_string()),
+            ("DTSTART", true) => {
+                event.start = Some(format_time(prop.value));
+                event.timezone = prop.param("TZID").map(str::to_string);
+            }
+            ("DTEND", true) => event.end = Some(format_time(prop.value)),
+            ("ORGANIZER", true) => {
+                let address = prop.value;
+                let address = address
+                    .get(..7)
+                    .filter(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+                    .map_or(address, |_| &address[7..]);
+                event.organizer = Some(address.to_string());
+                event.organizer_name = prop.param("CN").map(str::to_string);
+            }
+            _ => {}
+        }
+    }
+
+    found.then_some(event)
+}
+
+/// Join continuation lines (those starting with a space or tab).
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn parse_line(line: &str) -> Option<Property<'_>> {
+    // The value starts at the first colon outside a quoted parameter.
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+
+    let mut head = line[..colon].split(';');
+    let name = head.next()?.trim().to_ascii_uppercase();
+    let params = head
+        .filter_map(|p| {
+            let (k, v) = p.split_once('=')?;
+            Some((k.to_string(), v.trim_matches('"').to_string()))
+        })
+        .collect();
+    Some(Property {
+        name,
+        params,
+        value: &line[colon + 1..],
+    })
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Convert `20240101T100000Z`, `20240101T100000` or `20240101` to ISO 8601.
+/// Unrecognised values are passed through unchanged.
+fn format_time(value: &str) -> String {
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(v) => (v, "Z"),
+        None => (value, ""),
+    };
+    if let Ok(t) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return format!("{}{}", t.format("%Y-%m-%dT%H:%M:%S"), utc);
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return d.format("%Y-%m-%d").to_string();
+    }
+    format!("{}{}", value, utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVITE: &str = "BEGIN:VCALENDAR\r\nMETHOD:REQUEST\r\nBEGIN:VEVENT\r\n\
+UID:abc-123\r\nSUMMARY:Quarterly review\\, Q3\r\n\
+DTSTART;TZID=Europe/Berlin:20240301T100000\r\nDTEND;TZID=Europe/Berlin:20240301T113000\r\n\
+ORGANIZER;CN=\"Alice: Ops\":mailto:alice@example.com\r\n\
+LOCATION:Room 4\r\n B\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+
+    #[test]
+    fn test_parse_invite() {
+        let event = parse(INVITE).unwrap();
+        assert_eq!(event.method.as_deref(), Some("REQUEST"));
+        assert_eq!(event.summary.as_deref(), Some("Quarterly review, Q3"));
+        assert_eq!(event.start.as_deref(), Some("2024-03-01T10:00:00"));
+        assert_eq!(event.end.as_deref(), Some("2024-03-01T11:30:00"));
+        assert_eq!(event.timezone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(event.organizer.as_deref(), Some("alice@example.com"));
+        assert_eq!(event.organizer_name.as_deref(), Some("Alice: Ops"));
+        assert_eq!(event.location.as_deref(), Some("Room 4B"));
+    }
+
+    #[test]
+    fn test_utc_and_date_values() {
+        assert_eq!(format_time("20240301T100000Z"), "2024-03-01T10:00:00Z");
+        assert_eq!(format_time("20240301"), "2024-03-01");
+        assert!(parse("BEGIN:VCALENDAR\nEND:VCALENDAR").is_none());
+    }
+
+    #[test]
+    fn test_method_from_content_type() {
+        let raw = "Content-Type: multipart/alternative; boundary=b\n\n--b\n\
+Content-Type: text/plain\n\nYou are invited\n--b\n\
+Content-Type: text/calendar; method=cancel\n\nBEGIN:VEVENT\nSUMMARY:x\nEND:VEVENT\n--b--\n";
+        let event = from_message(&Part::parse(raw.as_bytes())).unwrap();
+        assert_eq!(event.method.as_deref(), Some("CANCEL"));
+    }
+}
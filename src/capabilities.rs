@@ -0,0 +1,62 @@
+//! What the checker makes of the server's IMAP capabilities, for
+//! `imap capabilities`.
+//!
+//! The server may advertise more once logged in, so the login mechanism is
+//! read from what it offered before and everything else from what it
+//! offers after.
+
+use crate::auth::Mechanism;
+
+/// The capabilities the checker knows, and what it does when the server
+/// offers each; `None` for ones it does not make use of.
+pub const KNOWN: &[(&str, Option<&str>)] = &[
+    ("NAMESPACE", Some("folder names are mapped to the server's namespaces")),
+    ("ACL", Some("shared folders are skipped unless the account may read them")),
+    ("IDLE", None),
+    ("CONDSTORE", None),
+    ("MOVE", None),
+    ("COMPRESS=DEFLATE", Some("the connection is compressed once logged in")),
+    ("QUOTA", Some("the mailbox's usage is exported and alerted on")),
+];
+
+/// One line per known capability, and one for the login mechanism: whether
+/// the server offers it and what the checker will therefore do.
+pub fn report(before_login: &[String], after_login: &[String], mechanism: Option<Mechanism>) -> Vec<String> {
+    let width = KNOWN.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let auth = match mechanism {
+        Some(mechanism) => format!("{} (configured)", mechanism.name()),
+        None => match Mechanism::negotiate(before_login) {
+            Ok(mechanism) => format!("{} (negotiated)", mechanism.name()),
+            Err(e) => e,
+        },
+    };
+    let mut lines = vec![format!("{:width$}  {}", "AUTH", auth)];
+    for (name, used) in KNOWN {
+        let offered = after_login.iter().any(|c| c.eq_ignore_ascii_case(name));
+        let note = match (offered, used) {
+            (false, _) => "not offered".to_string(),
+            (true, Some(used)) => format!("offered; {}", used),
+            (true, None) => "offered; not used".to_string(),
+        };
+        lines.push(format!("{:width$}  {}", name, note));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let caps = |caps: &str| caps.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        let before = caps("IMAP4rev1 AUTH=PLAIN");
+        let after = caps("IMAP4rev1 IDLE namespace");
+        let lines = report(&before, &after, None);
+        assert_eq!(lines[0], "AUTH              PLAIN (negotiated)");
+        assert_eq!(lines[1], "NAMESPACE         offered; folder names are mapped to the server's namespaces");
+        assert_eq!(lines[2], "ACL               not offered");
+        assert_eq!(lines[3], "IDLE              offered; not used");
+        assert_eq!(report(&before, &after, Some(Mechanism::Login))[0], "AUTH              LOGIN (configured)");
+    }
+}
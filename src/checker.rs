@@ -0,0 +1,1564 @@
+//! One check cycle: fetch unseen mail over IMAP, route and deliver it.
+//!
+//! A message is only marked `\Seen` after its sink accepted it, so a
+//! failed delivery is retried on the next cycle. Deliveries are also
+//! recorded in the [`State`] file, which catches messages whose `\Seen`
+//! flag was lost and duplicates with a known `Message-ID`. The reverse,
+//! a `\Seen` flag without a delivery, is found by the reconciliation
+//! pass of [`crate::sink::AckConfig`].
+//!
+//! Parsing, with the [`pipeline`] stages that edit the message, runs on
+//! a worker thread bounded by `message_timeout`. A message that panics
+//! the parser or takes too long is quarantined: left unseen on the
+//! server, copied to `quarantine_dir` if set, and not retried. A panic
+//! anywhere else fails just that cycle. Both are logged and counted
+//! instead of stalling or taking the daemon down.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::alert::{Alerts, Health};
+use crate::classify::Classifier;
+use crate::config::Config;
+use crate::contacts::Contacts;
+use crate::digest::{hex, sha256};
+use crate::email::EmailData;
+use crate::expect::{self, Expectation};
+use crate::imap::{Client, ImapError, Mailbox, Quota};
+use crate::pipeline::{self, Stage};
+use crate::scan::{self, ScanAction};
+use crate::sink::{self, Sink, SinkConfig};
+use crate::smtp::{self, SmtpSink};
+use crate::state::{Bandwidth, Burst, State, ThreadBody, Unsubscribed};
+use crate::trace::{self, Tracer};
+use crate::transport::Stream;
+use crate::{
+    archive, collapse, diff, dns, growth, ha, log, mailcow, mdn, metrics, redact, retention, rules, transport,
+    unsubscribe, watchdog,
+};
+
+pub const INBOX: &str = "INBOX";
+/// Seconds between looks for unused rules.
+const UNUSED_RULE_CHECK_INTERVAL: u64 = 86400;
+
+/// A message as delivered: the (decrypted) raw bytes and their parse.
+type Fetched = (Vec<u8>, EmailData);
+
+/// A folder to check, by its server name.
+struct Folder {
+    name: String,
+    /// In another user's or a shared namespace. Unless a trigger flag is
+    /// set, such folders are tracked by UID and never marked `\Seen`,
+    /// which would hide the mail from the folder's other readers.
+    shared: bool,
+}
+
+/// A message that could not be parsed, and why.
+struct Unparsed {
+    raw: Arc<Vec<u8>>,
+    reason: String,
+}
+
+pub struct Checker {
+    pub config: Config,
+    sinks: BTreeMap<String, Box<dyn Sink>>,
+    /// Whether the current password has logged in successfully.
+    password_worked: bool,
+    secrets_fetched: Instant,
+    /// Whether this instance held the HA lease when last asked.
+    leading: Option<bool>,
+    pruned: Option<Instant>,
+    rules_checked: Option<Instant>,
+    pub state: State,
+    alerts: Alerts,
+    /// Messages the last cycle left for a retry.
+    pending: usize,
+    /// The mailbox's quota as last read.
+    quota: Option<Quota>,
+    /// Open expectations as loaded at the start of the cycle.
+    expectations: Vec<Expectation>,
+    tracer: Tracer,
+    contacts: Contacts,
+    classifier: Classifier,
+}
+
+impl Checker {
+    pub fn new(config: Config) -> Self {
+        let sinks = sink::build(&config);
+        Self::with_sinks(config, sinks)
+    }
+
+    pub fn with_sinks(config: Config, sinks: BTreeMap<String, Box<dyn Sink>>) -> Self {
+        let state = if config.state_file.is_empty() {
+            State::default()
+        } else {
+            State::load(&config.state_file).unwrap_or_else(|e| {
+                log::warn(&format!("could not load state, starting empty: {}", e));
+                State::default()
+            })
+        };
+        Self {
+            alerts: Alerts::new(&config.alerts, &config.mailcow_username),
+            contacts: Contacts::new(&config.contacts),
+            classifier: Classifier::new(&config.classify),
+            config,
+            sinks,
+            password_worked: false,
+            secrets_fetched: Instant::now(),
+            leading: None,
+            pruned: None,
+            rules_checked: None,
+            state,
+            pending: 0,
+            quota: None,
+            expectations: Vec::new(),
+            tracer: Tracer::default(),
+        }
+    }
+
+    /// Connect to the configured server and run one cycle.
+    /// Returns the number of messages delivered.
+    pub fn run_once(&mut self) -> Result<usize, Box<dyn Error>> {
+        metrics::inc(metrics::CYCLES);
+        let account = [("account", self.config.mailcow_username.as_str())];
+        metrics::inc_with(metrics::ACCOUNT_CYCLES, &account);
+        let downloaded = metrics::get(metrics::IMAP_BYTES_DOWNLOADED);
+        let uploaded = metrics::get(metrics::GATEWAY_BYTES_UPLOADED);
+        self.tracer.begin();
+        let cycle = self.tracer.start("cycle");
+        self.tracer.attr(cycle, "imap.account", &self.config.mailcow_username);
+        let _account = log::field("ACCOUNT", &self.config.mailcow_username);
+        // Before new mail can start a burst in the place of one that ended.
+        self.close_bursts();
+        let result = guarded("the check cycle", || self.connect().and_then(|client| self.run_with(client)))
+            .unwrap_or_else(|e| Err(e.into()));
+        if let Ok(delivered) = &result {
+            self.tracer.attr(cycle, "messages.delivered", delivered);
+        }
+        self.tracer.end(cycle, result.as_ref().err().map(|e| e.to_string()));
+        if let Err(e) = self.tracer.export(&self.config.tracing) {
+            log::warn(&format!("could not export traces: {}", e));
+        }
+        for (name, sink) in &self.sinks {
+            if let Err(e) = sink.tick() {
+                log::warn(&format!("could not send the digest of sink {}: {}", name, e));
+            }
+        }
+        if result.is_err() {
+            metrics::inc(metrics::CYCLE_ERRORS);
+            metrics::inc_with(metrics::ACCOUNT_CYCLE_ERRORS, &[("account", self.config.mailcow_username.as_str())]);
+        }
+        self.record_bandwidth(Bandwidth {
+            downloaded: metrics::get(metrics::IMAP_BYTES_DOWNLOADED) - downloaded,
+            uploaded: metrics::get(metrics::GATEWAY_BYTES_UPLOADED) - uploaded,
+        });
+        let auth_failure = match result.as_ref().err().and_then(|e| e.downcast_ref::<ImapError>()) {
+            Some(ImapError::Auth(msg)) => Some(msg.clone()),
+            _ => None,
+        };
+        let now = chrono::Utc::now().timestamp();
+        let watchdogs = watchdog::overdue(&self.config.watchdogs, &mut self.state.watchdogs, now);
+        self.send_alerts(&Health {
+            auth_failure,
+            pending: self.pending,
+            quota: self.quota,
+            growth: self.growth_anomalies(),
+            watchdogs,
+        });
+        result
+    }
+
+    /// What is unusual about each folder's arrivals, by folder.
+    fn growth_anomalies(&self) -> BTreeMap<String, Option<String>> {
+        if !self.config.growth.enabled {
+            return BTreeMap::new();
+        }
+        let now = chrono::Utc::now().timestamp();
+        self.state
+            .growth
+            .iter()
+            .map(|(folder, growth)| (folder.clone(), growth::anomaly(growth, &self.config.growth, folder, now)))
+            .collect()
+    }
+
+    /// Run one cycle over an already connected stream.
+    pub fn run_on<S: Read + Write>(&mut self, stream: S) -> Result<usize, Box<dyn Error>> {
+        self.run_with(Client::new(stream)?)
+    }
+
+    fn run_with<S: Read + Write>(&mut self, mut client: Client<S>) -> Result<usize, Box<dyn Error>> {
+        self.login(&mut client)?;
+        self.read_quota(&mut client);
+        let mut delivered = 0;
+        self.pending = 0;
+        self.load_expectations();
+        let folders = self.resolve_folders(&mut client)?;
+        // Folders no longer checked have no arrivals to judge.
+        self.state.growth.retain(|name, _| folders.iter().any(|folder| folder.name == *name));
+        for folder in folders {
+            delivered += self.check_folder(&mut client, &folder)?;
+        }
+        self.report_expectations();
+        client.logout()?;
+        Ok(delivered)
+    }
+
+    /// Read the mailbox's quota from the server, or else from the Mailcow
+    /// API when that is configured, and export it. A failure keeps the last
+    /// reading.
+    fn read_quota<S: Read + Write>(&mut self, client: &mut Client<S>) {
+        let api = &self.config.mailcow_api;
+        let quota = match client.quota(INBOX) {
+            Ok(None) if !api.url.is_empty() && !api.api_key.is_empty() => {
+                mailcow::quota(&self.config).map(Some).map_err(|e| e.to_string())
+            }
+            other => other.map_err(|e| e.to_string()),
+        };
+        match quota {
+            Ok(Some(quota)) => {
+                let account = [("account", self.config.mailcow_username.as_str())];
+                metrics::set_with(metrics::QUOTA_USED, &account, quota.used as f64);
+                metrics::set_with(metrics::QUOTA_LIMIT, &account, quota.limit as f64);
+                self.quota = Some(quota);
+            }
+            Ok(None) => {}
+            Err(e) => log::warn(&format!("could not read the mailbox quota: {}", e)),
+        }
+    }
+
+    /// `folders` by their server names, with patterns expanded to the
+    /// selectable folders they match now, so folders created since the
+    /// last cycle are picked up. The namespaces are only asked for when a
+    /// folder other than INBOX is configured.
+    fn resolve_folders<S: Read + Write>(&self, client: &mut Client<S>) -> Result<Vec<Folder>, Box<dyn Error>> {
+        if self.config.folders.iter().all(|folder| folder.eq_ignore_ascii_case(INBOX)) {
+            return Ok(vec![Folder { name: INBOX.to_string(), shared: false }]);
+        }
+        let namespaces = client.namespace()?;
+        let mut folders: Vec<Folder> = Vec::new();
+        for folder in &self.config.folders {
+            let name = namespaces.server_name(folder);
+            let matched = if folder.contains(['*', '%']) {
+                let mailboxes = client.list("", &name)?;
+                mailboxes.into_iter().filter(Mailbox::is_selectable).map(|mailbox| mailbox.name).collect()
+            } else {
+                vec![name]
+            };
+            for name in matched {
+                if !folders.iter().any(|folder| folder.name == name) {
+                    let shared = namespaces.is_shared(&name);
+                    folders.push(Folder { name, shared });
+                }
+            }
+        }
+        Ok(folders)
+    }
+
+    /// Deliver one folder's new mail. Returns the number delivered.
+    fn check_folder<S: Read + Write>(&mut self, client: &mut Client<S>, folder: &Folder) -> Result<usize, Box<dyn Error>> {
+        let (shared, folder) = (folder.shared, folder.name.as_str());
+        if shared {
+            if let Some(rights) = client.my_rights(folder)?.filter(|rights| !rights.contains('r')) {
+                let user = &self.config.mailcow_username;
+                log::warn(&format!("{} may not read {} (rights '{}'); skipping it", user, folder, rights));
+                return Ok(0);
+            }
+        }
+        let selected = client.select(folder)?;
+        let uid_validity = selected.uid_validity;
+        if let Some(uid_next) = selected.uid_next.filter(|_| self.config.growth.enabled) {
+            let growth = self.state.growth.entry(folder.to_string()).or_default();
+            growth::record(growth, &self.config.growth, uid_validity, uid_next, chrono::Utc::now().timestamp());
+        }
+
+        // On a trigger flag, the flag decides what is due, in shared folders too.
+        let triggered = self.config.trigger.search_criteria();
+        let by_uid = shared && triggered.is_none();
+        let mark = match (&triggered, by_uid) {
+            (Some(_), _) => Some(self.config.trigger.keyword.clone()),
+            (None, true) => None,
+            (None, false) => Some("\\Seen".to_string()),
+        };
+        let mut uids = match (triggered, by_uid) {
+            (Some(criteria), _) => client.uid_search(&criteria)?,
+            (None, true) => {
+                let Some(after) = self.state.handled_through(folder, uid_validity) else {
+                    self.start_tracking(client, folder, uid_validity)?;
+                    return Ok(0);
+                };
+                uids_after(client, after)?
+            }
+            (None, false) => client.uid_search(&self.state.search_criteria())?,
+        };
+        log::info(&format!("Found {} new emails in {}", uids.len(), folder));
+        if self.config.ack.reconcile && mark.is_some() && self.config.trigger.flag.is_none() {
+            let unacked = self.unacknowledged(client, folder, uid_validity)?;
+            if !unacked.is_empty() {
+                log::warn(&format!("{} seen messages were never acknowledged; delivering them", unacked.len()));
+                metrics::add(metrics::RECONCILED, unacked.len() as u64);
+            }
+            uids.extend(unacked);
+            uids.sort_unstable();
+            uids.dedup();
+        }
+
+        let last = uids.last().copied();
+        let mut delivered = 0;
+        let mut first_pending = None;
+        for uid in uids {
+            if self.state.is_delivered(folder, uid_validity, uid) {
+                mark_handled(client, mark.as_deref(), uid)?;
+                continue;
+            }
+            if self.state.is_quarantined(folder, uid_validity, uid) || self.state.is_skipped(folder, uid_validity, uid) {
+                continue;
+            }
+            let now = chrono::Utc::now().timestamp();
+            if self.state.snoozed_until(folder, uid_validity, uid).is_some_and(|due| due > now) {
+                first_pending.get_or_insert(uid);
+                continue;
+            }
+            let span = self.tracer.start("message");
+            let correlation_id = hex(&trace::random_id::<8>());
+            self.tracer.attr(span, "imap.uid", uid);
+            self.tracer.attr(span, "message.correlation_id", &correlation_id);
+            let _uid = log::field("UID", uid);
+            let _correlation_id = log::field(log::CORRELATION_ID, &correlation_id);
+            let fetched = self.fetch(client, uid);
+            if let Err(e) = &fetched {
+                self.tracer.end(span, Some(e.to_string()));
+            }
+            let (raw, mut email) = match fetched? {
+                Ok(fetched) => fetched,
+                Err(unparsed) => {
+                    self.tracer.end(span, Some(unparsed.reason.clone()));
+                    self.quarantine(folder, uid_validity, uid, &unparsed);
+                    continue;
+                }
+            };
+            email.correlation_id = Some(correlation_id);
+            watchdog::observe(&self.config.watchdogs, &mut self.state.watchdogs, &email, now);
+            self.confirm_expectation(&email, now);
+            let message_id = email.header("Message-ID").map(str::to_string);
+            if message_id.as_deref().is_some_and(|id| self.state.is_duplicate(id)) {
+                log::info(&format!("Skipping duplicate: {}", email.subject));
+                self.tracer.attr(span, "message.duplicate", true);
+                self.tracer.end(span, None);
+                self.record(folder, uid_validity, uid, message_id.as_deref());
+                mark_handled(client, mark.as_deref(), uid)?;
+                continue;
+            }
+            if email.receipt.is_some() && self.config.mdn.suppress {
+                log::info(&format!("Suppressing read receipt: {}", email.subject));
+                self.tracer.attr(span, "message.receipt", true);
+                self.tracer.end(span, None);
+                self.record(folder, uid_validity, uid, message_id.as_deref());
+                mark_handled(client, mark.as_deref(), uid)?;
+                continue;
+            }
+            if !self.enrich(&mut email) {
+                self.tracer.attr(span, "message.skipped", true);
+                self.tracer.end(span, None);
+                self.skip(folder, uid_validity, uid);
+                continue;
+            }
+            self.classify(&mut email);
+            if self.scan(&raw, &mut email) {
+                self.tracer.attr(span, "message.dropped", true);
+                self.tracer.end(span, None);
+                self.record(folder, uid_validity, uid, message_id.as_deref());
+                mark_handled(client, mark.as_deref(), uid)?;
+                continue;
+            }
+            if let Some(due) = self.snooze(folder, uid_validity, uid, &email) {
+                let due = chrono::DateTime::from_timestamp(due, 0).unwrap_or_default().with_timezone(&chrono::Local);
+                log::info(&format!("Holding '{}' until {}", email.subject, due.format("%Y-%m-%d %H:%M")));
+                self.tracer.attr(span, "message.snoozed", true);
+                self.tracer.end(span, None);
+                first_pending.get_or_insert(uid);
+                continue;
+            }
+            if self.collapse(&email) {
+                self.tracer.attr(span, "message.collapsed", true);
+                self.tracer.end(span, None);
+                self.record(folder, uid_validity, uid, message_id.as_deref());
+                mark_handled(client, mark.as_deref(), uid)?;
+                continue;
+            }
+            self.diff(&mut email);
+            let key = self.config.ack.receipt.as_ref().map(|_| self.delivery_key(folder, uid_validity, uid));
+            if key.is_some() {
+                if self.state.begin_delivery(folder, uid_validity, uid, chrono::Utc::now().timestamp()) {
+                    log::info(&format!("Retrying UID {} under the same key; the last attempt went unanswered", uid));
+                }
+                self.save_state();
+            }
+            let result = self.deliver(folder, &email, key.as_deref());
+            if let Ok(Some(receipt)) = &result {
+                self.tracer.attr(span, "delivery.receipt", receipt);
+            }
+            self.tracer.end(span, result.as_ref().err().cloned());
+            if let Ok(receipt) = result {
+                if let Some(receipt) = receipt {
+                    self.state.record_receipt(folder, uid_validity, uid, &receipt);
+                }
+                self.record(folder, uid_validity, uid, message_id.as_deref());
+                mark_handled(client, mark.as_deref(), uid)?;
+                delivered += 1;
+                self.open_burst(&email);
+                self.remember_body(&email);
+                self.archive(&raw, &email);
+                self.send_receipt(&email);
+                self.unsubscribe(&email);
+            } else {
+                self.pending += 1;
+                first_pending.get_or_insert(uid);
+            }
+        }
+        if let (true, Some(last)) = (by_uid, last) {
+            let through = first_pending.map_or(last, |uid: u32| uid - 1);
+            self.state.set_handled_through(folder, uid_validity, through);
+            self.save_state();
+        }
+        Ok(delivered)
+    }
+
+    /// Start tracking a shared folder by UID from its newest message on,
+    /// leaving the mail already there alone.
+    fn start_tracking<S: Read + Write>(
+        &mut self,
+        client: &mut Client<S>,
+        folder: &str,
+        uid_validity: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let last = client.uid_search("ALL")?.into_iter().max().unwrap_or(0);
+        self.state.set_handled_through(folder, uid_validity, last);
+        self.save_state();
+        log::info(&format!("Tracking shared folder {} by UID; mail after UID {} will be delivered", folder, last));
+        Ok(())
+    }
+
+    /// Seen messages in the reconcile window that have no delivery record.
+    /// The first pass under a `UIDVALIDITY` only notes the highest UID,
+    /// so mail that was read before reconciliation started is left alone.
+    fn unacknowledged<S: Read + Write>(
+        &mut self,
+        client: &mut Client<S>,
+        folder: &str,
+        uid_validity: u32,
+    ) -> Result<Vec<u32>, Box<dyn Error>> {
+        let Some(after) = self.state.reconcile_after(folder, uid_validity) else {
+            let last = client.uid_search("ALL")?.into_iter().max().unwrap_or(0);
+            self.state.start_reconcile(folder, uid_validity, last);
+            self.save_state();
+            return Ok(Vec::new());
+        };
+        let window = chrono::Duration::seconds(self.config.ack.reconcile_window as i64);
+        let since = (chrono::Utc::now() - window).format("%d-%b-%Y");
+        let seen = client.uid_search(&format!("SEEN UID {}:* SINCE {}", after.saturating_add(1), since))?;
+        Ok(seen
+            .into_iter()
+            // `n:*` always matches the last message, even below `n`.
+            .filter(|&uid| uid > after)
+            .filter(|&uid| !self.state.is_delivered(folder, uid_validity, uid))
+            .filter(|&uid| !self.state.is_quarantined(folder, uid_validity, uid))
+            .filter(|&uid| !self.state.is_skipped(folder, uid_validity, uid))
+            .collect())
+    }
+
+    /// Connect and report what a cycle would deliver, without delivering,
+    /// marking anything seen or recording state.
+    pub fn preview(&mut self) -> Result<Vec<EmailData>, Box<dyn Error>> {
+        let client = self.connect()?;
+        self.preview_with(client)
+    }
+
+    pub fn preview_on<S: Read + Write>(&mut self, stream: S) -> Result<Vec<EmailData>, Box<dyn Error>> {
+        self.preview_with(Client::new(stream)?)
+    }
+
+    fn preview_with<S: Read + Write>(&mut self, mut client: Client<S>) -> Result<Vec<EmailData>, Box<dyn Error>> {
+        self.login(&mut client)?;
+        let mut emails = Vec::new();
+        for Folder { name: folder, shared } in self.resolve_folders(&mut client)? {
+            let uid_validity = client.select(&folder)?.uid_validity;
+            let triggered = self.config.trigger.search_criteria();
+            let uids = match (triggered, shared, self.state.handled_through(&folder, uid_validity)) {
+                (Some(criteria), _, _) => client.uid_search(&criteria)?,
+                (None, false, _) => client.uid_search(&self.state.search_criteria())?,
+                (None, true, Some(after)) => uids_after(&mut client, after)?,
+                // Tracking starts with the next cycle; nothing is due yet.
+                (None, true, None) => continue,
+            };
+            let now = chrono::Utc::now().timestamp();
+            for uid in uids {
+                if self.state.is_delivered(&folder, uid_validity, uid) {
+                    continue;
+                }
+                if self.state.snoozed_until(&folder, uid_validity, uid).is_some_and(|due| due > now) {
+                    continue;
+                }
+                let Ok((_, email)) = self.fetch(&mut client, uid)? else {
+                    continue;
+                };
+                if !email.header("Message-ID").is_some_and(|id| self.state.is_duplicate(id)) {
+                    emails.push(email);
+                }
+            }
+        }
+        client.logout()?;
+        Ok(emails)
+    }
+
+    /// Connect and list the account's folders, named as `folders` names
+    /// them.
+    pub fn list_folders(&mut self) -> Result<Vec<Mailbox>, Box<dyn Error>> {
+        let mut client = self.connect()?;
+        self.login(&mut client)?;
+        let namespaces = client.namespace()?;
+        let mut folders = client.list("", "*")?;
+        for folder in &mut folders {
+            folder.name = namespaces.config_name(folder);
+        }
+        client.logout()?;
+        Ok(folders)
+    }
+
+    /// The capabilities the server advertises before and after logging in.
+    pub fn capabilities(&mut self) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+        let mut client = self.connect()?;
+        let before = client.capability()?.to_vec();
+        self.login(&mut client)?;
+        let after = client.capability()?.to_vec();
+        client.logout()?;
+        Ok((before, after))
+    }
+
+    /// Connect with implicit TLS or STARTTLS as configured. If STARTTLS
+    /// fails and is not required, fall back to plaintext.
+    fn connect(&self) -> Result<Client<Box<dyn Stream>>, Box<dyn Error>> {
+        let (host, port) = (&self.config.mailcow_imap_host, self.config.mailcow_imap_port);
+        let tls = &self.config.tls;
+        let addr = dns::resolve(&self.config.dns, host, port)?;
+        if !tls.use_starttls(port) {
+            return Ok(Client::new(transport::connect_tls(host, addr, tls)?)?);
+        }
+        let mut client = Client::resume(transport::connect_starttls(host, addr, tls)?);
+        match client.capability() {
+            Ok(_) => Ok(client),
+            Err(ImapError::Io(e)) if !tls.require_starttls => {
+                log::warn(&format!("STARTTLS with {} failed ({}); continuing WITHOUT encryption", host, e));
+                Ok(Client::new(transport::connect_plain(addr)?)?)
+            }
+            Err(ImapError::Io(e)) => Err(format!("STARTTLS with {}:{} failed: {}", host, port, e).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fetch a message without setting `\Seen`, decrypting and verifying
+    /// it as configured. Returns the (decrypted) raw message too, or
+    /// [`Unparsed`] if parsing panicked or exceeded `message_timeout`.
+    fn fetch<S: Read + Write>(
+        &mut self,
+        client: &mut Client<S>,
+        uid: u32,
+    ) -> Result<Result<Fetched, Unparsed>, Box<dyn Error>> {
+        let span = self.tracer.start("fetch");
+        let message = client.uid_fetch_message(uid);
+        if let Ok(message) = &message {
+            self.tracer.attr(span, "message.size", message.raw.len());
+        }
+        self.tracer.end(span, message.as_ref().err().map(|e| e.to_string()));
+        let message = message?;
+
+        let span = self.tracer.start("parse");
+        let raw = Arc::new(message.raw);
+        let (worker_raw, pipeline, decryption) =
+            (Arc::clone(&raw), self.config.pipeline.clone(), self.config.decryption.clone());
+        let timeout = Some(Duration::from_secs(self.config.message_timeout)).filter(|t| !t.is_zero());
+        let parsed = bounded(format!("parsing UID {}", uid), timeout, move || {
+            pipeline::process(&worker_raw, &pipeline, &decryption)
+        });
+        self.tracer.end(span, parsed.as_ref().err().cloned());
+        Ok(parsed
+            .map(|(raw, mut email)| {
+                email.received = message.internal_date;
+                (raw, email)
+            })
+            .map_err(|reason| Unparsed { raw, reason }))
+    }
+
+    /// Stop retrying a message that could not be parsed, keeping a copy
+    /// in `quarantine_dir` if set.
+    fn quarantine(&mut self, folder: &str, uid_validity: u32, uid: u32, unparsed: &Unparsed) {
+        log::warn(&format!("Quarantined UID {}: {}", uid, unparsed.reason));
+        metrics::inc(metrics::QUARANTINED);
+        self.state.quarantine(folder, uid_validity, uid, chrono::Utc::now().timestamp());
+        self.save_state();
+        if let Some(dir) = &self.config.quarantine_dir {
+            match archive::write_maildir(Path::new(dir), &unparsed.raw) {
+                Ok(path) => log::info(&format!("  saved to {}", path.display())),
+                Err(e) => log::warn(&format!("could not save quarantined UID {}: {}", uid, e)),
+            }
+        }
+    }
+
+    /// Log in, and if a previously working password is now rejected,
+    /// re-fetch it from its provider and try once more. Compression is
+    /// turned on after, when the server offers it.
+    fn login<S: Read + Write>(&mut self, client: &mut Client<S>) -> Result<(), Box<dyn Error>> {
+        let mechanism = self.config.auth_mechanism;
+        let (username, password) = (&self.config.mailcow_username, self.config.mailcow_password.expose());
+        let result = client.authenticate(mechanism, username, password);
+        let result = match result {
+            Err(ImapError::Auth(msg)) if self.password_worked && self.config.mailcow_password_provider.is_some() => {
+                log::warn(&format!("login rejected ({}); re-fetching the password", msg));
+                if self.refresh_secrets()? {
+                    client.authenticate(mechanism, &self.config.mailcow_username, self.config.mailcow_password.expose())
+                } else {
+                    Err(ImapError::Auth(msg))
+                }
+            }
+            other => other,
+        };
+        self.password_worked = result.is_ok();
+        result?;
+        client.compress()?;
+        Ok(())
+    }
+
+    /// Look the sender up in the contact sources. Returns whether the
+    /// message may be forwarded under `contacts.allowlist`.
+    fn enrich(&mut self, email: &mut EmailData) -> bool {
+        if !self.config.contacts.is_enabled() || !self.config.pipeline.has(Stage::Enrich) {
+            return true;
+        }
+        let span = self.tracer.start("enrich");
+        email.contact = self.contacts.lookup(&email.from);
+        self.tracer.attr(span, "contact.known", email.contact.is_some());
+        self.tracer.end(span, None);
+        if !self.config.contacts.allowlist {
+            return true;
+        }
+        match self.contacts.allows(&email.from) {
+            Some(allowed) => allowed,
+            None => {
+                log::warn(&format!("Forwarding '{}': the contacts allowlist could not be read", email.subject));
+                true
+            }
+        }
+    }
+
+    fn classify(&mut self, email: &mut EmailData) {
+        if !self.config.classify.is_enabled() || !self.config.pipeline.has(Stage::Classify) {
+            return;
+        }
+        let span = self.tracer.start("classify");
+        email.classification = self.classifier.classify(email);
+        if let Some(classification) = &email.classification {
+            self.tracer.attr(span, "classification.label", &classification.label);
+        }
+        self.tracer.end(span, None);
+    }
+
+    /// Leave a message from a sender outside the allowlist unseen, and do
+    /// not look at it again.
+    fn skip(&mut self, folder: &str, uid_validity: u32, uid: u32) {
+        log::info(&format!("Holding back UID {}: the sender is not a contact", uid));
+        metrics::inc(metrics::UNKNOWN_SENDERS);
+        self.state.skip(folder, uid_validity, uid, chrono::Utc::now().timestamp());
+        self.save_state();
+    }
+
+    /// Run the configured scanners and attach their verdict to `email`.
+    /// Returns whether the message should be dropped.
+    fn scan(&mut self, raw: &[u8], email: &mut EmailData) -> bool {
+        if !self.config.scan.is_enabled() || !self.config.pipeline.has(Stage::Scan) {
+            return false;
+        }
+        let span = self.tracer.start("scan");
+        let verdict = email.scan.insert(scan::scan(&self.config.scan, raw));
+        for error in &verdict.errors {
+            log::warn(&format!("scan of '{}' incomplete: {}", email.subject, error));
+        }
+        self.tracer.attr(span, "scan.findings", verdict.findings.len());
+        self.tracer.end(span, verdict.errors.first().cloned());
+        if verdict.status != scan::Status::Threat {
+            return false;
+        }
+        metrics::inc(metrics::SCAN_THREATS);
+        let threats: Vec<&str> = verdict.findings.iter().map(|f| f.threat.as_str()).collect();
+        if self.config.scan.action == ScanAction::Drop {
+            log::warn(&format!("Dropped '{}' from {}: {}", email.subject, email.from, threats.join(", ")));
+            metrics::inc(metrics::SCAN_DROPPED);
+            return true;
+        }
+        log::warn(&format!("Forwarding '{}' flagged as {}", email.subject, threats.join(", ")));
+        false
+    }
+
+    /// The idempotency key a message is delivered under: the same for
+    /// every attempt, and without the address in it.
+    fn delivery_key(&self, folder: &str, uid_validity: u32, uid: u32) -> String {
+        let id = format!("{}\n{}\n{}\n{}", self.config.mailcow_username, folder, uid_validity, uid);
+        hex(&sha256(id.as_bytes()))[..32].to_string()
+    }
+
+    /// Route and deliver a message, under `key` if given. Returns the
+    /// sink's receipt, or the error, which has been logged.
+    fn deliver(&mut self, folder: &str, email: &EmailData, key: Option<&str>) -> Result<Option<String>, String> {
+        log::info(&format!("📧 New: {} from {}", email.subject, email.from));
+        let span = self.tracer.start("filter");
+        let mut route = rules::route(&self.config.rules, email);
+        if let Some(sink) = self.config.mdn.sink.as_deref().filter(|_| email.receipt.is_some()) {
+            route.sink = sink;
+        }
+        let _rule = route.rule.map(|rule| log::field("RULE", &rule.name));
+        if let Some(rule) = route.rule {
+            self.tracer.attr(span, "rule", &rule.name);
+        }
+        self.tracer.attr(span, "event_type", &route.event_type);
+        self.tracer.end(span, None);
+        let by_email = matches!(self.config.sinks.get(route.sink), Some(SinkConfig::Smtp { .. }));
+        if by_email && email.header(smtp::LOOP_HEADER) == Some(self.config.mailcow_username.as_str()) {
+            log::info(&format!("Not forwarding '{}' by email again: this account forwarded it", email.subject));
+            return Ok(None);
+        }
+        let Some(sink) = self.sinks.get(route.sink) else {
+            let error = format!("no sink named '{}'", route.sink);
+            log::error(&error);
+            return Err(error);
+        };
+        let span = self.tracer.start("deliver");
+        self.tracer.attr(span, "sink", route.sink);
+        self.tracer.attr(span, "imap.folder", folder);
+        let payload = route.payload(email, &self.config.payload);
+        let result = match key {
+            Some(key) => sink.deliver_keyed(&payload, key),
+            None => sink.deliver(&payload).map(|()| None),
+        };
+        self.tracer.end(span, result.as_ref().err().map(|e| e.to_string()));
+        self.alerts.delivery(route.sink, result.is_ok());
+        let mut labels = vec![("account", self.config.mailcow_username.as_str())];
+        labels.extend(route.rule.map(|rule| ("rule", rule.name.as_str())));
+        match result {
+            Ok(receipt) => {
+                #[cfg(feature = "sentry")]
+                crate::sentry::delivery_succeeded(route.sink);
+                if let Some(rule) = route.rule {
+                    metrics::inc_with(metrics::RULE_MATCHES, &labels);
+                    self.state.record_rule_hit(&rule.name, chrono::Utc::now().timestamp());
+                }
+                log::info(&format!("✓ Sent to {} as {}: {}", route.sink, route.event_type, email.subject));
+                metrics::inc(metrics::DELIVERED);
+                metrics::inc_with(metrics::ACCOUNT_DELIVERED, &labels);
+                self.observe_latency(email);
+                Ok(receipt)
+            }
+            Err(e) => {
+                #[cfg(feature = "sentry")]
+                crate::sentry::delivery_failed(route.sink, folder, email, e.as_ref());
+                log::error(&format!("✗ Failed to send to {}: {}", route.sink, e));
+                metrics::inc(metrics::DELIVERY_FAILURES);
+                metrics::inc_with(metrics::ACCOUNT_DELIVERY_FAILURES, &labels);
+                Err(e.to_string())
+            }
+        }
+    }
+
+    /// Hold a message back if its rule says so and it is not due yet.
+    /// Returns when it is due.
+    fn snooze(&mut self, folder: &str, uid_validity: u32, uid: u32, email: &EmailData) -> Option<i64> {
+        // A message already held has come due.
+        if self.state.snoozed_until(folder, uid_validity, uid).is_some() {
+            return None;
+        }
+        let rule = rules::route(&self.config.rules, email).rule?;
+        let arrived = email.arrived().map_or_else(chrono::Local::now, |at| at.with_timezone(&chrono::Local));
+        let due = rule.due(arrived).filter(|&due| due > chrono::Utc::now().timestamp())?;
+        self.state.snooze(folder, uid_validity, uid, due);
+        self.save_state();
+        Some(due)
+    }
+
+    /// Hold `email` back if it belongs to a burst that is still open.
+    fn collapse(&mut self, email: &EmailData) -> bool {
+        if !self.config.collapse.is_enabled() {
+            return false;
+        }
+        let now = chrono::Utc::now().timestamp();
+        let window = self.config.collapse.window as i64;
+        let Some(burst) = self.state.bursts.get_mut(&collapse::key(email)).filter(|b| now - b.started < window) else {
+            return false;
+        };
+        burst.collapsed += 1;
+        log::info(&format!("Collapsing '{}' into a burst of {}", email.subject, burst.collapsed + 1));
+        metrics::inc(metrics::COLLAPSED);
+        self.save_state();
+        true
+    }
+
+    /// Start a burst with a forwarded message, unless one is open.
+    fn open_burst(&mut self, email: &EmailData) {
+        if !self.config.collapse.is_enabled() {
+            return;
+        }
+        let sink = rules::route(&self.config.rules, email).sink.to_string();
+        let burst = Burst {
+            started: chrono::Utc::now().timestamp(),
+            subject: email.subject.clone(),
+            from: email.from.clone(),
+            sink,
+            collapsed: 0,
+        };
+        // A burst whose report failed is kept until the report goes out.
+        self.state.bursts.entry(collapse::key(email)).or_insert(burst);
+        self.save_state();
+    }
+
+    fn load_expectations(&mut self) {
+        let Some(dir) = &self.config.expectations.dir else {
+            return;
+        };
+        match expect::load(dir) {
+            Ok((expectations, errors)) => {
+                for error in errors {
+                    log::warn(&format!("could not read expectation {}", error));
+                }
+                self.expectations = expectations;
+            }
+            Err(e) => log::warn(&format!("could not read the expectations in {}: {}", dir, e)),
+        }
+    }
+
+    /// Confirm the first open expectation `email` matches, noting the
+    /// outcome in its file until it is reported.
+    fn confirm_expectation(&mut self, email: &EmailData, now: i64) {
+        let Some(expectation) = self.expectations.iter_mut().find_map(|e| e.confirm(email, now).then_some(e)) else {
+            return;
+        };
+        log::info(&format!("'{}' confirms expectation {}", email.subject, expectation.id));
+        if let Some(dir) = &self.config.expectations.dir {
+            if let Err(e) = expect::save(dir, expectation) {
+                log::warn(&format!("could not save expectation {}: {}", expectation.id, e));
+            }
+        }
+    }
+
+    /// Time out the expectations past their deadline, and report every
+    /// decided one. A report that fails is retried next cycle.
+    fn report_expectations(&mut self) {
+        let Some(dir) = self.config.expectations.dir.clone() else {
+            return;
+        };
+        let now = chrono::Utc::now().timestamp();
+        let name = &self.config.expectations.sink;
+        for mut expectation in std::mem::take(&mut self.expectations) {
+            if expectation.expire(now) {
+                log::warn(&format!("Expectation {} timed out", expectation.id));
+                if let Err(e) = expect::save(&dir, &expectation) {
+                    log::warn(&format!("could not save expectation {}: {}", expectation.id, e));
+                }
+            }
+            let Some(event) = expectation.event() else {
+                continue;
+            };
+            let Some(sink) = self.sinks.get(name) else {
+                log::error(&format!("no sink named '{}'", name));
+                return;
+            };
+            if let Err(e) = sink.deliver(&redact::redact_value(&event)) {
+                log::error(&format!("✗ Failed to report expectation {} to {}: {}", expectation.id, name, e));
+                continue;
+            }
+            if let Err(e) = expect::remove(&dir, &expectation.id) {
+                log::warn(&format!("could not remove expectation {}: {}", expectation.id, e));
+            }
+        }
+    }
+
+    /// Report the copies held back by bursts whose window has passed.
+    /// A report that fails is retried next cycle.
+    fn close_bursts(&mut self) {
+        let now = chrono::Utc::now().timestamp();
+        let window = self.config.collapse.window as i64;
+        let closed: Vec<String> =
+            self.state.bursts.iter().filter(|(_, b)| now - b.started >= window).map(|(key, _)| key.clone()).collect();
+        if closed.is_empty() {
+            return;
+        }
+        for key in closed {
+            let burst = &self.state.bursts[&key];
+            if burst.collapsed > 0 {
+                let Some(sink) = self.sinks.get(&burst.sink) else {
+                    log::error(&format!("no sink named '{}'", burst.sink));
+                    continue;
+                };
+                if let Err(e) = sink.deliver(&collapse::summarize(burst, now)) {
+                    log::error(&format!("✗ Failed to report a burst to {}: {}", burst.sink, e));
+                    continue;
+                }
+                log::info(&format!("✓ Reported {} collapsed copies of '{}'", burst.collapsed, burst.subject));
+            }
+            self.state.bursts.remove(&key);
+        }
+        self.save_state();
+    }
+
+    /// Diff `email` against the previous message in its thread.
+    fn diff(&self, email: &mut EmailData) {
+        if !self.config.diff.enabled {
+            return;
+        }
+        let Some(previous) = self.state.threads.get(&diff::thread(email)) else {
+            return;
+        };
+        let body: String = email.body.chars().take(self.config.diff.max_chars).collect();
+        email.diff = diff::diff(&previous.body, &body, previous.message_id.clone());
+        if let Some(diff) = &email.diff {
+            let (removed, added) = (diff.removed, diff.added);
+            log::info(&format!("'{}' removes {} lines and adds {} in its thread", email.subject, removed, added));
+        }
+    }
+
+    /// Keep a forwarded body for the next message in its thread.
+    fn remember_body(&mut self, email: &EmailData) {
+        if !self.config.diff.enabled {
+            return;
+        }
+        let body = ThreadBody {
+            at: chrono::Utc::now().timestamp(),
+            message_id: email.header("Message-ID").map(|id| id.trim().to_string()),
+            body: email.body.chars().take(self.config.diff.max_chars).collect(),
+        };
+        self.state.threads.insert(diff::thread(email), body);
+        self.save_state();
+    }
+
+    /// Answer a request for a read receipt, if `mdn.send` allows it.
+    /// Failures are logged but do not undo the delivery.
+    fn send_receipt(&self, email: &EmailData) {
+        let Some(to) = mdn::requested_by(email).filter(|_| self.config.mdn.send) else {
+            return;
+        };
+        let message = mdn::compose(email, &self.config.mailcow_username, &to);
+        let smtp = SmtpSink::new(std::slice::from_ref(&to), None, None, None, &self.config);
+        match smtp.send_message("", &message) {
+            Ok(()) => log::info(&format!("Sent a read receipt for '{}' to {}", email.subject, to)),
+            Err(e) => log::warn(&format!("could not send a read receipt to {}: {}", to, e)),
+        }
+    }
+
+    /// Unsubscribe from `email`'s list if its rule says so and that was
+    /// not done yet. A failure is logged and retried with the next mail.
+    fn unsubscribe(&mut self, email: &EmailData) {
+        if !rules::route(&self.config.rules, email).rule.is_some_and(|rule| rule.unsubscribe) {
+            return;
+        }
+        let dry_run = self.config.unsubscribe.dry_run;
+        let list = unsubscribe::list(email);
+        if self.state.unsubscribed.get(&list).is_some_and(|done| dry_run || !done.dry_run) {
+            return;
+        }
+        let Some(method) = unsubscribe::method(email) else {
+            log::info(&format!("Cannot unsubscribe from {}: no one-click or mailto List-Unsubscribe", list));
+            return;
+        };
+        if dry_run {
+            log::info(&format!("Would unsubscribe from {} by {}", list, method.describe()));
+        } else {
+            if let Err(e) = unsubscribe::send(&method, &self.config) {
+                log::warn(&format!("could not unsubscribe from {}: {}", list, e));
+                return;
+            }
+            log::info(&format!("Unsubscribed from {} by {}", list, method.describe()));
+        }
+        let entry = Unsubscribed { at: chrono::Utc::now().timestamp(), via: method.describe(), dry_run };
+        self.state.unsubscribed.insert(list, entry);
+        self.save_state();
+    }
+
+    /// Send health alerts that started or cleared to `alerts.sink`.
+    fn send_alerts(&mut self, health: &Health) {
+        let Some(name) = self.alerts.sink().map(str::to_string) else {
+            return;
+        };
+        for event in self.alerts.evaluate(health) {
+            log::warn(&format!("Health alert {}: {}", event["status"], event["message"]));
+            let Some(sink) = self.sinks.get(&name) else {
+                log::error(&format!("no sink named '{}'", name));
+                return;
+            };
+            if let Err(e) = sink.deliver(&redact::redact_value(&event)) {
+                log::error(&format!("✗ Failed to send health alert to {}: {}", name, e));
+            }
+        }
+    }
+
+    /// Record how long after arriving a message was delivered, warning
+    /// if that exceeds `latency_slo`.
+    fn observe_latency(&self, email: &EmailData) {
+        let Some(arrived) = email.arrived() else {
+            return;
+        };
+        let latency = (chrono::Utc::now() - arrived.to_utc()).num_seconds().max(0);
+        metrics::observe(metrics::DELIVERY_LATENCY, latency as f64);
+        metrics::observe_with(metrics::ACCOUNT_DELIVERY_LATENCY, &[("account", &self.config.mailcow_username)], latency as f64);
+        let Ok(Some(slo)) = self.config.latency_slo() else {
+            return;
+        };
+        if latency as u64 > slo.as_secs() {
+            metrics::inc(metrics::LATENCY_SLO_BREACHES);
+            log::warn(&format!(
+                "'{}' was delivered {}s after arriving, above the {}s latency SLO (p95 now {}s)",
+                email.subject,
+                latency,
+                slo.as_secs(),
+                metrics::quantile(metrics::DELIVERY_LATENCY, 0.95)
+            ));
+        }
+    }
+
+    /// Save the state file, if there is one; a failure is only warned
+    /// about, as the next save will try again.
+    fn save_state(&self) {
+        if self.config.state_file.is_empty() {
+            return;
+        }
+        if let Err(e) = self.state.save(&self.config.state_file) {
+            log::warn(&format!("could not save state: {}", e));
+        }
+    }
+
+    fn record(&mut self, folder: &str, uid_validity: u32, uid: u32, message_id: Option<&str>) {
+        if self.config.state_file.is_empty() {
+            return;
+        }
+        self.state.record(folder, uid_validity, uid, message_id, chrono::Utc::now().timestamp());
+        self.save_state();
+    }
+
+    /// Add a cycle's traffic to today's total for the account.
+    fn record_bandwidth(&mut self, traffic: Bandwidth) {
+        if self.config.state_file.is_empty() || traffic == Bandwidth::default() {
+            return;
+        }
+        let today = chrono::Local::now().date_naive();
+        self.state.record_bandwidth(&self.config.mailcow_username, today, traffic);
+        self.save_state();
+    }
+
+    /// Keep a local copy of a delivered message. Failures are logged but
+    /// do not hold up delivery.
+    fn archive(&self, raw: &[u8], email: &EmailData) {
+        if !self.config.archive.is_enabled() {
+            return;
+        }
+        let route = rules::route(&self.config.rules, email);
+        let payload = route.payload(email, &self.config.payload);
+        if let Err(e) = archive::store(&self.config.archive, raw, &route.event_type, &payload) {
+            log::warn(&format!("could not archive '{}': {}", email.subject, e));
+        }
+    }
+
+    /// Apply retention limits if they are set and `PRUNE_INTERVAL` has
+    /// passed since the last prune.
+    pub fn prune_if_due(&mut self) {
+        let due = Duration::from_secs(retention::PRUNE_INTERVAL);
+        if !self.config.retention.is_enabled() || self.pruned.is_some_and(|at| at.elapsed() < due) {
+            return;
+        }
+        self.pruned = Some(Instant::now());
+        match retention::prune(&self.config, &mut self.state) {
+            Ok(pruned) if pruned.messages > 0 || pruned.state_entries > 0 => {
+                log::info(&format!(
+                    "Pruned {} archived messages ({} bytes) and {} state entries",
+                    pruned.messages, pruned.bytes, pruned.state_entries
+                ));
+            }
+            Ok(_) => {}
+            Err(e) => log::warn(&format!("could not prune local data: {}", e)),
+        }
+    }
+
+    /// Warn about rules that have not matched in `unused_rule_days`, at
+    /// most once a day.
+    pub fn warn_unused_rules_if_due(&mut self) {
+        let days = self.config.unused_rule_days;
+        let due = Duration::from_secs(UNUSED_RULE_CHECK_INTERVAL);
+        if days == 0 || self.config.state_file.is_empty() || self.rules_checked.is_some_and(|at| at.elapsed() < due) {
+            return;
+        }
+        self.rules_checked = Some(Instant::now());
+        let now = chrono::Utc::now().timestamp();
+        let names: Vec<&str> = self.config.rules.iter().map(|r| r.name.as_str()).collect();
+        self.state.track_rules(&names, now);
+        for rule in self.state.unused_rules(now - days as i64 * 86400) {
+            log::warn(&format!("Rule '{}' has not matched in {} days; consider removing it", rule, days));
+        }
+    }
+
+    /// Re-fetch provider-backed secrets if `secret_refresh_interval` has passed.
+    pub fn refresh_secrets_if_due(&mut self) {
+        let due = Duration::from_secs(self.config.secret_refresh_interval as u64);
+        if self.config.mailcow_password_provider.is_none() || self.secrets_fetched.elapsed() < due {
+            return;
+        }
+        if let Err(e) = self.refresh_secrets() {
+            log::warn(&format!("could not refresh credentials: {}", e));
+        }
+    }
+
+    /// Whether this instance should poll: always without `ha.lease_file`,
+    /// else if it holds the account's lease, which this takes or renews
+    /// for `hold` plus `ha.grace`. Taking it over reloads the state file,
+    /// which the previous holder may have changed.
+    pub fn lead(&mut self, hold: Duration) -> bool {
+        let ha = &self.config.ha;
+        let Some(path) = &ha.lease_file else {
+            return true;
+        };
+        let (node, now) = (ha.node_id(), chrono::Utc::now().timestamp());
+        let until = now + (hold.as_secs() + ha.grace) as i64;
+        let (leading, message) = match ha::acquire(path, &self.config.mailcow_username, &node, until, now) {
+            Ok(lease) if lease.holder == node => (true, format!("Holding the lease as {}; polling", node)),
+            Ok(lease) => (false, format!("Standing by: {} holds the lease", lease.holder)),
+            Err(e) => (false, format!("Standing by: could not take the lease: {}", e)),
+        };
+        if self.leading != Some(leading) {
+            log::info(&message);
+            if leading && !self.config.state_file.is_empty() {
+                match State::load(&self.config.state_file) {
+                    Ok(state) => self.state = state,
+                    Err(e) => log::warn(&format!("could not reload state: {}", e)),
+                }
+            }
+        }
+        self.leading = Some(leading);
+        leading
+    }
+
+    /// Re-fetch secrets now. Returns whether the password changed.
+    fn refresh_secrets(&mut self) -> Result<bool, Box<dyn Error>> {
+        self.secrets_fetched = Instant::now();
+        let changed = self.config.resolve_secrets()?;
+        if changed {
+            log::info("Credentials rotated; using the new password from the secret provider.");
+            redact::install(self.config.redactor());
+        }
+        Ok(changed)
+    }
+}
+
+/// Decrypt, parse and verify a raw message.
+/// Run `f` on a worker thread, failing if it panics or runs past
+/// `timeout`. A worker that overruns is abandoned, not killed.
+fn bounded<T: Send + 'static>(
+    what: String,
+    timeout: Option<Duration>,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, String> {
+    let (tx, rx) = mpsc::channel();
+    let worker_what = what.clone();
+    thread::spawn(move || {
+        let _ = tx.send(guarded(&worker_what, f));
+    });
+    let received = match timeout {
+        Some(timeout) => rx.recv_timeout(timeout).map_err(|_| {
+            format!("{} took longer than {}s", what, timeout.as_secs_f64())
+        }),
+        None => rx.recv().map_err(|e| e.to_string()),
+    };
+    received.and_then(|result| result)
+}
+
+/// UIDs above `after`. `n:*` always matches the last message, even
+/// below `n`.
+fn uids_after<S: Read + Write>(client: &mut Client<S>, after: u32) -> Result<Vec<u32>, ImapError> {
+    let uids = client.uid_search(&format!("UID {}:*", after.saturating_add(1)))?;
+    let mut uids: Vec<u32> = uids.into_iter().filter(|&uid| uid > after).collect();
+    uids.sort_unstable();
+    Ok(uids)
+}
+
+/// Add `flag` to a handled message; `None` leaves it as it is.
+fn mark_handled<S: Read + Write>(client: &mut Client<S>, flag: Option<&str>, uid: u32) -> Result<(), ImapError> {
+    match flag {
+        Some(flag) => client.uid_add_flag(uid, flag),
+        None => Ok(()),
+    }
+}
+
+/// Run `f`, turning a panic into an error that is logged and counted.
+fn guarded<T>(what: &str, f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        metrics::inc(metrics::PANICS);
+        let message = format!("panic while {}: {}", what, panic_message(payload.as_ref()));
+        log::error(&message);
+        message
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imap::tests::Script;
+    use crate::secrets::SecretProvider;
+    use crate::trigger::TriggerConfig;
+    use serde_json::Value;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Recorder(Rc<RefCell<Vec<Value>>>);
+
+    impl Sink for Recorder {
+        fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(payload.clone());
+            Ok(())
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            state_file: String::new(),
+            ..Config::default()
+        }
+    }
+
+    fn checker(config: Config) -> (Checker, Rc<RefCell<Vec<Value>>>) {
+        let delivered = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: BTreeMap<String, Box<dyn Sink>> = BTreeMap::new();
+        sinks.insert(sink::DEFAULT_SINK.to_string(), Box::new(Recorder(delivered.clone())));
+        (Checker::with_sinks(config, sinks), delivered)
+    }
+
+    #[test]
+    fn test_cycle_delivers_and_marks_seen() {
+        let (mut checker, delivered) = checker(config());
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\nA2 OK\r\n* SEARCH 9\r\nA3 OK\r\n\
+* 1 FETCH (UID 9 BODY[] {15}\r\nSubject: Hi\r\n\r\n)\r\nA4 OK\r\nA5 OK\r\nA6 OK\r\n";
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 1);
+        assert_eq!(delivered.borrow()[0]["event_type"], "message");
+        assert_eq!(delivered.borrow()[0]["correlation_id"].as_str().map(str::len), Some(16));
+    }
+
+    #[test]
+    fn test_cycle_checks_every_folder() {
+        let (mut checker, delivered) = checker(Config { folders: vec!["INBOX".to_string(), "Entwürfe".to_string()], ..config() });
+        // Without NAMESPACE, the root's delimiter is asked for instead.
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\n* LIST (\\Noselect) \".\" \"\"\r\nA2 OK\r\n\
+A3 OK\r\n* SEARCH\r\nA4 OK\r\n* OK [UIDVALIDITY 7]\r\nA5 OK\r\n* SEARCH 3\r\nA6 OK\r\n\
+* 1 FETCH (UID 3 BODY[] {15}\r\nSubject: Hi\r\n\r\n)\r\nA7 OK\r\nA8 OK\r\nA9 OK\r\n";
+        let mut script = Script::new(server);
+        assert_eq!(checker.run_on(&mut script).unwrap(), 1);
+        assert_eq!(delivered.borrow().len(), 1);
+        let sent = String::from_utf8(script.output).unwrap();
+        assert!(sent.contains("A2 LIST \"\" \"\"\r\nA3 SELECT \"INBOX\"\r\n"));
+        assert!(sent.contains("A5 SELECT \"Entw&APw-rfe\"\r\n"));
+    }
+
+    #[test]
+    fn test_folder_patterns_expand_to_selectable_folders() {
+        let (mut checker, _) = checker(Config { folders: vec!["INBOX".to_string(), "INBOX/*".to_string()], ..config() });
+        let server = "* OK [CAPABILITY IMAP4rev1 NAMESPACE] ready\r\nA1 OK\r\n\
+* NAMESPACE ((\"INBOX.\" \".\")) NIL NIL\r\nA2 OK\r\n\
+* LIST (\\HasChildren \\Noselect) \".\" \"INBOX.Filtered\"\r\n* LIST () \".\" \"INBOX.Filtered.Lists\"\r\n\
+* LIST () \".\" INBOX.Support\r\nA3 OK\r\n\
+A4 OK\r\n* SEARCH\r\nA5 OK\r\nA6 OK\r\n* SEARCH\r\nA7 OK\r\nA8 OK\r\n* SEARCH\r\nA9 OK\r\nA10 OK\r\n";
+        let mut script = Script::new(server);
+        assert_eq!(checker.run_on(&mut script).unwrap(), 0);
+        let sent = String::from_utf8(script.output).unwrap();
+        assert!(sent.contains("A3 LIST \"\" \"INBOX.*\"\r\nA4 SELECT \"INBOX\"\r\n"));
+        assert!(sent.contains("A6 SELECT \"INBOX.Filtered.Lists\"\r\n"));
+        assert!(sent.contains("A8 SELECT \"INBOX.Support\"\r\n"));
+        assert!(!sent.contains("SELECT \"INBOX.Filtered\"\r\n"));
+    }
+
+    #[test]
+    fn test_shared_folders_are_tracked_by_uid() {
+        let folders = vec!["Shared/support".to_string(), "Shared/board".to_string()];
+        let (mut checker, delivered) = checker(Config { folders, ..config() });
+        let greeting = "* OK [CAPABILITY IMAP4rev1 NAMESPACE ACL] ready\r\nA1 OK\r\n\
+* NAMESPACE ((\"INBOX.\" \".\")) NIL ((\"Shared.\" \".\"))\r\nA2 OK\r\n\
+* MYRIGHTS \"Shared.support\" lr\r\nA3 OK\r\n* OK [UIDVALIDITY 3]\r\nA4 OK\r\n";
+        // The first cycle only notes where the folder stands.
+        let first = format!("{}* SEARCH 1 2\r\nA5 OK\r\n* MYRIGHTS \"Shared.board\" l\r\nA6 OK\r\nA7 OK\r\n", greeting);
+        assert_eq!(checker.run_on(Script::new(&first)).unwrap(), 0);
+        assert_eq!(checker.state.handled_through("Shared.support", 3), Some(2));
+        let second = format!(
+            "{}* SEARCH 3\r\nA5 OK\r\n* 1 FETCH (UID 3 BODY[] {{15}}\r\nSubject: Hi\r\n\r\n)\r\nA6 OK\r\n\
+* MYRIGHTS \"Shared.board\" l\r\nA7 OK\r\nA8 OK\r\n",
+            greeting
+        );
+        let mut script = Script::new(&second);
+        assert_eq!(checker.run_on(&mut script).unwrap(), 1);
+        assert_eq!(delivered.borrow().len(), 1);
+        assert_eq!(checker.state.handled_through("Shared.support", 3), Some(3));
+        let sent = String::from_utf8(script.output).unwrap();
+        assert!(sent.contains("A5 UID SEARCH UID 3:*\r\n"));
+        assert!(!sent.contains("STORE") && !sent.contains("SELECT \"Shared.board\""));
+    }
+
+    #[test]
+    fn test_trigger_flag_forwards_flagged_mail() {
+        let trigger = TriggerConfig { flag: Some("\\Flagged".to_string()), ..TriggerConfig::default() };
+        let (mut checker, delivered) = checker(Config { trigger, ..config() });
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\nA2 OK\r\n* SEARCH 9\r\nA3 OK\r\n\
+* 1 FETCH (UID 9 BODY[] {15}\r\nSubject: Hi\r\n\r\n)\r\nA4 OK\r\nA5 OK\r\nA6 OK\r\n";
+        let mut script = Script::new(server);
+        assert_eq!(checker.run_on(&mut script).unwrap(), 1);
+        assert_eq!(delivered.borrow().len(), 1);
+        let sent = String::from_utf8(script.output).unwrap();
+        assert!(sent.contains("A3 UID SEARCH FLAGGED UNKEYWORD $Forwarded\r\n"));
+        assert!(sent.contains("A5 UID STORE 9 +FLAGS.SILENT ($Forwarded)\r\n"));
+    }
+
+    #[test]
+    fn test_unsubscribe_dry_run_notes_the_list() {
+        let rule = serde_json::json!({"name": "news", "match": {"from": "shop"}, "unsubscribe": true});
+        let rules = vec![serde_json::from_value(rule).unwrap()];
+        let unsubscribe = crate::unsubscribe::UnsubscribeConfig { dry_run: true };
+        let (mut checker, _) = checker(Config { rules, unsubscribe, ..config() });
+        let message = "From: news@shop.example\r\nList-Unsubscribe: <mailto:leave@shop.example>\r\n\r\n";
+        let server = format!(
+            "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\nA2 OK\r\n* SEARCH 9\r\nA3 OK\r\n\
+* 1 FETCH (UID 9 BODY[] {{{}}}\r\n{})\r\nA4 OK\r\nA5 OK\r\nA6 OK\r\n",
+            message.len(),
+            message
+        );
+        assert_eq!(checker.run_on(Script::new(&server)).unwrap(), 1);
+        let noted = &checker.state.unsubscribed["news@shop.example"];
+        assert_eq!((noted.via.as_str(), noted.dry_run), ("mail to leave@shop.example", true));
+    }
+
+    #[test]
+    fn test_rule_holds_mail_until_due() {
+        let rule = serde_json::json!({"name": "news", "match": {"from": "shop"}, "delay": 3600});
+        let rules = vec![serde_json::from_value(rule).unwrap()];
+        let (mut checker, delivered) = checker(Config { rules, ..config() });
+        let message = format!("From: news@shop.example\r\nDate: {}\r\n\r\n", chrono::Local::now().to_rfc2822());
+        let fetch = format!("* 1 FETCH (UID 9 BODY[] {{{}}}\r\n{})\r\nA4 OK\r\n", message.len(), message);
+        let search = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\nA2 OK\r\n* SEARCH 9\r\nA3 OK\r\n";
+        let server = format!("{}{}A5 OK\r\n", search, fetch);
+        assert_eq!(checker.run_on(Script::new(&server)).unwrap(), 0);
+        let due = checker.state.snoozed_until(INBOX, 0, 9).unwrap();
+        assert!(due > chrono::Utc::now().timestamp() + 3500);
+
+        // Not fetched again while held; delivered once due.
+        assert_eq!(checker.run_on(Script::new(&format!("{}A4 OK\r\n", search))).unwrap(), 0);
+        checker.state.snooze(INBOX, 0, 9, due - 3600);
+        let server = format!("{}{}A5 OK\r\nA6 OK\r\n", search, fetch);
+        assert_eq!(checker.run_on(Script::new(&server)).unwrap(), 1);
+        assert_eq!(delivered.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_bursts_collapse_into_one_report() {
+        let collapse = crate::collapse::CollapseConfig { window: 600 };
+        let (mut checker, delivered) = checker(Config { collapse, ..config() });
+        let fetch = |uid: u32, tag: u32, subject: &str| {
+            let message = format!("From: root@cron.example\r\nSubject: {}\r\n\r\n", subject);
+            let fetch = format!("* 1 FETCH (UID {} BODY[] {{{}}}\r\n{})\r\n", uid, message.len(), message);
+            format!("{}A{} OK\r\nA{} OK\r\n", fetch, tag, tag + 1)
+        };
+        let server = format!(
+            "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\nA2 OK\r\n* SEARCH 1 2 3\r\nA3 OK\r\n{}{}{}A10 OK\r\n",
+            fetch(1, 4, "Job 1 failed"),
+            fetch(2, 6, "Job 2 failed"),
+            fetch(3, 8, "Backup done")
+        );
+        assert_eq!(checker.run_on(Script::new(&server)).unwrap(), 2);
+        assert_eq!(delivered.borrow().len(), 2);
+
+        checker.close_bursts();
+        assert_eq!(delivered.borrow().len(), 2, "the window is still open");
+        checker.state.bursts.values_mut().for_each(|burst| burst.started -= 600);
+        checker.close_bursts();
+        let report = delivered.borrow()[2].clone();
+        assert_eq!(report["event_type"], "collapsed");
+        assert_eq!(report["collapsed"]["count"], 1);
+        assert_eq!(report["collapsed"]["subject"], "Job 1 failed");
+        assert_eq!(delivered.borrow().len(), 3, "an empty burst is not reported");
+        assert!(checker.state.bursts.is_empty());
+    }
+
+    #[test]
+    fn test_resent_mail_carries_a_diff() {
+        let diff = crate::diff::DiffConfig { enabled: true, ..Default::default() };
+        let (mut checker, delivered) = checker(Config { diff, ..config() });
+        let fetch = |uid: u32, tag: u32, body: &str| {
+            let message = format!("From: bob@example.com\r\nSubject: Agenda\r\n\r\n{}", body);
+            let fetch = format!("* 1 FETCH (UID {} BODY[] {{{}}}\r\n{})\r\n", uid, message.len(), message);
+            format!("{}A{} OK\r\nA{} OK\r\n", fetch, tag, tag + 1)
+        };
+        let server = format!(
+            "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\nA2 OK\r\n* SEARCH 1 2\r\nA3 OK\r\n{}{}A8 OK\r\n",
+            fetch(1, 4, "1. Budget\r\n2. Hiring\r\n"),
+            fetch(2, 6, "1. Budget\r\n2. Hiring\r\n3. Offsite\r\n")
+        );
+        assert_eq!(checker.run_on(Script::new(&server)).unwrap(), 2);
+        assert!(delivered.borrow()[0].get("diff").is_none());
+        assert_eq!(delivered.borrow()[1]["diff"]["text"], "+3. Offsite");
+    }
+
+    #[test]
+    fn test_state_skips_delivered_and_duplicates() {
+        let path = std::env::temp_dir().join(format!("email_checker_checker_state_{}.json", std::process::id()));
+        let config = Config {
+            state_file: path.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let (mut checker, delivered) = checker(config);
+        checker.state.record(INBOX, 5, 8, None, 0);
+        checker.state.record(INBOX, 5, 1, Some("<dup@x>"), 0);
+
+        // UID 8 was delivered before; UID 9 repeats a delivered Message-ID.
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\n* OK [UIDVALIDITY 5]\r\nA2 OK\r\n* SEARCH 8 9 10\r\nA3 OK\r\nA4 OK\r\n\
+* 1 FETCH (UID 9 BODY[] {23}\r\nMessage-ID: <dup@x>\r\n\r\n)\r\nA5 OK\r\nA6 OK\r\n\
+* 2 FETCH (UID 10 BODY[] {23}\r\nMessage-ID: <new@x>\r\n\r\n)\r\nA7 OK\r\nA8 OK\r\nA9 OK\r\n";
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 1);
+        assert_eq!(delivered.borrow().len(), 1);
+        let saved = State::load(path.to_str().unwrap()).unwrap();
+        assert!(saved.is_delivered(INBOX, 5, 10) && saved.is_duplicate("<new@x>"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_seen_but_unacknowledged_is_redelivered() {
+        let path = std::env::temp_dir().join(format!("email_checker_reconcile_{}.json", std::process::id()));
+        let config = Config {
+            state_file: path.to_string_lossy().into_owned(),
+            ack: sink::AckConfig {
+                reconcile: true,
+                ..sink::AckConfig::default()
+            },
+            ..Config::default()
+        };
+        let (mut checker, delivered) = checker(config);
+        // The first pass only notes where reconciliation starts.
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\n* OK [UIDVALIDITY 5]\r\nA2 OK\r\n* SEARCH\r\nA3 OK\r\n\
+* SEARCH 3 4\r\nA4 OK\r\nA5 OK\r\n";
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 0);
+        assert_eq!(checker.state.reconcile_after(INBOX, 5), Some(4));
+
+        // UID 6 was delivered; UID 7 was marked seen by someone else.
+        checker.state.record(INBOX, 5, 6, None, 0);
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\n* OK [UIDVALIDITY 5]\r\nA2 OK\r\n* SEARCH\r\nA3 OK\r\n\
+* SEARCH 6 7\r\nA4 OK\r\n* 1 FETCH (UID 7 BODY[] {15}\r\nSubject: Hi\r\n\r\n)\r\nA5 OK\r\nA6 OK\r\nA7 OK\r\n";
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 1);
+        assert_eq!(delivered.borrow().len(), 1);
+        assert!(checker.state.is_delivered(INBOX, 5, 7));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Loses its first answer, then returns a receipt.
+    struct Receipts(Rc<RefCell<Vec<String>>>);
+
+    impl Sink for Receipts {
+        fn deliver(&self, _: &Value) -> Result<(), Box<dyn Error>> {
+            unreachable!("deliveries with receipts are keyed")
+        }
+
+        fn deliver_keyed(&self, _: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+            let mut keys = self.0.borrow_mut();
+            keys.push(key.to_string());
+            match keys.len() {
+                1 => Err("timed out".into()),
+                _ => Ok(Some("r-1".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_reuses_key_and_keeps_receipt() {
+        let path = std::env::temp_dir().join(format!("email_checker_receipts_{}.json", std::process::id()));
+        let config = Config {
+            state_file: path.to_string_lossy().into_owned(),
+            ack: sink::AckConfig {
+                receipt: Some("/receipt".to_string()),
+                ..sink::AckConfig::default()
+            },
+            ..Config::default()
+        };
+        let keys = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks: BTreeMap<String, Box<dyn Sink>> = BTreeMap::new();
+        sinks.insert(sink::DEFAULT_SINK.to_string(), Box::new(Receipts(keys.clone())));
+        let mut checker = Checker::with_sinks(config, sinks);
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\n* OK [UIDVALIDITY 5]\r\nA2 OK\r\n* SEARCH 9\r\nA3 OK\r\n\
+* 1 FETCH (UID 9 BODY[] {15}\r\nSubject: Hi\r\n\r\n)\r\nA4 OK\r\nA5 OK\r\nA6 OK\r\n";
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 0);
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 1);
+        let keys = keys.borrow();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0], keys[1]);
+        let saved = State::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(saved.receipt(INBOX, 5, 9), Some("r-1"));
+        assert!(saved.inflight[INBOX].uids.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rotated_password_is_refetched() {
+        let secret = std::env::temp_dir().join(format!("email_checker_rotation_{}", std::process::id()));
+        std::fs::write(&secret, "new-password").unwrap();
+        let config = Config {
+            mailcow_password: "old-password".into(),
+            mailcow_password_provider: Some(SecretProvider::File(secret.to_string_lossy().into_owned())),
+            ..config()
+        };
+        let (mut checker, _) = checker(config);
+        checker.password_worked = true;
+
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 NO [AUTHENTICATIONFAILED] bad\r\nA2 OK\r\nA3 OK\r\n* SEARCH\r\nA4 OK\r\nA5 OK\r\n";
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 0);
+        assert_eq!(checker.config.mailcow_password.expose(), "new-password");
+        assert!(checker.password_worked);
+
+        // A password that never worked is not retried.
+        checker.password_worked = false;
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 NO [AUTHENTICATIONFAILED] bad\r\n";
+        assert!(checker.run_on(Script::new(server)).is_err());
+        std::fs::remove_file(secret).unwrap();
+    }
+
+    #[test]
+    fn test_panics_are_contained() {
+        let before = metrics::get(metrics::PANICS);
+        let result: Result<(), String> = guarded("parsing UID 9", || panic!("bad header"));
+        assert_eq!(result.unwrap_err(), "panic while parsing UID 9: bad header");
+        assert_eq!(guarded("nothing", || 42), Ok(42));
+        assert_eq!(metrics::get(metrics::PANICS), before + 1);
+
+        let slow = bounded("parsing UID 10".to_string(), Some(Duration::from_millis(50)), || {
+            thread::sleep(Duration::from_secs(2));
+        });
+        assert_eq!(slow.unwrap_err(), "parsing UID 10 took longer than 0.05s");
+        assert_eq!(bounded("parsing".to_string(), None, || 7), Ok(7));
+    }
+
+    #[test]
+    fn test_quarantined_message_is_not_refetched() {
+        let (mut checker, delivered) = checker(config());
+        checker.state.quarantine(INBOX, 5, 9, 0);
+        let server = "* OK [CAPABILITY IMAP4rev1] ready\r\nA1 OK\r\n* OK [UIDVALIDITY 5]\r\nA2 OK\r\n\
+* SEARCH 9\r\nA3 OK\r\nA4 OK\r\n";
+        assert_eq!(checker.run_on(Script::new(server)).unwrap(), 0);
+        assert!(delivered.borrow().is_empty());
+        assert_eq!(checker.pending, 0);
+    }
+}
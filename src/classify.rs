@@ -0,0 +1,248 @@
+//! Labelling messages (billing, support, ci, personal, ...) for the
+//! `classify` pipeline stage.
+//!
+//! With `classify.url`, the subject, sender and text are posted as JSON
+//! to a model endpoint, which answers with `{"label": ..., "confidence":
+//! ...}`. Otherwise `classify.model` names a naive Bayes model trained
+//! with `email_checker train DIR`, where every subdirectory of `DIR` is
+//! a label holding example messages (`.eml` files or a maildir). Labels
+//! below `min_confidence` are left off; the rest go into the payload as
+//! `classification`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::email::EmailData;
+use crate::secrets::SecretString;
+use crate::{http, log, redact};
+
+pub const DEFAULT_MIN_CONFIDENCE: f64 = 0.5;
+
+/// Characters of the body sent to `url` or counted by the model.
+const MAX_TEXT_CHARS: usize = 4000;
+
+/// `classify` section of the config file.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ClassifyConfig {
+    pub url: Option<String>,
+    /// Extra request headers for `url`, e.g. an API key.
+    pub headers: BTreeMap<String, SecretString>,
+    pub model: Option<String>,
+    pub min_confidence: f64,
+}
+
+/// The `headers` values, such as the classifier's API token, print
+/// masked.
+impl fmt::Debug for ClassifyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        redact::debug_masked("ClassifyConfig", self, f)
+    }
+}
+
+impl Default for ClassifyConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            headers: BTreeMap::new(),
+            model: None,
+            min_confidence: DEFAULT_MIN_CONFIDENCE,
+        }
+    }
+}
+
+impl ClassifyConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.url.is_some() || self.model.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Classification {
+    pub label: String,
+    pub confidence: f64,
+}
+
+/// Word counts per label.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Model {
+    pub labels: BTreeMap<String, LabelCounts>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LabelCounts {
+    /// Training messages with this label.
+    pub messages: u64,
+    pub words: BTreeMap<String, u64>,
+}
+
+impl Model {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?)
+    }
+
+    pub fn learn(&mut self, label: &str, email: &EmailData) {
+        let counts = self.labels.entry(label.to_string()).or_default();
+        counts.messages += 1;
+        for word in words(email) {
+            *counts.words.entry(word).or_insert(0) += 1;
+        }
+    }
+
+    /// The most likely label, with its posterior probability.
+    pub fn classify(&self, email: &EmailData) -> Option<Classification> {
+        let total: u64 = self.labels.values().map(|l| l.messages).sum();
+        let vocabulary = self.labels.values().flat_map(|l| l.words.keys()).collect::<std::collections::BTreeSet<_>>().len();
+        let words = words(email);
+        let scores: Vec<(&String, f64)> = self
+            .labels
+            .iter()
+            .filter(|(_, counts)| counts.messages > 0)
+            .map(|(label, counts)| {
+                let size: u64 = counts.words.values().sum();
+                let denominator = (size + vocabulary as u64) as f64;
+                let likelihood: f64 = words
+                    .iter()
+                    .map(|w| ((counts.words.get(w).copied().unwrap_or(0) + 1) as f64 / denominator).ln())
+                    .sum();
+                (label, (counts.messages as f64 / total as f64).ln() + likelihood)
+            })
+            .collect();
+        let best = scores.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let evidence: f64 = scores.iter().map(|(_, score)| (score - best.1).exp()).sum();
+        Some(Classification { label: best.0.clone(), confidence: 1.0 / evidence })
+    }
+}
+
+/// Build a model from `dir`, one subdirectory of examples per label.
+/// Returns it with the number of messages read.
+pub fn train(dir: &Path) -> Result<(Model, usize), Box<dyn Error>> {
+    let mut model = Model::default();
+    let mut read = 0;
+    let mut labels: Vec<_> = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?.collect::<Result<_, _>>()?;
+    labels.sort_by_key(|entry| entry.file_name());
+    for entry in labels.iter().filter(|e| e.path().is_dir()) {
+        let label = entry.file_name().to_string_lossy().into_owned();
+        for path in files(&entry.path())? {
+            model.learn(&label, &EmailData::from_raw(&fs::read(&path)?));
+            read += 1;
+        }
+    }
+    if model.labels.len() < 2 {
+        return Err(format!("{}: needs a subdirectory of examples for at least two labels", dir.display()).into());
+    }
+    Ok((model, read))
+}
+
+fn files(dir: &Path) -> Result<Vec<std::path::PathBuf>, Box<dyn Error>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(files(&path)?);
+        } else if !path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')) {
+            found.push(path);
+        }
+    }
+    Ok(found)
+}
+
+/// Lowercased words of the subject and the start of the body.
+fn words(email: &EmailData) -> Vec<String> {
+    let text: String = email.body.chars().take(MAX_TEXT_CHARS).collect();
+    format!("{} {}", email.subject, text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| (2..=30).contains(&w.chars().count()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The classifier of one account: the model endpoint or a loaded model.
+pub struct Classifier {
+    config: ClassifyConfig,
+    model: Option<Model>,
+}
+
+impl Classifier {
+    /// A model that cannot be loaded is logged and classification skipped.
+    pub fn new(config: &ClassifyConfig) -> Self {
+        let model = config.model.as_deref().filter(|_| config.url.is_none()).and_then(|path| {
+            Model::load(path)
+                .map_err(|e| log::warn(&format!("could not load the classification model: {}", e)))
+                .ok()
+        });
+        Self { config: config.clone(), model }
+    }
+
+    pub fn classify(&self, email: &EmailData) -> Option<Classification> {
+        let classification = match &self.config.url {
+            Some(url) => self.ask(url, email).unwrap_or_else(|e| {
+                log::warn(&format!("classification of '{}' failed: {}", email.subject, e));
+                None
+            }),
+            None => self.model.as_ref()?.classify(email),
+        }?;
+        (classification.confidence >= self.config.min_confidence).then_some(classification)
+    }
+
+    fn ask(&self, url: &str, email: &EmailData) -> Result<Option<Classification>, Box<dyn Error>> {
+        let body = json!({
+            "subject": email.subject,
+            "from": email.from,
+            "text": email.body.chars().take(MAX_TEXT_CHARS).collect::<String>(),
+        });
+        let mut headers: Vec<(&str, &str)> = vec![("Content-Type", "application/json")];
+        headers.extend(self.config.headers.iter().map(|(k, v)| (k.as_str(), v.expose())));
+        let response = http::request("POST", url, &headers, Some(&body.to_string()), http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("{} returned HTTP {}", url, response.status).into());
+        }
+        let answer: serde_json::Value = serde_json::from_str(&response.body)?;
+        let Some(label) = answer["label"].as_str().filter(|l| !l.is_empty()) else {
+            return Ok(None);
+        };
+        Ok(Some(Classification {
+            label: label.to_string(),
+            confidence: answer["confidence"].as_f64().unwrap_or(1.0),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_train_and_classify() {
+        let dir = std::env::temp_dir().join(format!("email_checker_train_{}", std::process::id()));
+        let examples = [
+            ("billing", "Your invoice 4711", "Please find the invoice attached. Payment due in 14 days."),
+            ("billing", "Payment reminder", "The invoice payment is overdue."),
+            ("ci", "Build failed: main", "Pipeline failed at test stage. See the build log."),
+            ("ci", "Build passed", "All pipeline jobs passed on main."),
+        ];
+        for (i, (label, subject, body)) in examples.iter().enumerate() {
+            let label_dir = dir.join(label).join("cur");
+            fs::create_dir_all(&label_dir).unwrap();
+            fs::write(label_dir.join(format!("{}.eml", i)), format!("Subject: {}\r\n\r\n{}\r\n", subject, body)).unwrap();
+        }
+        let (model, read) = train(&dir).unwrap();
+        assert_eq!((read, model.labels.len()), (4, 2));
+
+        let email = EmailData::from_raw(b"Subject: New invoice\r\n\r\nInvoice for October, payment due.\r\n");
+        let classification = model.classify(&email).unwrap();
+        assert_eq!(classification.label, "billing");
+        assert!(classification.confidence > 0.9);
+        let email = EmailData::from_raw(b"Subject: Build failed\r\n\r\nthe pipeline failed\r\n");
+        assert_eq!(model.classify(&email).unwrap().label, "ci");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(train(&dir).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! The command line: subcommands, their words and flags.
+//!
+//! `main` parses arguments by hand, but takes the flags that consume a
+//! value from [`COMMANDS`] and [`GLOBAL_FLAGS`], and `completions` builds
+//! its scripts from the same tables, so adding a subcommand here is what
+//! makes it complete in every shell.
+
+pub struct Flag {
+    pub name: &'static str,
+    /// Placeholder of the value the flag takes, if any; `FILE`, `DIR` and
+    /// `PATH` complete file names.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    /// Fixed words that may follow, e.g. `export` and `import`.
+    pub words: &'static [&'static str],
+    /// Free arguments, as shown in usage lines.
+    pub args: &'static str,
+    pub flags: &'static [Flag],
+    pub help: &'static str,
+}
+
+const fn flag(name: &'static str, value: Option<&'static str>, help: &'static str) -> Flag {
+    Flag { name, value, help }
+}
+
+pub const GLOBAL_FLAGS: &[Flag] = &[
+    flag("--config", Some("FILE"), "Config file to load"),
+    flag("--profile", Some("NAME"), "Profile merged over the config"),
+    flag("--once", None, "Check once and exit"),
+    flag("--shadow", None, "Compare with the Python checker without delivering"),
+    flag("--allow-unpinned", None, "Run the Python checker even if it does not match python.sha256"),
+    flag("--strict-config", None, "Fail on unknown config keys and misspelt environment variables"),
+    flag("--lenient", None, "Let invalid numbers in environment variables fall back to defaults"),
+    flag("--shard", Some("N/M"), "Poll only this shard of the accounts"),
+];
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "search",
+        words: &[],
+        args: "TERMS...",
+        flags: &[flag("--limit", Some("N"), "Show at most N messages")],
+        help: "Search archived messages",
+    },
+    Command { name: "prune", words: &[], args: "", flags: &[], help: "Apply retention limits now" },
+    Command {
+        name: "purge",
+        words: &[],
+        args: "",
+        flags: &[
+            flag("--sender", Some("ADDRESS"), "Sender whose messages and state are deleted"),
+            flag("--dry-run", None, "Only report what would be deleted"),
+        ],
+        help: "Delete everything kept locally about one sender",
+    },
+    Command {
+        name: "state",
+        words: &["export", "import"],
+        args: "[FILE]",
+        flags: &[flag("--output", Some("FILE"), "File to export to instead of stdout")],
+        help: "Export or import the delivery state",
+    },
+    Command { name: "stats", words: &[], args: "", flags: &[], help: "Show traffic per account and day and rule matches" },
+    Command {
+        name: "unsubscribe",
+        words: &["report"],
+        args: "",
+        flags: &[],
+        help: "Show the lists rules unsubscribed from, or would have",
+    },
+    Command { name: "audit", words: &["verify"], args: "[FILE]", flags: &[], help: "Check the audit log's hash chain" },
+    Command {
+        name: "config",
+        words: &["schema", "show"],
+        args: "",
+        flags: &[flag("--format", Some("json|toml"), "Format of the effective config")],
+        help: "Print the config file's JSON Schema or the effective config",
+    },
+    Command {
+        name: "generate",
+        words: &["grafana-dashboard", "man"],
+        args: "",
+        flags: &[],
+        help: "Print a Grafana dashboard for the metrics or this man page",
+    },
+    Command {
+        name: "train",
+        words: &[],
+        args: "DIR",
+        flags: &[flag("--output", Some("FILE"), "Where to write the model")],
+        help: "Build the classification model from labelled examples",
+    },
+    Command {
+        name: "rules",
+        words: &["test"],
+        args: "",
+        flags: &[flag("--message", Some("FILE"), "Message to test instead of editing one")],
+        help: "Show the rules, route and payload for a message",
+    },
+    Command { name: "sieve", words: &["show", "push"], args: "", flags: &[], help: "Show or upload the Sieve script" },
+    Command {
+        name: "migrate",
+        words: &[],
+        args: "",
+        flags: &[flag("--from-python", Some("DIR"), "The Python checker's workspace")],
+        help: "Take over the Python checker's last check",
+    },
+    Command {
+        name: "mailcow",
+        words: &["create-app-password"],
+        args: "",
+        flags: &[flag("--name", Some("NAME"), "Name of the app password")],
+        help: "Create an IMAP app password through the mailcow API",
+    },
+    Command { name: "folders", words: &["list"], args: "", flags: &[], help: "List the folders the account can check" },
+    Command {
+        name: "imap",
+        words: &["capabilities"],
+        args: "",
+        flags: &[],
+        help: "Show the server's capabilities and what the checker uses",
+    },
+    Command {
+        name: "expect",
+        words: &["add", "list"],
+        args: "",
+        flags: &[
+            flag("--within", Some("DURATION"), "How long the mail may take, e.g. 30m"),
+            flag("--from", Some("TEXT"), "Text the sender must contain"),
+            flag("--subject", Some("TEXT"), "Text the subject must contain"),
+            flag("--body", Some("TEXT"), "Text the body must contain"),
+        ],
+        help: "Register or list mail that should arrive",
+    },
+    Command {
+        name: "self-update",
+        words: &[],
+        args: "",
+        flags: &[flag("--check", None, "Only report whether a release is available")],
+        help: "Install the latest signed release",
+    },
+    Command { name: "completions", words: SHELLS, args: "", flags: &[], help: "Print a shell completion script" },
+];
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// Whether `arg` is a flag followed by its value.
+pub fn takes_value(arg: &str) -> bool {
+    all_flags().any(|flag| flag.name == arg && flag.value.is_some())
+}
+
+fn all_flags() -> impl Iterator<Item = &'static Flag> {
+    GLOBAL_FLAGS.iter().chain(COMMANDS.iter().flat_map(|command| command.flags))
+}
+
+fn completes_files(flag: &Flag) -> bool {
+    matches!(flag.value, Some("FILE" | "DIR" | "PATH"))
+}
+
+/// The completion script for `shell`.
+pub fn completions(shell: &str) -> Result<String, String> {
+    match shell {
+        "bash" => Ok(bash()),
+        "zsh" => Ok(zsh()),
+        "fish" => Ok(fish()),
+        "powershell" => Ok(powershell()),
+        other => Err(format!("unknown shell '{}'; expected one of {}", other, SHELLS.join(", "))),
+    }
+}
+
+fn names<'a>(flags: impl IntoIterator<Item = &'a Flag>) -> Vec<&'a str> {
+    flags.into_iter().map(|flag| flag.name).collect()
+}
+
+/// Names of the flags `keep` selects, once each.
+fn flag_names(keep: fn(&Flag) -> bool) -> Vec<&'static str> {
+    let mut names: Vec<&str> = all_flags().filter(|flag| keep(flag)).map(|flag| flag.name).collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+fn bash() -> String {
+    let value_flags = flag_names(|flag| flag.value.is_some());
+    let file_flags = flag_names(completes_files);
+    let global = names(GLOBAL_FLAGS).join(" ");
+    let top: Vec<&str> = COMMANDS.iter().map(|c| c.name).collect();
+    let mut script = String::from("# bash completion for email_checker\n_email_checker() {\n");
+    script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    script.push_str(&format!("    case \"$prev\" in\n        {})\n", file_flags.join("|")));
+    script.push_str("            COMPREPLY=($(compgen -f -- \"$cur\"))\n            return ;;\n");
+    script.push_str(&format!("        {})\n            return ;;\n    esac\n", value_flags.join("|")));
+    script.push_str("    local i command=\"\"\n    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    script.push_str(&format!("        case \"${{COMP_WORDS[i]}}\" in\n            {}) ((i++)) ;;\n", value_flags.join("|")));
+    script.push_str("            -*) ;;\n            *) command=\"${COMP_WORDS[i]}\"; break ;;\n        esac\n    done\n");
+    script.push_str("    case \"$command\" in\n");
+    script.push_str(&format!("        \"\") COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\")) ;;\n", top.join(" "), global));
+    for command in COMMANDS {
+        let mut candidates: Vec<&str> = command.words.to_vec();
+        candidates.extend(names(command.flags));
+        candidates.extend(names(GLOBAL_FLAGS));
+        script.push_str(&format!("        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;\n", command.name, candidates.join(" ")));
+    }
+    script.push_str("    esac\n}\ncomplete -o default -F _email_checker email_checker\n");
+    script
+}
+
+/// `text` inside single quotes in a POSIX shell.
+fn shell_quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+fn zsh_flag(flag: &Flag) -> String {
+    let help = shell_quote(flag.help);
+    match flag.value {
+        Some(value) if completes_files(flag) => format!("'{}[{}]:{}:_files'", flag.name, help, value.to_lowercase()),
+        Some(value) => format!("'{}[{}]:{}:'", flag.name, help, value.to_lowercase()),
+        None => format!("'{}[{}]'", flag.name, help),
+    }
+}
+
+fn zsh() -> String {
+    let mut script = String::from("#compdef email_checker\n\n_email_checker() {\n    local -a commands\n    commands=(\n");
+    for command in COMMANDS {
+        script.push_str(&format!("        '{}:{}'\n", command.name, shell_quote(command.help)));
+    }
+    script.push_str("    )\n    _arguments -C \\\n");
+    for flag in GLOBAL_FLAGS {
+        script.push_str(&format!("        {} \\\n", zsh_flag(flag)));
+    }
+    script.push_str("        '1: :->command' \\\n        '*:: :->args'\n");
+    script.push_str("    case $state in\n        command) _describe 'command' commands ;;\n        args)\n            case $words[1] in\n");
+    for command in COMMANDS.iter().filter(|c| !c.words.is_empty() || !c.flags.is_empty()) {
+        let mut specs: Vec<String> = command.flags.iter().map(zsh_flag).collect();
+        if !command.words.is_empty() {
+            specs.push(format!("'1:subcommand:({})'", command.words.join(" ")));
+        }
+        if command.args.contains("FILE") || command.args.contains("DIR") {
+            specs.push("'*:file:_files'".to_string());
+        } else if !command.args.is_empty() {
+            specs.push("'*:argument:'".to_string());
+        }
+        script.push_str(&format!("                {}) _arguments {} ;;\n", command.name, specs.join(" ")));
+    }
+    script.push_str("            esac ;;\n    esac\n}\n\n_email_checker \"$@\"\n");
+    script
+}
+
+fn fish_flag(flag: &Flag) -> String {
+    let kind = match flag.value {
+        Some(_) if completes_files(flag) => " -r -F",
+        Some(_) => " -x",
+        None => "",
+    };
+    format!("-l {}{} -d '{}'", flag.name.trim_start_matches("--"), kind, flag.help.replace('\'', "\\'"))
+}
+
+fn fish() -> String {
+    let mut script = String::from("# fish completion for email_checker\ncomplete -c email_checker -f\n");
+    for flag in GLOBAL_FLAGS {
+        script.push_str(&format!("complete -c email_checker {}\n", fish_flag(flag)));
+    }
+    for command in COMMANDS {
+        script.push_str(&format!(
+            "complete -c email_checker -n __fish_use_subcommand -a {} -d '{}'\n",
+            command.name,
+            command.help.replace('\'', "\\'")
+        ));
+        let seen = format!("-n '__fish_seen_subcommand_from {}'", command.name);
+        if !command.words.is_empty() {
+            script.push_str(&format!("complete -c email_checker {} -a '{}'\n", seen, command.words.join(" ")));
+        }
+        for flag in command.flags {
+            script.push_str(&format!("complete -c email_checker {} {}\n", seen, fish_flag(flag)));
+        }
+    }
+    script
+}
+
+fn powershell() -> String {
+    let quote = |items: Vec<&str>| items.iter().map(|item| format!("'{}'", item)).collect::<Vec<_>>().join(", ");
+    let value_flags = flag_names(|flag| flag.value.is_some());
+    let global = names(GLOBAL_FLAGS);
+    let mut script = String::from("# PowerShell completion for email_checker\n");
+    script.push_str("Register-ArgumentCompleter -Native -CommandName email_checker -ScriptBlock {\n");
+    script.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    script.push_str("    $words = @($commandAst.CommandElements | Select-Object -Skip 1 | ForEach-Object { \"$_\" })\n");
+    script.push_str("    if ($wordToComplete) { $words = @($words | Select-Object -SkipLast 1) }\n");
+    script.push_str(&format!("    $valueFlags = @({})\n", quote(value_flags)));
+    script.push_str("    $command = ''\n    for ($i = 0; $i -lt $words.Count; $i++) {\n");
+    script.push_str("        if ($valueFlags -contains $words[$i]) { $i++ }\n");
+    script.push_str("        elseif (-not $words[$i].StartsWith('-')) { $command = $words[$i]; break }\n    }\n");
+    script.push_str("    $candidates = switch ($command) {\n");
+    let mut top: Vec<&str> = COMMANDS.iter().map(|c| c.name).collect();
+    top.extend(&global);
+    script.push_str(&format!("        '' {{ @({}) }}\n", quote(top)));
+    for command in COMMANDS {
+        let mut candidates: Vec<&str> = command.words.to_vec();
+        candidates.extend(names(command.flags));
+        candidates.extend(&global);
+        script.push_str(&format!("        '{}' {{ @({}) }}\n", command.name, quote(candidates)));
+    }
+    script.push_str("    }\n    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n");
+    script.push_str("        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n    }\n}\n");
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions_cover_commands() {
+        assert!(takes_value("--sender") && takes_value("--config") && !takes_value("--dry-run"));
+        for shell in SHELLS {
+            let script = completions(shell).unwrap();
+            for command in COMMANDS {
+                assert!(script.contains(command.name), "{} lacks {}", shell, command.name);
+                for word in command.words.iter().chain(command.flags.iter().map(|f| &f.name)) {
+                    assert!(script.contains(word.trim_start_matches("--")), "{} lacks {} {}", shell, command.name, word);
+                }
+            }
+        }
+        let bash = completions("bash").unwrap();
+        assert!(bash.contains("        state) COMPREPLY=($(compgen -W \"export import --output --config"));
+        assert!(completions("fish").unwrap().contains("complete -c email_checker -n '__fish_seen_subcommand_from purge' -l sender -x"));
+        assert!(completions("tcsh").is_err());
+    }
+}
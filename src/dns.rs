@@ -0,0 +1,94 @@
+//! Host name resolution for the IMAP connection.
+//!
+//! Lookups are cached for `dns.ttl` seconds, and when the resolver fails
+//! the last known address is reused, so a flaky resolver does not fail
+//! every cycle. The system resolver does not report record TTLs; set
+//! `ttl` to match the record if it changes often. `dns.hosts` pins
+//! names to fixed addresses and skips resolution entirely.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::log;
+
+pub const DEFAULT_DNS_TTL: u64 = 300;
+
+/// `dns` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    /// Static host → IP overrides.
+    pub hosts: BTreeMap<String, IpAddr>,
+    /// Seconds a lookup is reused; 0 disables the cache.
+    pub ttl: u64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            hosts: BTreeMap::new(),
+            ttl: DEFAULT_DNS_TTL,
+        }
+    }
+}
+
+static CACHE: Mutex<BTreeMap<String, (IpAddr, Instant)>> = Mutex::new(BTreeMap::new());
+
+/// Resolve `host:port`, honouring overrides and the cache.
+pub fn resolve(config: &DnsConfig, host: &str, port: usize) -> io::Result<SocketAddr> {
+    let port = port as u16;
+    if let Some(ip) = config.hosts.get(host) {
+        return Ok(SocketAddr::new(*ip, port));
+    }
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let cached = cache.get(host).copied();
+    if let Some((ip, at)) = cached {
+        if at.elapsed() < Duration::from_secs(config.ttl) {
+            return Ok(SocketAddr::new(ip, port));
+        }
+    }
+    let looked_up = (host, port)
+        .to_socket_addrs()
+        .and_then(|mut addrs| addrs.next().ok_or_else(|| io::Error::other("no addresses")));
+    match (looked_up, cached) {
+        (Ok(addr), _) => {
+            cache.insert(host.to_string(), (addr.ip(), Instant::now()));
+            Ok(addr)
+        }
+        (Err(e), Some((ip, _))) => {
+            log::warn(&format!("Resolving {} failed ({}); using cached {}", host, e, ip));
+            Ok(SocketAddr::new(ip, port))
+        }
+        (Err(e), None) => Err(io::Error::new(e.kind(), format!("failed to resolve {}: {}", host, e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_and_cache() {
+        let mut config = DnsConfig::default();
+        config.hosts.insert("mail.example.com".to_string(), "10.0.0.5".parse().unwrap());
+        assert_eq!(resolve(&config, "mail.example.com", 993).unwrap().to_string(), "10.0.0.5:993");
+        assert_eq!(resolve(&config, "::1", 143).unwrap().to_string(), "[::1]:143");
+
+        // A stale entry is used when the lookup fails.
+        let host = "email-checker-test.invalid";
+        assert!(resolve(&config, host, 993).is_err());
+        CACHE.lock().unwrap().insert(host.to_string(), ("10.0.0.6".parse().unwrap(), Instant::now()));
+        assert_eq!(resolve(&config, host, 993).unwrap().to_string(), "10.0.0.6:993");
+        config.ttl = 0;
+        assert_eq!(resolve(&config, host, 993).unwrap().to_string(), "10.0.0.6:993");
+    }
+}
@@ -0,0 +1,428 @@
+//! Parsed email and the payload forwarded to OpenClaw.
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::bounce::{self, Bounce};
+use crate::calendar::{self, CalendarEvent};
+use crate::classify::Classification;
+use crate::contacts::Contact;
+use crate::crypto::{Encryption, Signature};
+use crate::diff::Diff;
+use crate::mdn::{self, Receipt};
+use crate::mime::Part;
+use crate::redact;
+use crate::scan::Verdict;
+use crate::secrets::SecretString;
+
+/// OpenClaw channel the AI agent reads from.
+pub const OPENCLAW_CHANNEL: &str = "openclaw";
+
+pub const DEFAULT_MAX_BODY_CHARS: usize = 500;
+pub const DEFAULT_MAX_ATTACHMENTS: usize = 20;
+pub const DEFAULT_MAX_HEADERS: usize = 50;
+
+/// Headers included in the built-in payload unless configured otherwise.
+pub const DEFAULT_HEADERS: &[&str] = &["From", "To", "Subject", "Date", "Message-ID"];
+/// Headers never included, even when `headers` allows them.
+pub const DEFAULT_STRIP_HEADERS: &[&str] = &["Received", "X-*"];
+/// Headers reduced to bare addresses in minimized payloads.
+const ADDRESS_HEADERS: &[&str] = &["From", "To", "Cc", "Bcc", "Reply-To", "Sender", "Return-Path", "Delivered-To"];
+
+/// `payload` section of the config file: which headers the built-in
+/// payload carries, and limits so requests stay under the gateway's
+/// body-size limit. A payload cut short by any limit carries
+/// `"truncated": true`. Rule templates are rendered as written.
+///
+/// With `minimize`, the payload has no body, display names or attachment
+/// names, and every address in it is replaced by a keyed pseudonym (see
+/// [`redact::pseudonym`]); it carries `"minimized": true`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PayloadConfig {
+    /// Header names to include, case-insensitively; a trailing `*`
+    /// matches any suffix, so `["*"]` includes everything.
+    pub headers: Vec<String>,
+    /// Header names to leave out even if `headers` matches them.
+    pub strip_headers: Vec<String>,
+    /// Characters of the body in the message preview.
+    pub max_body_chars: usize,
+    pub max_attachments: usize,
+    pub max_headers: usize,
+    pub minimize: bool,
+    /// HMAC key for pseudonyms; required with `minimize`, so they cannot
+    /// be reversed by hashing a list of known addresses.
+    pub pseudonym_key: SecretString,
+}
+
+impl Default for PayloadConfig {
+    fn default() -> Self {
+        Self {
+            headers: DEFAULT_HEADERS.iter().map(|h| h.to_string()).collect(),
+            strip_headers: DEFAULT_STRIP_HEADERS.iter().map(|h| h.to_string()).collect(),
+            max_body_chars: DEFAULT_MAX_BODY_CHARS,
+            max_attachments: DEFAULT_MAX_ATTACHMENTS,
+            max_headers: DEFAULT_MAX_HEADERS,
+            minimize: false,
+            pseudonym_key: SecretString::default(),
+        }
+    }
+}
+
+impl PayloadConfig {
+    /// Whether header `name` belongs in the payload.
+    pub fn includes_header(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix)),
+            None => name.eq_ignore_ascii_case(pattern),
+        };
+        self.headers.iter().any(matches) && !self.strip_headers.iter().any(matches)
+    }
+}
+
+/// An attachment as listed in the payload; its content is not forwarded.
+#[derive(Debug, Clone, Serialize)]
+pub struct Attachment {
+    pub filename: Option<String>,
+    pub content_type: String,
+    /// Decoded size in bytes.
+    pub size: usize,
+}
+
+#[derive(Debug)]
+pub struct EmailData {
+    pub subject: String,
+    pub from: String,
+    pub date: String,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+    pub attachments: Vec<Attachment>,
+    pub calendar_event: Option<CalendarEvent>,
+    pub bounce: Option<Bounce>,
+    /// Set for read receipts; see [`crate::mdn`].
+    pub receipt: Option<Receipt>,
+    /// Set when the message arrived encrypted; see [`crate::crypto`].
+    pub encryption: Option<Encryption>,
+    pub signature: Option<Signature>,
+    /// The server's INTERNALDATE, when fetched over IMAP.
+    pub received: Option<DateTime<FixedOffset>>,
+    /// Set when scanners ran; see [`crate::scan`].
+    pub scan: Option<Verdict>,
+    /// The sender, when the `enrich` stage knows them.
+    pub contact: Option<Contact>,
+    /// Set by the `classify` stage.
+    pub classification: Option<Classification>,
+    /// Set when it changes an earlier message; see [`crate::diff`].
+    pub diff: Option<Diff>,
+    /// Made up when the message is fetched, and carried by its log lines,
+    /// spans, payload and audit entry.
+    pub correlation_id: Option<String>,
+}
+
+impl EmailData {
+    /// Parse a raw RFC 822 message, with everything [`Self::extract`] finds.
+    pub fn from_raw(raw: &[u8]) -> Self {
+        let message = Part::parse(raw);
+        let mut email = Self::from_part(&message);
+        email.extract(&message);
+        email
+    }
+
+    /// The headers and text of a parsed message.
+    pub fn from_part(message: &Part) -> Self {
+        let body = message
+            .find("text/plain")
+            .map(Part::text)
+            .unwrap_or_default();
+        Self {
+            subject: message
+                .decoded_header("Subject")
+                .unwrap_or_else(|| "(No Subject)".to_string()),
+            from: message
+                .decoded_header("From")
+                .unwrap_or_else(|| "(Unknown)".to_string()),
+            date: message
+                .header("Date")
+                .map(str::to_string)
+                .unwrap_or_else(|| chrono::Local::now().to_rfc3339()),
+            body,
+            attachments: Vec::new(),
+            calendar_event: None,
+            bounce: None,
+            receipt: None,
+            encryption: None,
+            signature: None,
+            received: None,
+            scan: None,
+            contact: None,
+            classification: None,
+            diff: None,
+            correlation_id: None,
+            headers: message.headers.clone(),
+        }
+    }
+
+    /// List the attachments and pick out a calendar invitation, bounce or
+    /// read receipt.
+    pub fn extract(&mut self, message: &Part) {
+        self.attachments = message
+            .walk()
+            .into_iter()
+            .filter(|p| p.is_attachment())
+            .map(|p| Attachment {
+                filename: p.filename(),
+                content_type: p.content_type().mime_type,
+                size: p.decoded_body().len(),
+            })
+            .collect();
+        self.calendar_event = calendar::from_message(message);
+        self.bounce = bounce::from_message(message);
+        self.receipt = mdn::from_message(message);
+    }
+
+    /// When the message arrived: INTERNALDATE, else the `Date` header.
+    pub fn arrived(&self) -> Option<DateTime<FixedOffset>> {
+        self.received
+            .or_else(|| self.header("Date").and_then(|d| DateTime::parse_from_rfc2822(d.trim()).ok()))
+    }
+
+    /// First top-level header with the given name, case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn to_openclaw_message(&self) -> String {
+        self.format_message(&self.from, Some(DEFAULT_MAX_BODY_CHARS))
+    }
+
+    /// The message text, with up to `preview` characters of the body.
+    fn format_message(&self, from: &str, preview: Option<usize>) -> String {
+        let mut message = format!("📧 New Email\n\nFrom: {}\nSubject: {}\nDate: {}", from, self.subject, self.date);
+        if let Some(max_chars) = preview {
+            message.push_str("\n\nPreview:\n");
+            message.extend(self.body.chars().take(max_chars));
+        }
+        message
+    }
+
+    /// Event type reported to OpenClaw: `bounce` for delivery status
+    /// notifications, `receipt` for read receipts, `message` for
+    /// everything else.
+    pub fn event_type(&self) -> &'static str {
+        if self.bounce.is_some() {
+            "bounce"
+        } else if self.receipt.is_some() {
+            "receipt"
+        } else {
+            "message"
+        }
+    }
+
+    /// JSON body for the gateway's `/api/message` endpoint.
+    pub fn to_payload(&self, limits: &PayloadConfig) -> Value {
+        // Minimized payloads keep addresses but no names beside them.
+        let minimize = limits.minimize;
+        let address_only = |value: &str| redact::addresses(value).join(", ");
+        let included: Vec<_> = self.headers.iter().filter(|(name, _)| limits.includes_header(name)).collect();
+        let mut headers = Map::new();
+        for (name, value) in included.iter().take(limits.max_headers) {
+            let is_address = ADDRESS_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name));
+            let value = if minimize && is_address { address_only(value) } else { value.clone() };
+            headers.entry(name.clone()).or_insert_with(|| json!(value));
+        }
+        let message = match minimize {
+            true => self.format_message(&address_only(&self.from), None),
+            false => self.format_message(&self.from, Some(limits.max_body_chars)),
+        };
+        let mut payload = json!({
+            "channel": OPENCLAW_CHANNEL,
+            "event_type": self.event_type(),
+            "message": message,
+            "headers": headers,
+        });
+        if let Some(id) = &self.correlation_id {
+            payload["correlation_id"] = json!(id);
+        }
+        if !self.attachments.is_empty() {
+            let mut attachments = self.attachments[..self.attachments.len().min(limits.max_attachments)].to_vec();
+            if minimize {
+                attachments.iter_mut().for_each(|a| a.filename = None);
+            }
+            payload["attachments"] = json!(attachments);
+        }
+        if (!minimize && self.body.chars().count() > limits.max_body_chars)
+            || self.attachments.len() > limits.max_attachments
+            || included.len() > limits.max_headers
+        {
+            payload["truncated"] = json!(true);
+        }
+        if let Some(event) = &self.calendar_event {
+            payload["calendar_event"] = json!(event);
+        }
+        if let Some(bounce) = &self.bounce {
+            payload["bounce"] = json!(bounce);
+        }
+        if let Some(receipt) = &self.receipt {
+            payload["receipt"] = json!(receipt);
+        }
+        if let Some(encryption) = &self.encryption {
+            payload["encryption"] = json!(encryption);
+        }
+        if let Some(signature) = &self.signature {
+            payload["signature"] = json!(signature);
+        }
+        if let Some(scan) = &self.scan {
+            payload["scan"] = json!(scan);
+        }
+        if let Some(contact) = &self.contact {
+            // A name would undo the pseudonym.
+            let name = contact.name.as_ref().filter(|_| !minimize).cloned();
+            payload["contact"] = json!(Contact { name, ..contact.clone() });
+        }
+        if let Some(classification) = &self.classification {
+            payload["classification"] = json!(classification);
+        }
+        // A diff is made of the body, which minimized payloads leave out.
+        if let Some(diff) = self.diff.as_ref().filter(|_| !minimize) {
+            payload["diff"] = json!(diff);
+        }
+        if minimize {
+            payload["minimized"] = json!(true);
+            return redact::pseudonymize_value(&payload, limits.pseudonym_key.expose());
+        }
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_format() {
+        let email = EmailData {
+            subject: "Test".to_string(),
+            from: "test@example.com".to_string(),
+            date: "2024-01-01".to_string(),
+            body: "Hello".to_string(),
+            headers: Vec::new(),
+            attachments: Vec::new(),
+            calendar_event: None,
+            bounce: None,
+            receipt: None,
+            encryption: None,
+            signature: None,
+            received: None,
+            scan: None,
+            contact: None,
+            classification: None,
+            diff: None,
+            correlation_id: None,
+        };
+        let message = email.to_openclaw_message();
+        assert!(message.contains("Test"));
+        assert!(message.contains("test@example.com"));
+        let payload = email.to_payload(&PayloadConfig::default());
+        assert_eq!(payload["event_type"], "message");
+        assert!(payload.get("calendar_event").is_none());
+        assert!(payload.get("truncated").is_none());
+    }
+
+    #[test]
+    fn test_payload_truncation() {
+        let raw = "Subject: Fotos\r\nFrom: a@example.com\r\nContent-Type: multipart/mixed; boundary=b\r\n\r\n\
+--b\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nGrüße aus Köln\r\n\
+--b\r\nContent-Type: image/jpeg; name=one.jpg\r\n\r\nxxxx\r\n\
+--b\r\nContent-Type: image/jpeg; name=two.jpg\r\n\r\nyy\r\n--b--\r\n";
+        let email = EmailData::from_raw(raw.as_bytes());
+        let payload = email.to_payload(&PayloadConfig::default());
+        assert_eq!(payload["attachments"][1]["filename"], "two.jpg");
+        assert_eq!(payload["headers"]["Subject"], "Fotos");
+        assert!(payload.get("truncated").is_none());
+
+        let limits = PayloadConfig {
+            max_body_chars: 4,
+            max_attachments: 1,
+            max_headers: 1,
+            ..PayloadConfig::default()
+        };
+        let payload = email.to_payload(&limits);
+        assert!(payload["message"].as_str().unwrap().ends_with("Preview:\nGrüß"));
+        assert_eq!(payload["attachments"].as_array().unwrap().len(), 1);
+        assert_eq!(payload["headers"].as_object().unwrap().len(), 1);
+        assert_eq!(payload["truncated"], true);
+    }
+
+    #[test]
+    fn test_minimized_payload() {
+        let raw = b"From: Alice Smith <Alice@example.com>\r\nTo: bob@example.org, Carol <carol@example.net>\r\n\
+Subject: Call alice@example.com back\r\nContent-Type: multipart/mixed; boundary=b\r\n\r\n\
+--b\r\nContent-Type: text/plain\r\n\r\nMy number is 555-0100\r\n\
+--b\r\nContent-Type: application/pdf; name=\"Alice Smith CV.pdf\"\r\n\r\npdf\r\n--b--\r\n";
+        let limits = PayloadConfig {
+            minimize: true,
+            pseudonym_key: "k".into(),
+            ..PayloadConfig::default()
+        };
+        let payload = EmailData::from_raw(raw).to_payload(&limits);
+        let text = payload.to_string();
+        for secret in ["Alice", "alice", "bob@", "Carol", "555-0100", "CV.pdf"] {
+            assert!(!text.contains(secret), "{} leaked: {}", secret, text);
+        }
+        let alice = redact::pseudonym("alice@example.com", "k");
+        assert_eq!(payload["headers"]["From"], alice);
+        assert!(payload["message"].as_str().unwrap().contains(&format!("Subject: Call {} back", alice)));
+        assert_eq!(payload["headers"]["To"].as_str().unwrap().matches("@pseudonym.invalid").count(), 2);
+        assert_eq!(payload["attachments"][0]["content_type"], "application/pdf");
+        assert_eq!(payload["minimized"], true);
+        assert!(payload.get("truncated").is_none());
+    }
+
+    #[test]
+    fn test_header_allowlist() {
+        let raw = b"Received: from mx\r\nX-Mailer: Outlook\r\nX-GitHub-Reason: ci_activity\r\nSubject: Hi\r\n\
+From: a@example.com\r\nList-Id: <dev.example.com>\r\n\r\nbody";
+        let email = EmailData::from_raw(raw);
+        let headers = |config: &PayloadConfig| {
+            let payload = email.to_payload(config);
+            payload["headers"].as_object().unwrap().keys().cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(headers(&PayloadConfig::default()), ["From", "Subject"]);
+        let config = PayloadConfig {
+            headers: vec!["*".to_string()],
+            strip_headers: vec!["received".to_string(), "X-Mailer".to_string()],
+            ..PayloadConfig::default()
+        };
+        assert_eq!(headers(&config), ["From", "List-Id", "Subject", "X-GitHub-Reason"]);
+    }
+
+    #[test]
+    fn test_calendar_payload() {
+        let raw = b"From: Bob <bob@example.com>\r\nSubject: Invitation: Sync\r\n\
+Content-Type: text/calendar; charset=utf-8; method=REQUEST\r\n\r\n\
+BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Sync\r\nDTSTART:20240102T090000Z\r\n\
+END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let email = EmailData::from_raw(raw);
+        let payload = email.to_payload(&PayloadConfig::default());
+        assert_eq!(payload["channel"], "openclaw");
+        assert_eq!(payload["calendar_event"]["summary"], "Sync");
+        assert_eq!(payload["calendar_event"]["method"], "REQUEST");
+        assert_eq!(payload["calendar_event"]["start"], "2024-01-02T09:00:00Z");
+    }
+
+    #[test]
+    fn test_bounce_payload() {
+        let raw = b"Subject: Undeliverable\r\n\
+Content-Type: multipart/report; report-type=delivery-status; boundary=b\r\n\r\n\
+--b\r\nContent-Type: message/delivery-status\r\n\r\nReporting-MTA: dns; mx\r\n\r\n\
+Final-Recipient: rfc822; gone@example.org\r\nAction: failed\r\nStatus: 5.2.2\r\n\r\n--b--\r\n";
+        let payload = EmailData::from_raw(raw).to_payload(&PayloadConfig::default());
+        assert_eq!(payload["event_type"], "bounce");
+        assert_eq!(payload["bounce"]["recipients"][0]["recipient"], "gone@example.org");
+        assert_eq!(payload["bounce"]["recipients"][0]["status"], "5.2.2");
+    }
+}
@@ -0,0 +1,228 @@
+//! Mail OpenClaw is waiting for.
+//!
+//! An expectation says that mail matching some conditions, the same as
+//! a routing rule's `match`, should arrive by a deadline: a sign-up
+//! confirmation, a reply to a ticket, a report a job was started for.
+//! `expect add` registers one, as does any program writing
+//! `<id>.json` with `match` and `deadline` into `expectations.dir`;
+//! files are written under a temporary name and renamed, so a checker
+//! never reads half of one.
+//!
+//! Each cycle, fetched mail confirms the first open expectation it
+//! matches, and an expectation whose deadline passed unmatched times out.
+//! Either outcome is sent to `expectations.sink` as one `expectation`
+//! event, after which the file is removed. The outcome is written to the
+//! file first, so a report that fails is sent again next cycle.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::digest::hex;
+use crate::email::{EmailData, OPENCLAW_CHANNEL};
+use crate::rules::Conditions;
+use crate::sink::DEFAULT_SINK;
+use crate::trace;
+
+/// `expectations` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExpectationsConfig {
+    /// Directory holding one file per open expectation; off when unset.
+    pub dir: Option<String>,
+    /// Sink receiving the outcomes.
+    pub sink: String,
+}
+
+impl Default for ExpectationsConfig {
+    fn default() -> Self {
+        Self { dir: None, sink: DEFAULT_SINK.to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Expectation {
+    /// The file name without `.json`.
+    #[serde(skip)]
+    pub id: String,
+    #[serde(default, rename = "match")]
+    pub conditions: Conditions,
+    /// When it was registered (Unix seconds).
+    #[serde(default)]
+    pub created: i64,
+    /// When the mail must have arrived by (Unix seconds).
+    pub deadline: i64,
+    /// Decided, but not yet reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Outcome {
+    pub status: Status,
+    /// When it was decided (Unix seconds).
+    pub at: i64,
+    /// The matching mail's sender, subject and `Message-ID`, if confirmed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Confirmed,
+    TimedOut,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Confirmed => "confirmed",
+            Status::TimedOut => "timed_out",
+        }
+    }
+}
+
+impl Expectation {
+    /// An expectation with a new random id.
+    pub fn new(conditions: Conditions, created: i64, deadline: i64) -> Self {
+        Self { id: hex(&trace::random_id::<8>()), conditions, created, deadline, outcome: None }
+    }
+
+    /// Confirm it with `email` at `now`, if it is open and matches.
+    /// Returns whether it did.
+    pub fn confirm(&mut self, email: &EmailData, now: i64) -> bool {
+        if self.outcome.is_some() || !self.conditions.matches(email) {
+            return false;
+        }
+        self.outcome = Some(Outcome {
+            status: Status::Confirmed,
+            at: now,
+            from: Some(email.from.clone()),
+            subject: Some(email.subject.clone()),
+            message_id: email.header("Message-ID").map(str::to_string),
+        });
+        true
+    }
+
+    /// Time it out if it is open and its deadline has passed at `now`.
+    /// Returns whether it did.
+    pub fn expire(&mut self, now: i64) -> bool {
+        if self.outcome.is_some() || now < self.deadline {
+            return false;
+        }
+        let outcome = Outcome { status: Status::TimedOut, at: now, from: None, subject: None, message_id: None };
+        self.outcome = Some(outcome);
+        true
+    }
+
+    /// The `expectation` event reporting its outcome, if decided.
+    pub fn event(&self) -> Option<Value> {
+        let outcome = self.outcome.as_ref()?;
+        let time = |at: i64| chrono::DateTime::from_timestamp(at, 0).map(|at| at.to_rfc3339());
+        let deadline = time(self.deadline);
+        let message = match outcome.status {
+            Status::Confirmed => format!("✅ Expected mail arrived: {}", outcome.subject.as_deref().unwrap_or("")),
+            Status::TimedOut => format!("⌛ Expected mail did not arrive by {}", deadline.as_deref().unwrap_or("")),
+        };
+        Some(json!({
+            "channel": OPENCLAW_CHANNEL,
+            "event_type": "expectation",
+            "message": message,
+            "expectation": {
+                "id": self.id,
+                "status": outcome.status.name(),
+                "match": self.conditions,
+                "created": time(self.created),
+                "deadline": deadline,
+                "decided": time(outcome.at),
+                "from": outcome.from,
+                "subject": outcome.subject,
+                "message_id": outcome.message_id,
+            },
+        }))
+    }
+}
+
+/// Write `expectation` to `dir`, replacing its file if there is one.
+pub fn save(dir: &str, expectation: &Expectation) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!("{}.json", expectation.id));
+    let tmp = Path::new(dir).join(format!(".{}.json.tmp", expectation.id));
+    fs::write(&tmp, serde_json::to_string_pretty(expectation)?)?;
+    fs::rename(tmp, path)
+}
+
+pub fn remove(dir: &str, id: &str) -> io::Result<()> {
+    fs::remove_file(Path::new(dir).join(format!("{}.json", id)))
+}
+
+/// The expectations in `dir`, oldest first, and the files that could not
+/// be read with why. A missing directory holds none.
+pub fn load(dir: &str) -> io::Result<(Vec<Expectation>, Vec<String>)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), Vec::new())),
+        Err(e) => return Err(e),
+    };
+    let (mut expectations, mut errors) = (Vec::new(), Vec::new());
+    for entry in entries {
+        let path = entry?.path();
+        let Some(id) = path.file_name().and_then(|name| name.to_str()?.strip_suffix(".json")) else {
+            continue;
+        };
+        if id.starts_with('.') {
+            continue;
+        }
+        let read = fs::read_to_string(&path).map_err(|e| e.to_string());
+        match read.and_then(|text| serde_json::from_str::<Expectation>(&text).map_err(|e| e.to_string())) {
+            Ok(expectation) => expectations.push(Expectation { id: id.to_string(), ..expectation }),
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    expectations.sort_by(|a, b| (a.created, &a.id).cmp(&(b.created, &b.id)));
+    Ok((expectations, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirm_and_expire() {
+        let dir = std::env::temp_dir().join(format!("email_checker_expect_{}", std::process::id()));
+        let dir = dir.to_string_lossy().into_owned();
+        let conditions = Conditions { subject: Some("confirm your account".to_string()), ..Conditions::default() };
+        let mut confirmed = Expectation::new(conditions.clone(), 100, 1000);
+        let mut timed_out = Expectation::new(conditions, 200, 500);
+        save(&dir, &confirmed).unwrap();
+        save(&dir, &timed_out).unwrap();
+        fs::write(Path::new(&dir).join("broken.json"), "{").unwrap();
+
+        let (loaded, errors) = load(&dir).unwrap();
+        assert_eq!(loaded.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), [&confirmed.id, &timed_out.id]);
+        assert_eq!(errors.len(), 1);
+
+        let email = EmailData::from_raw(b"From: shop@example.com\r\nSubject: Please confirm your account\r\n\r\n");
+        assert!(!timed_out.expire(499));
+        assert!(confirmed.confirm(&email, 400));
+        assert!(!confirmed.confirm(&email, 401), "an expectation is confirmed once");
+        assert!(timed_out.expire(500));
+        let event = confirmed.event().unwrap();
+        assert_eq!(event["event_type"], "expectation");
+        assert_eq!(event["expectation"]["status"], "confirmed");
+        assert_eq!(event["expectation"]["from"], "shop@example.com");
+        assert_eq!(timed_out.event().unwrap()["expectation"]["status"], "timed_out");
+
+        remove(&dir, &confirmed.id).unwrap();
+        assert_eq!(load(&dir).unwrap().0.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
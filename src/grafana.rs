@@ -0,0 +1,119 @@
+//! A Grafana dashboard for the exported metrics, printed by
+//! `generate grafana-dashboard`.
+//!
+//! The panels are generated from the metric tables in [`crate::metrics`],
+//! so the dashboard always queries the names and labels this binary
+//! writes. Counters are shown as rates per minute, labelled ones split by
+//! their labels, gauges as they are, and an `account` variable filters the per-account rows.
+
+use serde_json::{json, Value};
+
+use crate::metrics::{HELP, LABELLED_GAUGE_HELP, LABELLED_HELP, LABELLED_SUMMARY_HELP, QUANTILES, SUMMARY_HELP};
+
+const WIDTH: u64 = 12;
+const HEIGHT: u64 = 8;
+
+/// The dashboard JSON, ready for Grafana's import dialog.
+pub fn dashboard() -> Value {
+    let mut panels = Vec::new();
+    let mut row = |title: &str, targets: Vec<(String, String)>, unit: &str, description: &str| {
+        let index = panels.len() as u64;
+        let targets: Vec<Value> = targets
+            .into_iter()
+            .enumerate()
+            .map(|(i, (expr, legend))| {
+                json!({"refId": ((b'A' + i as u8) as char).to_string(), "expr": expr, "legendFormat": legend})
+            })
+            .collect();
+        panels.push(json!({
+            "id": index + 1,
+            "type": "timeseries",
+            "title": title,
+            "description": description,
+            "datasource": {"type": "prometheus", "uid": "${datasource}"},
+            "gridPos": {"x": (index % 2) * WIDTH, "y": (index / 2) * HEIGHT, "w": WIDTH, "h": HEIGHT},
+            "fieldConfig": {"defaults": {"unit": unit}, "overrides": []},
+            "targets": targets,
+        }));
+    };
+    for (name, help) in HELP {
+        let unit = if name.contains("_bytes_") { "Bps" } else { "cpm" };
+        let rate = if unit == "Bps" { format!("rate({}[5m])", name) } else { format!("rate({}[5m]) * 60", name) };
+        row(&title(name), vec![(rate, "all".to_string())], unit, help);
+    }
+    for (name, labels, help) in LABELLED_HELP {
+        let selector = if labels.contains(&"account") { "{account=~\"$account\"}" } else { "" };
+        let by = labels.join(", ");
+        let legend = labels.iter().map(|label| format!("{{{{{}}}}}", label)).collect::<Vec<_>>().join(" ");
+        let expr = format!("sum by ({}) (rate({}{}[5m])) * 60", by, name, selector);
+        row(&title(name), vec![(expr, legend)], "cpm", help);
+    }
+    for (name, help) in SUMMARY_HELP {
+        let targets = QUANTILES.iter().map(|q| (format!("{}{{quantile=\"{}\"}}", name, q), format!("p{}", q * 100.0))).collect();
+        row(&title(name), targets, "s", help);
+    }
+    for (name, labels, help) in LABELLED_SUMMARY_HELP {
+        let legend = labels.iter().map(|label| format!("{{{{{}}}}}", label)).collect::<Vec<_>>().join(" ");
+        let targets = QUANTILES
+            .iter()
+            .map(|q| (format!("{}{{account=~\"$account\",quantile=\"{}\"}}", name, q), format!("{} p{}", legend, q * 100.0)))
+            .collect();
+        row(&title(name), targets, "s", help);
+    }
+    for (name, labels, help) in LABELLED_GAUGE_HELP {
+        let legend = labels.iter().map(|label| format!("{{{{{}}}}}", label)).collect::<Vec<_>>().join(" ");
+        let unit = if name.ends_with("_bytes") { "bytes" } else { "short" };
+        row(&title(name), vec![(format!("{}{{account=~\"$account\"}}", name), legend)], unit, help);
+    }
+    json!({
+        "title": "Email Checker",
+        "uid": "email-checker",
+        "tags": ["email_checker"],
+        "schemaVersion": 39,
+        "time": {"from": "now-24h", "to": "now"},
+        "refresh": "1m",
+        "templating": {"list": [
+            {"name": "datasource", "type": "datasource", "query": "prometheus", "label": "Data source"},
+            {
+                "name": "account",
+                "type": "query",
+                "label": "Account",
+                "datasource": {"type": "prometheus", "uid": "${datasource}"},
+                "query": {"query": "label_values(email_checker_account_cycles_total, account)", "refId": "accounts"},
+                "definition": "label_values(email_checker_account_cycles_total, account)",
+                "includeAll": true,
+                "multi": true,
+                "allValue": ".*",
+                "current": {"text": "All", "value": "$__all"},
+                "refresh": 2,
+            },
+        ]},
+        "panels": panels,
+    })
+}
+
+/// `email_checker_delivery_failures_total` as "Delivery failures".
+fn title(name: &str) -> String {
+    let words = name.trim_start_matches("email_checker_").trim_end_matches("_total").replace('_', " ");
+    let mut chars = words.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics;
+
+    #[test]
+    fn test_panels_cover_metrics() {
+        let dashboard = dashboard();
+        let panels = dashboard["panels"].as_array().unwrap();
+        let labelled = LABELLED_HELP.len() + LABELLED_SUMMARY_HELP.len() + LABELLED_GAUGE_HELP.len();
+        assert_eq!(panels.len(), HELP.len() + SUMMARY_HELP.len() + labelled);
+        let exprs: Vec<&str> = panels.iter().flat_map(|p| p["targets"].as_array().unwrap()).map(|t| t["expr"].as_str().unwrap()).collect();
+        assert!(exprs.contains(&"sum by (account, rule) (rate(email_checker_account_messages_delivered_total{account=~\"$account\"}[5m])) * 60"));
+        assert!(exprs.contains(&"rate(email_checker_cycles_total[5m]) * 60"));
+        assert!(exprs.contains(&"email_checker_delivery_latency_seconds{quantile=\"0.95\"}"));
+        assert_eq!(title(metrics::DELIVERY_FAILURES), "Delivery failures");
+    }
+}
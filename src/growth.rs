@@ -0,0 +1,118 @@
+//! Alerts on mail arriving much faster or slower than usual.
+//!
+//! With `growth.enabled`, every cycle notes how far each folder's
+//! `UIDNEXT` moved, which counts arrivals even when mail is deleted or
+//! moved away in between, and keeps them per hour for `baseline_days`.
+//! Against the mean of those hours, a folder is flooded when the current
+//! hour has `flood_factor` times as many (and at least `min_flood`), and
+//! silent when a folder that usually gets `busy_per_hour` or more has had
+//! nothing for `silence_hours`: both tend to mean something upstream
+//! broke, a looping script or a monitoring system that stopped mailing.
+//! A folder is only judged after a day of history.
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::Growth;
+
+const HOUR: i64 = 3600;
+/// History needed before a folder is judged.
+const MIN_HISTORY: i64 = 24 * HOUR;
+
+/// `growth` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GrowthConfig {
+    pub enabled: bool,
+    /// Days of hourly arrivals the baseline is taken over.
+    pub baseline_days: u64,
+    pub flood_factor: f64,
+    /// Arrivals in an hour below which there is no flood, however quiet
+    /// the folder usually is.
+    pub min_flood: u64,
+    pub silence_hours: u64,
+    /// Mean arrivals per hour from which a folder counts as busy, and its
+    /// silence as unusual.
+    pub busy_per_hour: f64,
+}
+
+impl Default for GrowthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            baseline_days: 7,
+            flood_factor: 5.0,
+            min_flood: 20,
+            silence_hours: 6,
+            busy_per_hour: 1.0,
+        }
+    }
+}
+
+/// Note that the folder's next UID was `uid_next` at `now`. A changed
+/// `UIDVALIDITY` restarts the count without counting arrivals.
+pub fn record(growth: &mut Growth, config: &GrowthConfig, uid_validity: u32, uid_next: u32, now: i64) {
+    if growth.since == 0 {
+        growth.since = now;
+    }
+    if growth.uid_validity == uid_validity && uid_next > growth.uid_next && growth.uid_next > 0 {
+        *growth.hours.entry(now - now.rem_euclid(HOUR)).or_insert(0) += u64::from(uid_next - growth.uid_next);
+        growth.last_arrival = Some(now);
+    }
+    growth.uid_validity = uid_validity;
+    growth.uid_next = uid_next;
+    let cutoff = now - config.baseline_days as i64 * 24 * HOUR;
+    growth.hours.retain(|&hour, _| hour >= cutoff);
+}
+
+/// What is unusual about the folder's arrivals at `now`, if anything.
+pub fn anomaly(growth: &Growth, config: &GrowthConfig, folder: &str, now: i64) -> Option<String> {
+    if now - growth.since < MIN_HISTORY {
+        return None;
+    }
+    let hour = now - now.rem_euclid(HOUR);
+    let start = growth.since.max(hour - config.baseline_days as i64 * 24 * HOUR);
+    let hours = ((hour - start) / HOUR).max(1);
+    let before: u64 = growth.hours.range(start..hour).map(|(_, count)| count).sum();
+    let baseline = before as f64 / hours as f64;
+
+    let current = growth.hours.get(&hour).copied().unwrap_or(0);
+    if current >= config.min_flood && current as f64 >= config.flood_factor * baseline {
+        return Some(format!("{} got {} messages this hour, against {:.1} an hour usually", folder, current, baseline));
+    }
+    let quiet = (now - growth.last_arrival.unwrap_or(growth.since)) / HOUR;
+    if baseline >= config.busy_per_hour && quiet >= config.silence_hours as i64 {
+        return Some(format!("{} got no mail for {} hours, against {:.1} an hour usually", folder, quiet, baseline));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flood_and_silence() {
+        let config = GrowthConfig { enabled: true, ..GrowthConfig::default() };
+        let mut growth = Growth::default();
+        // Two messages an hour for two days.
+        let start = 1_700_000_000 - 1_700_000_000 % HOUR;
+        for hour in 0..48 {
+            record(&mut growth, &config, 7, 100 + 2 * hour as u32, start + hour * HOUR + 60);
+        }
+        let now = start + 48 * HOUR + 60;
+        record(&mut growth, &config, 7, 196, now);
+        assert_eq!(anomaly(&growth, &config, "INBOX", now), None);
+
+        record(&mut growth, &config, 7, 246, now + 60);
+        let flood = anomaly(&growth, &config, "INBOX", now + 60).unwrap();
+        assert_eq!(flood, "INBOX got 52 messages this hour, against 2.0 an hour usually");
+
+        let later = now + 7 * HOUR;
+        record(&mut growth, &config, 7, 246, later);
+        assert!(anomaly(&growth, &config, "INBOX", later).unwrap().starts_with("INBOX got no mail for 6 hours"));
+
+        // A new UIDVALIDITY is not a flood.
+        record(&mut growth, &config, 8, 5000, later + 60);
+        assert_eq!(growth.hours.get(&(later - later % HOUR)), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Active/standby operation of two or more instances.
+//!
+//! With `ha.lease_file` on storage every instance can reach (NFS, a
+//! shared volume), only the instance holding the account's lease polls
+//! it. The holder renews the lease before each cycle and before it
+//! sleeps, for the sleep plus `grace` seconds; the others check every
+//! `standby_poll` seconds and take over once it has expired, so a dead
+//! host is replaced within one interval plus `grace`. Updates to the
+//! file are serialized with a `.lock` file next to it. The state file
+//! should be shared too, so a new holder knows what was delivered.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_GRACE: u64 = 30;
+pub const DEFAULT_STANDBY_POLL: u64 = 10;
+
+/// A `.lock` file older than this was left by a crashed instance.
+const STALE_LOCK: Duration = Duration::from_secs(30);
+const LOCK_ATTEMPTS: usize = 50;
+
+/// `ha` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HaConfig {
+    /// Shared file holding the leases; every instance polls without one.
+    pub lease_file: Option<String>,
+    /// This instance's name in the lease; the host name and PID by default.
+    pub node_id: Option<String>,
+    pub grace: u64,
+    pub standby_poll: u64,
+}
+
+impl Default for HaConfig {
+    fn default() -> Self {
+        Self {
+            lease_file: None,
+            node_id: None,
+            grace: DEFAULT_GRACE,
+            standby_poll: DEFAULT_STANDBY_POLL,
+        }
+    }
+}
+
+impl HaConfig {
+    pub fn node_id(&self) -> String {
+        self.node_id.clone().unwrap_or_else(|| {
+            let host = fs::read_to_string("/etc/hostname").unwrap_or_default();
+            format!("{}:{}", host.trim(), std::process::id())
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Lease {
+    pub holder: String,
+    /// Unix time the lease runs out.
+    pub expires: i64,
+}
+
+/// Take or renew the lease on `account` for `node` until `until`, unless
+/// another node holds it past `now`. Returns the lease now in force.
+pub fn acquire(path: &str, account: &str, node: &str, until: i64, now: i64) -> Result<Lease, Box<dyn Error>> {
+    let lock = format!("{}.lock", path);
+    let _guard = LockFile::take(&lock)?;
+    let mut leases: BTreeMap<String, Lease> = match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(format!("{}: {}", path, e).into()),
+    };
+    if let Some(lease) = leases.get(account).filter(|l| l.holder != node && l.expires > now) {
+        return Ok(lease.clone());
+    }
+    let lease = Lease { holder: node.to_string(), expires: until };
+    leases.insert(account.to_string(), lease.clone());
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, serde_json::to_string_pretty(&leases)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(lease)
+}
+
+/// Exclusive `.lock` file, removed on drop.
+struct LockFile(String);
+
+impl LockFile {
+    fn take(path: &str) -> Result<Self, Box<dyn Error>> {
+        for _ in 0..LOCK_ATTEMPTS {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => return Ok(Self(path.to_string())),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| SystemTime::now().duration_since(t).ok());
+                    if age.is_some_and(|age| age > STALE_LOCK) {
+                        let _ = fs::remove_file(path);
+                    } else {
+                        thread::sleep(Duration::from_millis(20));
+                    }
+                }
+                Err(e) => return Err(format!("{}: {}", path, e).into()),
+            }
+        }
+        Err(format!("{} is held by another instance", path).into())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_failover() {
+        let path = std::env::temp_dir().join(format!("email_checker_lease_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let account = "bot@example.com";
+
+        assert_eq!(acquire(path, account, "a", 130, 100).unwrap().holder, "a");
+        // b stands by while a's lease runs, and a can renew it.
+        assert_eq!(acquire(path, account, "b", 140, 110).unwrap(), Lease { holder: "a".to_string(), expires: 130 });
+        assert_eq!(acquire(path, account, "a", 160, 120).unwrap().expires, 160);
+        // Other accounts are leased separately.
+        assert_eq!(acquire(path, "other@example.com", "b", 140, 120).unwrap().holder, "b");
+        // a stopped renewing; b takes over.
+        assert_eq!(acquire(path, account, "b", 200, 161).unwrap().holder, "b");
+        assert_eq!(acquire(path, account, "a", 210, 170).unwrap().holder, "b");
+        assert!(!std::path::Path::new(&format!("{}.lock", path)).exists());
+        fs::remove_file(path).unwrap();
+    }
+}
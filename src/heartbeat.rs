@@ -0,0 +1,73 @@
+//! Dead-man's-switch pings to an external monitor.
+//!
+//! After every cycle `heartbeat.url` is requested, or `failure_url` if
+//! the cycle failed, in the push style of Healthchecks.io
+//! (`https://hc-ping.com/<uuid>` and `.../fail`) or Uptime Kuma
+//! (`...?status=up` and `...?status=down`). The monitor raises the alarm
+//! when pings stop arriving.
+
+use serde::{Deserialize, Serialize};
+
+use crate::secrets::SecretString;
+use crate::{http, log};
+
+/// `heartbeat` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// Requested after each successful cycle.
+    pub url: Option<SecretString>,
+    /// Requested after each failed cycle.
+    pub failure_url: Option<SecretString>,
+}
+
+impl HeartbeatConfig {
+    pub fn url(&self, ok: bool) -> Option<&str> {
+        if ok {
+            self.url.as_ref().map(SecretString::expose)
+        } else {
+            self.failure_url.as_ref().map(SecretString::expose)
+        }
+    }
+}
+
+/// Ping the monitor for a cycle's outcome. Failures are only logged.
+pub fn ping(config: &HeartbeatConfig, ok: bool) {
+    let Some(url) = config.url(ok) else {
+        return;
+    };
+    match http::request("GET", url, &[], None, http::DEFAULT_TIMEOUT) {
+        Ok(response) if response.is_success() => {}
+        Ok(response) => log::warn(&format!("Heartbeat returned HTTP {}", response.status)),
+        Err(e) => log::warn(&format!("Heartbeat failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_ping_failure_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK").unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+
+        let config = HeartbeatConfig {
+            url: Some(format!("http://127.0.0.1:{}/ping/abc", port).into()),
+            failure_url: Some(format!("http://127.0.0.1:{}/ping/abc/fail", port).into()),
+        };
+        ping(&config, false);
+        assert!(server.join().unwrap().starts_with("GET /ping/abc/fail HTTP/1.1"));
+        assert_eq!(HeartbeatConfig::default().url(true), None);
+    }
+}
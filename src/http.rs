@@ -0,0 +1,563 @@
+//! Small HTTP/1.1 client for gateway and webhook deliveries.
+//!
+//! Plain `http://` URLs are spoken natively over a `TcpStream`;
+//! `https://` URLs are handed to `curl`, which keeps TLS out of the
+//! dependency tree.
+//!
+//! Sinks instead keep a [`Connection`] open between deliveries, so a busy
+//! mailbox does not pay for a TCP and TLS handshake per message. Its TLS
+//! runs through `openssl s_client`, verified like curl would, and can
+//! present a client certificate for mutual TLS. HTTP/2 is not spoken.
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::process;
+use crate::secrets::PrivateFile;
+use crate::transport::{self, Stream};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 30;
+
+/// `connections` section of the config file: how sinks reach their
+/// endpoints.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    /// Reuse a connection for the next delivery.
+    pub keepalive: bool,
+    /// Seconds an unused connection is kept; servers close idle ones too.
+    pub idle_timeout: u64,
+    /// PEM client certificate and key presented to `https://` sinks.
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// CA bundle for `https://` sinks instead of the system store.
+    pub ca_file: Option<String>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            keepalive: true,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            client_cert: None,
+            client_key: None,
+            ca_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// First value of header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Components of an `http(s)://host[:port]/path` URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let (https, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("unsupported URL scheme: {}", url));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let default_port = if https { 443 } else { 80 };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() => {
+                let port = port.parse().map_err(|_| format!("invalid port in URL: {}", url))?;
+                (host, port)
+            }
+            _ => (authority, default_port),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in URL: {}", url));
+        }
+        Ok(Self {
+            https,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// POST a JSON body and return the response, whatever its status.
+pub fn post_json(url: &str, body: &str, timeout: Duration) -> Result<Response, Box<dyn Error>> {
+    request("POST", url, &[("Content-Type", "application/json")], Some(body), timeout)
+}
+
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<Response, Box<dyn Error>> {
+    request_bytes(method, url, headers, body.map(str::as_bytes), timeout)
+}
+
+/// [`request`] with a body that need not be text, such as a raw message.
+/// A `Host` header in `headers` replaces the default one.
+pub fn request_bytes(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    timeout: Duration,
+) -> Result<Response, Box<dyn Error>> {
+    let parsed = Url::parse(url)?;
+    if parsed.https {
+        return request_with_curl(method, url, headers, body, timeout);
+    }
+
+    let addr = (parsed.host.as_str(), parsed.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("could not resolve {}", parsed.host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let body = body.unwrap_or_default();
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, parsed.path);
+    if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Host")) {
+        head.push_str(&format!("Host: {}:{}\r\n", parsed.host, parsed.port));
+    }
+    head.push_str(&format!("Connection: close\r\nContent-Length: {}\r\n", body.len()));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(&[head.as_bytes(), body].concat())?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    parse_response(&raw)
+}
+
+fn request_with_curl(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    timeout: Duration,
+) -> Result<Response, Box<dyn Error>> {
+    let mut command = process::network_command("curl", &[]);
+    command
+        .args(["-sS", "-X", method, "--max-time"])
+        .arg(timeout.as_secs().max(1).to_string())
+        .args(["-D", "-", "-w", "\n%{http_code}"]);
+    // The URL and headers carry tokens and API keys, which any user could
+    // read from curl's command line, so they go in a config file instead.
+    let mut config = format!("url = {}\n", curl_quote(url));
+    for (name, value) in headers {
+        config.push_str(&format!("header = {}\n", curl_quote(&format!("{}: {}", name, value))));
+    }
+    let config = PrivateFile::new("curl.conf", config.as_bytes())?;
+    command.arg("-K").arg(config.path());
+    if body.is_some() {
+        command.args(["--data-binary", "@-"]);
+    }
+    command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    let mut child = command.spawn().map_err(|e| format!("failed to run curl: {}", e))?;
+    if let (Some(body), Some(mut stdin)) = (body, child.stdin.take()) {
+        stdin.write_all(body)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!("curl failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (mut body, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+    // `-D -` writes the head of every response, interim 1xx ones
+    // included, before the body; the last one is the real one.
+    let mut head = "";
+    while body.starts_with("HTTP/") {
+        let Some((next, rest)) = body.split_once("\r\n\r\n") else { break };
+        (head, body) = (next, rest);
+    }
+    Ok(Response {
+        status: status.trim().parse()?,
+        headers: parse_header_lines(head),
+        body: body.to_string(),
+    })
+}
+
+/// `value` as a quoted string of curl's config file syntax.
+fn curl_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Percent-encode `text` for use in a URL path or query.
+pub fn encode_component(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A connection to one URL's server, opened on first use and kept for
+/// the next request while the server allows it.
+pub struct Connection {
+    url: String,
+    config: ConnectionConfig,
+    open: Option<(BufReader<Box<dyn Stream>>, Instant)>,
+}
+
+impl Connection {
+    pub fn new(url: &str, config: &ConnectionConfig) -> Self {
+        Self {
+            url: url.to_string(),
+            config: config.clone(),
+            open: None,
+        }
+    }
+
+    /// Send a request to the URL. A kept connection the server has closed
+    /// in the meantime is replaced and the request sent again, as long as
+    /// no part of a response arrived.
+    pub fn request(
+        &mut self,
+        method: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+        timeout: Duration,
+    ) -> Result<Response, Box<dyn Error>> {
+        let idle = Duration::from_secs(self.config.idle_timeout);
+        let reused = self.open.as_ref().is_some_and(|(_, used)| used.elapsed() < idle);
+        if !reused {
+            self.open = None;
+        }
+        match self.send(method, headers, body, timeout) {
+            Err(e) if reused && is_stale(&e) => {
+                self.open = None;
+                Ok(self.send(method, headers, body, timeout)?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    fn send(&mut self, method: &str, headers: &[(&str, &str)], body: &str, timeout: Duration) -> io::Result<Response> {
+        let url = Url::parse(&self.url).map_err(io::Error::other)?;
+        let (mut stream, _) = match self.open.take() {
+            Some(open) => open,
+            None => (BufReader::new(self.connect(&url, timeout)?), Instant::now()),
+        };
+        let keepalive = if self.config.keepalive { "keep-alive" } else { "close" };
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: {}\r\nContent-Length: {}\r\n",
+            method,
+            url.path,
+            url.host,
+            url.port,
+            keepalive,
+            body.len()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        head.push_str(body);
+        stream.get_mut().write_all(head.as_bytes())?;
+        stream.get_mut().flush()?;
+        let (response, reusable) = read_response(&mut stream)?;
+        if reusable && self.config.keepalive {
+            self.open = Some((stream, Instant::now()));
+        }
+        Ok(response)
+    }
+
+    fn connect(&self, url: &Url, timeout: Duration) -> io::Result<Box<dyn Stream>> {
+        let addr = (url.host.as_str(), url.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other(format!("could not resolve {}", url.host)))?;
+        if !url.https {
+            let stream = TcpStream::connect_timeout(&addr, timeout)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            return Ok(Box::new(stream));
+        }
+        let mut args: Vec<String> = ["-servername", &url.host, "-verify_return_error", "-verify_hostname", &url.host]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        for (flag, value) in [
+            ("-CAfile", &self.config.ca_file),
+            ("-cert", &self.config.client_cert),
+            ("-key", &self.config.client_key),
+        ] {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value.clone()]);
+            }
+        }
+        Ok(Box::new(transport::s_client(addr, &args)?))
+    }
+}
+
+/// Whether `error` means the server closed a kept connection before it
+/// started to answer, so the request can safely be sent again.
+fn is_stale(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Read one response from a kept connection. Also returns whether the
+/// connection can carry another request.
+fn read_response(stream: &mut impl BufRead) -> io::Result<(Response, bool)> {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            let kind = if head.is_empty() { io::ErrorKind::ConnectionAborted } else { io::ErrorKind::UnexpectedEof };
+            return Err(io::Error::new(kind, "connection closed before the response ended"));
+        }
+        if line.trim_end().is_empty() && !head.is_empty() {
+            break;
+        }
+        head.push_str(&line);
+    }
+    let status: u16 = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| io::Error::other("malformed HTTP status line"))?;
+    let headers = parse_header_lines(&head);
+    let header = |name: &str| headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+    let mut reusable = head.starts_with("HTTP/1.1") && !header("Connection").is_some_and(|v| v.eq_ignore_ascii_case("close"));
+
+    let mut body = Vec::new();
+    if header("Transfer-Encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        loop {
+            let mut size = String::new();
+            stream.read_line(&mut size)?;
+            let size = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16)
+                .map_err(|_| io::Error::other("malformed chunk size"))?;
+            if size == 0 {
+                // Trailers, up to the blank line.
+                let mut line = String::from("-");
+                while !line.trim_end().is_empty() {
+                    line.clear();
+                    if stream.read_line(&mut line)? == 0 {
+                        break;
+                    }
+                }
+                break;
+            }
+            let mut chunk = vec![0u8; size + 2];
+            stream.read_exact(&mut chunk)?;
+            body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = header("Content-Length") {
+        let length = length.parse().map_err(|_| io::Error::other("malformed Content-Length"))?;
+        body.resize(length, 0);
+        stream.read_exact(&mut body)?;
+    } else if status != 204 && status != 304 {
+        stream.read_to_end(&mut body)?;
+        reusable = false;
+    }
+    let body = String::from_utf8_lossy(&body).into_owned();
+    Ok((Response { status, headers, body }, reusable))
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response, Box<dyn Error>> {
+    let text = String::from_utf8_lossy(raw);
+    let (head, body) = text.split_once("\r\n\r\n").ok_or("malformed HTTP response")?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or("malformed HTTP status line")?;
+    let headers = parse_header_lines(head);
+    let chunked = headers
+        .iter()
+        .any(|(name, value)| name.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked"));
+    let body = if chunked { dechunk(body) } else { body.to_string() };
+    Ok(Response { status, headers, body })
+}
+
+/// The `Name: value` lines after a status line.
+fn parse_header_lines(head: &str) -> Vec<(String, String)> {
+    head.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn dechunk(mut body: &str) -> String {
+    let mut out = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16).unwrap_or(0);
+        if size == 0 || rest.len() < size {
+            break;
+        }
+        out.push_str(&rest[..size]);
+        body = rest[size..].trim_start_matches("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_url_parse() {
+        let url = Url::parse("http://localhost:18789/api/message").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("localhost", 18789, "/api/message"));
+        let url = Url::parse("https://example.com").unwrap();
+        assert_eq!((url.https, url.port, url.path.as_str()), (true, 443, "/"));
+        assert!(Url::parse("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let raw = b"HTTP/1.1 202 Accepted\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+        let response = parse_response(raw).unwrap();
+        assert_eq!(response.status, 202);
+        assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+        assert_eq!(response.body, "Wikipedia");
+    }
+
+    #[test]
+    fn test_connection_reuse() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            // Two requests on the first connection, which the server then
+            // drops; the third has to arrive on a new one.
+            for requests in [2, 1] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                for i in 0..requests {
+                    let mut line = String::new();
+                    while line != "\r\n" {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                    }
+                    let mut body = [0u8; 7];
+                    reader.read_exact(&mut body).unwrap();
+                    let reply = if i == 0 {
+                        "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_string()
+                    } else {
+                        "HTTP/1.1 202 Accepted\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nyes\r\n0\r\n\r\n".to_string()
+                    };
+                    reader.get_mut().write_all(reply.as_bytes()).unwrap();
+                }
+            }
+        });
+
+        let url = format!("http://127.0.0.1:{}/api/message", port);
+        let mut connection = Connection::new(&url, &ConnectionConfig::default());
+        let post = |c: &mut Connection| c.request("POST", &[], r#"{"a":1}"#, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(post(&mut connection).body, "ok");
+        assert_eq!(post(&mut connection).body, "yes");
+        // Give the server time to close the first connection.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(post(&mut connection).status, 200);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_post_json_roundtrip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+
+        let url = format!("http://127.0.0.1:{}/api/message", port);
+        let response = post_json(&url, r#"{"a":1}"#, DEFAULT_TIMEOUT).unwrap();
+        assert!(response.is_success());
+        assert_eq!(response.body, "ok");
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /api/message HTTP/1.1"));
+        assert!(request.ends_with(r#"{"a":1}"#));
+    }
+
+    #[test]
+    fn test_curl_gets_url_and_headers_from_a_file() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok").unwrap();
+            head
+        });
+        let url = format!("http://127.0.0.1:{}/api/v1/get/status?token=s3cret", port);
+        let headers = [("X-API-Key", "s3cret"), ("X-Quoted", "a \"b\" \\c")];
+        let response = request_with_curl("GET", &url, &headers, None, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!((response.status, response.body.as_str()), (200, "ok"));
+        let head = server.join().unwrap();
+        assert!(head.starts_with("GET /api/v1/get/status?token=s3cret HTTP/1.1\r\n"));
+        assert!(head.contains("X-API-Key: s3cret\r\n"));
+        assert!(head.contains("X-Quoted: a \"b\" \\c\r\n"));
+    }
+}
@@ -0,0 +1,780 @@
+//! Minimal IMAP4rev1 client (RFC 3501).
+//!
+//! Covers the commands the checker issues: CAPABILITY, LOGIN,
+//! AUTHENTICATE, COMPRESS, NAMESPACE, LIST, MYRIGHTS, GETQUOTAROOT, SELECT,
+//! UID SEARCH, UID FETCH, UID STORE and LOGOUT. The client is generic over the stream so
+//! tests can script a server conversation.
+//!
+//! Mailbox names are Unicode on this side: they are encoded to IMAP's
+//! modified UTF-7 when sent and decoded when listed, so "Entwürfe"
+//! selects `Entw&APw-rfe`.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::auth::{Exchange, Mechanism};
+use crate::deflate::Compressed;
+use crate::mime;
+
+#[derive(Debug)]
+pub enum ImapError {
+    Io(io::Error),
+    /// The server rejected the credentials.
+    Auth(String),
+    /// A tagged `NO` response to anything but authentication.
+    No(String),
+    /// A tagged `BAD` response.
+    Bad(String),
+    /// The server sent something we could not make sense of.
+    Protocol(String),
+}
+
+impl fmt::Display for ImapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImapError::Io(e) => write!(f, "IMAP connection error: {}", e),
+            ImapError::Auth(msg) => write!(f, "IMAP authentication failed: {}", msg),
+            ImapError::No(msg) => write!(f, "IMAP command failed: {}", msg),
+            ImapError::Bad(msg) => write!(f, "IMAP command rejected: {}", msg),
+            ImapError::Protocol(msg) => write!(f, "IMAP protocol error: {}", msg),
+        }
+    }
+}
+
+impl Error for ImapError {}
+
+impl From<io::Error> for ImapError {
+    fn from(e: io::Error) -> Self {
+        ImapError::Io(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ImapError>;
+
+/// One untagged response line, with any literals it carried.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseLine {
+    /// Line text; literals are left in place as `{n}` markers.
+    pub text: String,
+    pub literals: Vec<Vec<u8>>,
+}
+
+/// A fetched message.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub raw: Vec<u8>,
+    /// When the server received it.
+    pub internal_date: Option<DateTime<FixedOffset>>,
+}
+
+/// A mailbox from `LIST`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mailbox {
+    pub name: String,
+    pub delimiter: Option<char>,
+    /// E.g. `\Noselect` or `\HasChildren`.
+    pub attributes: Vec<String>,
+}
+
+impl Mailbox {
+    pub fn is_selectable(&self) -> bool {
+        !self.attributes.iter().any(|a| a.eq_ignore_ascii_case("\\Noselect") || a.eq_ignore_ascii_case("\\NonExistent"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Namespace {
+    pub prefix: String,
+    pub delimiter: Option<char>,
+}
+
+impl Namespace {
+    /// The prefix with `/` between levels, as configs write it.
+    fn config_prefix(&self) -> String {
+        self.delimiter.map_or(self.prefix.clone(), |d| self.prefix.replace(d, "/"))
+    }
+}
+
+/// The personal, other users' and shared namespaces (RFC 2342).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Namespaces {
+    pub personal: Vec<Namespace>,
+    pub other: Vec<Namespace>,
+    pub shared: Vec<Namespace>,
+}
+
+impl Namespaces {
+    /// The server's name for a folder written with `/` between levels.
+    /// Folders outside the other users' and shared namespaces are taken
+    /// to be personal, below the personal prefix (often `INBOX.`).
+    pub fn server_name(&self, folder: &str) -> String {
+        if folder.eq_ignore_ascii_case("INBOX") {
+            return "INBOX".to_string();
+        }
+        let within = self
+            .other
+            .iter()
+            .chain(&self.shared)
+            .chain(&self.personal)
+            .find(|ns| !ns.prefix.is_empty() && folder.starts_with(&ns.config_prefix()));
+        let (namespace, name) = match within {
+            Some(namespace) => (Some(namespace), folder.to_string()),
+            None => {
+                let namespace = self.personal.first();
+                (namespace, format!("{}{}", namespace.map(Namespace::config_prefix).unwrap_or_default(), folder))
+            }
+        };
+        match namespace.and_then(|ns| ns.delimiter) {
+            Some(delimiter) => name.replace('/', &delimiter.to_string()),
+            None => name,
+        }
+    }
+
+    /// Whether the server name `name` is in another user's or a shared
+    /// namespace, e.g. a team mailbox.
+    pub fn is_shared(&self, name: &str) -> bool {
+        let within = |ns: &Namespace| !ns.prefix.is_empty() && name.starts_with(&ns.prefix);
+        self.other.iter().chain(&self.shared).any(within)
+    }
+
+    /// The inverse of [`Namespaces::server_name`], for listing folders.
+    pub fn config_name(&self, mailbox: &Mailbox) -> String {
+        let within = |ns: &&Namespace| !ns.prefix.is_empty() && mailbox.name.starts_with(&ns.prefix);
+        let foreign = self.other.iter().chain(&self.shared).any(|ns| within(&ns));
+        let name = match self.personal.iter().find(within) {
+            Some(ns) if !foreign => &mailbox.name[ns.prefix.len()..],
+            _ => &mailbox.name,
+        };
+        mailbox.delimiter.map_or(name.to_string(), |d| name.replace(d, "/"))
+    }
+}
+
+/// What SELECT reported about a mailbox.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selected {
+    pub uid_validity: u32,
+    /// The UID the next message will get, if the server said.
+    pub uid_next: Option<u32>,
+}
+
+/// A mailbox's storage quota, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl Quota {
+    /// How full the mailbox is, in percent.
+    pub fn percent(&self) -> u64 {
+        (self.used * 100).checked_div(self.limit).unwrap_or(0)
+    }
+}
+
+pub struct Client<S: Read + Write> {
+    stream: BufReader<Compressed<S>>,
+    next_tag: usize,
+    pub capabilities: Vec<String>,
+}
+
+impl<S: Read + Write> Client<S> {
+    /// Wrap a connected stream and consume the server greeting.
+    pub fn new(stream: S) -> Result<Self> {
+        let mut client = Self {
+            stream: BufReader::new(Compressed::new(stream)),
+            next_tag: 1,
+            capabilities: Vec::new(),
+        };
+        let greeting = client.read_line()?;
+        if greeting.text.starts_with("* BYE") {
+            return Err(ImapError::Protocol(greeting.text));
+        }
+        if !greeting.text.starts_with("* OK") && !greeting.text.starts_with("* PREAUTH") {
+            return Err(ImapError::Protocol(format!("unexpected greeting: {}", greeting.text)));
+        }
+        client.capabilities = parse_capability_code(&greeting.text);
+        Ok(client)
+    }
+
+    /// Wrap a stream whose greeting was already consumed, e.g. by a
+    /// STARTTLS negotiation. Capabilities must be asked for again.
+    pub fn resume(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(Compressed::new(stream)),
+            next_tag: 1,
+            capabilities: Vec::new(),
+        }
+    }
+
+    /// Send a command and collect untagged responses until its completion.
+    pub fn command(&mut self, command: &str) -> Result<Vec<ResponseLine>> {
+        self.command_with(command, |_| Err(ImapError::Protocol("unexpected continuation request".to_string())))
+    }
+
+    /// Like [`Client::command`], answering `+` continuation requests with
+    /// `respond(decoded challenge)`, base64-encoded (as SASL needs).
+    fn command_with<F>(&mut self, command: &str, mut respond: F) -> Result<Vec<ResponseLine>>
+    where
+        F: FnMut(&[u8]) -> Result<Vec<u8>>,
+    {
+        let tag = format!("A{}", self.next_tag);
+        self.next_tag += 1;
+        self.write_line(&format!("{} {}", tag, command))?;
+
+        let mut untagged = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if let Some(challenge) = line.text.strip_prefix('+') {
+                let response = respond(&mime::decode_base64(challenge.trim().as_bytes()))?;
+                self.write_line(&mime::encode_base64(&response))?;
+                continue;
+            }
+            let Some(status) = line.text.strip_prefix(&tag).and_then(|rest| rest.strip_prefix(' ')) else {
+                untagged.push(line);
+                continue;
+            };
+            let (kind, message) = status.split_once(' ').unwrap_or((status, ""));
+            return match kind.to_ascii_uppercase().as_str() {
+                "OK" => Ok(untagged),
+                "NO" => Err(ImapError::No(message.to_string())),
+                "BAD" => Err(ImapError::Bad(message.to_string())),
+                _ => Err(ImapError::Protocol(line.text)),
+            };
+        }
+    }
+
+    /// Ask for the capabilities, unless the greeting already listed them.
+    pub fn capability(&mut self) -> Result<&[String]> {
+        if self.capabilities.is_empty() {
+            let lines = self.command("CAPABILITY")?;
+            self.capabilities = lines
+                .iter()
+                .filter_map(|line| line.text.strip_prefix("* CAPABILITY "))
+                .flat_map(|caps| caps.split_whitespace().map(str::to_string))
+                .collect();
+        }
+        Ok(&self.capabilities)
+    }
+
+    /// Authenticate with `mechanism`, or the best one advertised.
+    pub fn authenticate(&mut self, mechanism: Option<Mechanism>, username: &str, password: &str) -> Result<()> {
+        let mechanism = match mechanism {
+            Some(mechanism) => mechanism,
+            None => Mechanism::negotiate(self.capability()?).map_err(ImapError::Protocol)?,
+        };
+        if mechanism == Mechanism::Login {
+            return self.login(username, password);
+        }
+        let mut exchange = Exchange::new(mechanism, username, password);
+        let command = format!("AUTHENTICATE {}", mechanism.name());
+        match self.command_with(&command, |challenge| exchange.respond(challenge).map_err(ImapError::Protocol)) {
+            Ok(_) => {
+                // Capabilities may change after authentication.
+                self.capabilities.clear();
+                Ok(())
+            }
+            Err(ImapError::No(msg)) => Err(ImapError::Auth(msg)),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        let command = format!("LOGIN {} {}", quote(username)?, quote(password)?);
+        match self.command(&command) {
+            Ok(_) => Ok(()),
+            Err(ImapError::No(msg)) => Err(ImapError::Auth(msg)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The storage quota of `mailbox`'s quota root (RFC 2087), or `None`
+    /// without the QUOTA extension or a storage limit.
+    pub fn quota(&mut self, mailbox: &str) -> Result<Option<Quota>> {
+        if !self.capability()?.iter().any(|c| c.eq_ignore_ascii_case("QUOTA")) {
+            return Ok(None);
+        }
+        let lines = self.command(&format!("GETQUOTAROOT {}", quote(&encode_mailbox(mailbox))?))?;
+        Ok(lines.iter().filter(|line| line.text.starts_with("* QUOTA ")).find_map(|line| {
+            let items = parse_items(line, 2);
+            let resources: Vec<&str> = items.get(1)?.items().iter().filter_map(Item::as_str).collect();
+            let storage = resources.chunks(3).find(|r| r.len() == 3 && r[0].eq_ignore_ascii_case("STORAGE"))?;
+            let kib = |value: &str| value.parse::<u64>().ok().map(|kib| kib * 1024);
+            Some(Quota { used: kib(storage[1])?, limit: kib(storage[2])? })
+        }))
+    }
+
+    /// Turn on COMPRESS=DEFLATE (RFC 4978) if the server offers it.
+    /// Returns whether it did.
+    pub fn compress(&mut self) -> Result<bool> {
+        if !self.capability()?.iter().any(|c| c.eq_ignore_ascii_case("COMPRESS=DEFLATE")) {
+            return Ok(false);
+        }
+        match self.command("COMPRESS DEFLATE") {
+            Err(ImapError::No(_)) => return Ok(false),
+            result => result?,
+        };
+        // Whatever followed the reply in the buffer is already compressed.
+        let read = self.stream.buffer().to_vec();
+        self.stream.consume(read.len());
+        self.stream.get_mut().start(&read)?;
+        Ok(true)
+    }
+
+    /// The server's namespaces. Without the NAMESPACE extension all
+    /// mailboxes are personal, separated by the root's delimiter.
+    pub fn namespace(&mut self) -> Result<Namespaces> {
+        if !self.capability()?.iter().any(|c| c.eq_ignore_ascii_case("NAMESPACE")) {
+            let delimiter = self.list("", "")?.first().and_then(|root| root.delimiter);
+            return Ok(Namespaces { personal: vec![Namespace { prefix: String::new(), delimiter }], ..Namespaces::default() });
+        }
+        let lines = self.command("NAMESPACE")?;
+        let line = lines
+            .iter()
+            .find(|line| line.text.starts_with("* NAMESPACE "))
+            .ok_or_else(|| ImapError::Protocol("no NAMESPACE response".to_string()))?;
+        let groups = parse_items(line, 2);
+        let group = |i: usize| -> Vec<Namespace> {
+            groups.get(i).map(Item::items).unwrap_or_default().iter().filter_map(|namespace| {
+                let [prefix, delimiter, ..] = namespace.items() else { return None };
+                Some(Namespace { prefix: decode_mailbox(prefix.as_str()?), delimiter: delimiter.as_str().and_then(|d| d.chars().next()) })
+            }).collect()
+        };
+        Ok(Namespaces { personal: group(0), other: group(1), shared: group(2) })
+    }
+
+    /// `LIST reference pattern`, e.g. `LIST "" "*"` for every mailbox.
+    pub fn list(&mut self, reference: &str, pattern: &str) -> Result<Vec<Mailbox>> {
+        let command = format!("LIST {} {}", quote(&encode_mailbox(reference))?, quote(&encode_mailbox(pattern))?);
+        let lines = self.command(&command)?;
+        Ok(lines
+            .iter()
+            .filter(|line| line.text.starts_with("* LIST "))
+            .filter_map(|line| {
+                let items = parse_items(line, 2);
+                let [attributes, delimiter, name, ..] = items.as_slice() else { return None };
+                Some(Mailbox {
+                    name: decode_mailbox(name.as_str()?),
+                    delimiter: delimiter.as_str().and_then(|d| d.chars().next()),
+                    attributes: attributes.items().iter().filter_map(Item::as_str).map(str::to_string).collect(),
+                })
+            })
+            .collect())
+    }
+
+    /// The account's rights on `mailbox` (RFC 4314), e.g. `lrs`, or
+    /// `None` if the server has no ACL extension.
+    pub fn my_rights(&mut self, mailbox: &str) -> Result<Option<String>> {
+        if !self.capability()?.iter().any(|c| c.eq_ignore_ascii_case("ACL")) {
+            return Ok(None);
+        }
+        let lines = self.command(&format!("MYRIGHTS {}", quote(&encode_mailbox(mailbox))?))?;
+        Ok(lines
+            .iter()
+            .filter(|line| line.text.starts_with("* MYRIGHTS "))
+            .find_map(|line| parse_items(line, 2).get(1).and_then(Item::as_str).map(str::to_string)))
+    }
+
+    /// Select a mailbox and return its `UIDVALIDITY` (0 if not reported).
+    pub fn select(&mut self, mailbox: &str) -> Result<Selected> {
+        let lines = self.command(&format!("SELECT {}", quote(&encode_mailbox(mailbox))?))?;
+        let code = |name: &str| {
+            let value = lines.iter().find_map(|line| response_code(&line.text, name));
+            value.and_then(|value| value.parse().ok())
+        };
+        Ok(Selected { uid_validity: code("UIDVALIDITY").unwrap_or(0), uid_next: code("UIDNEXT") })
+    }
+
+    /// `UID SEARCH` with raw criteria, e.g. `UNSEEN`.
+    pub fn uid_search(&mut self, criteria: &str) -> Result<Vec<u32>> {
+        let lines = self.command(&format!("UID SEARCH {}", criteria))?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| line.text.strip_prefix("* SEARCH"))
+            .flat_map(|ids| ids.split_whitespace().filter_map(|id| id.parse().ok()))
+            .collect())
+    }
+
+    /// Fetch the full message and its INTERNALDATE without setting `\Seen`.
+    pub fn uid_fetch_message(&mut self, uid: u32) -> Result<Message> {
+        let lines = self.command(&format!("UID FETCH {} (INTERNALDATE BODY.PEEK[])", uid))?;
+        let line = lines
+            .into_iter()
+            .find(|line| line.text.contains(" FETCH ") && !line.literals.is_empty())
+            .ok_or_else(|| ImapError::Protocol(format!("no body returned for UID {}", uid)))?;
+        Ok(Message {
+            internal_date: parse_internal_date(&line.text),
+            raw: line.literals.into_iter().next().unwrap_or_default(),
+        })
+    }
+
+    pub fn uid_store_seen(&mut self, uid: u32) -> Result<()> {
+        self.uid_add_flag(uid, "\\Seen")
+    }
+
+    /// Add a flag or keyword, e.g. `$Forwarded`, to a message.
+    pub fn uid_add_flag(&mut self, uid: u32, flag: &str) -> Result<()> {
+        self.command(&format!("UID STORE {} +FLAGS.SILENT ({})", uid, flag)).map(|_| ())
+    }
+
+    pub fn logout(&mut self) -> Result<()> {
+        self.command("LOGOUT").map(|_| ())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{}\r\n", line).as_bytes())?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Read one response line, pulling in any `{n}` literals it announces.
+    fn read_line(&mut self) -> Result<ResponseLine> {
+        let mut line = ResponseLine::default();
+        loop {
+            let mut raw = Vec::new();
+            if self.stream.read_until(b'\n', &mut raw)? == 0 {
+                return Err(ImapError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "server closed the connection",
+                )));
+            }
+            let text = String::from_utf8_lossy(&raw);
+            let text = text.trim_end_matches(['\r', '\n']);
+            line.text.push_str(text);
+
+            match literal_size(text) {
+                Some(size) => {
+                    let mut literal = vec![0; size];
+                    self.stream.read_exact(&mut literal)?;
+                    line.literals.push(literal);
+                }
+                None => return Ok(line),
+            }
+        }
+    }
+}
+
+/// Size announced by a trailing `{n}` on a response line.
+fn literal_size(text: &str) -> Option<usize> {
+    let open = text.strip_suffix('}')?.rfind('{')?;
+    text[open + 1..text.len() - 1].parse().ok()
+}
+
+/// Argument of a `[NAME value]` response code.
+fn response_code<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let start = text.find(&format!("[{} ", name))? + name.len() + 2;
+    let rest = &text[start..];
+    Some(&rest[..rest.find(']')?])
+}
+
+/// `INTERNALDATE "17-Jul-1996 02:44:25 -0700"` in a FETCH response.
+fn parse_internal_date(text: &str) -> Option<DateTime<FixedOffset>> {
+    let start = text.find("INTERNALDATE \"")? + 14;
+    let rest = &text[start..];
+    let value = rest[..rest.find('"')?].trim_start();
+    DateTime::parse_from_str(value, "%d-%b-%Y %H:%M:%S %z").ok()
+}
+
+/// Capabilities from a `[CAPABILITY ...]` response code.
+fn parse_capability_code(text: &str) -> Vec<String> {
+    let Some(start) = text.find("[CAPABILITY ") else {
+        return Vec::new();
+    };
+    let rest = &text[start + 12..];
+    let end = rest.find(']').unwrap_or(rest.len());
+    rest[..end].split_whitespace().map(str::to_string).collect()
+}
+
+/// A string, `NIL` or parenthesized list in a response.
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Nil,
+    Str(String),
+    List(Vec<Item>),
+}
+
+impl Item {
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Item::Str(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn items(&self) -> &[Item] {
+        match self {
+            Item::List(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// The items of a response line after its first `skip` words, e.g. 2
+/// to skip `* LIST`. Literals are taken from the line's literals.
+fn parse_items(line: &ResponseLine, skip: usize) -> Vec<Item> {
+    let text = line.text.as_bytes();
+    let mut pos = 0;
+    for _ in 0..skip {
+        pos += text[pos..].iter().position(|&b| b == b' ').map_or(text.len() - pos, |n| n + 1);
+    }
+    parse_list(text, &mut pos, &mut line.literals.iter())
+}
+
+/// Items up to the `)` closing the current list, or the end of the line.
+fn parse_list<'a>(text: &[u8], pos: &mut usize, literals: &mut impl Iterator<Item = &'a Vec<u8>>) -> Vec<Item> {
+    let mut items = Vec::new();
+    while *pos < text.len() {
+        let start = *pos;
+        *pos += 1;
+        match text[start] {
+            b' ' => {}
+            b')' => break,
+            b'(' => items.push(Item::List(parse_list(text, pos, literals))),
+            b'"' => {
+                let mut value = Vec::new();
+                while *pos < text.len() && text[*pos] != b'"' {
+                    if text[*pos] == b'\\' {
+                        *pos += 1;
+                    }
+                    value.extend(text.get(*pos));
+                    *pos += 1;
+                }
+                *pos += 1;
+                items.push(Item::Str(String::from_utf8_lossy(&value).into_owned()));
+            }
+            b'{' => {
+                *pos += text[*pos..].iter().position(|&b| b == b'}').map_or(text.len() - *pos, |n| n + 1);
+                let literal = literals.next().map(|l| String::from_utf8_lossy(l).into_owned());
+                items.push(Item::Str(literal.unwrap_or_default()));
+            }
+            _ => {
+                *pos = text[start..].iter().position(|b| b" ()".contains(b)).map_or(text.len(), |n| start + n);
+                let atom = String::from_utf8_lossy(&text[start..*pos]).into_owned();
+                items.push(if atom.eq_ignore_ascii_case("NIL") { Item::Nil } else { Item::Str(atom) });
+            }
+        }
+    }
+    items
+}
+
+/// `name` in IMAP's modified UTF-7 (RFC 3501, section 5.1.3): printable
+/// ASCII as is, `&` as `&-`, and anything else as base64 of UTF-16 with
+/// `,` for `/`, between `&` and `-`.
+pub fn encode_mailbox(name: &str) -> String {
+    fn flush(pending: &mut Vec<u16>, out: &mut String) {
+        if pending.is_empty() {
+            return;
+        }
+        let bytes: Vec<u8> = pending.drain(..).flat_map(u16::to_be_bytes).collect();
+        out.push('&');
+        out.push_str(&mime::encode_base64(&bytes).trim_end_matches('=').replace('/', ","));
+        out.push('-');
+    }
+    let mut out = String::new();
+    let mut pending = Vec::new();
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut pending, &mut out);
+            match c {
+                '&' => out.push_str("&-"),
+                c => out.push(c),
+            }
+        } else {
+            pending.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+        }
+    }
+    flush(&mut pending, &mut out);
+    out
+}
+
+/// A mailbox name from the server, decoded from modified UTF-7.
+pub fn decode_mailbox(name: &str) -> String {
+    let mut out = String::new();
+    let mut rest = name;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start + 1..].find('-') else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let encoded = &rest[start + 1..start + 1 + len];
+        if encoded.is_empty() {
+            out.push('&');
+        } else {
+            let bytes = mime::decode_base64(encoded.replace(',', "/").as_bytes());
+            let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+            out.push_str(&String::from_utf16_lossy(&units));
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Quote a string argument.
+pub fn quote(value: &str) -> Result<String> {
+    if value.contains(['\r', '\n']) {
+        return Err(ImapError::Protocol("line breaks are not allowed in IMAP strings".to_string()));
+    }
+    Ok(format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A scripted server: reads come from `input`, writes are recorded.
+    pub(crate) struct Script {
+        pub input: Cursor<Vec<u8>>,
+        pub output: Vec<u8>,
+    }
+
+    impl Script {
+        pub(crate) fn new(server: &str) -> Self {
+            Self {
+                input: Cursor::new(server.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let server = "* OK [CAPABILITY IMAP4rev1 IDLE] ready\r\n\
+A1 OK logged in\r\n\
+* 3 EXISTS\r\n* OK [UIDVALIDITY 1700000000] UIDs valid\r\n* OK [UIDNEXT 8] Predicted next UID\r\n\
+A2 OK [READ-WRITE] selected\r\n\
+* SEARCH 4 7\r\nA3 OK done\r\n\
+* 2 FETCH (UID 7 INTERNALDATE \" 7-Mar-2026 18:04:05 +0100\" BODY[] {11}\r\nSubject: x\n)\r\nA4 OK done\r\n";
+        let mut client = Client::new(Script::new(server)).unwrap();
+        assert_eq!(client.capabilities, ["IMAP4rev1", "IDLE"]);
+        client.login("bot@example.com", "pa\"ss").unwrap();
+        assert_eq!(client.select("INBOX").unwrap(), Selected { uid_validity: 1700000000, uid_next: Some(8) });
+        assert_eq!(client.uid_search("UNSEEN").unwrap(), [4, 7]);
+        let message = client.uid_fetch_message(7).unwrap();
+        assert_eq!(message.raw, b"Subject: x\n");
+        assert_eq!(message.internal_date.unwrap().to_rfc3339(), "2026-03-07T18:04:05+01:00");
+
+        let sent = String::from_utf8(client.stream.into_inner().into_inner().output).unwrap();
+        assert!(sent.starts_with("A1 LOGIN \"bot@example.com\" \"pa\\\"ss\"\r\nA2 SELECT \"INBOX\"\r\n"));
+    }
+
+    #[test]
+    fn test_compress() {
+        let mut server = b"* OK [CAPABILITY IMAP4rev1 COMPRESS=DEFLATE] ready\r\nA1 OK DEFLATE active\r\n".to_vec();
+        // "* 1 EXISTS\r\n" and "A2 OK done\r\n", each deflated and flushed.
+        server.extend([
+            0xd2, 0x52, 0x30, 0x54, 0x70, 0x8d, 0xf0, 0x0c, 0x0e, 0x09, 0xe6, 0xe5, 0x02, 0x00, 0x00, 0x00, 0xff, 0xff,
+            0x72, 0x34, 0x52, 0xf0, 0xf7, 0x56, 0x48, 0xc9, 0xcf, 0x4b, 0xe5, 0xe5, 0x02, 0x00, 0x00, 0x00, 0xff, 0xff,
+        ]);
+        let script = Script { input: Cursor::new(server), output: Vec::new() };
+        let mut client = Client::new(script).unwrap();
+        assert!(client.compress().unwrap());
+        let lines = client.command("NOOP").unwrap();
+        assert_eq!(lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>(), ["* 1 EXISTS"]);
+
+        let sent = client.stream.into_inner().into_inner().output;
+        let expected = [&b"A1 COMPRESS DEFLATE\r\n"[..], &crate::deflate::stored(b"A2 NOOP\r\n")].concat();
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn test_namespaces_and_utf7() {
+        assert_eq!(encode_mailbox("Entwürfe"), "Entw&APw-rfe");
+        assert_eq!(encode_mailbox("已发送 & more"), "&XfJT0ZAB- &- more");
+        assert_eq!(decode_mailbox("&XfJT0ZAB- &- more"), "已发送 & more");
+        assert_eq!(decode_mailbox("Entw&APw-rfe"), "Entwürfe");
+
+        let server = "* OK [CAPABILITY IMAP4rev1 NAMESPACE] ready\r\n\
+* NAMESPACE ((\"INBOX.\" \".\")) ((\"Other Users.\" \".\")) NIL\r\nA1 OK done\r\n\
+* LIST (\\HasNoChildren) \".\" \"INBOX.Entw&APw-rfe\"\r\n* LIST (\\Noselect) \".\" {19}\r\nOther Users.bob \"x\"\r\nA2 OK done\r\n";
+        let mut client = Client::new(Script::new(server)).unwrap();
+        let namespaces = client.namespace().unwrap();
+        assert_eq!(namespaces.personal, [Namespace { prefix: "INBOX.".to_string(), delimiter: Some('.') }]);
+        assert_eq!(namespaces.other[0].prefix, "Other Users.");
+        assert!(namespaces.shared.is_empty());
+        assert_eq!(namespaces.server_name("Entwürfe"), "INBOX.Entwürfe");
+        assert_eq!(namespaces.server_name("INBOX/Archiv/2026"), "INBOX.Archiv.2026");
+        assert_eq!(namespaces.server_name("Other Users/bob/INBOX"), "Other Users.bob.INBOX");
+        assert_eq!(namespaces.server_name("inbox"), "INBOX");
+
+        let mailboxes = client.list("", "*").unwrap();
+        assert_eq!(mailboxes[0].name, "INBOX.Entwürfe");
+        assert_eq!(namespaces.config_name(&mailboxes[0]), "Entwürfe");
+        assert_eq!((mailboxes[1].name.as_str(), mailboxes[1].is_selectable()), ("Other Users.bob \"x\"", false));
+        assert_eq!(namespaces.config_name(&mailboxes[1]), "Other Users/bob \"x\"");
+        assert!(namespaces.is_shared("Other Users.bob.INBOX") && !namespaces.is_shared("INBOX.Entwürfe"));
+        let sent = String::from_utf8(client.stream.into_inner().into_inner().output).unwrap();
+        assert_eq!(sent, "A1 NAMESPACE\r\nA2 LIST \"\" \"*\"\r\n");
+    }
+
+    #[test]
+    fn test_my_rights() {
+        let server = "* OK [CAPABILITY IMAP4rev1 ACL] ready\r\n* MYRIGHTS \"Shared.support\" lr\r\nA1 OK\r\n";
+        let mut client = Client::new(Script::new(server)).unwrap();
+        assert_eq!(client.my_rights("Shared.support").unwrap().as_deref(), Some("lr"));
+        let mut client = Client::new(Script::new("* OK [CAPABILITY IMAP4rev1] ready\r\n")).unwrap();
+        assert_eq!(client.my_rights("INBOX").unwrap(), None);
+    }
+
+    #[test]
+    fn test_quota() {
+        let server = "* OK [CAPABILITY IMAP4rev1 QUOTA] ready\r\n\
+* QUOTAROOT INBOX \"User quota\"\r\n* QUOTA \"User quota\" (MESSAGE 4 1000 STORAGE 512 1024)\r\nA1 OK done\r\n";
+        let mut client = Client::new(Script::new(server)).unwrap();
+        let quota = client.quota("INBOX").unwrap().unwrap();
+        assert_eq!(quota, Quota { used: 512 * 1024, limit: 1024 * 1024 });
+        assert_eq!(quota.percent(), 50);
+        let sent = String::from_utf8(client.stream.into_inner().into_inner().output).unwrap();
+        assert_eq!(sent, "A1 GETQUOTAROOT \"INBOX\"\r\n");
+
+        let mut client = Client::new(Script::new("* OK [CAPABILITY IMAP4rev1] ready\r\n")).unwrap();
+        assert_eq!(client.quota("INBOX").unwrap(), None);
+    }
+
+    #[test]
+    fn test_auth_failure() {
+        let server = "* OK ready\r\nA1 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n";
+        let mut client = Client::new(Script::new(server)).unwrap();
+        assert!(matches!(client.login("a", "b"), Err(ImapError::Auth(_))));
+        assert!(quote("a\r\nb").is_err());
+    }
+
+    #[test]
+    fn test_authenticate_negotiates_cram_md5() {
+        let server = "* OK ready\r\n* CAPABILITY IMAP4rev1 AUTH=PLAIN AUTH=CRAM-MD5\r\nA1 OK\r\n\
++ PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\nA2 OK authenticated\r\n";
+        let mut client = Client::new(Script::new(server)).unwrap();
+        client.authenticate(None, "tim", "tanstaaftanstaaf").unwrap();
+        let sent = String::from_utf8(client.stream.into_inner().into_inner().output).unwrap();
+        assert_eq!(
+            sent,
+            "A1 CAPABILITY\r\nA2 AUTHENTICATE CRAM-MD5\r\ndGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw\r\n"
+        );
+
+        let server = "* OK ready\r\n+ \r\nA1 NO [AUTHENTICATIONFAILED] no\r\n";
+        let mut client = Client::new(Script::new(server)).unwrap();
+        assert!(matches!(client.authenticate(Some(Mechanism::Plain), "a", "b"), Err(ImapError::Auth(_))));
+    }
+}
@@ -0,0 +1,102 @@
+//! A sink appending every event as one JSON line to a file or stdout.
+//!
+//! Each payload is written as is, with a `time` field added unless a
+//! rule template set one, so the output can be piped into `jq` or
+//! tailed by vector or fluentbit. With the events on stdout, the log
+//! moves to stderr; see [`crate::log::reserve_stdout`].
+
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::sink::{Sink, SinkConfig};
+
+/// `path` meaning stdout.
+pub const STDOUT: &str = "-";
+
+pub struct JsonlSink {
+    /// File to append to; stdout when `None`.
+    pub path: Option<String>,
+    /// Keeps lines from concurrent deliveries whole.
+    lock: Mutex<()>,
+}
+
+impl JsonlSink {
+    pub fn new(path: Option<&str>) -> Self {
+        Self {
+            path: path.filter(|p| *p != STDOUT).map(str::to_string),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// The line written for `payload`.
+    pub fn line(payload: &Value) -> String {
+        let mut event = payload.clone();
+        if let Some(event) = event.as_object_mut() {
+            event
+                .entry("time")
+                .or_insert_with(|| Value::String(chrono::Utc::now().to_rfc3339()));
+        }
+        format!("{}\n", event)
+    }
+}
+
+impl Sink for JsonlSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        let line = Self::line(payload);
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        match &self.path {
+            Some(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .mode(0o600)
+                    .open(path)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                file.write_all(line.as_bytes()).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(line.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether any sink in `config` writes to stdout, so the log must not.
+pub fn uses_stdout(config: &Config) -> bool {
+    config
+        .sinks
+        .values()
+        .any(|sink| matches!(sink, SinkConfig::Jsonl { path } if path.as_deref().is_none_or(|p| p == STDOUT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_appends_lines() {
+        let path = std::env::temp_dir().join(format!("email_checker_jsonl_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = JsonlSink::new(path.to_str());
+        sink.deliver(&json!({"event_type": "message", "message": "one\ntwo"})).unwrap();
+        sink.deliver(&json!({"event_type": "ci", "time": "2026-10-14T09:00:00Z"})).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["message"], "one\ntwo");
+        assert!(lines[0]["time"].is_string());
+        assert_eq!(lines[1]["time"], "2026-10-14T09:00:00Z");
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(JsonlSink::new(Some(STDOUT)).path, None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Publishing events to Kafka. Only built with the `kafka` feature.
+//!
+//! Records go through a Kafka REST proxy (Confluent's REST Proxy v2 API,
+//! which Redpanda serves natively) rather than the broker protocol, so
+//! the checker needs no Kafka client library. Each payload becomes one
+//! record on `topic`, keyed by its `Message-ID` header, or the delivery's
+//! idempotency key when the header is not in the payload, so a message's
+//! events land on one partition in order. A delivery only succeeds once
+//! the proxy reports the record's offset; how many replicas must have it
+//! first is the proxy's `acks` producer setting. The receipt is
+//! `partition:offset`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::http;
+use crate::metrics;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+
+pub const DEFAULT_KEY: &str = "/headers/Message-ID";
+
+/// `sinks` entry of type `kafka`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct KafkaConfig {
+    /// Base URL of the REST proxy.
+    pub url: String,
+    pub topic: String,
+    /// JSON pointer to the record key in the payload.
+    #[serde(default = "default_key")]
+    pub key: String,
+    /// E.g. `Authorization` for a proxy behind basic auth.
+    #[serde(default)]
+    pub headers: BTreeMap<String, SecretString>,
+}
+
+fn default_key() -> String {
+    DEFAULT_KEY.to_string()
+}
+
+pub struct KafkaSink {
+    pub config: KafkaConfig,
+}
+
+impl KafkaSink {
+    /// The produce request body for `payload`.
+    pub fn records(&self, payload: &Value, key: Option<&str>) -> Value {
+        let key = payload.pointer(&self.config.key).and_then(Value::as_str).or(key);
+        json!({"records": [{"key": key, "value": payload}]})
+    }
+
+    fn send(&self, payload: &Value, key: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+        let url = format!("{}/topics/{}", self.config.url.trim_end_matches('/'), http::encode_component(&self.config.topic));
+        let mut headers = vec![
+            ("Content-Type", "application/vnd.kafka.json.v2+json"),
+            ("Accept", "application/vnd.kafka.v2+json"),
+        ];
+        headers.extend(self.config.headers.iter().map(|(k, v)| (k.as_str(), v.expose())));
+        let body = self.records(payload, key).to_string();
+        metrics::add(metrics::GATEWAY_BYTES_UPLOADED, body.len() as u64);
+        let response = http::request("POST", &url, &headers, Some(&body), http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("{} returned HTTP {}", url, response.status).into());
+        }
+        offset(&response.body).map(Some).map_err(|e| format!("{}: {}", url, e).into())
+    }
+}
+
+impl Sink for KafkaSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.send(payload, None).map(|_| ())
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.send(payload, Some(key))
+    }
+}
+
+/// `partition:offset` of the one record in a produce response, or the
+/// error the proxy reported for it.
+fn offset(body: &str) -> Result<String, String> {
+    let response: Value = serde_json::from_str(body).map_err(|_| "unexpected produce response".to_string())?;
+    let record = response.pointer("/offsets/0").ok_or("no offset in the produce response")?;
+    if let Some(error) = record["error"].as_str() {
+        return Err(format!("record refused: {}", error));
+    }
+    match (record["partition"].as_u64(), record["offset"].as_u64()) {
+        (Some(partition), Some(offset)) => Ok(format!("{}:{}", partition, offset)),
+        _ => Err("no offset in the produce response".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let config: KafkaConfig = serde_json::from_value(json!({"url": "http://proxy:8082", "topic": "mail"})).unwrap();
+        let sink = KafkaSink { config };
+        let payload = json!({"headers": {"Message-ID": "<a@b>"}});
+        assert_eq!(sink.records(&payload, Some("k1"))["records"][0]["key"], "<a@b>");
+        assert_eq!(sink.records(&json!({}), Some("k1"))["records"][0]["key"], "k1");
+        assert_eq!(offset(r#"{"offsets":[{"partition":2,"offset":17,"error_code":null,"error":null}]}"#), Ok("2:17".to_string()));
+        let refused = r#"{"offsets":[{"partition":null,"offset":null,"error_code":50003,"error":"Leader not available"}]}"#;
+        assert_eq!(offset(refused), Err("record refused: Leader not available".to_string()));
+    }
+}
@@ -0,0 +1,76 @@
+//! Email Checker for OpenClaw - library crate.
+//!
+//! The binary in `main.rs` drives these modules; they are kept in a
+//! library so the parsing code can be tested in isolation.
+
+pub mod alert;
+pub mod apprise;
+pub mod archive;
+pub mod audit;
+pub mod auth;
+pub mod bounce;
+pub mod calendar;
+pub mod capabilities;
+pub mod checker;
+pub mod classify;
+pub mod cli;
+pub mod collapse;
+pub mod config;
+pub mod contacts;
+pub mod crypto;
+pub mod diff;
+pub mod digest;
+pub mod deflate;
+pub mod dns;
+pub mod email;
+pub mod expect;
+pub mod grafana;
+pub mod growth;
+pub mod ha;
+pub mod heartbeat;
+pub mod http;
+pub mod imap;
+pub mod jsonl;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod log;
+pub mod mailcow;
+pub mod mdn;
+pub mod man;
+pub mod metrics;
+pub mod mime;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod pipeline;
+pub mod process;
+pub mod purge;
+pub mod push;
+pub mod python;
+pub mod rabbitmq;
+pub mod redact;
+pub mod redis;
+pub mod remote;
+pub mod retention;
+pub mod rules;
+pub mod s3;
+pub mod scan;
+pub mod schema;
+pub mod schedule;
+pub mod secrets;
+pub mod shard;
+pub mod sieve;
+#[cfg(feature = "sentry")]
+pub mod sentry;
+pub mod sink;
+pub mod smtp;
+pub mod summary;
+pub mod toml;
+pub mod sms;
+pub mod sql;
+pub mod state;
+pub mod trace;
+pub mod transport;
+pub mod trigger;
+pub mod unsubscribe;
+pub mod update;
+pub mod watchdog;
@@ -0,0 +1,268 @@
+//! Console logging. Every line goes through the installed redactor.
+//!
+//! With `logging.output` set to `syslog`, lines are sent as RFC 5424
+//! messages to the local syslog socket, or over UDP to `logging.server`,
+//! under `logging.facility` and `logging.tag`, instead of the console.
+//! With `journald`, or by default when systemd runs the checker, they go
+//! to the journal's native socket with the [`field`]s in scope attached,
+//! so `journalctl ACCOUNT=alice@example.com` finds one account's lines.
+
+use std::cell::RefCell;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::redact;
+
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+static SYSLOG: OnceLock<Syslog> = OnceLock::new();
+
+pub const DEFAULT_SOCKET: &str = "/dev/log";
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The field naming the message a line is about; see
+/// [`crate::email::EmailData::correlation_id`].
+pub const CORRELATION_ID: &str = "CORRELATION_ID";
+
+thread_local! {
+    static FIELDS: RefCell<Vec<(&'static str, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Output {
+    #[default]
+    Console,
+    Syslog,
+    Journald,
+}
+
+/// `logging` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub output: Output,
+    /// Syslog facility, e.g. `daemon`, `mail` or `local3`.
+    pub facility: String,
+    /// Syslog APP-NAME.
+    pub tag: String,
+    pub socket: String,
+    /// `host:port` of a syslog server reached over UDP instead of `socket`.
+    pub server: Option<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            output: Output::Console,
+            facility: "daemon".to_string(),
+            tag: "email_checker".to_string(),
+            socket: DEFAULT_SOCKET.to_string(),
+            server: None,
+        }
+    }
+}
+
+/// The code of a syslog facility name.
+pub fn facility(name: &str) -> Option<u8> {
+    const NAMES: [&str; 12] = ["kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp"];
+    if let Some(n) = name.strip_prefix("local").and_then(|n| n.parse::<u8>().ok()).filter(|n| *n < 8) {
+        return Some(16 + n);
+    }
+    NAMES.iter().position(|known| *known == name).map(|code| code as u8)
+}
+
+enum Transport {
+    Socket(UnixDatagram),
+    Udp(UdpSocket),
+    Journal(UnixDatagram),
+}
+
+struct Syslog {
+    transport: Transport,
+    facility: u8,
+    tag: String,
+    hostname: String,
+}
+
+impl Syslog {
+    /// Whether the line was sent; the console takes it otherwise.
+    fn send(&self, severity: u8, message: &str) -> bool {
+        let message = redact::redact(message);
+        let line = || {
+            let time = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+            let message = correlated(&message);
+            format_5424(self.facility, severity, &time, &self.hostname, &self.tag, std::process::id(), &message)
+        };
+        match &self.transport {
+            Transport::Socket(socket) => socket.send(line().as_bytes()).is_ok(),
+            Transport::Udp(socket) => socket.send(line().as_bytes()).is_ok(),
+            Transport::Journal(socket) => {
+                let fields = FIELDS.with(|fields| fields.borrow().clone());
+                socket.send(&journal_entry(self.facility, severity, &self.tag, &message, &fields)).is_ok()
+            }
+        }
+    }
+}
+
+/// One RFC 5424 message, without structured data.
+pub fn format_5424(facility: u8, severity: u8, time: &str, hostname: &str, tag: &str, pid: u32, message: &str) -> String {
+    format!("<{}>1 {} {} {} {} - - {}", facility * 8 + severity, time, hostname, tag, pid, message)
+}
+
+/// One entry in the journal's native protocol. Values spanning lines
+/// are sent length-prefixed.
+pub fn journal_entry(facility: u8, severity: u8, tag: &str, message: &str, fields: &[(&str, String)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let standard = [
+        ("MESSAGE", message.to_string()),
+        ("PRIORITY", severity.to_string()),
+        ("SYSLOG_FACILITY", facility.to_string()),
+        ("SYSLOG_IDENTIFIER", tag.to_string()),
+    ];
+    for (name, value) in standard.iter().map(|(n, v)| (*n, v)).chain(fields.iter().map(|(n, v)| (*n, v))) {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
+
+/// `message`, ending in the correlation ID of the message it is about if
+/// there is one, for outputs without fields to carry it in.
+fn correlated(message: &str) -> String {
+    FIELDS.with(|fields| match fields.borrow().iter().rfind(|(name, _)| *name == CORRELATION_ID) {
+        Some((_, id)) => format!("{} [{}]", message, id),
+        None => message.to_string(),
+    })
+}
+
+/// A journal field attached to lines logged on this thread until the
+/// returned guard drops, e.g. `ACCOUNT` for one account's cycle.
+pub fn field(name: &'static str, value: impl ToString) -> Field {
+    let value = redact::redact(&value.to_string());
+    FIELDS.with(|fields| fields.borrow_mut().push((name, value)));
+    Field(name)
+}
+
+pub struct Field(&'static str);
+
+impl Drop for Field {
+    fn drop(&mut self) {
+        FIELDS.with(|fields| {
+            let mut fields = fields.borrow_mut();
+            if let Some(i) = fields.iter().rposition(|(name, _)| *name == self.0) {
+                fields.remove(i);
+            }
+        });
+    }
+}
+
+/// Switch to the output `config` selects. The console needs nothing,
+/// unless systemd connected it to the journal, which is then used
+/// natively if it can be.
+pub fn install(config: &LoggingConfig) -> Result<(), String> {
+    let facility = facility(&config.facility).ok_or_else(|| format!("unknown syslog facility '{}'", config.facility))?;
+    let journal = || -> Result<Transport, String> {
+        let socket = UnixDatagram::unbound().map_err(|e| e.to_string())?;
+        socket.connect(JOURNAL_SOCKET).map_err(|e| format!("{}: {}", JOURNAL_SOCKET, e))?;
+        Ok(Transport::Journal(socket))
+    };
+    let transport = match (config.output, &config.server) {
+        (Output::Console, _) if std::env::var_os("JOURNAL_STREAM").is_none() => return Ok(()),
+        (Output::Console, _) => match journal() {
+            Ok(transport) => transport,
+            Err(_) => return Ok(()),
+        },
+        (Output::Journald, _) => journal()?,
+        (Output::Syslog, Some(server)) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+            socket.connect(server).map_err(|e| format!("{}: {}", server, e))?;
+            Transport::Udp(socket)
+        }
+        (Output::Syslog, None) => {
+            let socket = UnixDatagram::unbound().map_err(|e| e.to_string())?;
+            socket.connect(&config.socket).map_err(|e| format!("{}: {}", config.socket, e))?;
+            Transport::Socket(socket)
+        }
+    };
+    let hostname = std::fs::read_to_string("/etc/hostname")
+        .map(|h| h.trim().to_string())
+        .ok()
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "-".to_string());
+    let _ = SYSLOG.set(Syslog { transport, facility, tag: config.tag.clone(), hostname });
+    Ok(())
+}
+
+fn syslog(severity: u8, message: &str) -> bool {
+    SYSLOG.get().is_some_and(|syslog| syslog.send(severity, message))
+}
+
+/// Send info lines to stderr too, leaving stdout to a sink's output.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+pub fn info(message: &str) {
+    if syslog(6, message) {
+        return;
+    }
+    let message = correlated(&redact::redact(message));
+    if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+pub fn warn(message: &str) {
+    if !syslog(4, message) {
+        eprintln!("Warning: {}", correlated(&redact::redact(message)));
+    }
+}
+
+pub fn error(message: &str) {
+    if !syslog(3, message) {
+        eprintln!("Error: {}", correlated(&redact::redact(message)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syslog_format() {
+        assert_eq!((facility("daemon"), facility("local3"), facility("local8")), (Some(3), Some(19), None));
+        let line = format_5424(3, 4, "2026-10-14T09:30:00.000+02:00", "nas", "email_checker", 42, "disk full");
+        assert_eq!(line, "<28>1 2026-10-14T09:30:00.000+02:00 nas email_checker 42 - - disk full");
+    }
+
+    #[test]
+    fn test_journal_fields() {
+        let _account = field("ACCOUNT", "alice@example.com");
+        {
+            let _uid = field("UID", 7);
+            let _correlation_id = field(CORRELATION_ID, "5f1c");
+            assert_eq!(correlated("Delivered"), "Delivered [5f1c]");
+        }
+        assert_eq!(correlated("Delivered"), "Delivered");
+        let fields = FIELDS.with(|fields| fields.borrow().clone());
+        assert_eq!(fields, [("ACCOUNT", "alice@example.com".to_string())]);
+        let entry = journal_entry(3, 6, "email_checker", "two\nlines", &fields);
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\nPRIORITY=6\nSYSLOG_FACILITY=3\nSYSLOG_IDENTIFIER=email_checker\nACCOUNT=alice@example.com\n");
+        assert_eq!(entry, expected);
+    }
+}
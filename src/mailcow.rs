@@ -0,0 +1,171 @@
+//! Mailcow admin API.
+//!
+//! `mailcow create-app-password` mints an IMAP-only app password for the
+//! checker's mailbox and stores it through the password's secret
+//! provider, so the primary mailbox password is not needed on the host.
+//! The API also reports the mailbox's quota for servers that do not offer
+//! IMAP QUOTA.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::http;
+use crate::imap::Quota;
+use crate::secrets::SecretString;
+
+pub const DEFAULT_APP_NAME: &str = "email_checker";
+const APP_PASSWORD_LENGTH: usize = 32;
+
+/// `mailcow_api` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MailcowApiConfig {
+    /// Base URL of the Mailcow UI, e.g. `https://mail.example.com`.
+    pub url: String,
+    /// API key with write access (System → Configuration → Access → API).
+    pub api_key: SecretString,
+}
+
+/// Create an app password named `name` for `mailcow_username`, store it via
+/// `mailcow_password_provider` and return it.
+pub fn create_app_password(config: &Config, name: &str) -> Result<String, Box<dyn Error>> {
+    let api = api(config)?;
+    let Some(provider) = &config.mailcow_password_provider else {
+        return Err("mailcow_password must name a secret provider to store the app password in".into());
+    };
+
+    let password = random_password()?;
+    let body = json!({
+        "username": config.mailcow_username,
+        "app_name": name,
+        "app_passwd": password,
+        "app_passwd2": password,
+        "active": "1",
+        "protocols": ["imap_access"],
+    });
+    let url = format!("{}/api/v1/add/app-passwd", api.url.trim_end_matches('/'));
+    let response = http::request(
+        "POST",
+        &url,
+        &[("Content-Type", "application/json"), ("X-API-Key", api.api_key.expose())],
+        Some(&body.to_string()),
+        http::DEFAULT_TIMEOUT,
+    )?;
+    check_response(response.status, &response.body)?;
+    provider.store(&password)?;
+    Ok(password)
+}
+
+/// The `mailcow_api` section, if it is complete and its URL safe to send
+/// the API key to: https, or plain http to this host only.
+fn api(config: &Config) -> Result<&MailcowApiConfig, Box<dyn Error>> {
+    let api = &config.mailcow_api;
+    if api.url.is_empty() || api.api_key.is_empty() {
+        return Err("mailcow_api.url and mailcow_api.api_key must be set".into());
+    }
+    let url = http::Url::parse(&api.url)?;
+    let host = url.host.trim_start_matches('[').trim_end_matches(']');
+    let loopback = host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+    if !url.https && !loopback {
+        return Err("mailcow_api.url must be https:// so the API key is not sent in the clear".into());
+    }
+    Ok(api)
+}
+
+/// The storage quota of the account's mailbox.
+pub fn quota(config: &Config) -> Result<Quota, Box<dyn Error>> {
+    let api = api(config)?;
+    let username = &config.mailcow_username;
+    let url = format!("{}/api/v1/get/mailbox/{}", api.url.trim_end_matches('/'), http::encode_component(username));
+    let response = http::request("GET", &url, &[("X-API-Key", api.api_key.expose())], None, http::DEFAULT_TIMEOUT)?;
+    if !(200..300).contains(&response.status) {
+        return Err(format!("Mailcow API returned HTTP {}: {}", response.status, response.body.trim()).into());
+    }
+    let mailbox: Value = serde_json::from_str(&response.body)?;
+    match (mailbox["quota_used"].as_u64(), mailbox["quota"].as_u64()) {
+        (Some(used), Some(limit)) => Ok(Quota { used, limit }),
+        _ => Err(format!("no quota for {} in the Mailcow response", username).into()),
+    }
+}
+
+/// Mailcow answers 200 with `[{"type": "success" | "danger", "msg": ...}]`.
+fn check_response(status: u16, body: &str) -> Result<(), String> {
+    if !(200..300).contains(&status) {
+        return Err(format!("Mailcow API returned HTTP {}: {}", status, body.trim()));
+    }
+    let value: Value = serde_json::from_str(body).map_err(|_| format!("unexpected Mailcow response: {}", body.trim()))?;
+    let entries = value.as_array().cloned().unwrap_or_else(|| vec![value]);
+    match entries.iter().find(|e| e["type"] != "success") {
+        Some(entry) => Err(format!("Mailcow refused: {}", entry["msg"])),
+        None => Ok(()),
+    }
+}
+
+fn random_password() -> Result<String, Box<dyn Error>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz23456789";
+    let mut bytes = [0u8; APP_PASSWORD_LENGTH];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .map_err(|e| format!("no randomness for the app password: {}", e))?;
+    Ok(bytes.iter().map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_check_response() {
+        assert!(check_response(200, r#"[{"type": "success", "msg": ["app_passwd_added"]}]"#).is_ok());
+        let err = check_response(200, r#"[{"type": "danger", "msg": "access_denied"}]"#).unwrap_err();
+        assert!(err.contains("access_denied"));
+        assert!(check_response(401, "").is_err());
+        let password = random_password().unwrap();
+        assert_eq!(password.len(), APP_PASSWORD_LENGTH);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn test_api_key_only_over_https_or_loopback() {
+        let mut config = Config::default();
+        for (url, allowed) in [
+            ("https://mail.example.com", true),
+            ("http://127.0.0.1:8080", true),
+            ("http://localhost", true),
+            ("http://mail.example.com", false),
+        ] {
+            config.mailcow_api = MailcowApiConfig { url: url.to_string(), api_key: "k3y".into() };
+            assert_eq!(api(&config).is_ok(), allowed, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_quota_encodes_the_username() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap();
+            let body = r#"{"quota_used": 10, "quota": 100}"#;
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+        let config = Config {
+            mailcow_username: "../admin@example.com".to_string(),
+            mailcow_api: MailcowApiConfig { url: format!("http://127.0.0.1:{}", port), api_key: "k3y".into() },
+            ..Config::default()
+        };
+        assert_eq!(quota(&config).unwrap(), Quota { used: 10, limit: 100 });
+        assert!(server.join().unwrap().starts_with("GET /api/v1/get/mailbox/..%2Fadmin%40example.com HTTP/1.1"));
+    }
+}
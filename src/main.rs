@@ -4,157 +4,873 @@
 //!
 //! Usage:
 //!   cargo run --release -- --once
+//!   cargo run --release -- --shadow [--allow-unpinned]
 //!   cargo run --release
+//!   cargo run --release -- --config email_checker.json
+//!   cargo run --release -- --config email_checker.json --profile staging
+//!   cargo run --release -- --config email_checker.json --strict-config
+//!   cargo run --release -- --lenient
+//!   cargo run --release -- --shard 2/5
+//!   cargo run --release -- search invoice 4711
+//!   cargo run --release -- prune
+//!   cargo run --release -- purge --sender alice@example.com [--dry-run]
+//!   cargo run --release -- state export --output state.json
+//!   cargo run --release -- stats
+//!   cargo run --release -- unsubscribe report
+//!   cargo run --release -- audit verify
+//!   cargo run --release -- config schema > email_checker.schema.json
+//!   cargo run --release -- config show [--format json|toml]
+//!   cargo run --release -- generate grafana-dashboard > dashboard.json
+//!   cargo run --release -- generate man > email_checker.1
+//!   cargo run --release -- train examples/ --output model.json
+//!   cargo run --release -- rules test [--message sample.eml]
+//!   cargo run --release -- sieve show | sieve push
+//!   cargo run --release -- migrate --from-python ~/.openclaw/workspace/
+//!   cargo run --release -- mailcow create-app-password
+//!   cargo run --release -- folders list
+//!   cargo run --release -- imap capabilities
+//!   cargo run --release -- expect add --subject "confirm your account" --within 30m
+//!   cargo run --release -- self-update [--check]
+//!   cargo run --release -- completions bash > /etc/bash_completion.d/email_checker
 
 use std::env;
-use std::error::Error;
-use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
 use std::thread;
-use std::time::Duration;
-
-use serde::Deserialize;
-
-const DEFAULT_CHECK_INTERVAL: usize = 300;
-const DEFAULT_OPENCLAW_PORT: usize = 18789;
-
-#[derive(Debug, Deserialize, Clone)]
-struct Config {
-    mailcow_imap_host: String,
-    mailcow_imap_port: usize,
-    mailcow_username: String,
-    mailcow_password: String,
-    openclaw_gateway: String,
-    openclaw_port: usize,
-    check_interval: usize,
-    last_check_file: String,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            mailcow_imap_host: "localhost".to_string(),
-            mailcow_imap_port: 993,
-            mailcow_username: "".to_string(),
-            mailcow_password: "".to_string(),
-            openclaw_gateway: "localhost".to_string(),
-            openclaw_port: DEFAULT_OPENCLAW_PORT,
-            check_interval: DEFAULT_CHECK_INTERVAL,
-            last_check_file: "/home/hijirii/.openclaw/workspace/.last_email_check".to_string(),
-        }
-    }
-}
-
-#[derive(Debug)]
-struct EmailData {
-    subject: String,
-    from: String,
-    date: String,
-    body: String,
-}
-
-impl EmailData {
-    fn to_openclaw_message(&self) -> String {
-        let preview = if self.body.len() > 500 {
-            &self.body[..500]
-        } else {
-            &self.body
+use std::time::{Duration, Instant};
+
+use email_checker::checker::Checker;
+use email_checker::email::EmailData;
+use email_checker::expect::{self, Expectation};
+use email_checker::rules::Conditions;
+use email_checker::config::{self, load_config, load_config_unresolved, Backend, Config};
+use email_checker::state::{self, State};
+use email_checker::schedule::{self, Schedule};
+use email_checker::shard::{Shard, SHARD_ENV};
+use email_checker::{
+    archive, audit, capabilities, classify, cli, grafana, heartbeat, jsonl, log, mailcow, man, metrics, pipeline, purge,
+    python, redact, retention, rules, schema, sieve, sink, toml, update,
+};
+
+fn print_config(config: &Config) {
+    log::info("Email Checker Configuration:");
+    if let Some(profile) = &config.profile {
+        log::info(&format!("  Profile:        {}", profile));
+    }
+    log::info(&format!("  IMAP Host:     {}", config.mailcow_imap_host));
+    log::info(&format!("  IMAP Port:     {}", config.mailcow_imap_port));
+    log::info(&format!("  Username:       {}", config.mailcow_username));
+    log::info("  Password:       [SET]");
+    if config.openclaw_gateways.is_empty() {
+        log::info(&format!("  OpenClaw:       {}:{}", config.openclaw_gateway, config.openclaw_port));
+    } else {
+        let gateways: Vec<&str> = config.openclaw_gateways.iter().map(|g| g.gateway.as_str()).collect();
+        log::info(&format!("  OpenClaw:       {} (failover)", gateways.join(", ")));
+    }
+    log::info(&format!("  Interval:       {} seconds", config.check_interval));
+    if config.adaptive.enabled {
+        log::info(&format!(
+            "  Adaptive:       {}-{} seconds",
+            config.adaptive.min_interval, config.adaptive.max_interval
+        ));
+    }
+    log::info(&format!("  Last check:     {}", config.last_check_file));
+    log::info(&format!("  Rules:          {}", config.rules.len()));
+    if !config.accounts.is_empty() {
+        log::info(&format!("  Accounts:       {}", config.accounts.len()));
+    }
+    if let Some(maildir) = &config.archive.maildir {
+        log::info(&format!("  Archive:        {}", maildir));
+    }
+    log::info(&format!(
+        "  Sinks:          {}",
+        sink::build(config).keys().cloned().collect::<Vec<_>>().join(", ")
+    ));
+}
+
+/// Value following `flag` on the command line, if present.
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+/// Command-line words that are not flags or flag values.
+fn positional(args: &[String]) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if cli::takes_value(arg) {
+            iter.next();
+        } else if !arg.starts_with("--") {
+            words.push(arg.as_str());
+        }
+    }
+    words
+}
+
+/// `search <terms...> [--limit N]`: look up archived messages.
+fn search(config: &Config, args: &[String], terms: &[&str]) -> Result<(), String> {
+    let terms: Vec<String> = terms.iter().map(|t| t.to_string()).collect();
+    if terms.is_empty() {
+        return Err("usage: email_checker search <terms...> [--limit N]".to_string());
+    }
+    let limit = match arg_value(args, "--limit") {
+        Some(value) => value.parse().map_err(|_| format!("invalid --limit '{}'", value))?,
+        None => 20,
+    };
+    let Some(maildir) = &config.archive.maildir else {
+        if config.archive.notmuch {
+            return archive::notmuch_search(&config.archive, &terms).map_err(|e| e.to_string());
+        }
+        return Err("no archive configured; set archive.maildir".to_string());
+    };
+    let hits = archive::search(Path::new(maildir), &terms, limit).map_err(|e| e.to_string())?;
+    for hit in &hits {
+        log::info(&format!("{}  {}  {}", hit.date, hit.from, hit.subject));
+        log::info(&format!("    {}", hit.path.display()));
+    }
+    log::info(&format!("{} matching message(s)", hits.len()));
+    Ok(())
+}
+
+/// `prune`: apply retention limits now.
+fn prune(config: &Config) -> Result<(), String> {
+    if !config.retention.is_enabled() {
+        return Err("no retention configured; set retention.retain or retention.max_size".to_string());
+    }
+    let mut state = if config.state_file.is_empty() {
+        State::default()
+    } else {
+        load_state(config)?
+    };
+    let pruned = retention::prune(config, &mut state).map_err(|e| e.to_string())?;
+    log::info(&format!(
+        "Pruned {} archived messages ({} bytes) and {} state entries",
+        pruned.messages, pruned.bytes, pruned.state_entries
+    ));
+    Ok(())
+}
+
+/// `purge --sender ADDRESS [--dry-run]`: delete everything kept locally
+/// about one sender.
+fn purge(config: &Config, args: &[String]) -> Result<(), String> {
+    let Some(sender) = arg_value(args, "--sender") else {
+        return Err("usage: email_checker purge --sender ADDRESS [--dry-run]".to_string());
+    };
+    let dry_run = args.contains(&"--dry-run".to_string());
+    let mut state = if config.state_file.is_empty() {
+        State::default()
+    } else {
+        load_state(config)?
+    };
+    let purged = purge::purge_sender(config, &mut state, sender, dry_run).map_err(|e| e.to_string())?;
+    log::info(&format!(
+        "{} {} archived, {} quarantined and {} indexed messages ({}) and {} state entries",
+        if dry_run { "Would delete" } else { "Deleted" },
+        purged.archived,
+        purged.quarantined,
+        purged.indexed,
+        retention::format_size(purged.bytes),
+        purged.state_entries
+    ));
+    Ok(())
+}
+
+fn load_state(config: &Config) -> Result<State, String> {
+    if config.state_file.is_empty() {
+        return Err("no state_file configured".to_string());
+    }
+    State::load(&config.state_file).map_err(|e| e.to_string())
+}
+
+/// `state export [--output FILE]` and `state import FILE`.
+fn state(config: &Config, args: &[String], words: &[&str]) -> Result<(), String> {
+    let mut state = load_state(config)?;
+    match words {
+        ["export"] => {
+            let json = serde_json::to_string_pretty(&state).map_err(|e| e.to_string())?;
+            // Printed directly: log redaction would mangle Message-IDs.
+            match arg_value(args, "--output") {
+                Some(path) => std::fs::write(path, json + "\n").map_err(|e| format!("{}: {}", path, e))?,
+                None => println!("{}", json),
+            }
+            Ok(())
+        }
+        ["import", path] => {
+            if !Path::new(path).exists() {
+                return Err(format!("{}: no such file", path));
+            }
+            let imported = State::load(path).map_err(|e| e.to_string())?;
+            state.merge(imported);
+            state.save(&config.state_file).map_err(|e| e.to_string())?;
+            log::info(&format!("Imported state from {} into {}", path, config.state_file));
+            Ok(())
+        }
+        _ => Err("usage: email_checker state export [--output FILE] | state import FILE".to_string()),
+    }
+}
+
+/// `stats`: traffic per account and day, and deliveries per rule, from
+/// the state file.
+fn stats(config: &Config) -> Result<(), String> {
+    let mut state = load_state(config)?;
+    if state.bandwidth.is_empty() {
+        log::info("No traffic recorded yet");
+    }
+    for (account, days) in &state.bandwidth {
+        log::info(&format!("{}:", account));
+        log::info("  Day          Downloaded    Uploaded");
+        let mut total = (0, 0);
+        for (day, traffic) in days {
+            log::info(&format!(
+                "  {}  {:>10}  {:>10}",
+                day,
+                retention::format_size(traffic.downloaded),
+                retention::format_size(traffic.uploaded)
+            ));
+            total = (total.0 + traffic.downloaded, total.1 + traffic.uploaded);
+        }
+        log::info(&format!(
+            "  Total       {:>10}  {:>10}",
+            retention::format_size(total.0),
+            retention::format_size(total.1)
+        ));
+    }
+    if config.rules.is_empty() {
+        return Ok(());
+    }
+    let now = chrono::Utc::now().timestamp();
+    let names: Vec<&str> = config.rules.iter().map(|r| r.name.as_str()).collect();
+    state.track_rules(&names, now);
+    let unused = match config.unused_rule_days {
+        0 => Vec::new(),
+        days => state.unused_rules(now - days as i64 * 86400),
+    };
+    log::info("Rules:");
+    log::info("  Rule                  Matches  Last match");
+    for name in names {
+        let hits = state.rule_hits[name];
+        let last = hits
+            .last_match
+            .and_then(|at| chrono::DateTime::from_timestamp(at, 0))
+            .map_or("never".to_string(), |at| at.format("%Y-%m-%d").to_string());
+        let flag = if unused.contains(&name) { "  (unused)" } else { "" };
+        log::info(&format!("  {:<20}  {:>7}  {}{}", name, hits.matches, last, flag));
+    }
+    Ok(())
+}
+
+/// `unsubscribe report`: the lists rules unsubscribed from, or would
+/// have in a dry run.
+fn unsubscribe(config: &Config, words: &[&str]) -> Result<(), String> {
+    if words != ["report"] {
+        return Err("usage: email_checker unsubscribe report".to_string());
+    }
+    let state = load_state(config)?;
+    if state.unsubscribed.is_empty() {
+        log::info("No lists unsubscribed from yet");
+    }
+    for (list, done) in &state.unsubscribed {
+        let at = chrono::DateTime::from_timestamp(done.at, 0)
+            .map_or(String::new(), |at| at.format("%Y-%m-%d").to_string());
+        let what = if done.dry_run { "would unsubscribe" } else { "unsubscribed" };
+        log::info(&format!("  {}  {} by {}: {}", at, what, done.via, list));
+    }
+    Ok(())
+}
+
+/// `audit verify [FILE]`: check the audit log's hash chain.
+fn audit(config: &Config, words: &[&str]) -> Result<(), String> {
+    let path = match words {
+        ["verify"] => config.audit_log.as_deref().ok_or("no audit_log configured")?,
+        ["verify", path] => path,
+        _ => return Err("usage: email_checker audit verify [FILE]".to_string()),
+    };
+    let (entries, last_hash) = audit::verify(Path::new(path)).map_err(|e| format!("{}: chain broken at {}", path, e))?;
+    log::info(&format!("{}: {} entries, chain intact; last hash {}", path, entries, last_hash));
+    Ok(())
+}
+
+/// `train DIR [--output FILE]`: build the classification model from a
+/// directory of labelled examples.
+fn train(config: &Config, args: &[String], words: &[&str]) -> Result<(), String> {
+    let [dir] = words else {
+        return Err("usage: email_checker train DIR [--output FILE]".to_string());
+    };
+    let output = arg_value(args, "--output")
+        .or(config.classify.model.as_deref())
+        .ok_or("no --output given and no classify.model configured")?;
+    let (model, read) = classify::train(Path::new(dir)).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string(&model).map_err(|e| e.to_string())?;
+    std::fs::write(output, json).map_err(|e| format!("{}: {}", output, e))?;
+    let labels: Vec<&str> = model.labels.keys().map(String::as_str).collect();
+    log::info(&format!("Trained on {} messages labelled {}; model written to {}", read, labels.join(", "), output));
+    Ok(())
+}
+
+/// `rules test [--message FILE]`: show which rules a message matches, the
+/// route chosen and the payload sent. Without `--message`, edit a message
+/// interactively and see the result after every change.
+fn rules_test(config: &Config, args: &[String], words: &[&str]) -> Result<(), String> {
+    if words != ["test"] {
+        return Err("usage: email_checker rules test [--message FILE]".to_string());
+    }
+    let mut config = config.clone();
+    let read = |config: &Config, path: &str| -> Result<EmailData, String> {
+        let raw = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(pipeline::process(&raw, &config.pipeline, &config.decryption).1)
+    };
+    if let Some(path) = arg_value(args, "--message") {
+        print!("{}", rules::explain(&config.rules, &read(&config, path)?, &config.payload));
+        return Ok(());
+    }
+    const HELP: &str = "Commands:
+  load FILE            read a message from FILE
+  from|subject|body TEXT
+                       set that field
+  header NAME: VALUE   set a header
+  reload               read the rules from the config file again
+  show                 print the result again
+  quit";
+    println!("{}", HELP);
+    let mut email = EmailData::from_raw(b"");
+    let stdin = io::stdin();
+    loop {
+        print!("rules> ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(());
+        }
+        let (command, text) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let text = text.trim().to_string();
+        match command {
+            "" => continue,
+            "load" => match read(&config, &text) {
+                Ok(loaded) => email = loaded,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            },
+            "from" => email.from = text,
+            "subject" => email.subject = text,
+            "body" => email.body = text,
+            "header" => {
+                let Some((name, value)) = text.split_once(':') else {
+                    println!("usage: header NAME: VALUE");
+                    continue;
+                };
+                let (name, value) = (name.trim().to_string(), value.trim().to_string());
+                email.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+                email.headers.push((name, value));
+            }
+            "reload" => match load_config(arg_value(args, "--config"), arg_value(args, "--profile")) {
+                Ok(reloaded) => config = reloaded,
+                Err(e) => {
+                    println!("invalid configuration: {}", e);
+                    continue;
+                }
+            },
+            "show" => {}
+            "quit" | "exit" => return Ok(()),
+            _ => {
+                println!("{}", HELP);
+                continue;
+            }
+        }
+        print!("{}", rules::explain(&config.rules, &email, &config.payload));
+    }
+}
+
+/// `sieve show` and `sieve push`: print the Sieve script compiled from
+/// the rules, or upload it and make it active.
+fn sieve(config: &Config, words: &[&str]) -> Result<(), String> {
+    match words {
+        ["show"] => {
+            print!("{}", sieve::compile(&config.rules, &config.sieve.unmatched_folder));
+            Ok(())
+        }
+        ["push"] => {
+            sieve::push(config).map_err(|e| format!("could not upload the Sieve script: {}", e))?;
+            log::info(&format!(
+                "Uploaded {} rules as Sieve script '{}'; unmatched mail goes to {}",
+                config.rules.len(),
+                config.sieve.script,
+                config.sieve.unmatched_folder
+            ));
+            Ok(())
+        }
+        _ => Err("usage: email_checker sieve show | sieve push".to_string()),
+    }
+}
+
+/// `config show [--format json|toml]`: the effective config, defaults
+/// overridden by the files and the environment, with credentials masked.
+fn show_config(config: &Config, args: &[String], words: &[&str]) -> Result<(), String> {
+    if words != ["show"] {
+        return Err("usage: email_checker config show [--format json|toml]".to_string());
+    }
+    match arg_value(args, "--format").unwrap_or("json") {
+        "json" => println!("{}", config),
+        "toml" => print!("{}", toml::to_string(&config.masked())?),
+        other => return Err(format!("unknown format '{}'; expected json or toml", other)),
+    }
+    Ok(())
+}
+
+/// `migrate --from-python PATH`: seed the state from the Python checker's
+/// last check time, so mail it already handled is not searched again.
+fn migrate(config: &Config, args: &[String]) -> Result<(), String> {
+    let Some(path) = arg_value(args, "--from-python") else {
+        return Err("usage: email_checker migrate --from-python PATH".to_string());
+    };
+    let mut state = load_state(config)?;
+    let since = state::python_last_check(Path::new(path)).map_err(|e| e.to_string())?;
+    state.since = state.since.max(Some(since));
+    state.save(&config.state_file).map_err(|e| e.to_string())?;
+    log::info(&format!("Migrated Python state: only mail since {} will be checked", since));
+    Ok(())
+}
+
+/// `--shadow`: compare what the native checker and the Python checker
+/// would forward right now, without forwarding anything. Returns whether
+/// they agree.
+fn shadow(checker: &mut Checker) -> Result<bool, String> {
+    let rust: Vec<python::Forward> = checker
+        .preview()
+        .map_err(|e| format!("Rust preview failed: {}", e))?
+        .iter()
+        .map(python::Forward::from_email)
+        .collect();
+    let py = python::shadow(&checker.config).map_err(|e| format!("Python preview failed: {}", e))?;
+    let divergences = python::compare(&rust, &py);
+    for divergence in &divergences {
+        log::warn(divergence);
+    }
+    log::info(&format!(
+        "Shadow run: Rust would forward {}, Python {}; {} divergence(s)",
+        rust.len(),
+        py.len(),
+        divergences.len()
+    ));
+    Ok(divergences.is_empty())
+}
+
+/// Delegate every cycle to the Python checker. With `--once` its exit
+/// code becomes ours.
+fn run_python(checker: &mut Checker, run_once: bool) -> ! {
+    let config = checker.config.clone();
+    log::info(&format!("Backend: Python ({} {})", config.python.interpreter, config.python.script));
+    let mut schedule = Schedule::new(&config);
+    let mut interval = Duration::from_secs(config.check_interval as u64);
+    loop {
+        if !checker.lead(interval) {
+            if run_once {
+                std::process::exit(0);
+            }
+            thread::sleep(Duration::from_secs(config.ha.standby_poll.max(1)));
+            continue;
+        }
+        let mut delivered = None;
+        let code = match python::run_once(&config, &mut checker.state) {
+            Ok(outcome) => {
+                delivered = Some(outcome.delivered());
+                log::info(&format!(
+                    "Python checker delivered {} of {} messages",
+                    outcome.delivered(),
+                    outcome.results.len()
+                ));
+                outcome.code
+            }
+            Err(e) => {
+                log::error(&e.to_string());
+                1
+            }
         };
-        format!(
-            "📧 New Email\n\nFrom: {}\nSubject: {}\nDate: {}\n\nPreview:\n{}",
-            self.from, self.subject, self.date, preview
-        )
+        after_cycle(&config, code == 0);
+        if run_once {
+            std::process::exit(code);
+        }
+        if code != 0 {
+            log::error(&format!("Python checker exited with status {}", code));
+        }
+        interval = schedule.next(delivered);
+        checker.lead(interval);
+        log::info(&format!("\nSleeping for {} seconds...", interval.as_secs()));
+        thread::sleep(interval);
     }
 }
 
-fn load_config() -> Config {
-    let mut config = Config::default();
-    
-    if let Ok(host) = env::var("MAILCOW_IMAP_HOST") {
-        config.mailcow_imap_host = host;
+/// A checker for every account this instance polls, with their secrets
+/// fetched and added to the log redaction.
+fn build_checkers(config: &Config, shard: Option<Shard>) -> Result<Vec<Checker>, String> {
+    let mut redactor = config.redactor();
+    let mut checkers = Vec::new();
+    for mut account in config.accounts().map_err(|e| e.to_string())? {
+        let name = account.mailcow_username.clone();
+        if shard.is_some_and(|shard| !shard.owns(&name)) {
+            continue;
+        }
+        account.resolve_secrets().map_err(|e| format!("account {}: {}", name, e))?;
+        if account.mailcow_password.is_empty() {
+            return Err(format!("account {} has no password", name));
+        }
+        redactor.add_secret(account.mailcow_password.expose());
+        checkers.push(Checker::new(account));
+    }
+    redact::install(redactor);
+    Ok(checkers)
+}
+
+/// Run one cycle for each account whose lease this instance holds.
+/// Returns the messages delivered, or `None` if any cycle failed.
+fn check_all(checkers: &mut [Checker], hold: Duration) -> Option<usize> {
+    let many = checkers.len() > 1;
+    let mut delivered = Some(0);
+    for checker in checkers.iter_mut() {
+        if !checker.lead(hold) {
+            continue;
+        }
+        checker.refresh_secrets_if_due();
+        checker.prune_if_due();
+        checker.warn_unused_rules_if_due();
+        match checker.run_once() {
+            Ok(count) => delivered = delivered.map(|d| d + count),
+            Err(e) if many => {
+                log::error(&format!("Error checking emails for {}: {}", checker.config.mailcow_username, e));
+                delivered = None;
+            }
+            Err(e) => {
+                log::error(&format!("Error checking emails: {}", e));
+                delivered = None;
+            }
+        }
     }
-    if let Ok(port) = env::var("MAILCOW_IMAP_PORT") {
-        config.mailcow_imap_port = port.parse().unwrap_or(993);
+    delivered
+}
+
+/// Write the metrics file and ping the heartbeat monitor.
+fn after_cycle(config: &Config, ok: bool) {
+    heartbeat::ping(&config.heartbeat, ok);
+    if let Some(path) = &config.metrics_file {
+        if let Err(e) = metrics::write(path) {
+            log::warn(&format!("could not write metrics to {}: {}", path, e));
+        }
+    }
+}
+
+/// `mailcow create-app-password [--name NAME]`.
+fn mailcow(config: &Config, args: &[String], words: &[&str]) -> Result<(), String> {
+    if words != ["create-app-password"] {
+        return Err("usage: email_checker mailcow create-app-password [--name NAME]".to_string());
     }
-    if let Ok(username) = env::var("MAILCOW_USERNAME") {
-        config.mailcow_username = username;
+    let name = arg_value(args, "--name").unwrap_or(mailcow::DEFAULT_APP_NAME);
+    mailcow::create_app_password(config, name).map_err(|e| e.to_string())?;
+    log::info(&format!(
+        "Created app password '{}' for {} and stored it with the secret provider",
+        name, config.mailcow_username
+    ));
+    Ok(())
+}
+
+/// `folders list`: print each account's folders as `folders` names them,
+/// on stdout so log redaction leaves address-like shared folders alone.
+fn folders(config: &Config, words: &[&str]) -> Result<(), String> {
+    if words != ["list"] {
+        return Err("usage: email_checker folders list".to_string());
     }
-    if let Ok(password) = env::var("MAILCOW_PASSWORD") {
-        config.mailcow_password = password;
+    let mut checkers = build_checkers(config, None)?;
+    let many = checkers.len() > 1;
+    for checker in &mut checkers {
+        if many {
+            println!("{}:", checker.config.mailcow_username);
+        }
+        let folders = checker.list_folders().map_err(|e| format!("could not list folders: {}", e))?;
+        for folder in folders {
+            let indent = if many { "  " } else { "" };
+            let note = if folder.is_selectable() { "" } else { " (not selectable)" };
+            println!("{}{}{}", indent, folder.name, note);
+        }
     }
-    if let Ok(gateway) = env::var("OPENCLAW_GATEWAY") {
-        config.openclaw_gateway = gateway;
+    Ok(())
+}
+
+/// `imap capabilities`: what each account's server offers, and what the
+/// checker makes of it.
+fn imap(config: &Config, words: &[&str]) -> Result<(), String> {
+    if words != ["capabilities"] {
+        return Err("usage: email_checker imap capabilities".to_string());
     }
-    if let Ok(port) = env::var("OPENCLAW_PORT") {
-        config.openclaw_port = port.parse().unwrap_or(DEFAULT_OPENCLAW_PORT);
+    let mut checkers = build_checkers(config, None)?;
+    let many = checkers.len() > 1;
+    for checker in &mut checkers {
+        if many {
+            println!("{}:", checker.config.mailcow_username);
+        }
+        let (before, after) = checker.capabilities().map_err(|e| format!("could not read capabilities: {}", e))?;
+        let indent = if many { "  " } else { "" };
+        println!("{}{}", indent, after.join(" "));
+        for line in capabilities::report(&before, &after, checker.config.auth_mechanism) {
+            println!("{}  {}", indent, line);
+        }
     }
-    if let Ok(interval) = env::var("CHECK_INTERVAL") {
-        config.check_interval = interval.parse().unwrap_or(DEFAULT_CHECK_INTERVAL);
+    Ok(())
+}
+
+/// `expect add --within DURATION [--from TEXT] [--subject TEXT] [--body TEXT]`
+/// registers mail that should arrive and prints its id; `expect list`
+/// shows the open expectations.
+fn expect(config: &Config, args: &[String], words: &[&str]) -> Result<(), String> {
+    let Some(dir) = &config.expectations.dir else {
+        return Err("expectations.dir is not configured".to_string());
+    };
+    let now = chrono::Utc::now().timestamp();
+    match words {
+        ["add"] => {
+            let usage = "usage: email_checker expect add --within DURATION [--from TEXT] [--subject TEXT] \
+                         [--body TEXT]";
+            let within = arg_value(args, "--within").ok_or(usage)?;
+            let within = retention::parse_duration(within)?.as_secs() as i64;
+            let conditions = Conditions {
+                from: arg_value(args, "--from").map(str::to_string),
+                subject: arg_value(args, "--subject").map(str::to_string),
+                body: arg_value(args, "--body").map(str::to_string),
+                ..Conditions::default()
+            };
+            let expectation = Expectation::new(conditions, now, now + within);
+            expect::save(dir, &expectation).map_err(|e| format!("could not save the expectation: {}", e))?;
+            println!("{}", expectation.id);
+            Ok(())
+        }
+        ["list"] => {
+            let (expectations, errors) = expect::load(dir).map_err(|e| format!("{}: {}", dir, e))?;
+            for error in errors {
+                log::warn(&format!("could not read expectation {}", error));
+            }
+            for expectation in expectations {
+                let status = match &expectation.outcome {
+                    Some(outcome) => format!("{}, not yet reported", outcome.status.name()),
+                    None => format!("{}s left", (expectation.deadline - now).max(0)),
+                };
+                let conditions = serde_json::to_string(&expectation.conditions).unwrap_or_default();
+                println!("{}  {}  {}", expectation.id, status, conditions);
+            }
+            Ok(())
+        }
+        _ => Err("usage: email_checker expect add|list".to_string()),
     }
-    
-    config
 }
 
-fn print_config(config: &Config) {
-    println!("Email Checker Configuration:");
-    println!("  IMAP Host:     {}", config.mailcow_imap_host);
-    println!("  IMAP Port:     {}", config.mailcow_imap_port);
-    println!("  Username:       {}", config.mailcow_username);
-    println!("  Password:       [SET]");
-    println!("  OpenClaw:       {}:{}", config.openclaw_gateway, config.openclaw_port);
-    println!("  Interval:       {} seconds", config.check_interval);
+/// `self-update [--check]`: install the latest signed release.
+fn self_update(config: &Config, args: &[String]) -> Result<(), String> {
+    let release = update::latest(&config.update).map_err(|e| format!("could not check for updates: {}", e))?;
+    if !update::is_newer(&release.version, update::VERSION) {
+        log::info(&format!("email_checker {} is up to date", update::VERSION));
+        return Ok(());
+    }
+    if args.contains(&"--check".to_string()) {
+        log::info(&format!("email_checker {} is available (running {})", release.version, update::VERSION));
+        return Ok(());
+    }
+    let exe = env::current_exe().map_err(|e| format!("could not locate the running binary: {}", e))?;
+    update::install(&config.update, &release, &exe, update::VERSION).map_err(|e| e.to_string())?;
+    log::info(&format!("Updated {} from {} to {}; restart to use it", exe.display(), update::VERSION, release.version));
+    Ok(())
 }
 
 fn main() {
-    println!("Email Checker for OpenClaw (Rust)");
-    println!("===================================\n");
-    
     let args: Vec<String> = env::args().collect();
-    let _run_once = args.contains(&"--once".to_string());
     
-    let config = load_config();
+    let words = positional(&args);
+    // The schema describes every config, so it must not need a valid one.
+    if words == ["config", "schema"] {
+        println!("{}", serde_json::to_string_pretty(&schema::config_schema()).unwrap_or_default());
+        return;
+    }
+    // Likewise the dashboard and man page, generated from the binary itself.
+    if words == ["generate", "grafana-dashboard"] {
+        println!("{}", serde_json::to_string_pretty(&grafana::dashboard()).unwrap_or_default());
+        return;
+    }
+    if words == ["generate", "man"] {
+        print!("{}", man::page());
+        return;
+    }
+    if let ["completions", shell] = words[..] {
+        match cli::completions(shell) {
+            Ok(script) => print!("{}", script),
+            Err(e) => {
+                log::error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // Creating the app password must not require fetching it first,
+    // showing the config masks it anyway, and expectations need none.
+    config::set_strict(args.contains(&"--strict-config".to_string()));
+    config::set_lenient(args.contains(&"--lenient".to_string()));
+    let load = if matches!(words.first(), Some(&"mailcow" | &"config" | &"expect")) {
+        load_config_unresolved
+    } else { load_config };
+    let mut config = match load(arg_value(&args, "--config"), arg_value(&args, "--profile")) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error(&format!("invalid configuration: {}", e));
+            std::process::exit(1);
+        }
+    };
+    redact::install(config.redactor());
+    if jsonl::uses_stdout(&config) {
+        log::reserve_stdout();
+    }
+    if let Err(e) = log::install(&config.logging) {
+        log::error(&format!("could not open syslog: {}", e));
+        std::process::exit(1);
+    }
+    metrics::configure(&config.metrics);
+    #[cfg(feature = "sentry")]
+    if let Err(e) = email_checker::sentry::init(&config) {
+        log::error(&format!("invalid configuration: {}", e));
+        std::process::exit(1);
+    }
+
+    if let Some((&command, rest)) = words.split_first() {
+        let result = match command {
+            "search" => search(&config, &args, rest),
+            "prune" => prune(&config),
+            "purge" => purge(&config, &args),
+            "state" => state(&config, &args, rest),
+            "stats" => stats(&config),
+            "unsubscribe" => unsubscribe(&config, rest),
+            "audit" => audit(&config, rest),
+            "config" => show_config(&config, &args, rest),
+            "migrate" => migrate(&config, &args),
+            "train" => train(&config, &args, rest),
+            "rules" => rules_test(&config, &args, rest),
+            "sieve" => sieve(&config, rest),
+            "mailcow" => mailcow(&config, &args, rest),
+            "folders" => folders(&config, rest),
+            "imap" => imap(&config, rest),
+            "expect" => expect(&config, &args, rest),
+            "self-update" => self_update(&config, &args),
+            other => Err(format!("unknown command '{}'", other)),
+        };
+        if let Err(e) = result {
+            log::error(&e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    log::info("Email Checker for OpenClaw (Rust)");
+    log::info("===================================\n");
     print_config(&config);
-    println!();
+    log::info("");
     
-    if config.mailcow_password.is_empty() {
+    if config.accounts.is_empty() && config.mailcow_password.is_empty() {
         eprintln!("Error: MAILCOW_PASSWORD not set!");
         eprintln!("Please set the environment variable:");
         eprintln!("  export MAILCOW_PASSWORD=\"your-password\"");
         std::process::exit(1);
     }
     
-    // Call Python for actual IMAP (full Rust IMAP needs more work)
-    println!("Note: Using Python implementation for IMAP functionality.");
-    println!("      Full Rust IMAP implementation in development.\n");
-    
-    println!("Continuous mode: Checking every {} seconds", config.check_interval);
-    println!("Press Ctrl+C to stop.\n");
-    
-    loop {
-        thread::sleep(Duration::from_secs(config.check_interval as u64));
+    let run_once = args.contains(&"--once".to_string());
+    let shard = arg_value(&args, "--shard").map(str::to_string).or_else(|| env::var(SHARD_ENV).ok());
+    let shard = match shard.as_deref().map(Shard::parse).transpose() {
+        Ok(shard) => shard,
+        Err(e) => {
+            log::error(&e);
+            std::process::exit(1);
+        }
+    };
+    let mut checkers = match build_checkers(&config, shard) {
+        Ok(checkers) => checkers,
+        Err(e) => {
+            log::error(&format!("invalid configuration: {}", e));
+            std::process::exit(1);
+        }
+    };
+    if checkers.is_empty() {
+        log::info("No account falls into this shard");
+    }
+    for checker in checkers.iter().filter(|c| c.config.sieve.push_on_start) {
+        match sieve::push(&checker.config) {
+            Ok(()) => log::info(&format!("Uploaded Sieve script '{}'", checker.config.sieve.script)),
+            Err(e) => log::warn(&format!("could not upload the Sieve script: {}", e)),
+        }
+    }
+
+    if args.contains(&"--shadow".to_string()) || config.backend == Backend::Python {
+        let [checker] = checkers.as_mut_slice() else {
+            log::error("--shadow and the Python backend need exactly one account");
+            std::process::exit(1);
+        };
+        checker.config.python.allow_unpinned = args.contains(&"--allow-unpinned".to_string());
+        if config.backend == Backend::Python {
+            run_python(checker, run_once);
+        }
+        match shadow(checker) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(2),
+            Err(e) => {
+                log::error(&e);
+                std::process::exit(1);
+            }
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    let accounts: Vec<&str> = checkers.iter().map(|c| c.config.mailcow_username.as_str()).collect();
+    let delay = schedule::start_delay(&config, &accounts);
+    if !delay.is_zero() {
+        log::info(&format!("Starting the first check in {} seconds", delay.as_secs()));
+        thread::sleep(delay);
+    }
 
-    #[test]
-    fn test_config_defaults() {
-        let config = Config::default();
-        assert_eq!(config.mailcow_imap_port, 993);
-        assert_eq!(config.check_interval, DEFAULT_CHECK_INTERVAL);
+    if run_once {
+        let ok = check_all(&mut checkers, Duration::ZERO).is_some();
+        after_cycle(&config, ok);
+        if !ok {
+            std::process::exit(1);
+        }
+        return;
     }
 
-    #[test]
-    fn test_email_format() {
-        let email = EmailData {
-            subject: "Test".to_string(),
-            from: "test@example.com".to_string(),
-            date: "2024-01-01".to_string(),
-            body: "Hello".to_string(),
-        };
-        let message = email.to_openclaw_message();
-        assert!(message.contains("Test"));
-        assert!(message.contains("test@example.com"));
+    log::info(&format!("Continuous mode: Checking every {} seconds", config.check_interval));
+    log::info("Press Ctrl+C to stop.\n");
+
+    let mut schedule = Schedule::new(&config);
+    let mut interval = Duration::from_secs(config.check_interval as u64);
+    let mut config_fetched = Instant::now();
+    loop {
+        let refresh = Duration::from_secs(config.remote.refresh_interval);
+        if config.remote.url.is_some() && !refresh.is_zero() && config_fetched.elapsed() >= refresh {
+            config_fetched = Instant::now();
+            let reloaded = config.reload_if_changed().map_err(|e| e.to_string());
+            match reloaded.and_then(|c| c.map(|c| build_checkers(&c, shard).map(|built| (c, built))).transpose()) {
+                Ok(Some((reloaded, rebuilt))) => {
+                    log::info(&format!("Remote config changed; now polling {} account(s)", rebuilt.len()));
+                    (config, checkers) = (reloaded, rebuilt);
+                }
+                Ok(None) => {}
+                Err(e) => log::warn(&format!("could not refresh the remote config: {}", e)),
+            }
+        }
+        let leading: Vec<bool> = checkers.iter_mut().map(|c| c.lead(interval)).collect();
+        if !leading.contains(&true) {
+            thread::sleep(Duration::from_secs(config.ha.standby_poll.max(1)));
+            continue;
+        }
+        let delivered = check_all(&mut checkers, interval);
+        after_cycle(&config, delivered.is_some());
+        interval = schedule.next(delivered);
+        for checker in &mut checkers {
+            checker.lead(interval);
+        }
+        log::info(&format!("\nSleeping for {} seconds...", interval.as_secs()));
+        thread::sleep(interval);
     }
 }
@@ -0,0 +1,146 @@
+//! The man page, printed by `generate man`.
+//!
+//! Commands and flags come from [`crate::cli`] and config keys from
+//! [`crate::schema`], so the page documents what the binary accepts. It
+//! carries no date, so rebuilding a package reproduces it byte for byte.
+
+use serde_json::Value;
+
+use crate::cli::{Flag, COMMANDS, GLOBAL_FLAGS};
+use crate::schema;
+use crate::shard::SHARD_ENV;
+
+/// Environment variables read, with the setting each overrides.
+pub const ENVIRONMENT: &[(&str, &str)] = &[
+    ("MAILCOW_IMAP_HOST", "mailcow_imap_host"),
+    ("MAILCOW_IMAP_PORT", "mailcow_imap_port"),
+    ("MAILCOW_USERNAME", "mailcow_username"),
+    ("MAILCOW_PASSWORD", "mailcow_password"),
+    ("MAILCOW_AUTH_MECHANISM", "auth_mechanism"),
+    ("MAILCOW_API_KEY", "mailcow_api.api_key"),
+    ("OPENCLAW_GATEWAY", "openclaw_gateway"),
+    ("OPENCLAW_PORT", "openclaw_port"),
+    ("CHECK_INTERVAL", "check_interval"),
+    ("LAST_CHECK_FILE", "last_check_file"),
+    ("STATE_FILE", "state_file"),
+    (SHARD_ENV, "--shard"),
+];
+
+/// `text` safe to use as roff text.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+fn flag_item(page: &mut String, flag: &Flag) {
+    page.push_str(&format!(".TP\n.B {}", escape(flag.name)));
+    if let Some(value) = flag.value {
+        page.push_str(&format!(" \\fI{}\\fR", escape(value)));
+    }
+    page.push_str(&format!("\n{}.\n", escape(flag.help)));
+}
+
+/// One `.TP` item per config key below `schema`, nested keys dotted.
+fn config_items(page: &mut String, path: &str, schema: &Value) {
+    for (key, property) in schema["properties"].as_object().into_iter().flatten() {
+        if key == "$schema" {
+            continue;
+        }
+        let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+        let items = &property["items"];
+        if property.get("properties").is_some() {
+            config_items(page, &path, property);
+            continue;
+        }
+        if items.get("properties").is_some() {
+            config_items(page, &format!("{}[]", path), items);
+            continue;
+        }
+        let kind = match &property["type"] {
+            Value::String(kind) => kind.clone(),
+            Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(" or "),
+            _ => "see the schema".to_string(),
+        };
+        let mut text = kind;
+        if let Some(words) = property["enum"].as_array() {
+            let words: Vec<String> = words.iter().map(|w| w.as_str().map_or(w.to_string(), str::to_string)).collect();
+            text = format!("one of {}", words.join(", "));
+        }
+        match &property["default"] {
+            Value::Null => {}
+            Value::Array(a) if a.is_empty() => {}
+            Value::Object(o) if o.is_empty() => {}
+            Value::String(s) if s.is_empty() => {}
+            default => text.push_str(&format!("; default {}", default)),
+        }
+        if let Some(description) = property["description"].as_str() {
+            text.push_str(&format!(". {}", description.trim_end_matches('.')));
+        }
+        page.push_str(&format!(".TP\n.B {}\n{}.\n", escape(&path), escape(&text)));
+    }
+}
+
+/// The page, in roff for `man`, e.g. as `email_checker.1`.
+pub fn page() -> String {
+    let version = env!("CARGO_PKG_VERSION");
+    let mut page = format!(".TH EMAIL_CHECKER 1 \"\" \"email_checker {}\" \"User Commands\"\n", version);
+    page.push_str(".SH NAME\nemail_checker \\- forward new mail from IMAP to OpenClaw and other sinks\n");
+    page.push_str(".SH SYNOPSIS\n.B email_checker\n[\\fIOPTIONS\\fR]\n.br\n.B email_checker\n[\\fIOPTIONS\\fR] \\fICOMMAND\\fR ...\n");
+    page.push_str(
+        ".SH DESCRIPTION\nWithout a command, \\fBemail_checker\\fR polls the configured IMAP accounts every \
+         \\fBcheck_interval\\fR seconds and delivers unseen messages to the sinks their rules select, \
+         marking them seen only once delivered.\n",
+    );
+    page.push_str(".SH OPTIONS\n");
+    for flag in GLOBAL_FLAGS {
+        flag_item(&mut page, flag);
+    }
+    page.push_str(".SH COMMANDS\n");
+    for command in COMMANDS {
+        let mut usage = format!("\\fB{}\\fR", escape(command.name));
+        if !command.words.is_empty() {
+            usage.push_str(&format!(" {}", escape(&command.words.join("|"))));
+        }
+        if !command.args.is_empty() {
+            usage.push_str(&format!(" \\fI{}\\fR", escape(command.args)));
+        }
+        page.push_str(&format!(".TP\n{}\n{}.\n", usage, escape(command.help)));
+        if !command.flags.is_empty() {
+            page.push_str(".RS\n");
+            for flag in command.flags {
+                flag_item(&mut page, flag);
+            }
+            page.push_str(".RE\n");
+        }
+    }
+    page.push_str(
+        ".SH CONFIGURATION\nThe config file is JSON; \\fBemail_checker config schema\\fR prints its full JSON Schema. \
+         Nested keys are shown dotted, and \\fB[]\\fR marks the entries of a list.\n",
+    );
+    config_items(&mut page, "", &schema::config_schema());
+    page.push_str(".SH ENVIRONMENT\n");
+    for (name, overrides) in ENVIRONMENT {
+        page.push_str(&format!(".TP\n.B {}\nOverrides \\fB{}\\fR.\n", escape(name), escape(overrides)));
+    }
+    page.push_str(".SH SEE ALSO\n.BR openssl (1),\n.BR curl (1)\n");
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page() {
+        let page = page();
+        assert!(page.starts_with(".TH EMAIL_CHECKER 1 "));
+        assert!(page.contains(".TP\n\\fBstate\\fR export|import \\fI[FILE]\\fR\nExport or import the delivery state.\n.RS\n.TP\n.B \\-\\-output \\fIFILE\\fR\n"));
+        assert!(page.contains(".TP\n.B check_interval\ninteger or string; default 300.\n"));
+        assert!(page.contains(".B remote.refresh_interval\n"));
+        assert_eq!(escape(".hidden"), "\\&.hidden");
+    }
+}
@@ -0,0 +1,144 @@
+//! Message disposition notifications (RFC 8098), i.e. read receipts.
+//!
+//! A receipt is a `multipart/report; report-type=disposition-notification`
+//! message. Incoming receipts are forwarded as `receipt` events, to
+//! `mdn.sink` if set, or dropped with `mdn.suppress`.
+//!
+//! With `mdn.send`, a forwarded message whose sender asked for a receipt
+//! with `Disposition-Notification-To` is answered with one saying it was
+//! dispatched, from the null sender so it cannot bounce back. As RFC 8098
+//! advises, none is sent when that address is not the `Return-Path`, or
+//! for mail that was itself sent automatically.
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest::{hex, sha256};
+use crate::email::EmailData;
+use crate::mime::{self, Part};
+use crate::redact;
+
+/// `mdn` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MdnConfig {
+    /// Drop incoming receipts instead of forwarding them.
+    pub suppress: bool,
+    /// Sink incoming receipts go to instead of their rule's.
+    pub sink: Option<String>,
+    /// Send a receipt for forwarded mail that asks for one.
+    pub send: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Receipt {
+    pub reporting_ua: Option<String>,
+    pub final_recipient: Option<String>,
+    /// E.g. `manual-action/MDN-sent-manually; displayed`.
+    pub disposition: Option<String>,
+    pub original_message_id: Option<String>,
+}
+
+pub fn from_message(message: &Part) -> Option<Receipt> {
+    let content_type = message.content_type();
+    let is_receipt = content_type.mime_type == "multipart/report"
+        && content_type
+            .param("report-type")
+            .is_some_and(|t| t.eq_ignore_ascii_case("disposition-notification"));
+    if !is_receipt {
+        return None;
+    }
+    let report = message.find("message/disposition-notification")?;
+    let fields = mime::parse_headers(report.text().replace("\r\n", "\n").as_bytes());
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.trim().to_string())
+    };
+    Some(Receipt {
+        reporting_ua: field("Reporting-UA"),
+        final_recipient: field("Final-Recipient").map(|r| r.split_once(';').map_or(r.as_str(), |(_, a)| a).trim().to_string()),
+        disposition: field("Disposition"),
+        original_message_id: field("Original-Message-ID"),
+    })
+}
+
+/// The address a receipt for `email` should go to, if one was asked for
+/// and may be sent.
+pub fn requested_by(email: &EmailData) -> Option<String> {
+    if email.receipt.is_some() || email.header("Auto-Submitted").is_some_and(|v| !v.trim().eq_ignore_ascii_case("no")) {
+        return None;
+    }
+    let to = redact::addresses(email.header("Disposition-Notification-To")?).first()?.to_string();
+    match email.header("Return-Path").and_then(|path| redact::addresses(path).first().map(|a| a.to_string())) {
+        Some(path) if !path.eq_ignore_ascii_case(&to) => None,
+        _ => Some(to),
+    }
+}
+
+/// A receipt from `from` to `to` saying `email` was dispatched.
+pub fn compose(email: &EmailData, from: &str, to: &str) -> String {
+    let original = email.header("Message-ID").unwrap_or_default();
+    let id = hex(&sha256(format!("{}{}", original, chrono::Utc::now()).as_bytes()))[..32].to_string();
+    let domain = from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+    let boundary = format!("mdn-{}", &id[..16]);
+    let headers = [
+        ("Date", chrono::Local::now().to_rfc2822()),
+        ("From", from.to_string()),
+        ("To", to.to_string()),
+        ("Subject", crate::smtp::encode_header(&format!("Dispatched: {}", email.subject))),
+        ("Message-ID", format!("<{}@{}>", id, domain)),
+        ("MIME-Version", "1.0".to_string()),
+        ("Content-Type", format!("multipart/report; report-type=disposition-notification; boundary=\"{}\"", boundary)),
+        ("Auto-Submitted", "auto-replied".to_string()),
+    ];
+    let mut message: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    message.push_str(&format!(
+        "\r\n--{b}\r\nContent-Type: text/plain; charset=us-ascii\r\n\r\n\
+         Your message to {from} was forwarded automatically. It may not have been read yet.\r\n\
+         --{b}\r\nContent-Type: message/disposition-notification\r\n\r\n\
+         Reporting-UA: email_checker; {version}\r\nFinal-Recipient: rfc822; {from}\r\n",
+        b = boundary,
+        from = from,
+        version = env!("CARGO_PKG_VERSION"),
+    ));
+    if !original.is_empty() {
+        message.push_str(&format!("Original-Message-ID: {}\r\n", original));
+    }
+    message.push_str(&format!("Disposition: automatic-action/MDN-sent-automatically; dispatched\r\n--{}--\r\n", boundary));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipts_in_and_out() {
+        let incoming = "From: bob@example.org\r\n\
+Content-Type: multipart/report; report-type=disposition-notification; boundary=\"B\"\r\n\r\n\
+--B\r\nContent-Type: text/plain\r\n\r\nYour message was displayed.\r\n\
+--B\r\nContent-Type: message/disposition-notification\r\n\r\n\
+Reporting-UA: mail.example.org; Thunderbird\r\nFinal-Recipient: rfc822; bob@example.org\r\n\
+Original-Message-ID: <sent@example.com>\r\nDisposition: manual-action/MDN-sent-manually; displayed\r\n\
+--B--\r\n";
+        let receipt = EmailData::from_raw(incoming.as_bytes()).receipt.unwrap();
+        assert_eq!(receipt.final_recipient.as_deref(), Some("bob@example.org"));
+        assert_eq!(receipt.original_message_id.as_deref(), Some("<sent@example.com>"));
+        assert_eq!(receipt.disposition.as_deref(), Some("manual-action/MDN-sent-manually; displayed"));
+        assert!(requested_by(&EmailData::from_raw(incoming.as_bytes())).is_none());
+
+        let asking = "From: Alice <alice@example.com>\r\nReturn-Path: <alice@example.com>\r\n\
+Disposition-Notification-To: Alice <alice@example.com>\r\nMessage-ID: <m1@example.com>\r\nSubject: Hi\r\n\r\nbody";
+        let email = EmailData::from_raw(asking.as_bytes());
+        assert_eq!(requested_by(&email).as_deref(), Some("alice@example.com"));
+        let redirected = asking.replace("Return-Path: <alice@example.com>", "Return-Path: <list@example.net>");
+        assert!(requested_by(&EmailData::from_raw(redirected.as_bytes())).is_none());
+
+        let sent = EmailData::from_raw(compose(&email, "me@example.org", "alice@example.com").as_bytes());
+        let receipt = sent.receipt.unwrap();
+        assert_eq!(receipt.original_message_id.as_deref(), Some("<m1@example.com>"));
+        assert_eq!(receipt.disposition.as_deref(), Some("automatic-action/MDN-sent-automatically; dispatched"));
+        assert_eq!(sent.subject, "Dispatched: Hi");
+    }
+}
@@ -0,0 +1,340 @@
+//! Process-wide counters in the Prometheus text format.
+//!
+//! Both backends count into the same metrics. With `metrics_file` set,
+//! they are written after every cycle, for node_exporter's textfile
+//! collector or anything else that scrapes files.
+//!
+//! Latencies are summaries: p50 and p95 over the most recent
+//! `SUMMARY_WINDOW` observations, with a running sum and count.
+//!
+//! The quota gauges hold the mailbox's storage use and limit as last read,
+//! per `account`.
+//!
+//! The `account_*` families repeat the delivery metrics per `account`
+//! and `rule` label, for per-mailbox dashboards. `metrics.labels` picks
+//! the labels kept, and `metrics.max_series` caps the label sets per
+//! family: beyond it, new ones are counted under `other`, so a flood of
+//! rules or accounts cannot bloat the exported file.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+pub const CYCLES: &str = "email_checker_cycles_total";
+pub const CYCLE_ERRORS: &str = "email_checker_cycle_errors_total";
+pub const DELIVERED: &str = "email_checker_messages_delivered_total";
+pub const DELIVERY_FAILURES: &str = "email_checker_delivery_failures_total";
+pub const SUBPROCESS_TIMEOUTS: &str = "email_checker_subprocess_timeouts_total";
+pub const IMAP_BYTES_DOWNLOADED: &str = "email_checker_imap_bytes_downloaded_total";
+pub const GATEWAY_BYTES_UPLOADED: &str = "email_checker_gateway_bytes_uploaded_total";
+pub const GATEWAY_FAILOVERS: &str = "email_checker_gateway_failovers_total";
+pub const PANICS: &str = "email_checker_panics_total";
+pub const QUARANTINED: &str = "email_checker_messages_quarantined_total";
+pub const SCAN_THREATS: &str = "email_checker_scan_threats_total";
+pub const SCAN_DROPPED: &str = "email_checker_scan_dropped_total";
+pub const COLLAPSED: &str = "email_checker_messages_collapsed_total";
+pub const UNKNOWN_SENDERS: &str = "email_checker_unknown_senders_total";
+pub const RECONCILED: &str = "email_checker_messages_reconciled_total";
+pub const LATENCY_SLO_BREACHES: &str = "email_checker_latency_slo_breaches_total";
+pub const SERIES_OVERFLOW: &str = "email_checker_metric_series_overflow_total";
+pub const RULE_MATCHES: &str = "email_checker_rule_matches_total";
+pub const ACCOUNT_CYCLES: &str = "email_checker_account_cycles_total";
+pub const ACCOUNT_CYCLE_ERRORS: &str = "email_checker_account_cycle_errors_total";
+pub const ACCOUNT_DELIVERED: &str = "email_checker_account_messages_delivered_total";
+pub const ACCOUNT_DELIVERY_FAILURES: &str = "email_checker_account_delivery_failures_total";
+pub const DELIVERY_LATENCY: &str = "email_checker_delivery_latency_seconds";
+pub const ACCOUNT_DELIVERY_LATENCY: &str = "email_checker_account_delivery_latency_seconds";
+pub const QUOTA_USED: &str = "email_checker_account_quota_used_bytes";
+pub const QUOTA_LIMIT: &str = "email_checker_account_quota_limit_bytes";
+
+pub const DEFAULT_MAX_SERIES: usize = 100;
+
+const SUMMARY_WINDOW: usize = 1024;
+pub const QUANTILES: &[f64] = &[0.5, 0.95];
+
+/// Every counter with its help text, so all are exported even at zero.
+pub const HELP: &[(&str, &str)] = &[
+    (CYCLES, "Check cycles run."),
+    (CYCLE_ERRORS, "Check cycles that failed."),
+    (DELIVERED, "Messages delivered to a sink."),
+    (DELIVERY_FAILURES, "Message deliveries that failed."),
+    (SUBPROCESS_TIMEOUTS, "Python checker runs killed after the timeout."),
+    (IMAP_BYTES_DOWNLOADED, "Bytes received from the IMAP server."),
+    (GATEWAY_BYTES_UPLOADED, "Payload bytes posted to sinks."),
+    (GATEWAY_FAILOVERS, "Deliveries that succeeded only on a fallback gateway."),
+    (PANICS, "Panics caught while parsing a message or running a cycle."),
+    (QUARANTINED, "Messages quarantined after failing to parse in time."),
+    (SCAN_THREATS, "Messages a scanner flagged."),
+    (SCAN_DROPPED, "Flagged messages dropped instead of forwarded."),
+    (COLLAPSED, "Near-identical messages held back as part of a burst."),
+    (UNKNOWN_SENDERS, "Messages held back because the sender is not in the contacts allowlist."),
+    (RECONCILED, "Seen messages found without a delivery record and delivered again."),
+    (LATENCY_SLO_BREACHES, "Messages delivered later than latency_slo."),
+    (SERIES_OVERFLOW, "Labelled counts folded into other after reaching metrics.max_series."),
+];
+
+/// Counters kept per label set, with the labels they may carry, exported
+/// only once counted.
+pub const LABELLED_HELP: &[(&str, &[&str], &str)] = &[
+    (RULE_MATCHES, &["account", "rule"], "Messages delivered per routing rule."),
+    (ACCOUNT_CYCLES, &["account"], "Check cycles run per account."),
+    (ACCOUNT_CYCLE_ERRORS, &["account"], "Check cycles that failed per account."),
+    (ACCOUNT_DELIVERED, &["account", "rule"], "Messages delivered per account and rule."),
+    (ACCOUNT_DELIVERY_FAILURES, &["account", "rule"], "Message deliveries that failed per account and rule."),
+];
+
+pub const SUMMARY_HELP: &[(&str, &str)] = &[(DELIVERY_LATENCY, "Time from a message arriving to its delivery.")];
+
+/// Summaries kept per label set, like [`LABELLED_HELP`].
+pub const LABELLED_SUMMARY_HELP: &[(&str, &[&str], &str)] =
+    &[(ACCOUNT_DELIVERY_LATENCY, &["account"], "Time from a message arriving to its delivery per account.")];
+
+/// Gauges kept per label set, like [`LABELLED_HELP`].
+pub const LABELLED_GAUGE_HELP: &[(&str, &[&str], &str)] = &[
+    (QUOTA_USED, &["account"], "Mailbox storage in use per account."),
+    (QUOTA_LIMIT, &["account"], "Mailbox storage quota per account."),
+];
+
+/// `metrics` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Labels kept on labelled metrics; others are dropped, merging series.
+    pub labels: Vec<String>,
+    /// Label sets per labelled metric before new ones count as `other`.
+    pub max_series: usize,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            labels: vec!["account".to_string(), "rule".to_string()],
+            max_series: DEFAULT_MAX_SERIES,
+        }
+    }
+}
+
+static COUNTERS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+/// Labelled counters by name and rendered label set, e.g. `rule="ci"`.
+static LABELLED: Mutex<BTreeMap<(&'static str, String), u64>> = Mutex::new(BTreeMap::new());
+/// Summaries by name and rendered label set, empty for unlabelled ones.
+static SUMMARIES: Mutex<BTreeMap<(&'static str, String), Summary>> = Mutex::new(BTreeMap::new());
+/// Labelled gauges, like [`LABELLED`].
+static GAUGES: Mutex<BTreeMap<(&'static str, String), f64>> = Mutex::new(BTreeMap::new());
+/// Labels kept, all of them when unset.
+static LABELS: Mutex<Option<Vec<String>>> = Mutex::new(None);
+static MAX_SERIES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_SERIES);
+
+#[derive(Default)]
+struct Summary {
+    recent: VecDeque<f64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Summary {
+    fn push(&mut self, value: f64) {
+        if self.recent.len() == SUMMARY_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// The quantile, sum and count lines, each label set prefixed by `labels`.
+    fn render(&self, name: &str, labels: &str) -> String {
+        let mut out = String::new();
+        let prefix = if labels.is_empty() { String::new() } else { format!("{},", labels) };
+        for q in QUANTILES {
+            out.push_str(&format!("{}{{{}quantile=\"{}\"}} {}\n", name, prefix, q, self.quantile(*q)));
+        }
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        out.push_str(&format!("{}_sum{} {}\n{}_count{} {}\n", name, labels, self.sum, name, labels, self.count));
+        out
+    }
+
+    fn quantile(&self, q: f64) -> f64 {
+        let mut sorted: Vec<f64> = self.recent.iter().copied().collect();
+        if sorted.is_empty() {
+            return f64::NAN;
+        }
+        sorted.sort_by(f64::total_cmp);
+        let rank = ((q * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+        sorted[rank - 1]
+    }
+}
+
+pub fn inc(name: &'static str) {
+    add(name, 1);
+}
+
+pub fn add(name: &'static str, value: u64) {
+    let mut counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+    *counters.entry(name).or_insert(0) += value;
+}
+
+pub fn get(name: &str) -> u64 {
+    let counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+    counters.get(name).copied().unwrap_or(0)
+}
+
+/// Apply the `metrics` section.
+pub fn configure(config: &MetricsConfig) {
+    *LABELS.lock().unwrap_or_else(|e| e.into_inner()) = Some(config.labels.clone());
+    MAX_SERIES.store(config.max_series, Ordering::Relaxed);
+}
+
+/// `labels` rendered for the exposition format, without the disabled ones.
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    let enabled = LABELS.lock().unwrap_or_else(|e| e.into_inner());
+    labels
+        .iter()
+        .filter(|(key, _)| enabled.as_ref().is_none_or(|enabled| enabled.iter().any(|e| e == key)))
+        .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The label set `labels` is counted under in a family that already has
+/// `existing` of them: itself, or `other` past `MAX_SERIES`.
+fn series(labels: &[(&str, &str)], existing: usize, known: bool) -> String {
+    if known || existing < MAX_SERIES.load(Ordering::Relaxed) {
+        return render_labels(labels);
+    }
+    add(SERIES_OVERFLOW, 1);
+    let other: Vec<(&str, &str)> = labels.iter().map(|(key, _)| (*key, "other")).collect();
+    render_labels(&other)
+}
+
+/// Count one for the series of `name` with `labels`.
+pub fn inc_with(name: &'static str, labels: &[(&str, &str)]) {
+    let mut labelled = LABELLED.lock().unwrap_or_else(|e| e.into_inner());
+    let rendered = render_labels(labels);
+    let existing = labelled.keys().filter(|(n, _)| *n == name).count();
+    let known = labelled.contains_key(&(name, rendered));
+    let key = (name, series(labels, existing, known));
+    *labelled.entry(key).or_insert(0) += 1;
+}
+
+/// Set the series of gauge `name` with `labels` to `value`.
+pub fn set_with(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let mut gauges = GAUGES.lock().unwrap_or_else(|e| e.into_inner());
+    let rendered = render_labels(labels);
+    let existing = gauges.keys().filter(|(n, _)| *n == name).count();
+    let known = gauges.contains_key(&(name, rendered));
+    let key = (name, series(labels, existing, known));
+    gauges.insert(key, value);
+}
+
+pub fn observe(name: &'static str, value: f64) {
+    let mut summaries = SUMMARIES.lock().unwrap_or_else(|e| e.into_inner());
+    summaries.entry((name, String::new())).or_default().push(value);
+}
+
+/// Observe `value` for the series of `name` with `labels`.
+pub fn observe_with(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let mut summaries = SUMMARIES.lock().unwrap_or_else(|e| e.into_inner());
+    let rendered = render_labels(labels);
+    let existing = summaries.keys().filter(|(n, _)| *n == name).count();
+    let known = summaries.contains_key(&(name, rendered));
+    let key = (name, series(labels, existing, known));
+    summaries.entry(key).or_default().push(value);
+}
+
+/// Quantile `q` of the recent observations, NaN if there are none.
+pub fn quantile(name: &'static str, q: f64) -> f64 {
+    let summaries = SUMMARIES.lock().unwrap_or_else(|e| e.into_inner());
+    summaries.get(&(name, String::new())).map_or(f64::NAN, |s| s.quantile(q))
+}
+
+/// All metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for (name, help) in HELP {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n{} {}\n", name, help, name, name, get(name)));
+    }
+    let labelled = LABELLED.lock().unwrap_or_else(|e| e.into_inner());
+    for (name, _, help) in LABELLED_HELP {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n", name, help, name));
+        for ((_, labels), value) in labelled.iter().filter(|((n, _), _)| n == name) {
+            out.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+        }
+    }
+    let gauges = GAUGES.lock().unwrap_or_else(|e| e.into_inner());
+    for (name, _, help) in LABELLED_GAUGE_HELP {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name));
+        for ((_, labels), value) in gauges.iter().filter(|((n, _), _)| n == name) {
+            out.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+        }
+    }
+    let summaries = SUMMARIES.lock().unwrap_or_else(|e| e.into_inner());
+    let empty = Summary::default();
+    for (name, help) in SUMMARY_HELP {
+        let summary = summaries.get(&(*name, String::new())).unwrap_or(&empty);
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} summary\n", name, help, name));
+        out.push_str(&summary.render(name, ""));
+    }
+    for (name, _, help) in LABELLED_SUMMARY_HELP {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} summary\n", name, help, name));
+        for ((_, labels), summary) in summaries.iter().filter(|((n, _), _)| n == name) {
+            out.push_str(&summary.render(name, labels));
+        }
+    }
+    out
+}
+
+/// Write [`render`] to `path` atomically.
+pub fn write(path: &str) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, render())?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let before = get(CYCLE_ERRORS);
+        inc(CYCLE_ERRORS);
+        let text = render();
+        assert!(text.contains("# TYPE email_checker_cycles_total counter\n"));
+        assert!(text.contains(&format!("email_checker_cycle_errors_total {}\n", before + 1)));
+        inc_with(RULE_MATCHES, &[("rule", "say \"hi\"")]);
+        assert!(render().contains("email_checker_rule_matches_total{rule=\"say \\\"hi\\\"\"} 1\n"));
+        observe_with(ACCOUNT_DELIVERY_LATENCY, &[("account", "bob")], 3.0);
+        let text = render();
+        assert!(text.contains("email_checker_account_delivery_latency_seconds{account=\"bob\",quantile=\"0.5\"} 3\n"));
+        assert!(text.contains("email_checker_account_delivery_latency_seconds_count{account=\"bob\"} 1\n"));
+        set_with(QUOTA_USED, &[("account", "bob")], 2048.0);
+        set_with(QUOTA_USED, &[("account", "bob")], 4096.0);
+        assert!(render().contains("# TYPE email_checker_account_quota_used_bytes gauge\n"));
+        assert!(render().contains("email_checker_account_quota_used_bytes{account=\"bob\"} 4096\n"));
+    }
+
+    #[test]
+    fn test_series_cap() {
+        let labels = [("account", "a"), ("rule", "r")];
+        assert_eq!(series(&labels, DEFAULT_MAX_SERIES - 1, false), "account=\"a\",rule=\"r\"");
+        assert_eq!(series(&labels, DEFAULT_MAX_SERIES, true), "account=\"a\",rule=\"r\"");
+        let overflow = get(SERIES_OVERFLOW);
+        assert_eq!(series(&labels, DEFAULT_MAX_SERIES, false), "account=\"other\",rule=\"other\"");
+        assert_eq!(get(SERIES_OVERFLOW), overflow + 1);
+    }
+
+    #[test]
+    fn test_summary_quantiles() {
+        for seconds in 1..=100 {
+            observe(DELIVERY_LATENCY, seconds as f64);
+        }
+        assert_eq!(quantile(DELIVERY_LATENCY, 0.5), 50.0);
+        assert_eq!(quantile(DELIVERY_LATENCY, 0.95), 95.0);
+        assert!(render().contains("email_checker_delivery_latency_seconds{quantile=\"0.95\"} 95\n"));
+    }
+}
@@ -0,0 +1,446 @@
+//! Minimal MIME message parser.
+//!
+//! Handles what the checker needs from RFC 5322 / RFC 2045 messages:
+//! header unfolding, multipart bodies, transfer decoding and
+//! RFC 2047 encoded words. It is deliberately forgiving; malformed
+//! input yields an empty or partial part tree rather than an error.
+
+/// A MIME entity: the top-level message or one of its parts.
+#[derive(Debug, Clone, Default)]
+pub struct Part {
+    pub headers: Vec<(String, String)>,
+    /// Raw body bytes, still transfer-encoded.
+    pub body: Vec<u8>,
+    pub children: Vec<Part>,
+}
+
+/// A parsed `Content-Type` header value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentType {
+    /// Lower-cased `type/subtype`.
+    pub mime_type: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    pub fn parse(value: &str) -> Self {
+        let mut pieces = split_params(value).into_iter();
+        let mime_type = pieces.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let params = pieces
+            .filter_map(|p| {
+                let (k, v) = p.split_once('=')?;
+                Some((k.trim().to_ascii_lowercase(), unquote(v.trim())))
+            })
+            .collect();
+        Self { mime_type, params }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type.starts_with("multipart/")
+    }
+}
+
+impl Default for ContentType {
+    fn default() -> Self {
+        Self {
+            mime_type: "text/plain".to_string(),
+            params: Vec::new(),
+        }
+    }
+}
+
+/// Deepest nesting of multipart and `message/rfc822` parts that is
+/// parsed; anything below is kept as an unparsed body, so hostile mail
+/// cannot exhaust the stack.
+pub const MAX_DEPTH: usize = 32;
+
+impl Part {
+    pub fn parse(raw: &[u8]) -> Self {
+        Self::parse_at(raw, 0)
+    }
+
+    fn parse_at(raw: &[u8], depth: usize) -> Self {
+        let (head, body) = split_head_body(raw);
+        let mut part = Part {
+            headers: parse_headers(head),
+            body: body.to_vec(),
+            children: Vec::new(),
+        };
+
+        let content_type = part.content_type();
+        if depth >= MAX_DEPTH {
+            return part;
+        }
+        if content_type.is_multipart() {
+            if let Some(boundary) = content_type.param("boundary") {
+                part.children = split_multipart(body, boundary)
+                    .into_iter()
+                    .map(|child| Part::parse_at(child, depth + 1))
+                    .collect();
+            }
+        } else if content_type.mime_type == "message/rfc822" {
+            part.children = vec![Part::parse_at(&part.decoded_body(), depth + 1)];
+        }
+        part
+    }
+
+    /// First header with the given name, case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Header value with RFC 2047 encoded words decoded.
+    pub fn decoded_header(&self, name: &str) -> Option<String> {
+        self.header(name).map(decode_encoded_words)
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        self.header("Content-Type")
+            .map(ContentType::parse)
+            .unwrap_or_default()
+    }
+
+    /// Body with `Content-Transfer-Encoding` undone.
+    pub fn decoded_body(&self) -> Vec<u8> {
+        let encoding = self
+            .header("Content-Transfer-Encoding")
+            .unwrap_or("7bit")
+            .trim()
+            .to_ascii_lowercase();
+        match encoding.as_str() {
+            "base64" => decode_base64(&self.body),
+            "quoted-printable" => decode_quoted_printable(&self.body),
+            _ => self.body.clone(),
+        }
+    }
+
+    /// Decoded body as text, honouring the `charset` parameter.
+    pub fn text(&self) -> String {
+        let charset = self.content_type().param("charset").map(str::to_ascii_lowercase);
+        decode_charset(&self.decoded_body(), charset.as_deref())
+    }
+
+    /// Attachment file name from `Content-Disposition` or the
+    /// `Content-Type` `name` parameter.
+    pub fn filename(&self) -> Option<String> {
+        let disposition = self.header("Content-Disposition").map(ContentType::parse);
+        disposition
+            .as_ref()
+            .and_then(|d| d.param("filename"))
+            .map(decode_encoded_words)
+            .or_else(|| self.content_type().param("name").map(decode_encoded_words))
+    }
+
+    /// Whether this is a leaf part meant as a file rather than message text.
+    pub fn is_attachment(&self) -> bool {
+        let disposition = self.header("Content-Disposition").map(ContentType::parse);
+        let mime_type = self.content_type().mime_type;
+        self.children.is_empty()
+            && (disposition.is_some_and(|d| d.mime_type == "attachment")
+                || self.filename().is_some()
+                || !(mime_type.starts_with("text/") || mime_type.starts_with("multipart/")))
+    }
+
+    /// This part and all of its descendants, depth first.
+    pub fn walk(&self) -> Vec<&Part> {
+        let mut parts = vec![self];
+        for child in &self.children {
+            parts.extend(child.walk());
+        }
+        parts
+    }
+
+    /// First leaf part of the given MIME type.
+    pub fn find(&self, mime_type: &str) -> Option<&Part> {
+        self.walk()
+            .into_iter()
+            .find(|p| p.children.is_empty() && p.content_type().mime_type == mime_type)
+    }
+}
+
+fn split_head_body(raw: &[u8]) -> (&[u8], &[u8]) {
+    for i in 0..raw.len() {
+        if raw[i..].starts_with(b"\r\n\r\n") {
+            return (&raw[..i], &raw[i + 4..]);
+        }
+        if raw[i..].starts_with(b"\n\n") {
+            return (&raw[..i], &raw[i + 2..]);
+        }
+    }
+    (raw, &[])
+}
+
+/// Unfolded `(name, value)` pairs from a header block.
+pub fn parse_headers(head: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(head);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// Bodies of the parts between `--boundary` delimiters.
+pub fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut pos = 0;
+
+    while pos < body.len() {
+        let end = body[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(body.len(), |i| pos + i + 1);
+        let line = trim_line_end(&body[pos..end]);
+
+        if line.starts_with(delimiter.as_bytes()) {
+            let rest = &line[delimiter.len()..];
+            if let Some(s) = start {
+                parts.push(trim_line_end(&body[s..pos]));
+            }
+            if rest.starts_with(b"--") {
+                return parts;
+            }
+            start = Some(end);
+        }
+        pos = end;
+    }
+    if let Some(s) = start {
+        parts.push(&body[s..]);
+    }
+    parts
+}
+
+fn trim_line_end(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    while end > 0 && (line[end - 1] == b'\n' || line[end - 1] == b'\r') {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// Split a header value on `;`, ignoring separators inside quotes.
+fn split_params(value: &str) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ';' if !quoted => pieces.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    pieces.push(current);
+    pieces
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+pub fn decode_base64(input: &[u8]) -> Vec<u8> {
+    fn value(c: u8) -> Option<u8> {
+        match c {
+            b'A'..=b'Z' => Some(c - b'A'),
+            b'a'..=b'z' => Some(c - b'a' + 26),
+            b'0'..=b'9' => Some(c - b'0' + 52),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None,
+        }
+    }
+
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &c in input {
+        if c == b'=' {
+            break;
+        }
+        let Some(v) = value(c) else { continue };
+        buffer = (buffer << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    out
+}
+
+/// Standard base64 with padding, no line breaks.
+pub fn encode_base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub fn decode_quoted_printable(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'=' {
+            let rest = &input[i + 1..];
+            if rest.starts_with(b"\r\n") {
+                i += 3;
+                continue;
+            }
+            if rest.starts_with(b"\n") {
+                i += 2;
+                continue;
+            }
+            if rest.len() >= 2 {
+                if let Ok(byte) = u8::from_str_radix(&String::from_utf8_lossy(&rest[..2]), 16) {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                }
+            }
+        }
+        out.push(input[i]);
+        i += 1;
+    }
+    out
+}
+
+fn decode_charset(bytes: &[u8], charset: Option<&str>) -> String {
+    match charset {
+        Some("iso-8859-1" | "latin1" | "windows-1252") => bytes.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Decode RFC 2047 encoded words (`=?charset?B|Q?text?=`) in a header value.
+pub fn decode_encoded_words(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut last_was_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let Some(word) = parse_encoded_word(&rest[start..]) else {
+            out.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            last_was_word = false;
+            continue;
+        };
+        let between = &rest[..start];
+        // Whitespace between adjacent encoded words is not significant.
+        if !(last_was_word && between.trim().is_empty()) {
+            out.push_str(between);
+        }
+        out.push_str(&word.0);
+        rest = &rest[start + word.1..];
+        last_was_word = true;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns the decoded text and the length of the encoded word consumed.
+fn parse_encoded_word(input: &str) -> Option<(String, usize)> {
+    let inner = input.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let text = &inner[..end];
+    let bytes = match encoding.to_ascii_uppercase().as_str() {
+        "B" => decode_base64(text.as_bytes()),
+        "Q" => decode_quoted_printable(text.replace('_', " ").as_bytes()),
+        _ => return None,
+    };
+    let charset = charset.split('*').next().unwrap_or("").to_ascii_lowercase();
+    let consumed = input.len() - inner.len() + end + 2;
+    Some((decode_charset(&bytes, Some(&charset)), consumed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart_walk() {
+        let raw = b"Subject: Hi\r\nContent-Type: multipart/mixed; boundary=\"XX\"\r\n\r\n\
+preamble\r\n--XX\r\nContent-Type: text/plain\r\n\r\nHello\r\n--XX\r\n\
+Content-Type: text/html\r\nContent-Transfer-Encoding: base64\r\n\r\nPGI+aGk8L2I+\r\n--XX--\r\n";
+        let message = Part::parse(raw);
+        assert_eq!(message.children.len(), 2);
+        assert_eq!(message.find("text/plain").unwrap().text(), "Hello");
+        assert_eq!(message.find("text/html").unwrap().text(), "<b>hi</b>");
+    }
+
+    #[test]
+    fn test_encoded_words() {
+        assert_eq!(decode_encoded_words("=?UTF-8?B?SGVsbG8=?= =?utf-8?Q?_W=C3=B6rld?="), "Hello Wörld");
+        assert_eq!(decode_encoded_words("plain =?broken"), "plain =?broken");
+        for text in ["", "a", "ab", "abc", "Hello Wörld"] {
+            assert_eq!(decode_base64(encode_base64(text.as_bytes()).as_bytes()), text.as_bytes());
+        }
+        assert_eq!(encode_base64(b"ab"), "YWI=");
+    }
+
+    #[test]
+    fn test_folded_headers() {
+        let message = Part::parse(b"Subject: one\n two\nFrom: a@b\n\nbody");
+        assert_eq!(message.header("subject"), Some("one two"));
+        assert_eq!(message.body, b"body");
+    }
+
+    #[test]
+    fn test_fuzz_corpus_parses() {
+        let corpus = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus");
+        for dir in fs_entries(&corpus) {
+            for file in fs_entries(&dir) {
+                let raw = std::fs::read(&file).unwrap();
+                let message = Part::parse(&raw);
+                for part in message.walk() {
+                    let _ = part.text();
+                    let _ = part.decoded_header("Subject");
+                }
+                let _ = decode_encoded_words(&String::from_utf8_lossy(&raw));
+            }
+        }
+
+        let nested = b"Content-Type: message/rfc822\n\n".repeat(10_000);
+        assert_eq!(Part::parse(&nested).walk().len(), MAX_DEPTH + 1);
+    }
+
+    fn fs_entries(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect()
+    }
+}
@@ -0,0 +1,182 @@
+//! Publishing events to NATS. Only built with the `nats` feature.
+//!
+//! Each payload is published to `subject` over the NATS text protocol, on
+//! plain TCP; servers that require TLS are not supported. Without
+//! `jetstream` a PING round trip confirms the server has the message,
+//! which is all core NATS promises: a subscriber that is not listening
+//! misses it. With `jetstream` the publish waits for the stream's
+//! acknowledgement, whose sequence number becomes the receipt, and
+//! carries the delivery's idempotency key as `Nats-Msg-Id`, so a retry
+//! inside the stream's duplicate window is not stored twice.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::metrics;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `sinks` entry of type `nats`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct NatsConfig {
+    /// `host:port`, usually port 4222.
+    pub server: String,
+    pub subject: String,
+    pub user: Option<String>,
+    pub password: Option<SecretString>,
+    pub token: Option<SecretString>,
+    /// Wait for a JetStream acknowledgement.
+    #[serde(default)]
+    pub jetstream: bool,
+}
+
+pub struct NatsSink {
+    pub config: NatsConfig,
+}
+
+impl NatsSink {
+    fn send(&self, payload: &Value, key: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+        let stream = TcpStream::connect(&self.config.server).map_err(|e| format!("{}: {}", self.config.server, e))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let body = payload.to_string();
+        metrics::add(metrics::GATEWAY_BYTES_UPLOADED, body.len() as u64);
+        publish(stream, &self.config, &body, key).map_err(|e| format!("{}: {}", self.config.server, e).into())
+    }
+}
+
+impl Sink for NatsSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.send(payload, None).map(|_| ())
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.send(payload, Some(key))
+    }
+}
+
+/// Publish `body` on a fresh connection and wait for the confirmation.
+/// Returns the JetStream receipt, `stream:sequence`.
+pub fn publish<S: Read + Write>(stream: S, config: &NatsConfig, body: &str, key: Option<&str>) -> Result<Option<String>, String> {
+    let mut stream = BufReader::new(stream);
+    let info = read_line(&mut stream)?;
+    if !info.starts_with("INFO ") {
+        return Err(format!("unexpected greeting '{}'", info));
+    }
+    let connect = json!({
+        "verbose": false,
+        "pedantic": false,
+        "headers": true,
+        "name": "email_checker",
+        "user": config.user,
+        "pass": config.password,
+        "auth_token": config.token,
+    });
+    let mut out = format!("CONNECT {}\r\n", connect);
+    let inbox = format!("_INBOX.email_checker.{}", key.unwrap_or("once"));
+    let reply = if config.jetstream {
+        out.push_str(&format!("SUB {} 1\r\n", inbox));
+        format!(" {}", inbox)
+    } else {
+        String::new()
+    };
+    match key.filter(|_| config.jetstream) {
+        Some(key) => {
+            let headers = format!("NATS/1.0\r\nNats-Msg-Id: {}\r\n\r\n", key);
+            let total = headers.len() + body.len();
+            out.push_str(&format!("HPUB {}{} {} {}\r\n{}{}\r\n", config.subject, reply, headers.len(), total, headers, body));
+        }
+        None => out.push_str(&format!("PUB {}{} {}\r\n{}\r\n", config.subject, reply, body.len(), body)),
+    }
+    if !config.jetstream {
+        out.push_str("PING\r\n");
+    }
+    let writer = stream.get_mut();
+    writer.write_all(out.as_bytes()).and_then(|()| writer.flush()).map_err(|e| e.to_string())?;
+    loop {
+        let line = read_line(&mut stream)?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("PONG") if !config.jetstream => return Ok(None),
+            Some("PING") => {
+                let writer = stream.get_mut();
+                writer.write_all(b"PONG\r\n").and_then(|()| writer.flush()).map_err(|e| e.to_string())?;
+            }
+            Some("-ERR") => return Err(format!("server refused: {}", line.trim_start_matches("-ERR ").trim_matches('\''))),
+            Some("MSG") | Some("HMSG") => {
+                let size: usize = line.rsplit(' ').next().and_then(|n| n.parse().ok()).ok_or("malformed MSG")?;
+                let mut message = vec![0; size + 2];
+                stream.read_exact(&mut message).map_err(|e| e.to_string())?;
+                let message = String::from_utf8_lossy(&message[..size]).into_owned();
+                // A status header without a body, e.g. 503 when no stream listens.
+                if line.starts_with("HMSG") && message.starts_with("NATS/1.0 ") {
+                    let status = message.lines().next().unwrap_or_default().trim_start_matches("NATS/1.0 ");
+                    return Err(format!("no acknowledgement: {}", status));
+                }
+                return ack(&message);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The receipt in a JetStream publish acknowledgement.
+fn ack(message: &str) -> Result<Option<String>, String> {
+    let ack: Value = serde_json::from_str(message).map_err(|_| format!("unexpected acknowledgement '{}'", message))?;
+    if let Some(error) = ack.get("error") {
+        return Err(format!("stream refused: {}", error["description"].as_str().unwrap_or("unknown error")));
+    }
+    match (ack["stream"].as_str(), ack["seq"].as_u64()) {
+        (Some(stream), Some(seq)) => Ok(Some(format!("{}:{}", stream, seq))),
+        _ => Err(format!("unexpected acknowledgement '{}'", message)),
+    }
+}
+
+fn read_line<R: BufRead>(stream: &mut R) -> Result<String, String> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+        return Err("connection closed".to_string());
+    }
+    Ok(line.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imap::tests::Script;
+
+    #[test]
+    fn test_publish() {
+        let mut config = NatsConfig {
+            server: "127.0.0.1:4222".to_string(),
+            subject: "mail.new".to_string(),
+            user: None,
+            password: None,
+            token: Some("s3cret".into()),
+            jetstream: false,
+        };
+        let mut script = Script::new("INFO {\"server_id\":\"x\"}\r\nPONG\r\n");
+        assert_eq!(publish(&mut script, &config, "{}", None), Ok(None));
+        let sent = String::from_utf8(script.output).unwrap();
+        assert!(sent.contains("\"auth_token\":\"s3cret\""));
+        assert!(sent.ends_with("\r\nPUB mail.new 2\r\n{}\r\nPING\r\n"));
+
+        config.jetstream = true;
+        let ack = r#"{"stream":"MAIL","seq":42}"#;
+        let server = format!("INFO {{}}\r\nMSG _INBOX.email_checker.k1 1 {}\r\n{}\r\n", ack.len(), ack);
+        let mut script = Script::new(&server);
+        assert_eq!(publish(&mut script, &config, "{}", Some("k1")), Ok(Some("MAIL:42".to_string())));
+        let sent = String::from_utf8(script.output).unwrap();
+        assert!(sent.contains("SUB _INBOX.email_checker.k1 1\r\nHPUB mail.new _INBOX.email_checker.k1 29 31\r\nNATS/1.0\r\nNats-Msg-Id: k1\r\n\r\n{}\r\n"));
+
+        let mut script = Script::new("INFO {}\r\nHMSG _INBOX.email_checker.k1 1 16 16\r\nNATS/1.0 503\r\n\r\n\r\n");
+        assert_eq!(publish(&mut script, &config, "{}", Some("k1")), Err("no acknowledgement: 503".to_string()));
+    }
+}
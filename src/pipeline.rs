@@ -0,0 +1,186 @@
+//! The stages a message passes through between fetch and delivery.
+//!
+//! `pipeline.stages` lists them in the order they run:
+//!
+//! - `decode`: decrypt S/MIME and PGP mail and check signatures, as
+//!   configured in `decryption`
+//! - `sanitize`: strip control, zero-width and bidirectional-override
+//!   characters from the text forwarded, so the agent reads what a person
+//!   would see
+//! - `extract`: list attachments and pick out calendar invitations and
+//!   bounces
+//! - `enrich`: look the sender up in `contacts`
+//! - `classify`: label the message as configured in `classify`
+//! - `scan`: run the scanners configured in `scan`
+//! - `route`: choose the event type, sink and payload with `rules`
+//!
+//! A stage left out is skipped. `decode` has to come first, since the
+//! others work on its output, and `route` last; `enrich`, `classify` and
+//! `scan` run in the checker, after the stages that edit the parsed
+//! message. Like
+//! any key, `pipeline` can be set per entry of `accounts`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, DecryptionConfig};
+use crate::email::EmailData;
+use crate::mime::Part;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Decode,
+    Sanitize,
+    Extract,
+    Enrich,
+    Classify,
+    Scan,
+    Route,
+}
+
+impl Stage {
+    pub const ALL: &[Stage] = &[
+        Stage::Decode,
+        Stage::Sanitize,
+        Stage::Extract,
+        Stage::Enrich,
+        Stage::Classify,
+        Stage::Scan,
+        Stage::Route,
+    ];
+
+    /// Whether the stage edits the parsed message, on the parse worker.
+    fn edits_message(self) -> bool {
+        matches!(self, Stage::Sanitize | Stage::Extract)
+    }
+}
+
+/// `pipeline` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    pub stages: Vec<Stage>,
+}
+
+impl Default for PipelineConfig {
+    /// Everything but `sanitize`, which changes what is forwarded.
+    fn default() -> Self {
+        Self {
+            stages: vec![Stage::Decode, Stage::Extract, Stage::Enrich, Stage::Classify, Stage::Scan, Stage::Route],
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn has(&self, stage: Stage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if self.stages[..i].contains(stage) {
+                return Err(format!("pipeline.stages lists {:?} twice", stage));
+            }
+        }
+        if self.stages.last() != Some(&Stage::Route) {
+            return Err("pipeline.stages must end with route".to_string());
+        }
+        if self.stages.iter().skip(1).any(|&s| s == Stage::Decode) {
+            return Err("pipeline.stages must start with decode".to_string());
+        }
+        let checker = self.stages.iter().position(|s| matches!(s, Stage::Enrich | Stage::Classify | Stage::Scan));
+        if let Some(at) = checker.filter(|&at| self.stages[at..].iter().any(|s| s.edits_message())) {
+            let stage = format!("{:?}", self.stages[at]).to_lowercase();
+            return Err(format!("pipeline.stages: {} must come after sanitize and extract", stage));
+        }
+        Ok(())
+    }
+}
+
+/// Decode and parse a message, then run the stages that edit it. Returns
+/// the (decrypted) raw message too; `scan` and `route` are left to the
+/// checker.
+pub fn process(raw: &[u8], config: &PipelineConfig, decryption: &DecryptionConfig) -> (Vec<u8>, EmailData) {
+    let decode = config.has(Stage::Decode);
+    let (raw, encryption) = if decode { crypto::decrypt(raw, decryption) } else { (raw.to_vec(), None) };
+    let message = Part::parse(&raw);
+    let mut email = EmailData::from_part(&message);
+    email.encryption = encryption;
+    if decode {
+        email.signature = crypto::verify(&raw, decryption);
+    }
+    for stage in &config.stages {
+        match stage {
+            Stage::Sanitize => sanitize(&mut email),
+            Stage::Extract => email.extract(&message),
+            _ => {}
+        }
+    }
+    (raw, email)
+}
+
+/// Strip invisible characters from the text a payload is built from.
+pub fn sanitize(email: &mut EmailData) {
+    for text in [&mut email.subject, &mut email.from, &mut email.date, &mut email.body] {
+        *text = visible(text);
+    }
+    for (_, value) in &mut email.headers {
+        *value = visible(value);
+    }
+    for filename in email.attachments.iter_mut().filter_map(|a| a.filename.as_mut()) {
+        *filename = visible(filename);
+    }
+    if let Some(event) = &mut email.calendar_event {
+        for text in [&mut event.summary, &mut event.location, &mut event.organizer_name].into_iter().flatten() {
+            *text = visible(text);
+        }
+    }
+}
+
+/// `text` without control characters other than line breaks and tabs,
+/// zero-width characters and bidirectional overrides.
+fn visible(text: &str) -> String {
+    text.chars()
+        .filter(|&c| {
+            let control = c.is_control() && c != '\n' && c != '\t';
+            let invisible = matches!(c, '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2060}'..='\u{2064}'
+                | '\u{2066}'..='\u{2069}' | '\u{feff}' | '\u{ad}');
+            !control && !invisible
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stages_run_as_configured() {
+        let raw = b"Subject: Pay \xe2\x80\xaeinvoice\r\nContent-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\n\
+Content-Type: text/plain\r\n\r\nHi\x07 there\r\n--b\r\nContent-Type: application/pdf\r\n\
+Content-Disposition: attachment; filename=a.pdf\r\n\r\n%PDF\r\n--b--\r\n";
+        let decryption = DecryptionConfig::default();
+        let (_, email) = process(raw, &PipelineConfig::default(), &decryption);
+        assert_eq!((email.subject.as_str(), email.attachments.len()), ("Pay \u{202e}invoice", 1));
+
+        let config = PipelineConfig {
+            stages: vec![Stage::Decode, Stage::Sanitize, Stage::Route],
+        };
+        let (_, email) = process(raw, &config, &decryption);
+        assert_eq!(email.subject, "Pay invoice");
+        assert!(email.body.starts_with("Hi there"));
+        assert!(email.attachments.is_empty());
+    }
+
+    #[test]
+    fn test_validate_order() {
+        let stages = |stages: &[Stage]| PipelineConfig { stages: stages.to_vec() }.validate();
+        assert!(stages(Stage::ALL).is_ok() && stages(&[Stage::Route]).is_ok());
+        assert!(stages(&[Stage::Decode, Stage::Extract, Stage::Sanitize, Stage::Route]).is_ok());
+        assert!(stages(&[Stage::Decode, Stage::Extract]).is_err());
+        assert!(stages(&[Stage::Extract, Stage::Decode, Stage::Route]).is_err());
+        assert!(stages(&[Stage::Scan, Stage::Extract, Stage::Route]).is_err());
+        assert!(stages(&[Stage::Enrich, Stage::Sanitize, Stage::Route]).is_err());
+        assert!(stages(&[Stage::Extract, Stage::Extract, Stage::Route]).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Commands for the programs the checker runs.
+//!
+//! Every subprocess (curl, openssl, gpg, notmuch, the database clients,
+//! age, sops, vault, aws, apprise, scanner commands and the Python
+//! checker) starts from an empty environment. It gets [`BASE_ENV`], the
+//! variables its own tool reads, and whatever the caller sets, so a
+//! secret exported for one tool, or for nothing the checker runs, never
+//! reaches another.
+
+use std::env;
+use std::ffi::OsStr;
+use std::process::Command;
+
+/// Variables every subprocess inherits: where to find programs, whose
+/// they are, and the locale and time zone.
+pub const BASE_ENV: &[&str] = &["PATH", "HOME", "USER", "LOGNAME", "LANG", "LC_ALL", "LC_CTYPE", "TZ", "TMPDIR"];
+/// Certificate locations for programs that speak TLS.
+pub const TLS_ENV: &[&str] = &["SSL_CERT_FILE", "SSL_CERT_DIR", "OPENSSL_CONF"];
+/// Proxy settings for programs that make HTTP requests.
+pub const PROXY_ENV: &[&str] = &[
+    "http_proxy",
+    "https_proxy",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "ALL_PROXY",
+    "all_proxy",
+    "NO_PROXY",
+    "no_proxy",
+    "CURL_CA_BUNDLE",
+];
+
+/// `program` with only [`BASE_ENV`] and the `inherit`ed variables from our
+/// environment. A trailing `*` in `inherit` matches any suffix, e.g.
+/// `AWS_*`.
+pub fn command(program: impl AsRef<OsStr>, inherit: &[&str]) -> Command {
+    let mut command = Command::new(program);
+    command.env_clear();
+    let matches = |name: &str| {
+        BASE_ENV.iter().chain(inherit).any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == *pattern,
+        })
+    };
+    command.envs(env::vars_os().filter(|(name, _)| name.to_str().is_some_and(matches)));
+    command
+}
+
+/// [`command`] for a program that makes requests over the network, with
+/// [`PROXY_ENV`] and [`TLS_ENV`] as well.
+pub fn network_command(program: impl AsRef<OsStr>, inherit: &[&str]) -> Command {
+    command(program, &[inherit, PROXY_ENV, TLS_ENV].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_allowed_variables_are_passed() {
+        // Cargo sets CARGO_MANIFEST_DIR for tests.
+        let print = |inherit: &[&str]| {
+            let output = command("sh", inherit).args(["-c", "echo \"$PATH|$CARGO_MANIFEST_DIR\""]).output().unwrap();
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        let path = env::var("PATH").unwrap();
+        assert_eq!(print(&[]), format!("{}|", path));
+        assert_eq!(print(&["CARGO_*"]), format!("{}|{}", path, env!("CARGO_MANIFEST_DIR")));
+    }
+}
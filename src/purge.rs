@@ -0,0 +1,138 @@
+//! Erasure of everything kept locally about one sender.
+//!
+//! `purge --sender ADDRESS` deletes the sender's messages from the
+//! archive Maildir, `quarantine_dir` and the notmuch database, and
+//! forgets their `Message-ID`s in the state file. A message belongs to
+//! the sender if `From`, `Sender` or `Reply-To` carries the address.
+//! Log output is not touched; see `redaction.mask_emails`.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive;
+use crate::config::Config;
+use crate::mime::Part;
+use crate::redact;
+use crate::state::State;
+
+const SENDER_HEADERS: &[&str] = &["From", "Sender", "Reply-To"];
+
+/// What a purge removed (or, as a dry run, would remove).
+#[derive(Debug, Default, PartialEq)]
+pub struct Purged {
+    pub archived: usize,
+    pub quarantined: usize,
+    /// Messages removed from the notmuch database.
+    pub indexed: usize,
+    pub bytes: u64,
+    pub state_entries: usize,
+}
+
+/// Remove `sender`'s messages everywhere, saving `state` if it changed.
+pub fn purge_sender(config: &Config, state: &mut State, sender: &str, dry_run: bool) -> Result<Purged, Box<dyn Error>> {
+    let mut purged = Purged::default();
+    let mut message_ids = Vec::new();
+    if let Some(maildir) = &config.archive.maildir {
+        let files = matching(archive::messages(Path::new(maildir))?, sender, &mut message_ids)?;
+        (purged.archived, purged.bytes) = remove(&files, dry_run)?;
+    }
+    if let Some(dir) = &config.quarantine_dir {
+        let files = matching(archive::messages(Path::new(dir))?, sender, &mut message_ids)?;
+        let (count, bytes) = remove(&files, dry_run)?;
+        purged.quarantined = count;
+        purged.bytes += bytes;
+    }
+    if config.archive.notmuch {
+        let candidates = archive::notmuch_files(&config.archive, &format!("from:{}", sender))?;
+        let files = matching(candidates, sender, &mut message_ids)?;
+        let (count, bytes) = remove(&files, dry_run)?;
+        purged.indexed = count;
+        purged.bytes += bytes;
+        if count > 0 && !dry_run {
+            archive::notmuch_reindex()?;
+        }
+    }
+
+    let forgotten: Vec<&String> = message_ids.iter().filter(|id| state.delivered.contains_key(*id)).collect();
+    purged.state_entries = forgotten.len();
+    if !dry_run && !forgotten.is_empty() {
+        for id in &message_ids {
+            state.delivered.remove(id);
+        }
+        if !config.state_file.is_empty() {
+            state.save(&config.state_file)?;
+        }
+    }
+    Ok(purged)
+}
+
+/// The files among `paths` sent by `sender`, collecting their `Message-ID`s.
+fn matching(paths: Vec<PathBuf>, sender: &str, message_ids: &mut Vec<String>) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        // notmuch may list files the Maildir pass already removed.
+        let Ok(raw) = fs::read(&path) else { continue };
+        let message = Part::parse(&raw);
+        let from_sender = SENDER_HEADERS
+            .iter()
+            .filter_map(|name| message.header(name))
+            .any(|value| redact::addresses(value).iter().any(|a| a.eq_ignore_ascii_case(sender)));
+        if from_sender {
+            if let Some(id) = message.header("Message-ID") {
+                message_ids.push(id.to_string());
+            }
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn remove(files: &[PathBuf], dry_run: bool) -> Result<(usize, u64), Box<dyn Error>> {
+    let mut bytes = 0;
+    for path in files {
+        bytes += fs::metadata(path)?.len();
+        if !dry_run {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok((files.len(), bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_purge_sender() {
+        let dir = std::env::temp_dir().join(format!("email_checker_purge_{}", std::process::id()));
+        let archive_dir = dir.join("archive");
+        let quarantine_dir = dir.join("quarantine");
+        let alice = archive::write_maildir(&archive_dir, b"From: Alice <ALICE@example.com>\r\nMessage-ID: <1@x>\r\n\r\nhi").unwrap();
+        let bob = archive::write_maildir(&archive_dir, b"From: bob@example.com\r\nMessage-ID: <2@x>\r\nCc: alice@example.com\r\n\r\n").unwrap();
+        archive::write_maildir(&quarantine_dir, b"Reply-To: alice@example.com\r\nMessage-ID: <3@x>\r\n\r\n").unwrap();
+
+        let mut config = Config {
+            state_file: String::new(),
+            quarantine_dir: Some(quarantine_dir.to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+        config.archive.maildir = Some(archive_dir.to_string_lossy().into_owned());
+        let mut state = State::default();
+        for (uid, id) in [(1, "<1@x>"), (2, "<2@x>")] {
+            state.record("INBOX", 1, uid, Some(id), 0);
+        }
+
+        let dry = purge_sender(&config, &mut state, "alice@example.com", true).unwrap();
+        assert_eq!((dry.archived, dry.quarantined, dry.state_entries), (1, 1, 1));
+        assert!(alice.exists());
+
+        let purged = purge_sender(&config, &mut state, "alice@example.com", false).unwrap();
+        assert_eq!(purged, dry);
+        assert!(!alice.exists());
+        assert!(bob.exists());
+        assert_eq!(archive::messages(&quarantine_dir).unwrap().len(), 0);
+        assert_eq!(state.delivered.keys().collect::<Vec<_>>(), ["<2@x>"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
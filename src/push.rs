@@ -0,0 +1,205 @@
+//! Push notifications through self-hosted ntfy and Gotify servers.
+//!
+//! An `ntfy` or `gotify` sink sends the payload's `message` with a title,
+//! a priority and, where the service has one, a click URL. `topic`,
+//! `title`, `priority` and `click` may use `{{field}}` placeholders for
+//! payload fields, with dots for nested ones (`{{headers.Subject}}`,
+//! `{{event_type}}`), so a rule template can pass anything through.
+//! `priorities` picks the priority by event type instead, e.g. `5` for
+//! `ci_failure`: ntfy counts 1 to 5, Gotify 0 to 10.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::http;
+use crate::metrics;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+
+pub const DEFAULT_NTFY_URL: &str = "https://ntfy.sh";
+const DEFAULT_TITLE: &str = "{{headers.Subject}}";
+
+/// `sinks` entry of type `ntfy` or `gotify`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PushConfig {
+    /// Server; ntfy.sh for ntfy by default.
+    pub url: Option<String>,
+    /// ntfy topic.
+    pub topic: Option<String>,
+    /// ntfy access token or Gotify application token.
+    pub token: Option<SecretString>,
+    pub title: Option<String>,
+    pub priority: Option<String>,
+    /// Priority per event type, over `priority`.
+    pub priorities: BTreeMap<String, u8>,
+    pub click: Option<String>,
+    /// ntfy tags, shown as emoji where ntfy knows them.
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    Ntfy,
+    Gotify,
+}
+
+impl PushConfig {
+    pub fn validate(&self, service: Service) -> Result<(), String> {
+        match service {
+            Service::Ntfy if self.topic.is_none() => Err("ntfy needs topic".to_string()),
+            Service::Gotify if self.url.is_none() || self.token.is_none() => Err("gotify needs url and token".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct PushSink {
+    pub service: Service,
+    pub config: PushConfig,
+}
+
+impl PushSink {
+    /// The URL, headers and JSON body that publish `payload`.
+    pub fn request(&self, payload: &Value) -> (String, Vec<(String, String)>, Value) {
+        let config = &self.config;
+        let message = match &payload["message"] {
+            Value::String(message) => message.clone(),
+            _ => serde_json::to_string_pretty(payload).unwrap_or_default(),
+        };
+        let title = Some(render(config.title.as_deref().unwrap_or(DEFAULT_TITLE), payload)).filter(|t| !t.is_empty());
+        let click = config.click.as_deref().map(|click| render(click, payload)).filter(|c| !c.is_empty());
+        let (default, max) = match self.service {
+            Service::Ntfy => (3, 5),
+            Service::Gotify => (5, 10),
+        };
+        let priority = payload["event_type"]
+            .as_str()
+            .and_then(|event_type| config.priorities.get(event_type).copied())
+            .or_else(|| config.priority.as_deref().and_then(|p| render(p, payload).trim().parse().ok()))
+            .unwrap_or(default)
+            .min(max);
+        let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+        match self.service {
+            Service::Ntfy => {
+                if let Some(token) = &config.token {
+                    headers.push(("Authorization".to_string(), format!("Bearer {}", token.expose())));
+                }
+                let mut body = json!({
+                    "topic": render(config.topic.as_deref().unwrap_or_default(), payload),
+                    "message": message,
+                    "priority": priority,
+                });
+                for (key, value) in [("title", title), ("click", click)] {
+                    if let Some(value) = value {
+                        body[key] = json!(value);
+                    }
+                }
+                if !config.tags.is_empty() {
+                    body["tags"] = json!(config.tags);
+                }
+                (config.url.as_deref().unwrap_or(DEFAULT_NTFY_URL).to_string(), headers, body)
+            }
+            Service::Gotify => {
+                let token = config.token.as_ref().map_or("", SecretString::expose);
+                headers.push(("X-Gotify-Key".to_string(), token.to_string()));
+                let mut body = json!({"message": message, "priority": priority});
+                if let Some(title) = title {
+                    body["title"] = json!(title);
+                }
+                if let Some(click) = click {
+                    body["extras"] = json!({"client::notification": {"click": {"url": click}}});
+                }
+                let url = format!("{}/message", config.url.as_deref().unwrap_or_default().trim_end_matches('/'));
+                (url, headers, body)
+            }
+        }
+    }
+}
+
+impl Sink for PushSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        let (url, headers, body) = self.request(payload);
+        let headers: Vec<(&str, &str)> = headers.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let body = body.to_string();
+        metrics::add(metrics::GATEWAY_BYTES_UPLOADED, body.len() as u64);
+        let response = http::request("POST", &url, &headers, Some(&body), http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("{} returned HTTP {}", url, response.status).into());
+        }
+        Ok(())
+    }
+}
+
+/// `template` with each `{{path}}` replaced by that payload field, empty
+/// if it is missing.
+pub(crate) fn render(template: &str, payload: &Value) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let field = rest[start + 2..start + end].trim().split('.').try_fold(payload, |value, key| value.get(key));
+        match field {
+            Some(Value::String(text)) => out.push_str(text),
+            Some(Value::Null) | None => {}
+            Some(other) => out.push_str(&other.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_requests() {
+        let payload = json!({
+            "event_type": "ci_failure",
+            "message": "Build failed",
+            "headers": {"Subject": "CI: main is red", "X-Build-Url": "https://ci.local/7"},
+        });
+        let config: PushConfig = serde_json::from_value(json!({
+            "topic": "mail-{{event_type}}",
+            "click": "{{headers.X-Build-Url}}",
+            "priorities": {"ci_failure": 5},
+            "tags": ["warning"],
+        }))
+        .unwrap();
+        let ntfy = PushSink { service: Service::Ntfy, config: config.clone() };
+        let (url, _, body) = ntfy.request(&payload);
+        assert_eq!(url, DEFAULT_NTFY_URL);
+        assert_eq!(
+            body,
+            json!({
+                "topic": "mail-ci_failure", "message": "Build failed", "priority": 5, "title": "CI: main is red",
+                "click": "https://ci.local/7", "tags": ["warning"],
+            })
+        );
+
+        let gotify = PushSink {
+            service: Service::Gotify,
+            config: PushConfig {
+                url: Some("https://push.local/".to_string()),
+                token: Some("A1".into()),
+                priority: Some("8".to_string()),
+                priorities: BTreeMap::new(),
+                ..config
+            },
+        };
+        assert!(gotify.config.validate(Service::Gotify).is_ok());
+        let (url, headers, body) = gotify.request(&payload);
+        assert_eq!(url, "https://push.local/message");
+        assert!(headers.contains(&("X-Gotify-Key".to_string(), "A1".to_string())));
+        assert_eq!(body["priority"], 8);
+        assert_eq!(body["extras"]["client::notification"]["click"]["url"], "https://ci.local/7");
+    }
+}
@@ -0,0 +1,532 @@
+//! The Python checker (`email_checker.py`), run as a subprocess.
+//!
+//! With `"backend": "python"` each cycle is delegated to it; its output
+//! goes through our log (and redaction), and the per-message results it
+//! prints with `--json-results` feed the same metrics and state as the
+//! native checker. `--shadow` uses it to compare what each
+//! implementation would forward while the migration is under way.
+//!
+//! The script is only run if its SHA-256 matches `python.sha256`, checked
+//! before every run, so one edited in a shared directory is not executed
+//! with the account's credentials. `--allow-unpinned` runs it anyway.
+//! The interpreter runs a private copy of the bytes that were checked,
+//! not the script's path, so swapping the file after the check is too late.
+//!
+//! With `python.sandbox`, the default, the script gets a scrubbed
+//! environment: the account settings, the variables [`crate::process`]
+//! passes to every subprocess, [`PYTHON_ENV`] and those named in
+//! `python.env`, but none of our other variables. On
+//! Linux it also runs with `no_new_privs`, so nothing it starts can gain
+//! privileges through setuid, and without core dumps, which would hold
+//! the password. A seccomp filter or network namespace would have to let
+//! through the IMAP and gateway traffic the script exists for, so none
+//! is applied.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::digest::{hex, sha256};
+use crate::email::EmailData;
+use crate::secrets::PrivateFile;
+use crate::state::State;
+use crate::{log, metrics, process};
+
+pub const PYTHON_INTERPRETER: &str = "python3";
+pub const PYTHON_SCRIPT: &str = "email_checker.py";
+pub const DEFAULT_PYTHON_TIMEOUT: u64 = 600;
+/// Variables a sandboxed script inherits besides the basics, for its
+/// virtualenv and TLS to work as they do in our process.
+pub const PYTHON_ENV: &[&str] = &["VIRTUAL_ENV", "SSL_CERT_FILE", "SSL_CERT_DIR"];
+
+/// `python` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PythonConfig {
+    /// Interpreter to run, e.g. `python3.12` or `/opt/checker/venv/bin/python`.
+    pub interpreter: String,
+    /// Script path, relative to `workdir` if that is set.
+    pub script: String,
+    /// Directory to run the script in (default: the current one).
+    pub workdir: Option<String>,
+    /// Seconds a cycle may take before the script is killed; 0 disables.
+    pub timeout: u64,
+    /// Hex SHA-256 the script must have to be run.
+    pub sha256: Option<String>,
+    /// Scrub the script's environment and restrict its privileges.
+    pub sandbox: bool,
+    /// More variables a sandboxed script inherits, e.g. `PYTHONPATH`.
+    pub env: Vec<String>,
+    /// Run the script even if it does not match `sha256`; set by
+    /// `--allow-unpinned`.
+    #[serde(skip)]
+    pub allow_unpinned: bool,
+}
+
+impl Default for PythonConfig {
+    fn default() -> Self {
+        Self {
+            interpreter: PYTHON_INTERPRETER.to_string(),
+            script: PYTHON_SCRIPT.to_string(),
+            workdir: None,
+            timeout: DEFAULT_PYTHON_TIMEOUT,
+            sha256: None,
+            sandbox: true,
+            env: Vec::new(),
+            allow_unpinned: false,
+        }
+    }
+}
+
+/// One message either implementation would forward.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Forward {
+    pub message_id: Option<String>,
+    pub subject: String,
+    pub from: String,
+    pub date: String,
+    pub message: String,
+}
+
+impl Forward {
+    pub fn from_email(email: &EmailData) -> Self {
+        Self {
+            message_id: email.header("Message-ID").map(str::to_string),
+            subject: email.subject.clone(),
+            from: email.from.clone(),
+            date: email.date.clone(),
+            message: email.to_openclaw_message(),
+        }
+    }
+
+    /// Key the two sides are matched on.
+    fn key(&self) -> String {
+        match &self.message_id {
+            Some(id) => id.trim().to_string(),
+            None => format!("{}\n{}", self.from, self.subject),
+        }
+    }
+}
+
+/// A forwarding attempt reported by the script.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MessageResult {
+    pub message_id: Option<String>,
+    pub subject: String,
+    pub from: String,
+    pub sent: bool,
+    pub error: Option<String>,
+}
+
+/// How a delegated cycle went.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub code: i32,
+    pub results: Vec<MessageResult>,
+    /// The script hung and was killed.
+    pub timed_out: bool,
+}
+
+impl Outcome {
+    pub fn delivered(&self) -> usize {
+        self.results.iter().filter(|r| r.sent).count()
+    }
+}
+
+/// Environment handed to the script, so it uses the same account.
+fn checker_env(config: &Config) -> Vec<(&'static str, String)> {
+    vec![
+        ("MAILCOW_IMAP_HOST", config.mailcow_imap_host.clone()),
+        ("MAILCOW_IMAP_PORT", config.mailcow_imap_port.to_string()),
+        ("MAILCOW_USERNAME", config.mailcow_username.clone()),
+        ("MAILCOW_PASSWORD", config.mailcow_password.expose().to_string()),
+        ("OPENCLAW_GATEWAY", config.openclaw_gateway.clone()),
+        ("OPENCLAW_PORT", config.openclaw_port.to_string()),
+        ("LAST_CHECK_FILE", config.last_check_file.clone()),
+    ]
+}
+
+fn script_path(python: &PythonConfig) -> PathBuf {
+    match &python.workdir {
+        Some(dir) => Path::new(dir).join(&python.script),
+        None => PathBuf::from(&python.script),
+    }
+}
+
+/// Read the script and check it against `python.sha256`. Without
+/// `allow_unpinned`, an unpinned or modified script is an error; with it,
+/// a warning. Returns the script as checked.
+pub fn verify(python: &PythonConfig) -> Result<Vec<u8>, String> {
+    let path = script_path(python);
+    let script = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let actual = hex(&sha256(&script));
+    let problem = match &python.sha256 {
+        Some(pinned) if pinned.trim().eq_ignore_ascii_case(&actual) => return Ok(script),
+        Some(pinned) => format!("{} has SHA-256 {}, not the pinned {}", path.display(), actual, pinned),
+        None => format!("{} is not pinned; set python.sha256 to {} to run it", path.display(), actual),
+    };
+    if !python.allow_unpinned {
+        return Err(format!("{}; refusing to run it without --allow-unpinned", problem));
+    }
+    log::warn(&format!("{}; running it because of --allow-unpinned", problem));
+    Ok(script)
+}
+
+/// The command running the script, and the private copy of it that the
+/// command runs, which must outlive it.
+fn command(config: &Config, args: &[&str]) -> Result<(Command, PrivateFile), String> {
+    let python = &config.python;
+    let script = verify(python)?;
+    let name = Path::new(&python.script).file_name().map_or(PYTHON_SCRIPT.into(), |name| name.to_string_lossy());
+    let copy = PrivateFile::new(&name, &script).map_err(|e| format!("could not copy {}: {}", python.script, e))?;
+    let mut command = if python.sandbox {
+        let inherit: Vec<&str> = PYTHON_ENV.iter().copied().chain(python.env.iter().map(String::as_str)).collect();
+        process::command(&python.interpreter, &inherit)
+    } else {
+        Command::new(&python.interpreter)
+    };
+    #[cfg(target_os = "linux")]
+    if python.sandbox {
+        restrict(&mut command);
+    }
+    command.arg(copy.path()).args(args).envs(checker_env(config)).stdin(Stdio::null());
+    if let Some(dir) = &python.workdir {
+        command.current_dir(dir);
+    }
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    Ok((command, copy))
+}
+
+/// Set `no_new_privs` and disable core dumps in the child before it
+/// runs the interpreter.
+#[cfg(target_os = "linux")]
+fn restrict(command: &mut Command) {
+    #[repr(C)]
+    struct Rlimit {
+        current: u64,
+        max: u64,
+    }
+    extern "C" {
+        fn prctl(option: i32, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> i32;
+        fn setrlimit(resource: i32, limit: *const Rlimit) -> i32;
+    }
+    const PR_SET_NO_NEW_PRIVS: i32 = 38;
+    const RLIMIT_CORE: i32 = 4;
+    let pre_exec = || {
+        // Only async-signal-safe calls between fork and exec.
+        if unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0
+            || unsafe { setrlimit(RLIMIT_CORE, &Rlimit { current: 0, max: 0 }) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    };
+    unsafe { std::os::unix::process::CommandExt::pre_exec(command, pre_exec) };
+}
+
+fn spawn_error(config: &Config, e: std::io::Error) -> String {
+    format!("failed to run {} {}: {}", config.python.interpreter, config.python.script, e)
+}
+
+/// Run one Python check cycle (`--once`), relaying its output to our log
+/// and recording what it delivered in `state` and the metrics.
+pub fn run_once(config: &Config, state: &mut State) -> Result<Outcome, Box<dyn Error>> {
+    let (mut command, _script) = command(config, &["--once", "--json-results"])?;
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error(config, e))?;
+    let stderr = child.stderr.take().map(|stderr| {
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                log::warn(&format!("python: {}", line));
+            }
+        })
+    });
+    let stdout = child.stdout.take().map(|stdout| {
+        thread::spawn(move || {
+            let mut results = Vec::new();
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                match parse_result(&line) {
+                    Some(result) => results.push(result),
+                    None if !line.trim().is_empty() => log::info(&line),
+                    None => {}
+                }
+            }
+            results
+        })
+    });
+
+    let mut outcome = Outcome::default();
+    let status = wait(&mut child, config.python.timeout, &mut outcome)?;
+    if let Some(handle) = stderr {
+        let _ = handle.join();
+    }
+    if let Some(handle) = stdout {
+        outcome.results = handle.join().unwrap_or_default();
+    }
+    // Killed by a signal: report it the way a shell would.
+    outcome.code = status.code().unwrap_or_else(|| 128 + signal(&status));
+
+    let account = [("account", config.mailcow_username.as_str())];
+    metrics::inc(metrics::CYCLES);
+    metrics::inc_with(metrics::ACCOUNT_CYCLES, &account);
+    if outcome.code != 0 {
+        metrics::inc(metrics::CYCLE_ERRORS);
+        metrics::inc_with(metrics::ACCOUNT_CYCLE_ERRORS, &account);
+    }
+    if outcome.timed_out {
+        metrics::inc(metrics::SUBPROCESS_TIMEOUTS);
+    }
+    let now = chrono::Utc::now().timestamp();
+    for result in &outcome.results {
+        if result.sent {
+            metrics::inc(metrics::DELIVERED);
+            metrics::inc_with(metrics::ACCOUNT_DELIVERED, &account);
+            if let Some(id) = &result.message_id {
+                state.record_message(id, now);
+            }
+        } else {
+            metrics::inc(metrics::DELIVERY_FAILURES);
+            metrics::inc_with(metrics::ACCOUNT_DELIVERY_FAILURES, &account);
+        }
+    }
+    if outcome.delivered() > 0 && !config.state_file.is_empty() {
+        state.save(&config.state_file)?;
+    }
+    Ok(outcome)
+}
+
+/// Wait for the child, killing its whole process group once `timeout`
+/// seconds (0: no limit) have passed.
+fn wait(child: &mut Child, timeout: u64, outcome: &mut Outcome) -> io::Result<ExitStatus> {
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if timeout > 0 && Instant::now() >= deadline {
+            log::error(&format!("Python checker still running after {}s; killing it", timeout));
+            kill_group(child);
+            outcome.timed_out = true;
+            return child.wait();
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(child: &mut Child) {
+    extern "C" {
+        fn kill(pid: i32, sig: i32) -> i32;
+    }
+    const SIGKILL: i32 = 9;
+    // The child leads its own group (see `command`), so this also takes
+    // down anything it spawned.
+    if unsafe { kill(-(child.id() as i32), SIGKILL) } != 0 {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_group(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(unix)]
+fn signal(status: &std::process::ExitStatus) -> i32 {
+    std::os::unix::process::ExitStatusExt::signal(status).unwrap_or(0)
+}
+
+#[cfg(not(unix))]
+fn signal(_: &std::process::ExitStatus) -> i32 {
+    0
+}
+
+/// Run the script with `--shadow` and collect what it would forward.
+pub fn shadow(config: &Config) -> Result<Vec<Forward>, Box<dyn Error>> {
+    let (mut command, _script) = command(config, &["--shadow"])?;
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| spawn_error(config, e))?;
+    if !output.status.success() {
+        return Err(format!("{} exited with {}", config.python.script, output.status).into());
+    }
+    Ok(parse_shadow(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `{"shadow": {...}}` lines from the script's output; other lines are
+/// its usual progress messages.
+fn parse_shadow(stdout: &str) -> Vec<Forward> {
+    stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line.trim()).ok())
+        .filter_map(|value| value.get("shadow").cloned())
+        .filter_map(|value| serde_json::from_value(value).ok())
+        .collect()
+}
+
+fn parse_result(line: &str) -> Option<MessageResult> {
+    let value: Value = serde_json::from_str(line.trim()).ok()?;
+    serde_json::from_value(value.get("result")?.clone()).ok()
+}
+
+/// Describe every way the two sets differ; empty means parity.
+pub fn compare(rust: &[Forward], python: &[Forward]) -> Vec<String> {
+    let rust: BTreeMap<String, &Forward> = rust.iter().map(|f| (f.key(), f)).collect();
+    let python: BTreeMap<String, &Forward> = python.iter().map(|f| (f.key(), f)).collect();
+    let mut divergences = Vec::new();
+    for (key, r) in &rust {
+        let Some(p) = python.get(key) else {
+            divergences.push(format!("only Rust would forward '{}' from {}", r.subject, r.from));
+            continue;
+        };
+        for (field, a, b) in [
+            ("subject", &r.subject, &p.subject),
+            ("from", &r.from, &p.from),
+            ("message", &r.message, &p.message),
+        ] {
+            if a != b {
+                divergences.push(format!("'{}': {} differs (Rust {:?}, Python {:?})", r.subject, field, a, b));
+            }
+        }
+    }
+    for (key, p) in &python {
+        if !rust.contains_key(key) {
+            divergences.push(format!("only Python would forward '{}' from {}", p.subject, p.from));
+        }
+    }
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_compare() {
+        let stdout = "Found 2 new emails\n\
+{\"shadow\": {\"message_id\": \"<a@x>\", \"subject\": \"=?UTF-8?B?SMOp?=\", \"from\": \"a@x\", \"date\": \"d\", \"message\": \"m\"}}\n\
+{\"shadow\": {\"message_id\": \"<b@x>\", \"subject\": \"B\", \"from\": \"b@x\", \"date\": \"d\", \"message\": \"m\"}}\n";
+        let python = parse_shadow(stdout);
+        assert_eq!(python.len(), 2);
+
+        let rust = vec![
+            Forward { message_id: Some("<a@x>".into()), subject: "Hé".into(), from: "a@x".into(), message: "m".into(), ..Forward::default() },
+            Forward { message_id: Some("<c@x>".into()), subject: "C".into(), ..Forward::default() },
+        ];
+        let divergences = compare(&rust, &python);
+        assert_eq!(divergences.len(), 3);
+        assert!(divergences[0].contains("subject differs"));
+        assert!(divergences[1].starts_with("only Rust"));
+        assert!(divergences[2].starts_with("only Python would forward 'B'"));
+        assert!(compare(&rust, &rust).is_empty());
+    }
+
+    #[test]
+    fn test_run_once_exit_code_and_workdir() {
+        let dir = std::env::temp_dir().join(format!("email_checker_python_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // It runs from a private copy, not the checked file.
+        let script = "[ \"$0\" != check.sh ] || exit 4\necho 'Found 2 new emails'\n\
+echo '{\"result\": {\"message_id\": \"<a@x>\", \"subject\": \"A\", \"sent\": true}}'\n\
+echo '{\"result\": {\"message_id\": \"<b@x>\", \"subject\": \"B\", \"sent\": false, \"error\": \"refused\"}}'\n\
+[ \"$1\" = --once ] && [ -n \"$MAILCOW_IMAP_HOST\" ] && exit 3\n";
+        std::fs::write(dir.join("check.sh"), script).unwrap();
+        let mut config = Config {
+            python: PythonConfig {
+                interpreter: "sh".to_string(),
+                script: "check.sh".to_string(),
+                workdir: Some(dir.to_string_lossy().into_owned()),
+                sha256: Some(hex(&sha256(script.as_bytes()))),
+                ..PythonConfig::default()
+            },
+            state_file: String::new(),
+            ..Config::default()
+        };
+        let mut state = State::default();
+        let outcome = run_once(&config, &mut state).unwrap();
+        assert_eq!(outcome.code, 3);
+        assert_eq!(outcome.results.len(), 2);
+        assert_eq!(outcome.results[1].error.as_deref(), Some("refused"));
+        assert!(state.is_duplicate("<a@x>") && !state.is_duplicate("<b@x>"));
+
+        // An edited script is refused unless explicitly allowed.
+        std::fs::write(dir.join("check.sh"), format!("{}exit 0\n", script)).unwrap();
+        let refused = run_once(&config, &mut state).unwrap_err().to_string();
+        assert!(refused.contains("not the pinned") && refused.contains("--allow-unpinned"), "{}", refused);
+        config.python.allow_unpinned = true;
+        assert_eq!(run_once(&config, &mut state).unwrap().code, 3);
+        config.python.interpreter = "/nonexistent/python".to_string();
+        assert!(run_once(&config, &mut state).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandboxed_environment() {
+        let dir = std::env::temp_dir().join(format!("email_checker_python_env_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Cargo sets CARGO_MANIFEST_DIR for tests; the script must not see it.
+        let script = "[ -n \"$MAILCOW_USERNAME\" ] || exit 10\n[ -z \"$CARGO_MANIFEST_DIR\" ] || exit 11\n\
+[ ! -e /proc/self/status ] || grep -q 'NoNewPrivs:[[:space:]]*1' /proc/self/status || exit 12\n";
+        std::fs::write(dir.join("env.sh"), script).unwrap();
+        let mut config = Config {
+            mailcow_username: "me@example.com".to_string(),
+            python: PythonConfig {
+                interpreter: "sh".to_string(),
+                script: "env.sh".to_string(),
+                workdir: Some(dir.to_string_lossy().into_owned()),
+                allow_unpinned: true,
+                ..PythonConfig::default()
+            },
+            state_file: String::new(),
+            ..Config::default()
+        };
+        assert_eq!(run_once(&config, &mut State::default()).unwrap().code, 0);
+        config.python.sandbox = false;
+        assert_eq!(run_once(&config, &mut State::default()).unwrap().code, 11);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_hung_script_is_killed() {
+        let dir = std::env::temp_dir().join(format!("email_checker_python_hang_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // The grandchild keeps stdout open; only killing the group ends it.
+        std::fs::write(dir.join("hang.sh"), "sleep 30 &\nwait\n").unwrap();
+        let config = Config {
+            python: PythonConfig {
+                interpreter: "sh".to_string(),
+                script: "hang.sh".to_string(),
+                workdir: Some(dir.to_string_lossy().into_owned()),
+                timeout: 1,
+                allow_unpinned: true,
+                ..PythonConfig::default()
+            },
+            state_file: String::new(),
+            ..Config::default()
+        };
+        let before = metrics::get(metrics::SUBPROCESS_TIMEOUTS);
+        let started = Instant::now();
+        let outcome = run_once(&config, &mut State::default()).unwrap();
+        assert!(outcome.timed_out);
+        assert_eq!(outcome.code, 128 + 9);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(metrics::get(metrics::SUBPROCESS_TIMEOUTS), before + 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! Publishing events to a RabbitMQ exchange.
+//!
+//! A `rabbitmq` sink publishes each payload to `exchange` through the
+//! management plugin's HTTP API, which saves speaking AMQP; `url` is the
+//! management endpoint, usually on port 15672. `routing_key` may use the
+//! `{{field}}` placeholders of the push sinks, e.g. `mail.{{event_type}}`.
+//! Messages are persistent and carry the delivery's idempotency key as
+//! `message_id`. A publish that no queue was bound to receive counts as
+//! failed, so the event is retried instead of silently dropped.
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::http::{self, encode_component};
+use crate::metrics;
+use crate::mime::encode_base64;
+use crate::push::render;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+
+/// `sinks` entry of type `rabbitmq`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RabbitmqConfig {
+    /// Management API base URL.
+    pub url: String,
+    #[serde(default = "default_vhost")]
+    pub vhost: String,
+    pub exchange: String,
+    #[serde(default)]
+    pub routing_key: String,
+    pub username: String,
+    pub password: SecretString,
+}
+
+fn default_vhost() -> String {
+    "/".to_string()
+}
+
+pub struct RabbitmqSink {
+    pub config: RabbitmqConfig,
+}
+
+impl RabbitmqSink {
+    /// The URL and body that publish `payload`.
+    pub fn request(&self, payload: &Value, key: Option<&str>) -> (String, Value) {
+        let config = &self.config;
+        let url = format!(
+            "{}/api/exchanges/{}/{}/publish",
+            config.url.trim_end_matches('/'),
+            encode_component(&config.vhost),
+            encode_component(&config.exchange)
+        );
+        let mut properties = json!({"content_type": "application/json", "delivery_mode": 2});
+        if let Some(key) = key {
+            properties["message_id"] = json!(key);
+        }
+        let body = json!({
+            "properties": properties,
+            "routing_key": render(&config.routing_key, payload),
+            "payload": payload.to_string(),
+            "payload_encoding": "string",
+        });
+        (url, body)
+    }
+
+    fn send(&self, payload: &Value, key: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let (url, body) = self.request(payload, key);
+        let credentials = format!("{}:{}", self.config.username, self.config.password.expose());
+        let auth = format!("Basic {}", encode_base64(credentials.as_bytes()));
+        let headers = [("Content-Type", "application/json"), ("Authorization", auth.as_str())];
+        let body = body.to_string();
+        metrics::add(metrics::GATEWAY_BYTES_UPLOADED, body.len() as u64);
+        let response = http::request("POST", &url, &headers, Some(&body), http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("{} returned HTTP {}", url, response.status).into());
+        }
+        let routed: Value = serde_json::from_str(&response.body).unwrap_or_default();
+        if routed["routed"] != json!(true) {
+            return Err(format!("{}: no queue is bound to receive the message", url).into());
+        }
+        Ok(())
+    }
+}
+
+impl Sink for RabbitmqSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.send(payload, None)
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.send(payload, Some(key)).map(|()| None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_request() {
+        let config: RabbitmqConfig = serde_json::from_value(json!({
+            "url": "http://rabbit:15672/", "exchange": "mail", "routing_key": "mail.{{event_type}}",
+            "username": "checker", "password": "pw",
+        }))
+        .unwrap();
+        let (url, body) = RabbitmqSink { config }.request(&json!({"event_type": "ci"}), Some("k1"));
+        assert_eq!(url, "http://rabbit:15672/api/exchanges/%2F/mail/publish");
+        assert_eq!(body["routing_key"], "mail.ci");
+        assert_eq!(body["payload"], r#"{"event_type":"ci"}"#);
+        assert_eq!(body["properties"]["message_id"], "k1");
+    }
+}
@@ -0,0 +1,350 @@
+//! Masking of secrets in anything the checker writes out.
+//!
+//! A process-wide [`Redactor`] is installed at startup from the config.
+//! Log lines pass through [`redact`]; the payloads of forwarded mail
+//! pass through [`redact_payload`], which masks the configured secrets
+//! but leaves addresses, and unless `mask_bodies` is set the mail's own
+//! text, alone. With `payload.minimize`, [`pseudonymize_value`] also
+//! replaces every address in a payload with a keyed hash.
+
+use std::fmt;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::digest::{hex, hmac_sha256};
+
+pub const MASK: &str = "[REDACTED]";
+/// Domain of pseudonymized addresses; `.invalid` never resolves.
+pub const PSEUDONYM_DOMAIN: &str = "pseudonym.invalid";
+
+/// Keys whose values are masked in `key=value` / `key: value` text.
+const DEFAULT_PATTERNS: &[&str] = &["password", "passwd", "token", "secret", "api_key", "apikey", "authorization"];
+/// Config keys holding credentials, matched case-insensitively against
+/// the whole key or its end after a `_` or `-`, e.g. `carddav_password`
+/// or an `X-Api-Key` header.
+const CREDENTIAL_KEYS: &[&str] = &[
+    "password",
+    "pass",
+    "token",
+    "secret",
+    "api_key",
+    "access_key",
+    "secret_key",
+    "pseudonym_key",
+    "authorization",
+    "dsn",
+];
+
+/// `redaction` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    /// Extra keys to mask in addition to the built-in ones.
+    pub patterns: Vec<String>,
+    /// Mask the local part of email addresses in log output.
+    pub mask_emails: bool,
+    /// Apply the key/value patterns to forwarded mail too. Off by
+    /// default: the `code: 123456` of a login mail is usually why it is
+    /// forwarded.
+    pub mask_bodies: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    /// Literal secret values, e.g. the resolved IMAP password.
+    secrets: Vec<String>,
+    patterns: Vec<String>,
+    mask_emails: bool,
+    mask_bodies: bool,
+}
+
+static REDACTOR: RwLock<Option<Redactor>> = RwLock::new(None);
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig) -> Self {
+        let mut patterns: Vec<String> = DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect();
+        patterns.extend(config.patterns.iter().map(|p| p.to_ascii_lowercase()));
+        Self {
+            secrets: Vec::new(),
+            patterns,
+            mask_emails: config.mask_emails,
+            mask_bodies: config.mask_bodies,
+        }
+    }
+
+    /// Always mask this exact value. Very short values are ignored so
+    /// that a one-character password does not garble every line.
+    pub fn add_secret(&mut self, secret: &str) {
+        if secret.len() >= 4 && !self.secrets.iter().any(|s| s == secret) {
+            self.secrets.push(secret.to_string());
+        }
+    }
+
+    /// Mask secrets, key/value patterns and (if enabled) addresses.
+    pub fn redact(&self, text: &str) -> String {
+        let text = self.redact_secrets(text);
+        if self.mask_emails {
+            mask_emails(&text)
+        } else {
+            text
+        }
+    }
+
+    /// Mask secrets and key/value patterns only.
+    pub fn redact_secrets(&self, text: &str) -> String {
+        let mut text = self.redact_literals(text);
+        for pattern in &self.patterns {
+            text = mask_key_values(&text, pattern);
+        }
+        text
+    }
+
+    /// Mask text from a forwarded mail: the literal secrets, and the
+    /// key/value patterns only with `mask_bodies`.
+    pub fn redact_payload(&self, text: &str) -> String {
+        if self.mask_bodies {
+            self.redact_secrets(text)
+        } else {
+            self.redact_literals(text)
+        }
+    }
+
+    fn redact_literals(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.secrets {
+            text = text.replace(secret.as_str(), MASK);
+        }
+        text
+    }
+
+    /// [`Redactor::redact_secrets`] over every string of a JSON value.
+    pub fn redact_value(&self, value: &Value) -> Value {
+        map_strings(value, &|s| self.redact_secrets(s))
+    }
+}
+
+/// Replace the process-wide redactor.
+pub fn install(redactor: Redactor) {
+    *REDACTOR.write().unwrap_or_else(|e| e.into_inner()) = Some(redactor);
+}
+
+/// Redact text for logging with the installed redactor.
+pub fn redact(text: &str) -> String {
+    match &*REDACTOR.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(redactor) => redactor.redact(text),
+        None => Redactor::new(&RedactionConfig::default()).redact(text),
+    }
+}
+
+/// Mask secrets in every string of a JSON payload.
+pub fn redact_value(value: &Value) -> Value {
+    let guard = REDACTOR.read().unwrap_or_else(|e| e.into_inner());
+    let Some(redactor) = &*guard else {
+        return value.clone();
+    };
+    redactor.redact_value(value)
+}
+
+/// A config section in JSON with the strings under [`CREDENTIAL_KEYS`]
+/// masked, whatever section they are in, and every `headers` value, as
+/// [`Redactor`]s treat them too.
+pub fn mask_credentials(value: &Value) -> Value {
+    let is_credential = |key: &str| {
+        let key = key.to_ascii_lowercase().replace('-', "_");
+        CREDENTIAL_KEYS.iter().any(|k| key == *k || key.ends_with(&format!("_{}", k)))
+    };
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(mask_credentials).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| match value {
+                    Value::String(s) if !s.is_empty() && is_credential(key) => (key.clone(), Value::from(MASK)),
+                    Value::Object(headers) if key == "headers" => {
+                        (key.clone(), headers.keys().map(|name| (name.clone(), Value::from(MASK))).collect())
+                    }
+                    _ => (key.clone(), mask_credentials(value)),
+                })
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// `Debug` for config types that hold credentials: `name` and the JSON
+/// form of `value` through [`mask_credentials`].
+pub fn debug_masked(name: &str, value: &impl Serialize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let value = serde_json::to_value(value).map_err(|_| fmt::Error)?;
+    write!(f, "{} {}", name, mask_credentials(&value))
+}
+
+/// [`Redactor::redact_payload`] over every string of a mail's payload.
+pub fn redact_payload(value: &Value) -> Value {
+    let guard = REDACTOR.read().unwrap_or_else(|e| e.into_inner());
+    let Some(redactor) = &*guard else {
+        return value.clone();
+    };
+    map_strings(value, &|s| redactor.redact_payload(s))
+}
+
+fn map_strings(value: &Value, f: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(s) => Value::String(f(s)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| map_strings(v, f)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), map_strings(v, f))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Mask the value in `key=value`, `key: value` and `"key": "value"`.
+fn mask_key_values(text: &str, key: &str) -> String {
+    // ASCII lowercasing keeps byte offsets aligned with `text`.
+    let lower = text.to_ascii_lowercase();
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut search = 0;
+
+    while let Some(found) = lower[search..].find(key) {
+        let mut i = search + found + key.len();
+        search = i;
+        while i < bytes.len() && matches!(bytes[i], b'"' | b'\'' | b' ') {
+            i += 1;
+        }
+        if i >= bytes.len() || !matches!(bytes[i], b'=' | b':') {
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && matches!(bytes[i], b'"' | b'\'' | b' ') {
+            i += 1;
+        }
+        for scheme in ["bearer ", "basic "] {
+            if lower[i..].starts_with(scheme) {
+                i += scheme.len();
+            }
+        }
+        let start = i;
+        while i < bytes.len() && !matches!(bytes[i], b' ' | b'\t' | b'\r' | b'\n' | b'"' | b'\'' | b'&' | b',' | b';' | b'}') {
+            i += 1;
+        }
+        if i > start && &text[start..i] != MASK {
+            out.push_str(&text[copied..start]);
+            out.push_str(MASK);
+            copied = i;
+        }
+        search = i;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Stable stand-in for `address`: the first 16 hex digits of its
+/// HMAC-SHA256 under `key`, case-insensitively, at [`PSEUDONYM_DOMAIN`].
+pub fn pseudonym(address: &str, key: &str) -> String {
+    let digest = hmac_sha256(key.as_bytes(), address.to_lowercase().as_bytes());
+    format!("{}@{}", &hex(&digest)[..16], PSEUDONYM_DOMAIN)
+}
+
+/// Addresses in `text`, e.g. both in `Alice <alice@example.com>, bob@example.org`.
+pub fn addresses(text: &str) -> Vec<&str> {
+    text.split(is_separator).filter(|word| is_address(word)).collect()
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || "<>()\",;:[]".contains(c)
+}
+
+fn is_address(word: &str) -> bool {
+    match word.find('@') {
+        Some(at) => at > 0 && word[at + 1..].contains('.') && !word.ends_with(PSEUDONYM_DOMAIN),
+        None => false,
+    }
+}
+
+/// Replace every address in every string of a JSON payload with its
+/// [`pseudonym`].
+pub fn pseudonymize_value(value: &Value, key: &str) -> Value {
+    map_strings(value, &|text| {
+        let mut out = String::with_capacity(text.len());
+        let mut word = 0;
+        for (i, c) in text.char_indices().filter(|&(_, c)| is_separator(c)).chain([(text.len(), ' ')]) {
+            match &text[word..i] {
+                address if is_address(address) => out.push_str(&pseudonym(address, key)),
+                other => out.push_str(other),
+            }
+            if i < text.len() {
+                out.push(c);
+            }
+            word = i + c.len_utf8();
+        }
+        out
+    })
+}
+
+/// `alice@example.com` becomes `***@example.com`.
+fn mask_emails(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (i, word) in text.split(' ').enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        match word.find('@') {
+            Some(at) if at > 0 && word[at + 1..].contains('.') => {
+                let start = word[..at]
+                    .rfind(['<', '(', '"', ':', ','])
+                    .map_or(0, |p| p + 1);
+                out.push_str(&word[..start]);
+                out.push_str("***");
+                out.push_str(&word[at..]);
+            }
+            _ => out.push_str(word),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_values_and_secrets() {
+        let mut redactor = Redactor::new(&RedactionConfig {
+            patterns: vec!["X-Api-Key".to_string()],
+            mask_emails: false,
+            mask_bodies: false,
+        });
+        redactor.add_secret("hunter22");
+        assert_eq!(
+            redactor.redact("login failed: password=hunter22 token: abc123"),
+            "login failed: password=[REDACTED] token: [REDACTED]"
+        );
+        assert_eq!(redactor.redact(r#"{"x-api-key": "k-1", "user": "bob"}"#), r#"{"x-api-key": "[REDACTED]", "user": "bob"}"#);
+        assert_eq!(redactor.redact("Authorization: Bearer eyJhb.c"), "Authorization: Bearer [REDACTED]");
+        assert_eq!(redactor.redact("secret hunter22 leaked"), "secret [REDACTED] leaked");
+    }
+
+    #[test]
+    fn test_payload_text_keeps_key_values() {
+        let config = RedactionConfig { mask_bodies: false, ..RedactionConfig::default() };
+        let mut redactor = Redactor::new(&config);
+        redactor.add_secret("hunter22");
+        assert_eq!(redactor.redact_payload("Your token: 123456 (hunter22)"), "Your token: 123456 ([REDACTED])");
+        let mut redactor = Redactor::new(&RedactionConfig { mask_bodies: true, ..config });
+        redactor.add_secret("hunter22");
+        assert_eq!(redactor.redact_payload("Your token: 123456 (hunter22)"), "Your token: [REDACTED] ([REDACTED])");
+    }
+
+    #[test]
+    fn test_mask_emails() {
+        let redactor = Redactor::new(&RedactionConfig {
+            patterns: Vec::new(),
+            mask_emails: true,
+            mask_bodies: false,
+        });
+        assert_eq!(redactor.redact("From: Alice <alice@example.com>"), "From: Alice <***@example.com>");
+        assert_eq!(redactor.redact("user bob@example.org logged in"), "user ***@example.org logged in");
+        assert_eq!(redactor.redact_secrets("bob@example.org"), "bob@example.org");
+    }
+}
@@ -0,0 +1,176 @@
+//! Publishing events to a Redis stream.
+//!
+//! A `redis` sink appends each payload to `stream` with `XADD`, as the
+//! fields `event` (the payload as JSON), `event_type` and `key`, the
+//! delivery's idempotency key: streams do not deduplicate, so consumers
+//! that must not see a retried event twice skip keys they have handled.
+//! `maxlen` caps the stream, trimmed approximately as Redis prefers. The
+//! entry ID is the receipt. `tls` connects with implicit TLS under the
+//! global `tls` settings, e.g. for managed Redis on port 6380.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::dns::{self, DnsConfig};
+use crate::metrics;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+use crate::transport::{self, TlsConfig};
+
+pub const DEFAULT_PORT: usize = 6379;
+
+/// `sinks` entry of type `redis`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RedisConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: usize,
+    /// ACL user; the `default` user when only `password` is set.
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    #[serde(default)]
+    pub db: u32,
+    #[serde(default)]
+    pub tls: bool,
+    pub stream: String,
+    pub maxlen: Option<u64>,
+}
+
+fn default_port() -> usize {
+    DEFAULT_PORT
+}
+
+pub struct RedisSink {
+    pub config: RedisConfig,
+    tls: TlsConfig,
+    dns: DnsConfig,
+}
+
+impl RedisSink {
+    pub fn new(redis: &RedisConfig, config: &Config) -> Self {
+        Self { config: redis.clone(), tls: config.tls.clone(), dns: config.dns.clone() }
+    }
+
+    /// The commands, one argument list each, that append `payload`.
+    pub fn commands(&self, payload: &Value, key: Option<&str>) -> Vec<Vec<String>> {
+        let config = &self.config;
+        let mut commands = Vec::new();
+        if let Some(password) = &config.password {
+            let mut auth = vec!["AUTH".to_string()];
+            auth.extend(config.username.clone());
+            auth.push(password.expose().to_string());
+            commands.push(auth);
+        }
+        if config.db != 0 {
+            commands.push(vec!["SELECT".to_string(), config.db.to_string()]);
+        }
+        let mut xadd = vec!["XADD".to_string(), config.stream.clone()];
+        if let Some(maxlen) = config.maxlen {
+            xadd.extend(["MAXLEN".to_string(), "~".to_string(), maxlen.to_string()]);
+        }
+        xadd.extend(["*".to_string(), "event".to_string(), payload.to_string()]);
+        if let Some(event_type) = payload["event_type"].as_str() {
+            xadd.extend(["event_type".to_string(), event_type.to_string()]);
+        }
+        if let Some(key) = key {
+            xadd.extend(["key".to_string(), key.to_string()]);
+        }
+        commands.push(xadd);
+        commands
+    }
+
+    fn send(&self, payload: &Value, key: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+        let config = &self.config;
+        let addr = dns::resolve(&self.dns, &config.host, config.port)?;
+        let stream = if config.tls {
+            transport::connect_tls(&config.host, addr, &self.tls)?
+        } else {
+            transport::connect_plain(addr)?
+        };
+        let commands = self.commands(payload, key);
+        metrics::add(metrics::GATEWAY_BYTES_UPLOADED, commands.iter().flatten().map(String::len).sum::<usize>() as u64);
+        let id = execute(stream, &commands).map_err(|e| format!("{}:{}: {}", config.host, config.port, e))?;
+        Ok(Some(id))
+    }
+}
+
+impl Sink for RedisSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.send(payload, None).map(|_| ())
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.send(payload, Some(key))
+    }
+}
+
+/// Send `commands` in one pipeline and return the last reply, which for
+/// `XADD` is the entry ID.
+pub fn execute<S: Read + Write>(stream: S, commands: &[Vec<String>]) -> Result<String, String> {
+    let mut stream = BufReader::new(stream);
+    let mut out = String::new();
+    for command in commands {
+        out.push_str(&format!("*{}\r\n", command.len()));
+        for arg in command {
+            out.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+    }
+    let writer = stream.get_mut();
+    writer.write_all(out.as_bytes()).and_then(|()| writer.flush()).map_err(|e| e.to_string())?;
+    let mut last = String::new();
+    for command in commands {
+        last = reply(&mut stream).map_err(|e| format!("{} failed: {}", command[0], e))?;
+    }
+    Ok(last)
+}
+
+/// One simple string, integer or bulk string reply.
+fn reply<R: BufRead>(stream: &mut R) -> Result<String, String> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+        return Err("connection closed".to_string());
+    }
+    let line = line.trim_end();
+    match line.split_at_checked(1) {
+        Some(("+" | ":", value)) => Ok(value.to_string()),
+        Some(("-", error)) => Err(error.to_string()),
+        Some(("$", size)) => {
+            let size: usize = size.parse().map_err(|_| "null reply".to_string())?;
+            let mut value = vec![0; size + 2];
+            stream.read_exact(&mut value).map_err(|e| e.to_string())?;
+            Ok(String::from_utf8_lossy(&value[..size]).into_owned())
+        }
+        _ => Err(format!("unexpected reply '{}'", line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imap::tests::Script;
+    use serde_json::json;
+
+    #[test]
+    fn test_xadd() {
+        let redis: RedisConfig = serde_json::from_value(json!({
+            "host": "redis.local", "password": "pw", "db": 2, "stream": "mail", "maxlen": 1000,
+        }))
+        .unwrap();
+        let sink = RedisSink::new(&redis, &Config::default());
+        let commands = sink.commands(&json!({"event_type": "message"}), Some("k1"));
+        assert_eq!(commands[0], ["AUTH", "pw"]);
+        assert_eq!(commands[1], ["SELECT", "2"]);
+        let expected = ["XADD", "mail", "MAXLEN", "~", "1000", "*", "event", r#"{"event_type":"message"}"#, "event_type", "message", "key", "k1"];
+        assert_eq!(commands[2], expected);
+
+        let mut script = Script::new("+OK\r\n+OK\r\n$15\r\n1700000000000-0\r\n");
+        assert_eq!(execute(&mut script, &commands), Ok("1700000000000-0".to_string()));
+        assert!(String::from_utf8(script.output).unwrap().starts_with("*2\r\n$4\r\nAUTH\r\n$2\r\npw\r\n*2\r\n$6\r\nSELECT"));
+        let mut script = Script::new("-WRONGPASS invalid username-password pair\r\n");
+        assert_eq!(execute(&mut script, &commands), Err("AUTH failed: WRONGPASS invalid username-password pair".to_string()));
+    }
+}
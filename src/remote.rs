@@ -0,0 +1,326 @@
+//! Config fetched from a URL, so a fleet can be managed centrally.
+//!
+//! With `remote.url` set in the local config file, the JSON document at
+//! that URL is merged over the local file, before the behavior and
+//! secrets files, so the local file only holds what differs per host.
+//! With `remote.public_key` (a PEM public key) the document must come
+//! with a base64 signature at `{url}.sig`, made with
+//!
+//! ```text
+//! openssl dgst -sha256 -sign key.pem config.json | base64 > config.json.sig
+//! ```
+//!
+//! and is rejected otherwise. Plain `http://` is only accepted with a
+//! key. The last good copy is kept in `remote.cache`, with its signature
+//! checked again on every load, and used while the server cannot be
+//! reached. A document may carry an integer `version`; one older than
+//! the cached copy is refused, so an old signed document cannot be
+//! replayed. Every `refresh_interval` seconds the checker asks again
+//! with `If-None-Match` and reloads its config if the document changed.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Stdio;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::http;
+use crate::log;
+use crate::mime::decode_base64;
+use crate::process;
+use crate::redact;
+use crate::secrets::{PrivateFile, SecretString};
+
+pub const DEFAULT_REFRESH_INTERVAL: u64 = 300;
+
+/// `remote` section of the config file.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub url: Option<String>,
+    /// PEM file with the key the document is signed with.
+    pub public_key: Option<String>,
+    /// File keeping the last good document and its ETag.
+    pub cache: Option<String>,
+    /// Seconds between checks for a new version; 0 disables them.
+    pub refresh_interval: u64,
+    /// Extra request headers, e.g. an `Authorization` token.
+    pub headers: BTreeMap<String, SecretString>,
+}
+
+/// The `headers` values sent with each fetch print masked.
+impl fmt::Debug for RemoteConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        redact::debug_masked("RemoteConfig", self, f)
+    }
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            public_key: None,
+            cache: None,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+/// A verified config document.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Document {
+    pub body: String,
+    pub etag: Option<String>,
+    /// Base64 signature of `body`, so a cached copy can be checked again.
+    pub signature: Option<String>,
+}
+
+impl Document {
+    /// The document's `version`, 0 if it has none.
+    fn version(&self) -> u64 {
+        let value: Option<Value> = serde_json::from_str(&self.body).ok();
+        value.and_then(|v| v.get("version")?.as_u64()).unwrap_or(0)
+    }
+}
+
+/// Fetch the document, or `None` if it still has `etag`.
+pub fn fetch(config: &RemoteConfig, etag: Option<&str>) -> Result<Option<Document>, Box<dyn Error>> {
+    let url = config.url.as_deref().ok_or("remote.url is not set")?;
+    if !url.starts_with("https://") && config.public_key.is_none() {
+        return Err(format!("{}: remote config over plain HTTP needs remote.public_key", url).into());
+    }
+    let mut headers: Vec<(&str, &str)> = config.headers.iter().map(|(k, v)| (k.as_str(), v.expose())).collect();
+    if let Some(etag) = etag {
+        headers.push(("If-None-Match", etag));
+    }
+    let response = http::request("GET", url, &headers, None, http::DEFAULT_TIMEOUT)?;
+    if response.status == 304 {
+        return Ok(None);
+    }
+    if !response.is_success() {
+        return Err(format!("{} returned HTTP {}", url, response.status).into());
+    }
+    let mut signature = None;
+    if config.public_key.is_some() {
+        let sig_url = format!("{}.sig", url);
+        headers.retain(|(name, _)| *name != "If-None-Match");
+        let response = http::request("GET", &sig_url, &headers, None, http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("{} returned HTTP {}", sig_url, response.status).into());
+        }
+        signature = Some(response.body.trim().to_string());
+    }
+    let document = Document {
+        body: response.body.clone(),
+        etag: response.header("ETag").map(str::to_string),
+        signature,
+    };
+    check_signature(config, &document).map_err(|e| format!("{}: {}", url, e))?;
+    Ok(Some(document))
+}
+
+/// Fetch the document for startup, falling back to the cached copy.
+pub fn load(config: &RemoteConfig) -> Result<Document, Box<dyn Error>> {
+    let cached = config.cache.as_deref().and_then(|path| load_cache(config, path));
+    let fetched = fetch(config, cached.as_ref().and_then(|c| c.etag.as_deref())).and_then(|document| {
+        match (document, &cached) {
+            (Some(document), Some(cached)) if document.version() < cached.version() => Err(format!(
+                "version {} is older than the cached version {}",
+                document.version(),
+                cached.version()
+            )
+            .into()),
+            (document, _) => Ok(document),
+        }
+    });
+    match fetched {
+        Ok(Some(document)) => {
+            if let Some(path) = &config.cache {
+                if let Err(e) = save_cache(path, &document) {
+                    log::warn(&format!("could not cache remote config in {}: {}", path, e));
+                }
+            }
+            Ok(document)
+        }
+        Ok(None) => cached.ok_or_else(|| "server reported no change, but nothing is cached".into()),
+        Err(e) => match cached {
+            Some(document) => {
+                log::warn(&format!("could not fetch remote config, using the cached copy: {}", e));
+                Ok(document)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// The cached document, unless it is missing or no longer matches its
+/// signature.
+fn load_cache(config: &RemoteConfig, path: &str) -> Option<Document> {
+    let document: Document = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    match check_signature(config, &document) {
+        Ok(()) => Some(document),
+        Err(e) => {
+            log::warn(&format!("ignoring the cached remote config in {}: {}", path, e));
+            None
+        }
+    }
+}
+
+/// Write the cache readable by this user only, replacing the old copy in
+/// one step.
+fn save_cache(path: &str, document: &Document) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let _ = fs::remove_file(&tmp);
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp)?;
+    file.write_all(json!(document).to_string().as_bytes())?;
+    fs::rename(&tmp, path)
+}
+
+/// Check the document against `remote.public_key`, if one is set.
+fn check_signature(config: &RemoteConfig, document: &Document) -> Result<(), String> {
+    let Some(key) = &config.public_key else {
+        return Ok(());
+    };
+    let signature = document.signature.as_deref().ok_or("the document has no signature")?;
+    verify(document.body.as_bytes(), &decode_base64(signature.as_bytes()), key)
+}
+
+/// Check a SHA-256 signature over `data` with the PEM public key at `key`.
+pub(crate) fn verify(data: &[u8], signature: &[u8], key: &str) -> Result<(), String> {
+    let file = PrivateFile::new("config.sig", signature).map_err(|e| e.to_string())?;
+    let output = process::command("openssl", process::TLS_ENV)
+        .args(["dgst", "-sha256", "-verify", key, "-signature"])
+        .arg(file.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().map(|mut stdin| stdin.write_all(data)).transpose()?;
+            child.wait_with_output()
+        });
+    let output = output.map_err(|e| format!("failed to run openssl: {}", e))?;
+    if !output.status.success() {
+        return Err("signature verification failed".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::process::Command;
+    use std::thread;
+
+    #[test]
+    fn test_signed_fetch_and_cache() {
+        let dir = env::temp_dir().join(format!("email_checker_remote_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (key, public_key) = (dir.join("key.pem"), dir.join("public.pem"));
+        let openssl = |args: &[&str]| assert!(Command::new("openssl").args(args).current_dir(&dir).output().unwrap().status.success());
+        openssl(&["genpkey", "-algorithm", "RSA", "-pkeyopt", "rsa_keygen_bits:2048", "-out", "key.pem"]);
+        openssl(&["pkey", "-in", "key.pem", "-pubout", "-out", "public.pem"]);
+        let body = r#"{"check_interval": 60}"#;
+        fs::write(dir.join("config.json"), body).unwrap();
+        openssl(&["dgst", "-sha256", "-sign", key.to_str().unwrap(), "-out", "config.sig", "config.json"]);
+        let signature = crate::mime::encode_base64(&fs::read(dir.join("config.sig")).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            for (i, stream) in listener.incoming().take(5).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let reply = match (i, request.starts_with("GET /config.json.sig")) {
+                    (_, true) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", signature.len(), signature),
+                    (0, _) => format!("HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n{}", body.len(), body),
+                    // A copy that does not match the signature.
+                    (2, _) => "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}".to_string(),
+                    _ => {
+                        assert!(request.contains("If-None-Match: \"v1\""));
+                        "HTTP/1.1 304 Not Modified\r\nContent-Length: 0\r\n\r\n".to_string()
+                    }
+                };
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        let config = RemoteConfig {
+            url: Some(format!("http://127.0.0.1:{}/config.json", port)),
+            public_key: Some(public_key.to_string_lossy().into_owned()),
+            cache: Some(dir.join("cache.json").to_string_lossy().into_owned()),
+            ..RemoteConfig::default()
+        };
+        let document = load(&config).unwrap();
+        assert_eq!((document.body.as_str(), document.etag.as_deref()), (body, Some("\"v1\"")));
+        let tampered = load(&config);
+        assert_eq!(tampered.unwrap(), document, "falls back to the cache");
+        assert_eq!(fetch(&config, Some("\"v1\"")).unwrap(), None);
+        server.join().unwrap();
+        let unsigned = RemoteConfig { public_key: None, ..config };
+        assert!(fetch(&unsigned, None).unwrap_err().to_string().contains("needs remote.public_key"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_is_rechecked_and_not_rolled_back() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = env::temp_dir().join(format!("email_checker_remote_cache_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let openssl = |args: &[&str]| {
+            assert!(Command::new("openssl").args(args).current_dir(&dir).output().unwrap().status.success())
+        };
+        openssl(&["genpkey", "-algorithm", "RSA", "-pkeyopt", "rsa_keygen_bits:2048", "-out", "key.pem"]);
+        openssl(&["pkey", "-in", "key.pem", "-pubout", "-out", "public.pem"]);
+        let sign = |body: &str| {
+            fs::write(dir.join("doc.json"), body).unwrap();
+            openssl(&["dgst", "-sha256", "-sign", "key.pem", "-out", "doc.sig", "doc.json"]);
+            crate::mime::encode_base64(&fs::read(dir.join("doc.sig")).unwrap())
+        };
+        let (old, new) = (r#"{"version": 1}"#, r#"{"version": 2}"#);
+        let old_signature = sign(old);
+        let cached = Document { body: new.to_string(), etag: None, signature: Some(sign(new)) };
+        let cache = dir.join("cache.json");
+        save_cache(cache.to_str().unwrap(), &cached).unwrap();
+        assert_eq!(fs::metadata(&cache).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // The server replays an older document, validly signed.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).unwrap();
+                let reply = if buf[..n].starts_with(b"GET /config.json.sig") { &old_signature } else { old };
+                let reply = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", reply.len(), reply);
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        let config = RemoteConfig {
+            url: Some(format!("http://127.0.0.1:{}/config.json", port)),
+            public_key: Some(dir.join("public.pem").to_string_lossy().into_owned()),
+            cache: Some(cache.to_string_lossy().into_owned()),
+            ..RemoteConfig::default()
+        };
+        assert_eq!(load(&config).unwrap(), cached);
+        server.join().unwrap();
+
+        // A cached copy that no longer matches its signature is not used.
+        let forged = Document { body: r#"{"version": 3, "check_interval": 1}"#.to_string(), ..cached };
+        save_cache(cache.to_str().unwrap(), &forged).unwrap();
+        let offline = RemoteConfig { url: Some("http://127.0.0.1:1/config.json".to_string()), ..config };
+        assert!(load(&offline).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Retention limits for data the checker keeps locally.
+//!
+//! Archived messages older than `retain`, or beyond `max_size` in total
+//! (oldest first), are deleted by `prune`, as are state entries older
+//! than `retain`.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive;
+use crate::config::Config;
+use crate::state::State;
+
+/// Seconds between automatic prunes in continuous mode.
+pub const PRUNE_INTERVAL: u64 = 3600;
+
+/// `retention` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Maximum age, e.g. `"90d"`, `"12h"`.
+    pub retain: Option<String>,
+    /// Maximum total size, e.g. `"500MB"`, `"2GB"`.
+    pub max_size: Option<String>,
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.retain.is_some() || self.max_size.is_some()
+    }
+
+    pub fn max_age(&self) -> Result<Option<Duration>, String> {
+        self.retain.as_deref().map(parse_duration).transpose()
+    }
+
+    pub fn max_bytes(&self) -> Result<Option<u64>, String> {
+        self.max_size.as_deref().map(parse_size).transpose()
+    }
+}
+
+/// What a prune removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Pruned {
+    pub messages: usize,
+    pub bytes: u64,
+    pub state_entries: usize,
+}
+
+/// Parse a duration such as `45s`, `30m`, `12h`, `90d` or `2w`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", text))?;
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return Err(format!("invalid duration '{}' (use s, m, h, d or w)", text)),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// Parse a size such as `500KB`, `200MB` or `2GB` (powers of 1024).
+/// A bare number is taken as bytes.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size '{}'", text))?;
+    let factor = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("invalid size '{}' (use KB, MB or GB)", text)),
+    };
+    Ok(number * factor)
+}
+
+/// `1536` → `1.5 KB`, in the units [`parse_size`] accepts.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        1048576..=1073741823 => format!("{:.1} MB", bytes as f64 / 1048576.0),
+        _ => format!("{:.1} GB", bytes as f64 / 1073741824.0),
+    }
+}
+
+/// Apply the configured limits now, saving `state` if it changed.
+pub fn prune(config: &Config, state: &mut State) -> Result<Pruned, Box<dyn Error>> {
+    let retention = &config.retention;
+    let max_age = retention.max_age()?;
+    let mut pruned = Pruned::default();
+    if let Some(maildir) = &config.archive.maildir {
+        pruned = prune_maildir(Path::new(maildir), max_age, retention.max_bytes()?)?;
+    }
+    if let Some(max_age) = max_age {
+        let cutoff = chrono::Utc::now().timestamp() - max_age.as_secs() as i64;
+        pruned.state_entries = state.prune(cutoff);
+        if pruned.state_entries > 0 && !config.state_file.is_empty() {
+            state.save(&config.state_file)?;
+        }
+    }
+    Ok(pruned)
+}
+
+fn prune_maildir(maildir: &Path, max_age: Option<Duration>, max_bytes: Option<u64>) -> Result<Pruned, Box<dyn Error>> {
+    let now = SystemTime::now();
+    let mut pruned = Pruned::default();
+    let mut kept = 0u64;
+    // Newest first, so the size budget goes to the most recent mail.
+    for path in archive::messages(maildir)? {
+        let meta = fs::metadata(&path)?;
+        let age = now.duration_since(meta.modified()?).unwrap_or_default();
+        let too_old = max_age.is_some_and(|max| age > max);
+        let over_budget = max_bytes.is_some_and(|max| kept + meta.len() > max);
+        if too_old || over_budget {
+            fs::remove_file(&path)?;
+            pruned.messages += 1;
+            pruned.bytes += meta.len();
+        } else {
+            kept += meta.len();
+        }
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(parse_duration("90d").unwrap(), Duration::from_secs(90 * 86400));
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert!(parse_duration("1y").is_err());
+        assert_eq!(parse_size("2MB").unwrap(), 2 << 20);
+        assert_eq!(parse_size("10 kb").unwrap(), 10 << 10);
+        assert!(parse_size("lots").is_err());
+        assert_eq!(format_size(1536), "1.5 KB");
+    }
+
+    #[test]
+    fn test_prune_by_size_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("email_checker_prune_{}", std::process::id()));
+        let old = archive::write_maildir(&dir, &[b'a'; 100]).unwrap();
+        let new = archive::write_maildir(&dir, &[b'b'; 100]).unwrap();
+
+        let pruned = prune_maildir(&dir, None, Some(150)).unwrap();
+        assert_eq!((pruned.messages, pruned.bytes), (1, 100));
+        assert!(!old.exists());
+        assert!(new.exists());
+        assert_eq!(prune_maildir(&dir, Some(Duration::from_secs(3600)), None).unwrap().messages, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
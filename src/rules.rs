@@ -0,0 +1,303 @@
+//! Routing rules: pick an event type, sink and payload per message.
+//!
+//! Rules are evaluated in order and the first whose conditions all
+//! match wins. Messages matching no rule go to the default sink with
+//! the built-in payload.
+//!
+//! A rule can also hold its mail back, by `delay` seconds after it
+//! arrived or `until` a time of day, so e.g. newsletters all arrive at
+//! 9 am. Held mail stays unseen on the server and is noted in the state
+//! file, so the hold survives restarts.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::email::{EmailData, PayloadConfig};
+use crate::redact;
+use crate::sink::DEFAULT_SINK;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default, rename = "match")]
+    pub conditions: Conditions,
+    /// Event type template, e.g. `ci_failure` or `github_{{header.X-GitHub-Reason}}`.
+    pub event_type: Option<String>,
+    #[serde(default = "default_sink")]
+    pub sink: String,
+    /// Payload template; string leaves may contain `{{field}}` placeholders.
+    pub template: Option<Value>,
+    /// Also unsubscribe from the matched mail's list; see [`crate::unsubscribe`].
+    #[serde(default)]
+    pub unsubscribe: bool,
+    /// Seconds after arriving that matched mail is forwarded.
+    pub delay: Option<u64>,
+    /// Local time of day, e.g. `09:00`, until which matched mail is held.
+    pub until: Option<String>,
+}
+
+impl Rule {
+    /// When mail this rule matched that `arrived` is due, in Unix seconds,
+    /// if the rule holds mail back.
+    pub fn due(&self, arrived: DateTime<Local>) -> Option<i64> {
+        let delayed = self.delay.map(|secs| arrived.timestamp() + secs as i64);
+        let until = self.until_time().and_then(|time| {
+            let at = |day: chrono::NaiveDate| Local.from_local_datetime(&day.and_time(time)).earliest();
+            let day = arrived.date_naive();
+            at(day).filter(|at| *at > arrived).or_else(|| at(day.succ_opt()?)).map(|at| at.timestamp())
+        });
+        delayed.max(until)
+    }
+
+    fn until_time(&self) -> Option<NaiveTime> {
+        self.until.as_deref().and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.until.is_some() && self.until_time().is_none() {
+            return Err(format!("rule '{}': until must be a time of day such as 09:00", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// Case-insensitive substring conditions; unset conditions always match.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Conditions {
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_sink() -> String {
+    DEFAULT_SINK.to_string()
+}
+
+impl Conditions {
+    pub fn matches(&self, email: &EmailData) -> bool {
+        self.mismatches(email).is_empty()
+    }
+
+    /// The conditions `email` fails, described for `rules test`.
+    pub fn mismatches(&self, email: &EmailData) -> Vec<String> {
+        let mut failed = Vec::new();
+        let fields = [("from", &email.from, &self.from), ("subject", &email.subject, &self.subject), ("body", &email.body, &self.body)];
+        for (field, haystack, needle) in fields {
+            if let Some(needle) = needle.as_ref().filter(|n| !contains(Some(haystack), &Some(n.to_string()))) {
+                failed.push(format!("{} does not contain '{}'", field, needle));
+            }
+        }
+        for (name, needle) in &self.headers {
+            match email.header(name) {
+                None => failed.push(format!("no {} header", name)),
+                Some(value) if !contains(Some(value), &Some(needle.clone())) => {
+                    failed.push(format!("{} does not contain '{}'", name, needle));
+                }
+                Some(_) => {}
+            }
+        }
+        failed
+    }
+}
+
+fn contains(haystack: Option<&str>, needle: &Option<String>) -> bool {
+    match (haystack, needle) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some(h), Some(n)) => h.to_lowercase().contains(&n.to_lowercase()),
+    }
+}
+
+/// Outcome of routing one message.
+#[derive(Debug)]
+pub struct Route<'a> {
+    pub rule: Option<&'a Rule>,
+    pub event_type: String,
+    pub sink: &'a str,
+}
+
+pub fn route<'a>(rules: &'a [Rule], email: &EmailData) -> Route<'a> {
+    match rules.iter().find(|rule| rule.conditions.matches(email)) {
+        Some(rule) => Route {
+            rule: Some(rule),
+            event_type: rule
+                .event_type
+                .as_deref()
+                .map(|t| render(t, email, email.event_type(), &rule.name))
+                .unwrap_or_else(|| email.event_type().to_string()),
+            sink: &rule.sink,
+        },
+        None => Route {
+            rule: None,
+            event_type: email.event_type().to_string(),
+            sink: DEFAULT_SINK,
+        },
+    }
+}
+
+impl Route<'_> {
+    /// Payload for this route: the rule's template, else the built-in one.
+    /// Either carries the message's `correlation_id`, which the audit log
+    /// and sinks read from it, unless a template sets it itself. Known
+    /// secrets are masked before the payload leaves the process.
+    pub fn payload(&self, email: &EmailData, limits: &PayloadConfig) -> Value {
+        let rule_name = self.rule.map_or("", |r| r.name.as_str());
+        let mut payload = match self.rule.and_then(|r| r.template.as_ref()) {
+            Some(template) => render_value(template, email, &self.event_type, rule_name),
+            None => {
+                let mut payload = email.to_payload(limits);
+                payload["event_type"] = Value::String(self.event_type.clone());
+                payload
+            }
+        };
+        if let (Some(id), Some(fields)) = (&email.correlation_id, payload.as_object_mut()) {
+            fields.entry("correlation_id").or_insert_with(|| Value::String(id.clone()));
+        }
+        redact::redact_payload(&payload)
+    }
+}
+
+/// What `rules test` shows for a message: how each rule fared, the
+/// route chosen and the payload it produces.
+pub fn explain(rules: &[Rule], email: &EmailData, limits: &PayloadConfig) -> String {
+    let mut out = format!("Message: '{}' from {}\n", email.subject, email.from);
+    let mut matched = false;
+    for rule in rules {
+        let line = if matched {
+            "  - not evaluated, an earlier rule matched".to_string()
+        } else {
+            match rule.conditions.mismatches(email).as_slice() {
+                [] => {
+                    matched = true;
+                    "  ✓ matched".to_string()
+                }
+                failed => format!("  ✗ {}", failed.join("; ")),
+            }
+        };
+        out.push_str(&format!("{}\n{}\n", rule.name, line));
+    }
+    let route = route(rules, email);
+    out.push_str(&format!(
+        "Route: {} → sink {}, event type {}\n",
+        route.rule.map_or("no rule (default)".to_string(), |r| format!("rule {}", r.name)),
+        route.sink,
+        route.event_type
+    ));
+    let payload = serde_json::to_string_pretty(&route.payload(email, limits)).unwrap_or_default();
+    out.push_str(&format!("Payload:\n{}\n", payload));
+    out
+}
+
+fn render_value(template: &Value, email: &EmailData, event_type: &str, rule: &str) -> Value {
+    match template {
+        Value::String(s) => Value::String(render(s, email, event_type, rule)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| render_value(v, email, event_type, rule)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), render_value(v, email, event_type, rule)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Substitute `{{subject}}`, `{{from}}`, `{{date}}`, `{{body}}`,
+/// `{{message}}`, `{{event_type}}`, `{{rule}}`, `{{correlation_id}}` and
+/// `{{header.Name}}`.
+/// Unknown placeholders render as empty strings.
+pub fn render(template: &str, email: &EmailData, event_type: &str, rule: &str) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else { break };
+        out.push_str(&rest[..start]);
+        let key = rest[start + 2..start + end].trim();
+        let value = match key {
+            "subject" => email.subject.clone(),
+            "from" => email.from.clone(),
+            "date" => email.date.clone(),
+            "body" => email.body.clone(),
+            "message" => email.to_openclaw_message(),
+            "event_type" => event_type.to_string(),
+            "rule" => rule.to_string(),
+            "correlation_id" => email.correlation_id.clone().unwrap_or_default(),
+            _ => key
+                .strip_prefix("header.")
+                .and_then(|name| email.header(name))
+                .unwrap_or("")
+                .to_string(),
+        };
+        out.push_str(&value);
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<Rule> {
+        serde_json::from_str(
+            r#"[
+                {"name": "ci", "match": {"from": "notifications@github.com", "headers": {"X-GitHub-Reason": "ci_activity"}},
+                 "event_type": "ci_failure", "sink": "ci",
+                 "template": {"channel": "openclaw", "text": "CI: {{subject}} ({{header.X-GitHub-Reason}})"}},
+                {"name": "invoice", "match": {"subject": "invoice"}, "event_type": "billing_document"}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route_with_template() {
+        let email = EmailData::from_raw(
+            b"From: GitHub <notifications@github.com>\r\nX-GitHub-Reason: ci_activity\r\nSubject: Build failed\r\n\r\nlog",
+        );
+        let rules = rules();
+        let route = route(&rules, &email);
+        assert_eq!(route.rule.unwrap().name, "ci");
+        assert_eq!(route.event_type, "ci_failure");
+        assert_eq!(route.sink, "ci");
+        assert_eq!(route.payload(&email, &PayloadConfig::default())["text"], "CI: Build failed (ci_activity)");
+        let email = EmailData { correlation_id: Some("3f9a0c61d2e84b57".to_string()), ..email };
+        assert_eq!(route.payload(&email, &PayloadConfig::default())["correlation_id"], "3f9a0c61d2e84b57");
+    }
+
+    #[test]
+    fn test_route_default_payload() {
+        let email = EmailData::from_raw(b"From: billing@shop.example\r\nSubject: Your Invoice 4711\r\n\r\nhi");
+        let rules = rules();
+        let route = route(&rules, &email);
+        assert_eq!(route.sink, DEFAULT_SINK);
+        assert_eq!(route.payload(&email, &PayloadConfig::default())["event_type"], "billing_document");
+
+        let explained = explain(&rules, &email, &PayloadConfig::default());
+        assert!(explained.contains("ci\n  ✗ from does not contain 'notifications@github.com'; no X-GitHub-Reason header"));
+        assert!(explained.contains("invoice\n  ✓ matched"));
+        assert!(explained.contains("Route: rule invoice → sink openclaw, event type billing_document"));
+
+        let other = EmailData::from_raw(b"Subject: hello\r\n\r\n");
+        let route = super::route(&rules, &other);
+        assert!(route.rule.is_none());
+        assert_eq!(route.payload(&other, &PayloadConfig::default())["event_type"], "message");
+    }
+
+    #[test]
+    fn test_due() {
+        let rule: Rule = serde_json::from_str(r#"{"name": "news", "until": "09:00"}"#).unwrap();
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2026, 3, day, hour, 30, 0).unwrap();
+        assert_eq!(rule.due(at(2, 7)), Some(Local.with_ymd_and_hms(2026, 3, 2, 9, 0, 0).unwrap().timestamp()));
+        assert_eq!(rule.due(at(2, 10)), Some(Local.with_ymd_and_hms(2026, 3, 3, 9, 0, 0).unwrap().timestamp()));
+        let delayed = Rule { delay: Some(7200), until: None, ..rule.clone() };
+        assert_eq!(delayed.due(at(2, 10)), Some(at(2, 12).timestamp()));
+        assert_eq!(rules()[0].due(at(2, 10)), None);
+        assert!(Rule { until: Some("9am".to_string()), ..rule }.validate().is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! Archive copies in S3-compatible object storage.
+//!
+//! With `archive.s3` set, every delivered message is uploaded twice: the
+//! raw message as `eml/YYYY/MM/DD/HHMMSS-<hash>.eml` and its payload as
+//! the same name under `json/` with `.json`, both below `prefix`. The
+//! date comes first so lifecycle rules can expire or transition whole
+//! days, and per kind, e.g. JSON after 90 days and mail after 7 years.
+//! The hash is of the message itself, so a retried upload overwrites the
+//! same object. Requests are signed with AWS Signature Version 4, which
+//! MinIO, Backblaze B2, Wasabi and Ceph accept too.
+
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::digest::{hex, hmac_sha256, sha256};
+use crate::http::{self, encode_component, Url};
+use crate::metrics;
+use crate::secrets::SecretString;
+
+/// `archive.s3` section of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct S3Config {
+    /// E.g. `https://s3.eu-central-1.amazonaws.com` or `http://minio:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: SecretString,
+    pub secret_key: SecretString,
+    /// Key prefix, e.g. `mail/`.
+    #[serde(default)]
+    pub prefix: String,
+    /// Address the bucket as a subdomain of the endpoint rather than as
+    /// the first path segment.
+    #[serde(default)]
+    pub virtual_host: bool,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// The object key for one kind (`eml` or `json`) of a message.
+pub fn key(prefix: &str, kind: &str, raw: &[u8], now: DateTime<Utc>) -> String {
+    let hash = hex(&sha256(raw));
+    format!("{}{}/{}-{}.{}", prefix, kind, now.format("%Y/%m/%d/%H%M%S"), &hash[..16], kind)
+}
+
+/// Upload the message and its payload.
+pub fn store(config: &S3Config, raw: &[u8], payload: &Value) -> Result<(), Box<dyn Error>> {
+    let now = Utc::now();
+    put(config, &key(&config.prefix, "eml", raw, now), raw, "message/rfc822", now)?;
+    let payload = serde_json::to_vec(payload)?;
+    put(config, &key(&config.prefix, "json", raw, now), &payload, "application/json", now)
+}
+
+/// The URL of `key`, and the `Host` and path it is signed for.
+fn location(config: &S3Config, key: &str) -> Result<(String, String, String), String> {
+    let endpoint = Url::parse(&config.endpoint)?;
+    let scheme = if endpoint.https { "https" } else { "http" };
+    let default_port = if endpoint.https { 443 } else { 80 };
+    let mut host = endpoint.host.clone();
+    let path: String = key.split('/').map(|segment| format!("/{}", encode_component(segment))).collect();
+    let path = if config.virtual_host {
+        host = format!("{}.{}", config.bucket, host);
+        path
+    } else {
+        format!("/{}{}", encode_component(&config.bucket), path)
+    };
+    if endpoint.port != default_port {
+        host = format!("{}:{}", host, endpoint.port);
+    }
+    Ok((format!("{}://{}{}", scheme, host, path), host, path))
+}
+
+fn put(config: &S3Config, key: &str, body: &[u8], content_type: &str, now: DateTime<Utc>) -> Result<(), Box<dyn Error>> {
+    let (url, host, path) = location(config, key)?;
+    let payload_hash = hex(&sha256(body));
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let authorization = authorization(config, "PUT", &host, &path, &payload_hash, &amz_date);
+    let headers = [
+        ("Host", host.as_str()),
+        ("Content-Type", content_type),
+        ("x-amz-content-sha256", payload_hash.as_str()),
+        ("x-amz-date", amz_date.as_str()),
+        ("Authorization", authorization.as_str()),
+    ];
+    metrics::add(metrics::GATEWAY_BYTES_UPLOADED, body.len() as u64);
+    let response = http::request_bytes("PUT", &url, &headers, Some(body), http::DEFAULT_TIMEOUT)?;
+    if !response.is_success() {
+        let code = response.body.split("<Code>").nth(1).and_then(|rest| rest.split('<').next()).unwrap_or_default();
+        return Err(format!("uploading {} returned HTTP {} {}", key, response.status, code).trim_end().into());
+    }
+    Ok(())
+}
+
+/// The SigV4 `Authorization` header of a request without a query string,
+/// signing `host`, `x-amz-content-sha256` and `x-amz-date`.
+pub fn authorization(config: &S3Config, method: &str, host: &str, path: &str, payload_hash: &str, amz_date: &str) -> String {
+    let date = &amz_date[..8];
+    let signed = "host;x-amz-content-sha256;x-amz-date";
+    let canonical = format!(
+        "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method, path, host, payload_hash, amz_date, signed, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+    let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex(&sha256(canonical.as_bytes())));
+    let key = [config.region.as_str(), "s3", "aws4_request"]
+        .iter()
+        .fold(hmac_sha256(format!("AWS4{}", config.secret_key.expose()).as_bytes(), date.as_bytes()), |key, part| {
+            hmac_sha256(&key, part.as_bytes())
+        });
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        config.access_key.expose(),
+        scope,
+        signed,
+        hex(&hmac_sha256(&key, to_sign.as_bytes()))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_keys_and_signature() {
+        let config = S3Config {
+            endpoint: "http://minio.local:9000".to_string(),
+            bucket: "archive".to_string(),
+            region: default_region(),
+            access_key: "AKIDEXAMPLE".into(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            prefix: "mail/".to_string(),
+            virtual_host: false,
+        };
+        let now = Utc.with_ymd_and_hms(2026, 10, 14, 9, 30, 0).unwrap();
+        let key = key(&config.prefix, "eml", b"Subject: hi\r\n\r\n", now);
+        assert!(key.starts_with("mail/eml/2026/10/14/093000-") && key.ends_with(".eml"));
+        let (url, host, path) = location(&config, "mail/eml/a b.eml").unwrap();
+        assert_eq!(url, "http://minio.local:9000/archive/mail/eml/a%20b.eml");
+        assert_eq!((host.as_str(), path.as_str()), ("minio.local:9000", "/archive/mail/eml/a%20b.eml"));
+        let virtual_host = S3Config { endpoint: "https://s3.amazonaws.com".to_string(), virtual_host: true, ..config.clone() };
+        assert_eq!(location(&virtual_host, "k").unwrap().1, "archive.s3.amazonaws.com");
+
+        let empty = hex(&sha256(b""));
+        let authorization = authorization(&config, "PUT", "minio.local:9000", "/archive/k", &empty, "20261014T093000Z");
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20261014/us-east-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=b6c60d8dab645ddd5f2016274a1204f25972af67e88b1def5a8972726eb88772"
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Malware and URL scanning before a message is forwarded.
+//!
+//! Attachments are streamed to clamd (`scan.clamd`: a Unix socket path
+//! or `host:port`) and/or handed to `scan.command`, which also gets every
+//! link found in the text parts. The command is run through `sh -c` once
+//! per item, with the item on stdin and `SCAN_TYPE` (`attachment` or
+//! `url`) and `SCAN_NAME` in its environment. Like `clamscan`, it exits 0
+//! for clean, 1 for a threat (named on the first line of its output) and
+//! anything else for an error.
+//!
+//! The verdict is added to the payload as `scan`. With `action: "drop"`
+//! a message with a threat is marked seen but not forwarded. Scanner
+//! errors never drop a message; they are reported in the verdict.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mime::Part;
+use crate::process;
+use crate::transport::Stream;
+
+pub const DEFAULT_SCAN_TIMEOUT: u64 = 30;
+
+/// Links passed to `scan.command` per message, at most.
+const MAX_URLS: usize = 50;
+/// clamd rejects INSTREAM chunks above its `StreamMaxLength`; stay small.
+const CLAMD_CHUNK: usize = 64 * 1024;
+
+/// `scan` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ScanConfig {
+    pub clamd: Option<String>,
+    pub command: Option<String>,
+    /// Also pass links in the message text to `command`.
+    pub urls: bool,
+    pub action: ScanAction,
+    /// Seconds per clamd request or command run.
+    pub timeout: u64,
+    /// Variables `command` inherits besides [`crate::process::BASE_ENV`].
+    pub env: Vec<String>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            clamd: None,
+            command: None,
+            urls: true,
+            action: ScanAction::Tag,
+            timeout: DEFAULT_SCAN_TIMEOUT,
+            env: Vec::new(),
+        }
+    }
+}
+
+impl ScanConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.clamd.is_some() || self.command.is_some()
+    }
+}
+
+/// What to do with a message a scanner flagged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanAction {
+    /// Forward it with the verdict attached.
+    #[default]
+    Tag,
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Clean,
+    Threat,
+    /// Nothing was flagged, but at least one scan failed.
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// `attachment` or `url`.
+    pub kind: &'static str,
+    /// The attachment's file name or the URL.
+    pub target: String,
+    pub threat: String,
+    pub scanner: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Verdict {
+    pub status: Status,
+    pub findings: Vec<Finding>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// Scan a raw message with the configured scanners.
+pub fn scan(config: &ScanConfig, raw: &[u8]) -> Verdict {
+    let message = Part::parse(raw);
+    let timeout = Duration::from_secs(config.timeout.max(1));
+    let mut findings = Vec::new();
+    let mut errors = Vec::new();
+    let mut check = |kind, target: &str, scanner, result: Result<Option<String>, String>| match result {
+        Ok(Some(threat)) => findings.push(Finding { kind, target: target.to_string(), threat, scanner }),
+        Ok(None) => {}
+        Err(e) => errors.push(format!("{} {}: {}", scanner, target, e)),
+    };
+
+    let attachments = message.walk().into_iter().filter(|p| p.is_attachment());
+    for (i, part) in attachments.enumerate() {
+        let name = part.filename().unwrap_or_else(|| format!("attachment-{}", i + 1));
+        let data = part.decoded_body();
+        if let Some(address) = &config.clamd {
+            check("attachment", &name, "clamd", clamd(address, &data, timeout).map_err(|e| e.to_string()));
+        }
+        if let Some(command) = &config.command {
+            check("attachment", &name, "command", run_command(config, command, "attachment", &name, &data));
+        }
+    }
+    if let (Some(command), true) = (&config.command, config.urls) {
+        for url in urls(&message) {
+            check("url", &url, "command", run_command(config, command, "url", &url, url.as_bytes()));
+        }
+    }
+
+    let status = if !findings.is_empty() {
+        Status::Threat
+    } else if !errors.is_empty() {
+        Status::Error
+    } else {
+        Status::Clean
+    };
+    Verdict { status, findings, errors }
+}
+
+/// Distinct `http(s)://` links in the message's text parts.
+pub fn urls(message: &Part) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    let texts = message.walk().into_iter().filter(|p| {
+        let mime_type = p.content_type().mime_type;
+        p.children.is_empty() && (mime_type == "text/plain" || mime_type == "text/html")
+    });
+    for text in texts.map(Part::text) {
+        let mut rest = text.as_str();
+        while let Some(start) = ["http://", "https://"].iter().filter_map(|scheme| rest.find(scheme)).min() {
+            let tail = &rest[start..];
+            let end = tail
+                .find(|c: char| c.is_whitespace() || "\"'<>()[]{}".contains(c))
+                .unwrap_or(tail.len());
+            let url = tail[..end].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+            if url.len() > "https://".len() && !urls.iter().any(|u| u == url) && urls.len() < MAX_URLS {
+                urls.push(url.to_string());
+            }
+            rest = &tail[end.max(1)..];
+        }
+    }
+    urls
+}
+
+/// Stream `data` to clamd with `INSTREAM`; returns the signature name if
+/// it found one.
+fn clamd(address: &str, data: &[u8], timeout: Duration) -> io::Result<Option<String>> {
+    let mut stream: Box<dyn Stream> = if address.starts_with('/') {
+        let stream = UnixStream::connect(address)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Box::new(stream)
+    } else {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Box::new(stream)
+    };
+    stream.write_all(b"zINSTREAM\0")?;
+    for chunk in data.chunks(CLAMD_CHUNK) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        stream.write_all(chunk)?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply)?;
+    let reply = String::from_utf8_lossy(&reply);
+    let reply = reply.trim_end_matches(['\0', '\n']).trim();
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        Ok(None)
+    } else if let Some(threat) = result.strip_suffix(" FOUND") {
+        Ok(Some(threat.to_string()))
+    } else {
+        Err(io::Error::other(format!("unexpected reply '{}'", reply)))
+    }
+}
+
+/// Run the scanner command on one item, killing it after `timeout`.
+fn run_command(
+    config: &ScanConfig,
+    command: &str,
+    kind: &str,
+    name: &str,
+    input: &[u8],
+) -> Result<Option<String>, String> {
+    let timeout = Duration::from_secs(config.timeout.max(1));
+    let inherit: Vec<&str> = config.env.iter().map(String::as_str).collect();
+    let mut child = process::command("sh", &inherit)
+        .arg("-c")
+        .arg(command)
+        .env("SCAN_TYPE", kind)
+        .env("SCAN_NAME", name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run scanner: {}", e))?;
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_vec();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    let deadline = Instant::now() + timeout;
+    while child.try_wait().map_err(|e| e.to_string())?.is_none() {
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("scanner still running after {}s", timeout.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first_line = stdout.lines().next().unwrap_or("").trim();
+    match output.status.code() {
+        Some(0) => Ok(None),
+        Some(1) => Ok(Some(if first_line.is_empty() { "flagged".to_string() } else { first_line.to_string() })),
+        _ => Err(format!("scanner failed: {}", String::from_utf8_lossy(&output.stderr).trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"Subject: invoice\r\nContent-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+--b\r\nContent-Type: text/plain\r\n\r\nPay at https://evil.example/pay. Or (http://ok.example/x)\r\n\
+--b\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=\"invoice.exe\"\r\n\
+Content-Transfer-Encoding: base64\r\n\r\nTVqQ\r\n--b--\r\n";
+
+    #[test]
+    fn test_urls() {
+        let message = Part::parse(MESSAGE);
+        assert_eq!(urls(&message), ["https://evil.example/pay", "http://ok.example/x"]);
+        assert_eq!(message.walk().iter().filter(|p| p.is_attachment()).count(), 1);
+    }
+
+    #[test]
+    fn test_command_scanner() {
+        let config = ScanConfig {
+            command: Some(r#"if grep -q evil; then echo "Phishing.URL"; exit 1; fi; [ "$SCAN_TYPE" = attachment ] && echo "$SCAN_NAME" >&2 && exit 2; exit 0"#.to_string()),
+            ..ScanConfig::default()
+        };
+        assert!(!ScanConfig::default().is_enabled());
+        let verdict = scan(&config, MESSAGE);
+        assert_eq!(verdict.status, Status::Threat);
+        assert_eq!(verdict.findings.len(), 1);
+        assert_eq!(verdict.findings[0].target, "https://evil.example/pay");
+        assert_eq!(verdict.findings[0].threat, "Phishing.URL");
+        assert_eq!(verdict.errors, ["command invoice.exe: scanner failed: invoice.exe"]);
+    }
+
+    #[test]
+    fn test_clamd_instream() {
+        let path = std::env::temp_dir().join(format!("email_checker_clamd_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut command = [0u8; 10];
+            conn.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+            let mut data = Vec::new();
+            loop {
+                let mut len = [0u8; 4];
+                conn.read_exact(&mut len).unwrap();
+                let mut chunk = vec![0u8; u32::from_be_bytes(len) as usize];
+                if chunk.is_empty() {
+                    break;
+                }
+                conn.read_exact(&mut chunk).unwrap();
+                data.extend(chunk);
+            }
+            assert_eq!(data, b"MZ\x90");
+            conn.write_all(b"stream: Win.Test.EICAR FOUND\0").unwrap();
+        });
+        let config = ScanConfig {
+            clamd: Some(path.to_string_lossy().into_owned()),
+            ..ScanConfig::default()
+        };
+        let verdict = scan(&config, MESSAGE);
+        server.join().unwrap();
+        assert_eq!(verdict.status, Status::Threat);
+        assert_eq!(verdict.findings[0].target, "invoice.exe");
+        assert_eq!(verdict.findings[0].threat, "Win.Test.EICAR");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+//! Time between check cycles.
+//!
+//! By default every cycle waits `check_interval`. With `adaptive` enabled
+//! the wait halves after a cycle that delivered mail and grows by half
+//! after a quiet one, staying within `min_interval` and `max_interval`.
+//!
+//! The first cycle can be delayed, so a fleet started from one template
+//! does not poll the server in lockstep: by up to `jitter.startup` random
+//! seconds, and with `jitter.phase` by a fixed share of `check_interval`
+//! derived from the accounts polled. The phase keeps instances with
+//! different accounts spread over the interval across restarts.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::digest::sha256;
+use crate::trace;
+
+pub const DEFAULT_MIN_INTERVAL: usize = 60;
+pub const DEFAULT_MAX_INTERVAL: usize = 1800;
+
+/// `adaptive` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AdaptiveConfig {
+    pub enabled: bool,
+    pub min_interval: usize,
+    pub max_interval: usize,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+        }
+    }
+}
+
+/// `jitter` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JitterConfig {
+    /// Most random seconds to wait before the first cycle.
+    pub startup: u64,
+    /// Also wait a share of `check_interval` fixed by the account names.
+    pub phase: bool,
+}
+
+/// Wait before the first cycle of an instance polling `accounts`.
+pub fn start_delay(config: &Config, accounts: &[&str]) -> Duration {
+    let random = u64::from_le_bytes(trace::random_id());
+    let jitter = random % (config.jitter.startup + 1);
+    let phase = if config.jitter.phase { phase(accounts, config.check_interval as u64) } else { 0 };
+    Duration::from_secs(jitter + phase)
+}
+
+/// Seconds into `interval` that `accounts`, in any order, start at.
+fn phase(accounts: &[&str], interval: u64) -> u64 {
+    if interval == 0 {
+        return 0;
+    }
+    let mut accounts = accounts.to_vec();
+    accounts.sort_unstable();
+    let hash = sha256(accounts.join("\n").as_bytes());
+    u64::from_le_bytes(hash[..8].try_into().unwrap_or_default()) % interval
+}
+
+pub struct Schedule {
+    adaptive: AdaptiveConfig,
+    interval: usize,
+}
+
+impl Schedule {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            adaptive: config.adaptive.clone(),
+            interval: config.check_interval,
+        }
+    }
+
+    /// Wait before the next cycle, given how many messages the last one
+    /// delivered (`None` if it failed, which leaves the interval as is).
+    pub fn next(&mut self, delivered: Option<usize>) -> Duration {
+        let adaptive = &self.adaptive;
+        if adaptive.enabled {
+            self.interval = match delivered {
+                Some(0) => self.interval + self.interval.div_ceil(2),
+                Some(_) => self.interval / 2,
+                None => self.interval,
+            }
+            .clamp(adaptive.min_interval, adaptive.max_interval.max(adaptive.min_interval));
+        }
+        Duration::from_secs(self.interval as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_delay() {
+        let mut config = Config::default();
+        assert_eq!(start_delay(&config, &["a@example.com"]), Duration::ZERO);
+        config.jitter = JitterConfig { startup: 5, phase: true };
+        let phase = phase(&["a@example.com", "b@example.com"], 300);
+        assert_eq!(phase, super::phase(&["b@example.com", "a@example.com"], 300));
+        assert_ne!(phase, super::phase(&["c@example.com"], 300));
+        let delay = start_delay(&config, &["a@example.com", "b@example.com"]).as_secs();
+        assert!((phase..=phase + 5).contains(&delay));
+    }
+
+    #[test]
+    fn test_adaptive_interval() {
+        let mut config = Config::default();
+        let mut fixed = Schedule::new(&config);
+        assert_eq!(fixed.next(Some(3)), Duration::from_secs(300));
+
+        config.adaptive.enabled = true;
+        let mut schedule = Schedule::new(&config);
+        assert_eq!(schedule.next(Some(2)).as_secs(), 150);
+        assert_eq!(schedule.next(Some(1)).as_secs(), 75);
+        assert_eq!(schedule.next(Some(1)).as_secs(), 60);
+        assert_eq!(schedule.next(None).as_secs(), 60);
+        assert_eq!(schedule.next(Some(0)).as_secs(), 90);
+        for _ in 0..20 {
+            schedule.next(Some(0));
+        }
+        assert_eq!(schedule.next(Some(0)).as_secs(), 1800);
+    }
+}
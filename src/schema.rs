@@ -0,0 +1,479 @@
+//! JSON Schema for the config file, printed by `config schema`.
+//!
+//! The schema follows the defaults: every key of [`Config::default`]
+//! becomes a property typed after, and defaulting to, its default value,
+//! with unknown keys rejected so typos are caught. Keys whose default
+//! says nothing about what they accept (unset options that are not
+//! strings, maps, lists of objects, fixed sets of words) are described
+//! by hand in [`overrides`].
+
+use serde_json::{json, Map, Value};
+
+use crate::config::Config;
+use crate::pipeline::Stage;
+use crate::sink::DEFAULT_SINK;
+use crate::sms::DEFAULT_MAX_CHARS;
+
+pub const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// The schema of a whole config file.
+pub fn config_schema() -> Value {
+    let defaults = serde_json::to_value(Config::default()).unwrap_or_default();
+    let mut schema = describe("", &defaults);
+    schema["$schema"] = json!(DRAFT);
+    schema["title"] = json!("email_checker config");
+    // Lets editors find the schema from the file itself.
+    schema["properties"]["$schema"] = json!({"type": "string"});
+    schema["properties"]["profiles"] = json!({
+        "type": "object",
+        "additionalProperties": {"$ref": "#"},
+        "description": "Named sets of keys merged over the file when selected with --profile.",
+    });
+    schema["properties"]["include"] = json!({
+        "type": "array",
+        "items": {"type": "string"},
+        "description": "Files merged over this one, in order; globs match file names.",
+    });
+    schema
+}
+
+/// Schemas for keys, by dotted path, that cannot be told from their default.
+fn overrides(path: &str) -> Option<Value> {
+    let string_map = json!({"type": "object", "additionalProperties": {"type": "string"}});
+    let conditions = json!({
+        "type": "object",
+        "properties": {
+            "from": {"type": ["string", "null"]},
+            "subject": {"type": ["string", "null"]},
+            "body": {"type": ["string", "null"]},
+            "headers": string_map,
+        },
+        "additionalProperties": false,
+    });
+    let provider = json!({
+        "type": "object",
+        "properties": {
+            "vault": {"type": "string"},
+            "aws_secret": {"type": "string"},
+            "env": {"type": "string"},
+            "file": {"type": "string"},
+        },
+        "minProperties": 1,
+        "maxProperties": 1,
+        "additionalProperties": false,
+    });
+    let push = |kind: &str, required: &[&str]| {
+        json!({
+            "type": "object",
+            "properties": {
+                "type": {"const": kind},
+                "url": {"type": ["string", "null"]},
+                "topic": {"type": ["string", "null"]},
+                "token": {"type": ["string", "null"]},
+                "title": {"type": ["string", "null"]},
+                "priority": {"type": ["string", "null"]},
+                "priorities": {"type": "object", "additionalProperties": {"type": "integer", "minimum": 0}},
+                "click": {"type": ["string", "null"]},
+                "tags": {"type": "array", "items": {"type": "string"}},
+            },
+            "required": ([&["type"], required].concat()),
+            "additionalProperties": false,
+        })
+    };
+    let schema = match path {
+        "mailcow_password" => json!({"oneOf": [{"type": "string"}, provider], "default": ""}),
+        "mailcow_password_provider" => json!({"oneOf": [{"type": "null"}, provider]}),
+        "auth_mechanism" => json!({"enum": [null, "login", "plain", "cram-md5", "ntlm", "xoauth2"]}),
+        "backend" => json!({"enum": ["native", "python"]}),
+        "scan.action" => json!({"enum": ["tag", "drop"]}),
+        "logging.output" => json!({"enum": ["console", "syslog", "journald"]}),
+        "pipeline.stages" => json!({"type": "array", "items": {"enum": Stage::ALL}}),
+        "metrics.labels" => json!({"type": "array", "items": {"enum": ["account", "rule"]}, "default": ["account", "rule"]}),
+        "tls.starttls" => json!({"type": ["boolean", "null"]}),
+        "check_interval" | "min_check_interval" => {
+            json!({"type": ["integer", "string"], "minimum": 0, "pattern": "^ *[0-9]+ *[smhdw]? *$"})
+        }
+        "archive.notmuch_tags" | "redaction.patterns" | "contacts.vip" | "python.env" | "scan.env" => {
+            json!({"type": "array", "items": {"type": "string"}})
+        }
+        "tracing.headers" | "remote.headers" | "dns.hosts" | "contacts.headers" | "classify.headers" => string_map,
+        "openclaw_gateways" => json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "gateway": {"type": "string"},
+                    "port": {"type": ["integer", "null"], "minimum": 0},
+                    "priority": {"type": "integer", "minimum": 0, "default": 0},
+                    "weight": {"type": "integer", "minimum": 0, "default": 1},
+                },
+                "required": ["gateway"],
+                "additionalProperties": false,
+            },
+        }),
+        "accounts" => json!({"type": "array", "items": {"$ref": "#"}}),
+        "archive.s3" => json!({
+            "type": ["object", "null"],
+            "properties": {
+                "endpoint": {"type": "string"},
+                "bucket": {"type": "string"},
+                "region": {"type": "string", "default": "us-east-1"},
+                "access_key": {"type": "string"},
+                "secret_key": {"type": "string"},
+                "prefix": {"type": "string", "default": ""},
+                "virtual_host": {"type": "boolean", "default": false},
+            },
+            "required": ["endpoint", "bucket", "access_key", "secret_key"],
+            "additionalProperties": false,
+        }),
+        "sinks" => {
+            // Sinks behind features only exist when they are built.
+            #[allow(unused_mut)]
+            let mut sinks = json!({
+                "type": "object",
+                "additionalProperties": {"oneOf": [
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "openclaw"},
+                            "gateway": {"type": ["string", "null"]},
+                            "port": {"type": ["integer", "null"], "minimum": 0},
+                        },
+                        "required": ["type"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "webhook"},
+                            "url": {"type": "string"},
+                            "headers": string_map,
+                        },
+                        "required": ["type", "url"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "smtp"},
+                            "to": {"type": "array", "items": {"type": "string"}, "minItems": 1},
+                            "from": {"type": ["string", "null"]},
+                            "host": {"type": ["string", "null"]},
+                            "port": {"type": ["integer", "null"], "minimum": 0},
+                        },
+                        "required": ["type", "to"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "sms"},
+                            "provider": {"enum": ["generic", "twilio", "vonage", "messagebird"], "default": "generic"},
+                            "to": {"type": "array", "items": {"type": "string"}, "minItems": 1},
+                            "from": {"type": ["string", "null"]},
+                            "account": {"type": ["string", "null"]},
+                            "token": {"type": ["string", "null"]},
+                            "url": {"type": ["string", "null"]},
+                            "headers": string_map,
+                            "template": {},
+                            "max_chars": {"type": "integer", "minimum": 1, "default": DEFAULT_MAX_CHARS},
+                        },
+                        "required": ["type", "to"],
+                        "additionalProperties": false,
+                    },
+                    push("ntfy", &["topic"]),
+                    push("gotify", &["url", "token"]),
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "apprise"},
+                            "urls": {"type": "array", "items": {"type": "string"}, "minItems": 1},
+                        },
+                        "required": ["type", "urls"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {"type": {"const": "jsonl"}, "path": {"type": ["string", "null"]}},
+                        "required": ["type"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "redis"},
+                            "host": {"type": "string"},
+                            "port": {"type": "integer", "minimum": 0, "default": crate::redis::DEFAULT_PORT},
+                            "username": {"type": ["string", "null"]},
+                            "password": {"type": ["string", "null"]},
+                            "db": {"type": "integer", "minimum": 0, "default": 0},
+                            "tls": {"type": "boolean", "default": false},
+                            "stream": {"type": "string"},
+                            "maxlen": {"type": ["integer", "null"], "minimum": 1},
+                        },
+                        "required": ["type", "host", "stream"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "rabbitmq"},
+                            "url": {"type": "string"},
+                            "vhost": {"type": "string", "default": "/"},
+                            "exchange": {"type": "string"},
+                            "routing_key": {"type": "string", "default": ""},
+                            "username": {"type": "string"},
+                            "password": {"type": "string"},
+                        },
+                        "required": ["type", "url", "exchange", "username", "password"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "sql"},
+                            "driver": {"enum": ["sqlite", "postgres", "mysql"]},
+                            "database": {"type": "string"},
+                            "host": {"type": ["string", "null"]},
+                            "port": {"type": ["integer", "null"], "minimum": 0, "maximum": 65535},
+                            "user": {"type": ["string", "null"]},
+                            "password": {"type": ["string", "null"]},
+                        },
+                        "required": ["type", "driver", "database"],
+                        "additionalProperties": false,
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "type": {"const": "digest"},
+                            "sink": {"type": "string"},
+                            "window": {"type": "integer", "minimum": 1, "default": crate::summary::DEFAULT_WINDOW},
+                            "max_subjects": {
+                                "type": "integer",
+                                "minimum": 0,
+                                "default": crate::summary::DEFAULT_MAX_SUBJECTS,
+                            },
+                            "file": {"type": ["string", "null"]},
+                        },
+                        "required": ["type", "sink"],
+                        "additionalProperties": false,
+                    },
+                ]},
+            });
+            #[cfg(feature = "kafka")]
+            sinks["additionalProperties"]["oneOf"].as_array_mut().unwrap().push(json!({
+                "type": "object",
+                "properties": {
+                    "type": {"const": "kafka"},
+                    "url": {"type": "string"},
+                    "topic": {"type": "string"},
+                    "key": {"type": "string", "default": crate::kafka::DEFAULT_KEY},
+                    "headers": string_map,
+                },
+                "required": ["type", "url", "topic"],
+                "additionalProperties": false,
+            }));
+            #[cfg(feature = "nats")]
+            sinks["additionalProperties"]["oneOf"].as_array_mut().unwrap().push(json!({
+                "type": "object",
+                "properties": {
+                    "type": {"const": "nats"},
+                    "server": {"type": "string"},
+                    "subject": {"type": "string"},
+                    "user": {"type": ["string", "null"]},
+                    "password": {"type": ["string", "null"]},
+                    "token": {"type": ["string", "null"]},
+                    "jetstream": {"type": "boolean", "default": false},
+                },
+                "required": ["type", "server", "subject"],
+                "additionalProperties": false,
+            }));
+            sinks
+        }
+        "rules" => json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                    "match": conditions,
+                    "event_type": {"type": ["string", "null"]},
+                    "sink": {"type": "string", "default": DEFAULT_SINK},
+                    "template": {},
+                    "unsubscribe": {"type": "boolean", "default": false},
+                    "delay": {"type": ["integer", "null"], "minimum": 0},
+                    "until": {"type": ["string", "null"], "pattern": "^[0-9]{1,2}:[0-9]{2}$"},
+                },
+                "required": ["name"],
+                "additionalProperties": false,
+            },
+        }),
+        "watchdogs" => json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                    "match": conditions,
+                    "within": {"type": ["integer", "string"], "minimum": 1, "pattern": "^ *[0-9]+ *[smhdw]? *$"},
+                },
+                "required": ["name", "within"],
+                "additionalProperties": false,
+            },
+        }),
+        _ => return None,
+    };
+    Some(schema)
+}
+
+/// Keys in the config file `value` that [`config_schema`] does not know,
+/// as dotted paths, each with the known key at its level it is most
+/// likely a typo of.
+pub fn unknown_keys(value: &Value) -> Vec<(String, Option<String>)> {
+    let root = config_schema();
+    let mut unknown = Vec::new();
+    walk(&root, &root, value, "", &mut unknown);
+    unknown
+}
+
+fn walk(root: &Value, schema: &Value, value: &Value, path: &str, unknown: &mut Vec<(String, Option<String>)>) {
+    let schema = if schema.get("$ref").is_some() { root } else { schema };
+    if let Some(branches) = schema["oneOf"].as_array() {
+        // A tagged sink picks its branch by `type`; otherwise only an
+        // unambiguous object branch can be checked.
+        let objects: Vec<&Value> = branches.iter().filter(|b| b.get("properties").is_some()).collect();
+        let tag = &value["type"];
+        let tagged = objects.iter().find(|b| !tag.is_null() && &b["properties"]["type"]["const"] == tag);
+        match (tagged, objects.as_slice()) {
+            (Some(branch), _) | (None, [branch]) => walk(root, branch, value, path, unknown),
+            _ => {}
+        }
+        return;
+    }
+    let join = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+    match value {
+        Value::Object(map) => {
+            let properties = schema["properties"].as_object();
+            for (key, value) in map {
+                match (properties.and_then(|p| p.get(key)), &schema["additionalProperties"]) {
+                    (Some(property), _) => walk(root, property, value, &join(key), unknown),
+                    (None, Value::Bool(false)) => {
+                        let known = properties.into_iter().flat_map(|p| p.keys()).map(String::as_str);
+                        let suggestion = closest(key, known, (key.len() / 3).max(2)).map(join);
+                        unknown.push((join(key), suggestion));
+                    }
+                    (None, Value::Object(_)) => walk(root, &schema["additionalProperties"], value, &join(key), unknown),
+                    _ => {}
+                }
+            }
+        }
+        Value::Array(items) if schema.get("items").is_some() => {
+            for (i, item) in items.iter().enumerate() {
+                walk(root, &schema["items"], item, &format!("{}[{}]", path, i), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The candidate at the smallest edit distance from `word`, if it is at
+/// most `max_distance` edits away.
+pub fn closest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>, max_distance: usize) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (distance(word, candidate), candidate))
+        .filter(|&(d, _)| d <= max_distance)
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb { diagonal } else { 1 + diagonal.min(above).min(row[j]) };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+fn describe(path: &str, default: &Value) -> Value {
+    if let Some(mut schema) = overrides(path) {
+        if !default.is_null() && schema.get("default").is_none() {
+            schema["default"] = default.clone();
+        }
+        return schema;
+    }
+    match default {
+        // Unset options are strings unless overridden.
+        Value::Null => json!({"type": ["string", "null"]}),
+        Value::Bool(_) => json!({"type": "boolean", "default": default}),
+        Value::Number(n) if n.is_f64() => json!({"type": "number", "default": default}),
+        Value::Number(_) => json!({"type": "integer", "minimum": 0, "default": default}),
+        Value::String(_) => json!({"type": "string", "default": default}),
+        Value::Array(items) => {
+            let mut item = items.first().map(|i| describe(&format!("{}[]", path), i)).unwrap_or_else(|| json!({}));
+            if let Some(item) = item.as_object_mut() {
+                item.remove("default");
+            }
+            json!({"type": "array", "items": item, "default": default})
+        }
+        Value::Object(map) => {
+            let properties: Map<String, Value> = map
+                .iter()
+                .map(|(key, value)| {
+                    let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    (key.clone(), describe(&path, value))
+                })
+                .collect();
+            json!({"type": "object", "properties": properties, "additionalProperties": false})
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Property schemas below `schema`, with their dotted paths.
+    fn properties(path: &str, schema: &Value, out: &mut Vec<(String, Value)>) {
+        for (key, property) in schema["properties"].as_object().into_iter().flatten() {
+            let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+            properties(&path, property, out);
+            out.push((path, property.clone()));
+        }
+    }
+
+    #[test]
+    fn test_schema_covers_config() {
+        let schema = config_schema();
+        let mut all = Vec::new();
+        properties("", &schema, &mut all);
+        assert!(all.iter().any(|(path, _)| path == "payload.max_body_chars"));
+        for (path, property) in &all {
+            // Empty maps and lists need an override to say what they hold.
+            let typed = ["type", "enum", "oneOf", "const"].iter().any(|k| property.get(k).is_some());
+            assert!(typed, "{} has no type", path);
+            assert!(property.get("items").is_none_or(|item| item != &json!({})), "{} has untyped items", path);
+            assert!(property.get("properties") != Some(&json!({})), "{} accepts no keys", path);
+        }
+        // Every word an enum allows is accepted by the config.
+        for (path, property) in &all {
+            for word in property["enum"].as_array().into_iter().flatten() {
+                let mut config = json!({});
+                let mut at = &mut config;
+                for key in path.split('.') {
+                    at = &mut at[key];
+                }
+                *at = word.clone();
+                assert!(serde_json::from_value::<Config>(config).is_ok(), "{} = {}", path, word);
+            }
+        }
+    }
+}
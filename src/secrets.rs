@@ -0,0 +1,396 @@
+//! Access to encrypted configuration and external secret stores.
+//!
+//! Files encrypted with [age](https://age-encryption.org) or
+//! [sops](https://github.com/getsops/sops) are decrypted by running the
+//! respective tool, so no crypto code lives in this crate. Without an
+//! identity file, `age` prompts for the passphrase on the terminal.
+//!
+//! Secret providers work the same way: Vault and AWS Secrets Manager
+//! are queried through their CLIs, which pick up the usual
+//! `VAULT_ADDR`/`VAULT_TOKEN` and AWS credential chain. Each tool only
+//! sees the variables it reads; see [`crate::process`].
+//!
+//! Secrets handed to a subprocess go through its stdin, its environment
+//! or a [`PrivateFile`], never its command line, which any local user
+//! can read.
+
+use std::env;
+use std::error::Error;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::process;
+
+/// Environment variable naming the age identity (private key) file.
+pub const AGE_IDENTITY_ENV: &str = "EMAIL_CHECKER_AGE_IDENTITY";
+
+/// Variables sops reads for its key services.
+const SOPS_ENV: &[&str] = &[
+    "SOPS_*",
+    "AWS_*",
+    "VAULT_*",
+    "AZURE_*",
+    "GOOGLE_APPLICATION_CREDENTIALS",
+    "GNUPGHOME",
+    "GPG_AGENT_INFO",
+    "GPG_TTY",
+    "TERM",
+];
+
+const AGE_ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+const AGE_BINARY_HEADER: &[u8] = b"age-encryption.org/";
+
+static PRIVATE_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encryption {
+    None,
+    Age,
+    Sops,
+}
+
+/// Detect how a config file is encrypted from its contents.
+pub fn detect(contents: &[u8]) -> Encryption {
+    if contents.starts_with(AGE_ARMOR_HEADER) || contents.starts_with(AGE_BINARY_HEADER) {
+        return Encryption::Age;
+    }
+    let is_sops = serde_json::from_slice::<serde_json::Value>(contents)
+        .ok()
+        .is_some_and(|v| v.get("sops").is_some_and(|s| s.get("mac").is_some()));
+    if is_sops {
+        Encryption::Sops
+    } else {
+        Encryption::None
+    }
+}
+
+/// Read a file, decrypting it first when it is age- or sops-encrypted.
+pub fn read_decrypted(path: &str) -> Result<String, Box<dyn Error>> {
+    let contents = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    match detect(&contents) {
+        Encryption::None => Ok(String::from_utf8(contents).map_err(|e| format!("{}: {}", path, e))?),
+        Encryption::Age => {
+            let mut command = process::command("age", &["TERM"]);
+            command.arg("--decrypt");
+            if let Ok(identity) = env::var(AGE_IDENTITY_ENV) {
+                command.arg("--identity").arg(identity);
+            }
+            run_decrypt(command.arg(path), "age")
+        }
+        Encryption::Sops => {
+            let mut command = process::network_command("sops", SOPS_ENV);
+            command.args(["--decrypt", "--input-type", "json", "--output-type", "json"]);
+            run_decrypt(command.arg(path), "sops")
+        }
+    }
+}
+
+fn run_decrypt(command: &mut Command, tool: &str) -> Result<String, Box<dyn Error>> {
+    // stdin/stderr stay attached to the terminal for passphrase prompts.
+    let output = command
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", tool, e))?;
+    if !output.status.success() {
+        return Err(format!("{} could not decrypt the config ({})", tool, output.status).into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Where a credential is fetched from, e.g. `{"vault": "secret/mailcow#password"}`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretProvider {
+    /// `path#field` in Vault's KV store.
+    Vault(String),
+    /// Secrets Manager ARN or name, with an optional `#key` for JSON secrets.
+    AwsSecret(String),
+    /// Environment variable name.
+    Env(String),
+    /// File whose trimmed contents are the secret.
+    File(String),
+}
+
+impl SecretProvider {
+    pub fn resolve(&self) -> Result<String, Box<dyn Error>> {
+        match self {
+            SecretProvider::Vault(reference) => {
+                let (path, field) = reference
+                    .split_once('#')
+                    .ok_or_else(|| format!("vault reference '{}' needs a #field", reference))?;
+                let mut command = process::network_command("vault", &["VAULT_*"]);
+                command.args(["kv", "get"]).arg(format!("-field={}", field)).arg(path);
+                run_provider(&mut command, "vault")
+            }
+            SecretProvider::AwsSecret(reference) => {
+                let (id, key) = match reference.rsplit_once('#') {
+                    Some((id, key)) => (id, Some(key)),
+                    None => (reference.as_str(), None),
+                };
+                let mut command = process::network_command("aws", &["AWS_*"]);
+                command
+                    .args(["secretsmanager", "get-secret-value", "--secret-id", id])
+                    .args(["--query", "SecretString", "--output", "text"]);
+                let secret = run_provider(&mut command, "aws")?;
+                match key {
+                    Some(key) => json_field(&secret, key),
+                    None => Ok(secret),
+                }
+            }
+            SecretProvider::Env(name) => env::var(name).map_err(|_| format!("{} is not set", name).into()),
+            SecretProvider::File(path) => Ok(fs::read_to_string(path)
+                .map_err(|e| format!("{}: {}", path, e))?
+                .trim()
+                .to_string()),
+        }
+    }
+
+    /// Write a new value for the secret. Values go to the CLIs on stdin,
+    /// never on the command line.
+    pub fn store(&self, value: &str) -> Result<(), Box<dyn Error>> {
+        match self {
+            SecretProvider::Vault(reference) => {
+                let (path, field) = reference
+                    .split_once('#')
+                    .ok_or_else(|| format!("vault reference '{}' needs a #field", reference))?;
+                // `patch` keeps the other fields stored at the path.
+                let mut command = process::network_command("vault", &["VAULT_*"]);
+                command.args(["kv", "patch", path]).arg(format!("{}=-", field));
+                run_provider_with_input(&mut command, "vault", value).map(|_| ())
+            }
+            SecretProvider::AwsSecret(reference) => {
+                let (id, secret) = match reference.rsplit_once('#') {
+                    Some((id, key)) => {
+                        let current = SecretProvider::AwsSecret(id.to_string()).resolve()?;
+                        let mut json: serde_json::Value = serde_json::from_str(&current)?;
+                        json[key] = serde_json::Value::String(value.to_string());
+                        (id, json.to_string())
+                    }
+                    None => (reference.as_str(), value.to_string()),
+                };
+                let mut command = process::network_command("aws", &["AWS_*"]);
+                command
+                    .args(["secretsmanager", "put-secret-value", "--secret-id", id])
+                    .args(["--secret-string", "file:///dev/stdin"]);
+                run_provider_with_input(&mut command, "aws", &secret).map(|_| ())
+            }
+            SecretProvider::Env(name) => {
+                Err(format!("cannot store into environment variable {}; set it yourself", name).into())
+            }
+            SecretProvider::File(path) => {
+                let mut options = fs::OpenOptions::new();
+                options.write(true).create(true).truncate(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let mut file = options.open(path).map_err(|e| format!("{}: {}", path, e))?;
+                file.write_all(format!("{}\n", value).as_bytes())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn run_provider(command: &mut Command, tool: &str) -> Result<String, Box<dyn Error>> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", tool, e))?;
+    provider_output(output, tool)
+}
+
+fn run_provider_with_input(command: &mut Command, tool: &str, input: &str) -> Result<String, Box<dyn Error>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run {}: {}", tool, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    provider_output(child.wait_with_output()?, tool)
+}
+
+fn provider_output(output: Output, tool: &str) -> Result<String, Box<dyn Error>> {
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} failed: {}", tool, stderr.trim()).into());
+    }
+    Ok(String::from_utf8(output.stdout)?.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn json_field(secret: &str, key: &str) -> Result<String, Box<dyn Error>> {
+    let value: serde_json::Value = serde_json::from_str(secret)?;
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| format!("secret has no string key '{}'", key).into())
+}
+
+/// A file for handing a subprocess what must not go on its command line,
+/// readable by this user only and removed on drop. It lives in a new
+/// directory of its own, so nothing another user put in the temporary
+/// directory, a file or a symlink, is written through.
+pub struct PrivateFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl PrivateFile {
+    pub fn new(name: &str, contents: &[u8]) -> io::Result<Self> {
+        let dir = private_dir()?;
+        let file = Self { path: dir.join(name), dir };
+        let mut out = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&file.path)?;
+        out.write_all(contents)?;
+        Ok(file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PrivateFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// Create a fresh 0700 directory under the temporary directory. The name
+/// need not be secret: creating it fails if it already exists, and then
+/// the next name is tried.
+fn private_dir() -> io::Result<PathBuf> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    let mut attempts = 0;
+    loop {
+        let n = PRIVATE_DIRS.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("email_checker_{}_{}_{}", std::process::id(), nanos, n));
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => attempts += 1,
+            result => return result.map(|()| dir),
+        }
+    }
+}
+
+/// A password, token or key from the config.
+///
+/// Its bytes are overwritten with zeros when it is dropped, and it has no
+/// `Display` while its `Debug` prints [`crate::redact::MASK`], so it
+/// cannot end up in a log line by accident; [`SecretString::expose`] is
+/// the one way to read it. It serializes as the plain string, since the
+/// config is merged and written back as JSON. Copies left behind by the
+/// parser or by reallocation are not reached.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(crate::redact::MASK)
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        // Zeros are valid UTF-8, and volatile writes are not optimized
+        // away as dead stores.
+        for byte in unsafe { self.0.as_mut_vec() }.iter_mut() {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"-----BEGIN AGE ENCRYPTED FILE-----\nYWdl\n"), Encryption::Age);
+        assert_eq!(detect(b"age-encryption.org/v1\n-> X25519 abc\n"), Encryption::Age);
+        assert_eq!(
+            detect(br#"{"mailcow_password": "ENC[AES256_GCM,data:x]", "sops": {"mac": "ENC[...]"}}"#),
+            Encryption::Sops
+        );
+        assert_eq!(detect(br#"{"check_interval": 60}"#), Encryption::None);
+    }
+
+    #[test]
+    fn test_provider_parse_and_resolve() {
+        let provider: SecretProvider = serde_json::from_str(r#"{"vault": "secret/mailcow#password"}"#).unwrap();
+        assert_eq!(provider, SecretProvider::Vault("secret/mailcow#password".to_string()));
+        let provider: SecretProvider = serde_json::from_str(r#"{"aws_secret": "arn:aws:secretsmanager:eu-west-1:1:secret:mc#pw"}"#).unwrap();
+        assert!(matches!(provider, SecretProvider::AwsSecret(_)));
+
+        assert!(SecretProvider::Vault("secret/mailcow".to_string()).resolve().is_err());
+        assert_eq!(json_field(r#"{"pw": "x1"}"#, "pw").unwrap(), "x1");
+        assert_eq!(SecretProvider::Env("PATH".to_string()).resolve().unwrap(), env::var("PATH").unwrap());
+    }
+
+    #[test]
+    fn test_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let file = PrivateFile::new("headers", b"X-API-Key: s3cret\n").unwrap();
+        let path = file.path().to_path_buf();
+        assert_eq!(fs::read(&path).unwrap(), b"X-API-Key: s3cret\n");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::metadata(path.parent().unwrap()).unwrap().permissions().mode() & 0o777, 0o700);
+        drop(file);
+        assert!(!path.parent().unwrap().exists());
+    }
+
+    #[test]
+    fn test_store_file() {
+        let path = env::temp_dir().join(format!("email_checker_store_{}", std::process::id()));
+        let provider = SecretProvider::File(path.to_string_lossy().into_owned());
+        provider.store("app-pass").unwrap();
+        assert_eq!(provider.resolve().unwrap(), "app-pass");
+        assert!(SecretProvider::Env("X".to_string()).store("y").is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_secret_string_is_masked() {
+        let config: crate::config::Config = serde_json::from_str(r#"{"mailcow_password": "hunter22"}"#).unwrap();
+        assert_eq!(config.mailcow_password.expose(), "hunter22");
+        assert!(!format!("{:?}", config).contains("hunter22"));
+        assert_eq!(serde_json::to_value(&config).unwrap()["mailcow_password"], "hunter22");
+    }
+}
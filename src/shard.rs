@@ -0,0 +1,63 @@
+//! Splitting the account list across instances with `--shard I/N`.
+//!
+//! An account belongs to shard `hash(username) mod N`, so every instance
+//! works out the same split from the same list without talking to the
+//! others, and adding or removing an account moves no other account.
+//! Changing `N` reassigns accounts, so restart all instances together.
+
+use crate::digest::sha256;
+
+/// Environment variable selecting the shard (overridden by `--shard`).
+pub const SHARD_ENV: &str = "EMAIL_CHECKER_SHARD";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// 1-based.
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// Parse `I/N`, e.g. `2/5`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid shard '{}'; expected I/N with 1 <= I <= N", text);
+        let (index, count) = text.split_once('/').ok_or_else(invalid)?;
+        let (index, count): (u64, u64) = (
+            index.trim().parse().map_err(|_| invalid())?,
+            count.trim().parse().map_err(|_| invalid())?,
+        );
+        if index == 0 || index > count {
+            return Err(invalid());
+        }
+        Ok(Self { index, count })
+    }
+
+    pub fn owns(&self, account: &str) -> bool {
+        let hash = sha256(account.to_ascii_lowercase().as_bytes());
+        let mut bucket = [0u8; 8];
+        bucket.copy_from_slice(&hash[..8]);
+        u64::from_be_bytes(bucket) % self.count == self.index - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shards_partition_accounts() {
+        assert_eq!(Shard::parse("2/5"), Ok(Shard { index: 2, count: 5 }));
+        assert!(Shard::parse("0/5").is_err() && Shard::parse("6/5").is_err() && Shard::parse("2").is_err());
+
+        let accounts: Vec<String> = (0..200).map(|i| format!("user{}@example.com", i)).collect();
+        let shards: Vec<Shard> = (1..=5).map(|index| Shard { index, count: 5 }).collect();
+        let mut sizes = [0; 5];
+        for account in &accounts {
+            let owners: Vec<usize> = (0..5).filter(|&i| shards[i].owns(account)).collect();
+            assert_eq!(owners.len(), 1, "{} has owners {:?}", account, owners);
+            sizes[owners[0]] += 1;
+        }
+        assert!(sizes.iter().all(|&n| n > 20), "uneven split {:?}", sizes);
+        assert_eq!(shards[0].owns("User0@Example.com"), shards[0].owns("user0@example.com"));
+    }
+}
@@ -0,0 +1,211 @@
+//! Routing rules compiled to a Sieve script and uploaded with ManageSieve.
+//!
+//! The script keeps mail matching any rule in the inbox and files the
+//! rest into `sieve.unmatched_folder`, so the server does the coarse
+//! sorting and the checker only sees mail a rule wants; unmatched mail
+//! then no longer reaches the default sink. Conditions compile to
+//! `:contains` tests, which compare case-insensitively like the checker
+//! does. The script is stored as `sieve.script` on the ManageSieve server
+//! (port 4190, upgraded with STARTTLS and logged into with `PLAIN`) and
+//! made the active one, replacing whichever script was active before.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::mime::encode_base64;
+use crate::rules::{Conditions, Rule};
+use crate::{dns, transport};
+
+pub const DEFAULT_PORT: usize = 4190;
+pub const DEFAULT_SCRIPT: &str = "email_checker";
+pub const DEFAULT_UNMATCHED_FOLDER: &str = "Unrouted";
+
+/// `sieve` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SieveConfig {
+    /// ManageSieve server; the IMAP host by default.
+    pub host: Option<String>,
+    pub port: usize,
+    /// Name the script is stored under.
+    pub script: String,
+    /// Folder receiving mail that matches no rule.
+    pub unmatched_folder: String,
+    /// Upload the script every time the checker starts.
+    pub push_on_start: bool,
+}
+
+impl Default for SieveConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: DEFAULT_PORT,
+            script: DEFAULT_SCRIPT.to_string(),
+            unmatched_folder: DEFAULT_UNMATCHED_FOLDER.to_string(),
+            push_on_start: false,
+        }
+    }
+}
+
+/// The Sieve script for `rules`.
+pub fn compile(rules: &[Rule], unmatched_folder: &str) -> String {
+    let uses_body = rules.iter().any(|r| r.conditions.body.is_some());
+    let mut script = String::from("# Generated by email_checker from its routing rules; edits are overwritten.\n");
+    script.push_str(if uses_body { "require [\"fileinto\", \"body\"];\n" } else { "require [\"fileinto\"];\n" });
+    for rule in rules {
+        script.push_str(&format!("\n# {}\nif {} {{\n    keep;\n    stop;\n}}\n", rule.name.replace('\n', " "), test(&rule.conditions)));
+    }
+    script.push_str(&format!("\nfileinto {};\n", quote(unmatched_folder)));
+    script
+}
+
+fn test(conditions: &Conditions) -> String {
+    let header = |name: &str, needle: &str| format!("header :contains {} {}", quote(name), quote(needle));
+    let mut tests = Vec::new();
+    if let Some(from) = &conditions.from {
+        tests.push(header("from", from));
+    }
+    if let Some(subject) = &conditions.subject {
+        tests.push(header("subject", subject));
+    }
+    if let Some(body) = &conditions.body {
+        tests.push(format!("body :text :contains {}", quote(body)));
+    }
+    tests.extend(conditions.headers.iter().map(|(name, needle)| header(name, needle)));
+    match tests.as_slice() {
+        [] => "true".to_string(),
+        [test] => test.clone(),
+        _ => format!("allof ({})", tests.join(", ")),
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Compile the rules and make them the active script on the server.
+pub fn push(config: &Config) -> Result<(), Box<dyn Error>> {
+    if config.rules.is_empty() {
+        return Err("no rules to compile; the script would file away all mail".into());
+    }
+    let sieve = &config.sieve;
+    let script = compile(&config.rules, &sieve.unmatched_folder);
+    let host = sieve.host.as_deref().unwrap_or(&config.mailcow_imap_host);
+    let addr = dns::resolve(&config.dns, host, sieve.port)?;
+    let mut session = Session::new(transport::connect_sieve(host, addr, &config.tls)?)?;
+    session.authenticate(&config.mailcow_username, config.mailcow_password.expose())?;
+    session.put_script(&sieve.script, &script)?;
+    session.set_active(&sieve.script)?;
+    session.logout()?;
+    Ok(())
+}
+
+/// A ManageSieve connection (RFC 5804).
+pub struct Session<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Session<S> {
+    /// Start on a fresh stream by reading the server's capabilities.
+    pub fn new(stream: S) -> Result<Self, String> {
+        let mut session = Self { stream: BufReader::new(stream) };
+        session.response()?;
+        Ok(session)
+    }
+
+    pub fn authenticate(&mut self, username: &str, password: &str) -> Result<(), String> {
+        let initial = encode_base64(format!("\0{}\0{}", username, password).as_bytes());
+        self.command(&format!("AUTHENTICATE \"PLAIN\" \"{}\"", initial))
+            .map_err(|e| format!("authentication failed: {}", e))
+    }
+
+    pub fn put_script(&mut self, name: &str, script: &str) -> Result<(), String> {
+        self.command(&format!("PUTSCRIPT {} {{{}+}}\r\n{}", quote(name), script.len(), script))
+    }
+
+    pub fn set_active(&mut self, name: &str) -> Result<(), String> {
+        self.command(&format!("SETACTIVE {}", quote(name)))
+    }
+
+    pub fn logout(&mut self) -> Result<(), String> {
+        self.command("LOGOUT")
+    }
+
+    fn command(&mut self, command: &str) -> Result<(), String> {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .and_then(|()| stream.flush())
+            .map_err(|e| e.to_string())?;
+        self.response()
+    }
+
+    /// Read up to the `OK`, or the `NO`/`BYE` line as the error.
+    fn response(&mut self) -> Result<(), String> {
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                return Err("connection closed".to_string());
+            }
+            let line = line.trim_end();
+            let (status, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match status.to_ascii_uppercase().as_str() {
+                "OK" => return Ok(()),
+                "NO" | "BYE" => return Err(self.reason(rest).map_err(|e| e.to_string())?),
+                _ => {}
+            }
+        }
+    }
+
+    /// The human-readable part of a `NO` or `BYE`, which may be a literal.
+    fn reason(&mut self, rest: &str) -> std::io::Result<String> {
+        let literal = rest.rsplit_once('{').and_then(|(_, n)| n.strip_suffix('}')).and_then(|n| n.parse().ok());
+        let Some(length) = literal else {
+            return Ok(rest.trim_start_matches(|c| c != '"').trim_matches('"').to_string());
+        };
+        let mut text = vec![0; length];
+        self.stream.read_exact(&mut text)?;
+        Ok(String::from_utf8_lossy(&text).trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imap::tests::Script;
+
+    #[test]
+    fn test_compile_rules() {
+        let rules: Vec<Rule> = serde_json::from_value(serde_json::json!([
+            {"name": "ci", "match": {"from": "notifications@github.com", "headers": {"X-GitHub-Reason": "ci_activity"}}},
+            {"name": "quoted", "match": {"body": "say \"hi\""}},
+        ]))
+        .unwrap();
+        let script = compile(&rules, "Unrouted");
+        assert!(script.contains("require [\"fileinto\", \"body\"];\n"));
+        assert!(script.contains(
+            "# ci\nif allof (header :contains \"from\" \"notifications@github.com\", \
+             header :contains \"X-GitHub-Reason\" \"ci_activity\") {\n    keep;\n    stop;\n}\n"
+        ));
+        assert!(script.contains("if body :text :contains \"say \\\"hi\\\"\" {"));
+        assert!(script.ends_with("\nfileinto \"Unrouted\";\n"));
+    }
+
+    #[test]
+    fn test_upload_session() {
+        let server = "\"IMPLEMENTATION\" \"Dovecot Pigeonhole\"\r\n\"SASL\" \"PLAIN\"\r\nOK \"ready\"\r\n\
+OK \"Logged in.\"\r\n\
+NO {27}\r\nline 2: unknown command 'x'\r\n";
+        let mut session = Session::new(Script::new(server)).unwrap();
+        session.authenticate("bot", "secret").unwrap();
+        assert_eq!(session.put_script("email_checker", "x;\n").unwrap_err(), "line 2: unknown command 'x'");
+        let sent = String::from_utf8(session.stream.into_inner().output).unwrap();
+        assert_eq!(
+            sent,
+            "AUTHENTICATE \"PLAIN\" \"AGJvdABzZWNyZXQ=\"\r\nPUTSCRIPT \"email_checker\" {3+}\r\nx;\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,478 @@
+//! Delivery targets for routed events.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::apprise::AppriseSink;
+use crate::audit::{AuditLog, Audited};
+use crate::config::Config;
+use crate::jsonl::JsonlSink;
+use crate::push::{PushConfig, PushSink, Service};
+use crate::rabbitmq::{RabbitmqConfig, RabbitmqSink};
+use crate::redis::{RedisConfig, RedisSink};
+use crate::secrets::SecretString;
+use crate::smtp::SmtpSink;
+use crate::sms::{SmsConfig, SmsSink};
+use crate::sql::{SqlConfig, SqlSink};
+use crate::summary::{DigestConfig, DigestSink};
+use crate::{http, log, metrics, redact, trace};
+
+/// Name of the implicit sink that posts to the configured OpenClaw gateway.
+pub const DEFAULT_SINK: &str = "openclaw";
+
+/// `sinks` entry in the config file, tagged by `type`.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// An OpenClaw gateway; unset fields fall back to the global gateway.
+    Openclaw {
+        gateway: Option<String>,
+        port: Option<usize>,
+    },
+    /// Any HTTP(S) endpoint accepting a JSON POST.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, SecretString>,
+    },
+    /// Email to `to`, sent as the account; see [`crate::smtp`].
+    Smtp {
+        to: Vec<String>,
+        from: Option<String>,
+        host: Option<String>,
+        port: Option<usize>,
+    },
+    /// Text messages through an SMS gateway; see [`crate::sms`].
+    Sms(SmsConfig),
+    /// Push notifications through ntfy; see [`crate::push`].
+    Ntfy(PushConfig),
+    /// Push notifications through Gotify.
+    Gotify(PushConfig),
+    /// Apprise notification URLs; see [`crate::apprise`].
+    Apprise { urls: Vec<SecretString> },
+    /// JSON lines appended to `path`, or stdout; see [`crate::jsonl`].
+    Jsonl { path: Option<String> },
+    /// Entries on a Redis stream; see [`crate::redis`].
+    Redis(RedisConfig),
+    /// Messages on a RabbitMQ exchange; see [`crate::rabbitmq`].
+    Rabbitmq(RabbitmqConfig),
+    /// Rows in a SQL database; see [`crate::sql`].
+    Sql(SqlConfig),
+    /// Records on a Kafka topic; see [`crate::kafka`].
+    #[cfg(feature = "kafka")]
+    Kafka(crate::kafka::KafkaConfig),
+    /// Messages on a NATS subject; see [`crate::nats`].
+    #[cfg(feature = "nats")]
+    Nats(crate::nats::NatsConfig),
+    /// Periodic summaries to another sink; see [`crate::summary`].
+    Digest(DigestConfig),
+}
+
+/// Passwords, tokens and `headers` values print masked, and so does
+/// every Apprise URL, each of which embeds its service's token.
+impl fmt::Debug for SinkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkConfig::Apprise { urls } => f.debug_struct("Apprise").field("urls", urls).finish(),
+            _ => redact::debug_masked("SinkConfig", self, f),
+        }
+    }
+}
+
+pub const DEFAULT_RECONCILE_WINDOW: u64 = 86400;
+
+/// `ack` section of the config file: what counts as a delivery.
+///
+/// A message is a two-phase commit: the sink acknowledges it first, and
+/// only then is it recorded in the state file and flagged `\Seen`. An
+/// acknowledgement is a 2xx response and, with `field`, a JSON response
+/// body whose value at that pointer is `true` or a non-empty string.
+/// With `reconcile`, each cycle also looks for messages flagged `\Seen`
+/// in the last `reconcile_window` seconds that were never acknowledged,
+/// e.g. read in webmail first, and delivers them.
+///
+/// With `receipt`, delivery is effectively exactly-once for gateways that
+/// deduplicate on an `Idempotency-Key`. Each message is posted under a key
+/// derived from its account, mailbox and UID, so a retry after an
+/// ambiguous failure (a timeout, a dropped connection) carries the same
+/// key and is answered with the original receipt instead of delivered
+/// twice. The receipt ID is stored with the UID in the state file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AckConfig {
+    /// JSON pointer, e.g. `/accepted`.
+    pub field: Option<String>,
+    /// JSON pointer to the receipt ID, e.g. `/receipt/id`.
+    pub receipt: Option<String>,
+    pub reconcile: bool,
+    pub reconcile_window: u64,
+}
+
+impl Default for AckConfig {
+    fn default() -> Self {
+        Self {
+            field: None,
+            receipt: None,
+            reconcile: false,
+            reconcile_window: DEFAULT_RECONCILE_WINDOW,
+        }
+    }
+}
+
+/// Whether `body` acknowledges a delivery at the JSON pointer `field`.
+pub fn acknowledged(body: &str, field: &str) -> bool {
+    let body: Value = serde_json::from_str(body).unwrap_or_default();
+    match body.pointer(field) {
+        Some(Value::Bool(acked)) => *acked,
+        Some(Value::String(receipt)) => !receipt.is_empty(),
+        _ => false,
+    }
+}
+
+/// The receipt ID at the JSON pointer `field` of `body`.
+pub fn receipt(body: &str, field: &str) -> Option<String> {
+    let body: Value = serde_json::from_str(body).ok()?;
+    match body.pointer(field)? {
+        Value::String(id) if !id.is_empty() => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// An entry of `openclaw_gateways`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Gateway {
+    pub gateway: String,
+    /// `openclaw_port` when unset.
+    pub port: Option<usize>,
+    /// Gateways with a lower priority are tried first.
+    #[serde(default)]
+    pub priority: u32,
+    /// Share of the deliveries among gateways of the same priority.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+pub trait Sink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>>;
+
+    /// Deliver under an idempotency `key` that stays the same across
+    /// retries of one message, returning the sink's receipt ID. Sinks
+    /// without receipts just deliver.
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let _ = key;
+        self.deliver(payload).map(|()| None)
+    }
+
+    /// Called once per cycle, for sinks that send on their own schedule.
+    fn tick(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+pub struct WebhookSink {
+    pub url: String,
+    pub headers: BTreeMap<String, SecretString>,
+    /// `ack.field`.
+    pub ack: Option<String>,
+    /// `ack.receipt`.
+    pub receipt: Option<String>,
+    connection: Mutex<http::Connection>,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str, headers: BTreeMap<String, SecretString>, config: &Config) -> Self {
+        Self {
+            url: url.to_string(),
+            headers,
+            ack: config.ack.field.clone(),
+            receipt: config.ack.receipt.clone(),
+            connection: Mutex::new(http::Connection::new(url, &config.connections)),
+        }
+    }
+
+    pub fn openclaw(gateway: &str, port: usize, config: &Config) -> Self {
+        Self::new(&format!("http://{}:{}/api/message", gateway, port), BTreeMap::new(), config)
+    }
+
+    fn post(&self, payload: &Value, key: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+        let mut headers: Vec<(&str, &str)> = vec![("Content-Type", "application/json")];
+        headers.extend(self.headers.iter().map(|(k, v)| (k.as_str(), v.expose())));
+        if let Some(key) = key {
+            headers.push(("Idempotency-Key", key));
+        }
+        if let Some(id) = payload["correlation_id"].as_str() {
+            headers.push(("X-Correlation-ID", id));
+        }
+        let body = payload.to_string();
+        metrics::add(metrics::GATEWAY_BYTES_UPLOADED, body.len() as u64);
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let response = connection.request("POST", &headers, &body, http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("{} returned HTTP {}", self.url, response.status).into());
+        }
+        if let Some(field) = self.ack.as_deref().filter(|field| !acknowledged(&response.body, field)) {
+            return Err(format!("{} did not acknowledge the delivery at {}", self.url, field).into());
+        }
+        let Some(field) = &self.receipt else {
+            return Ok(None);
+        };
+        let receipt = receipt(&response.body, field);
+        if receipt.is_none() {
+            log::warn(&format!("{} returned no receipt at {}", self.url, field));
+        }
+        Ok(receipt)
+    }
+}
+
+impl Sink for WebhookSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.post(payload, None).map(|_| ())
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.post(payload, Some(key))
+    }
+}
+
+/// A gateway is skipped for this long after failing, doubling with each
+/// further failure up to `MAX_COOLDOWN`.
+const COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_COOLDOWN: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Default)]
+struct Health {
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+/// OpenClaw gateways tried in order of priority, spread by weight within
+/// one priority. A gateway that fails is passed over until its cooldown
+/// ends, unless every gateway is down.
+pub struct Failover {
+    gateways: Vec<(Gateway, WebhookSink)>,
+    health: Mutex<Vec<Health>>,
+}
+
+impl Failover {
+    /// The failover over `config.openclaw_gateways`.
+    pub fn new(config: &Config) -> Self {
+        let gateways = &config.openclaw_gateways;
+        Self {
+            gateways: gateways
+                .iter()
+                .map(|g| (g.clone(), WebhookSink::openclaw(&g.gateway, g.port.unwrap_or(config.openclaw_port), config)))
+                .collect(),
+            health: Mutex::new(vec![Health::default(); gateways.len()]),
+        }
+    }
+
+    /// Indices of the gateways in the order to try them.
+    fn order(&self, now: Instant) -> Vec<usize> {
+        let health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        // Weighted random order (Efraimidis-Spirakis): each gateway draws
+        // u^(1/weight) and the largest goes first.
+        let mut keyed: Vec<(bool, u32, f64, usize)> = self
+            .gateways
+            .iter()
+            .enumerate()
+            .map(|(i, (gateway, _))| {
+                let down = health[i].down_until.is_some_and(|until| until > now);
+                let u = (u64::from_le_bytes(trace::random_id()) >> 11) as f64 / (1u64 << 53) as f64;
+                let draw = if gateway.weight == 0 { 0.0 } else { u.powf(1.0 / gateway.weight as f64) };
+                (down, gateway.priority, -draw, i)
+            })
+            .collect();
+        keyed.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)).then(a.2.total_cmp(&b.2)));
+        keyed.into_iter().map(|(.., i)| i).collect()
+    }
+
+    fn record(&self, i: usize, ok: bool) {
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        let health = &mut health[i];
+        if ok {
+            *health = Health::default();
+        } else {
+            health.failures += 1;
+            let cooldown = COOLDOWN.saturating_mul(1 << (health.failures - 1).min(10)).min(MAX_COOLDOWN);
+            health.down_until = Some(Instant::now() + cooldown);
+        }
+    }
+}
+
+impl Failover {
+    /// Run `deliver` against each gateway in turn until one succeeds.
+    fn attempt<T>(&self, deliver: impl Fn(&WebhookSink) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        let mut errors = Vec::new();
+        for (attempt, i) in self.order(Instant::now()).into_iter().enumerate() {
+            let (gateway, sink) = &self.gateways[i];
+            match deliver(sink) {
+                Ok(delivered) => {
+                    self.record(i, true);
+                    if attempt > 0 {
+                        metrics::inc(metrics::GATEWAY_FAILOVERS);
+                        log::warn(&format!("delivered via fallback gateway {} after: {}", gateway.gateway, errors.join("; ")));
+                    }
+                    return Ok(delivered);
+                }
+                Err(e) => {
+                    self.record(i, false);
+                    errors.push(format!("{}: {}", gateway.gateway, e));
+                }
+            }
+        }
+        Err(format!("every gateway failed: {}", errors.join("; ")).into())
+    }
+}
+
+impl Sink for Failover {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.attempt(|sink| sink.deliver(payload))
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.attempt(|sink| sink.deliver_keyed(payload, key))
+    }
+}
+
+/// Instantiate every configured sink, plus the implicit `openclaw` one,
+/// recording deliveries in `audit_log` if set.
+pub fn build(config: &Config) -> BTreeMap<String, Box<dyn Sink>> {
+    let mut sinks: BTreeMap<String, Box<dyn Sink>> = BTreeMap::new();
+    sinks.insert(DEFAULT_SINK.to_string(), openclaw(config));
+    for (name, sink) in &config.sinks {
+        sinks.insert(name.clone(), build_one(name, sink, config));
+    }
+    if let Some(path) = &config.audit_log {
+        let audit = Arc::new(Mutex::new(AuditLog::open(Path::new(path))));
+        sinks = sinks
+            .into_iter()
+            .map(|(name, inner)| {
+                let log = Arc::clone(&audit);
+                let sink: Box<dyn Sink> = Box::new(Audited { name: name.clone(), inner, log });
+                (name, sink)
+            })
+            .collect();
+    }
+    sinks
+}
+
+/// The implicit [`DEFAULT_SINK`].
+fn openclaw(config: &Config) -> Box<dyn Sink> {
+    if config.openclaw_gateways.is_empty() {
+        Box::new(WebhookSink::openclaw(&config.openclaw_gateway, config.openclaw_port, config))
+    } else {
+        Box::new(Failover::new(config))
+    }
+}
+
+fn build_one(name: &str, sink: &SinkConfig, config: &Config) -> Box<dyn Sink> {
+    match sink {
+        SinkConfig::Openclaw { gateway, port } => Box::new(WebhookSink::openclaw(
+            gateway.as_deref().unwrap_or(&config.openclaw_gateway),
+            port.unwrap_or(config.openclaw_port),
+            config,
+        )),
+        SinkConfig::Webhook { url, headers } => Box::new(WebhookSink::new(url, headers.clone(), config)),
+        SinkConfig::Smtp { to, from, host, port } => {
+            Box::new(SmtpSink::new(to, from.as_deref(), host.as_deref(), *port, config))
+        }
+        SinkConfig::Sms(sms) => Box::new(SmsSink { config: sms.clone() }),
+        SinkConfig::Ntfy(push) => Box::new(PushSink { service: Service::Ntfy, config: push.clone() }),
+        SinkConfig::Gotify(push) => Box::new(PushSink { service: Service::Gotify, config: push.clone() }),
+        SinkConfig::Apprise { urls } => Box::new(AppriseSink::new(urls, config)),
+        SinkConfig::Jsonl { path } => Box::new(JsonlSink::new(path.as_deref())),
+        SinkConfig::Redis(redis) => Box::new(RedisSink::new(redis, config)),
+        SinkConfig::Rabbitmq(rabbitmq) => Box::new(RabbitmqSink { config: rabbitmq.clone() }),
+        SinkConfig::Sql(sql) => Box::new(SqlSink::new(sql)),
+        #[cfg(feature = "kafka")]
+        SinkConfig::Kafka(kafka) => Box::new(crate::kafka::KafkaSink { config: kafka.clone() }),
+        #[cfg(feature = "nats")]
+        SinkConfig::Nats(nats) => Box::new(crate::nats::NatsSink { config: nats.clone() }),
+        SinkConfig::Digest(digest) => {
+            // Validation keeps digests from targeting each other.
+            let target = match config.sinks.get(&digest.sink) {
+                Some(target) => build_one(&digest.sink, target, config),
+                None => openclaw(config),
+            };
+            let file = digest.file.clone().or_else(|| {
+                (!config.state_file.is_empty()).then(|| format!("{}.{}.digest", config.state_file, name))
+            });
+            Box::new(DigestSink::new(digest, file, target))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_config_parse() {
+        let sinks: BTreeMap<String, SinkConfig> = serde_json::from_str(
+            r#"{"ci": {"type": "webhook", "url": "http://ci.local/hook"}, "staging": {"type": "openclaw", "port": 9000}}"#,
+        )
+        .unwrap();
+        assert!(matches!(&sinks["ci"], SinkConfig::Webhook { url, .. } if url == "http://ci.local/hook"));
+
+        let config = Config {
+            sinks,
+            ..Config::default()
+        };
+        let built = build(&config);
+        assert_eq!(built.keys().collect::<Vec<_>>(), ["ci", "openclaw", "staging"]);
+    }
+
+    #[test]
+    fn test_acknowledged() {
+        assert!(acknowledged(r#"{"accepted": true}"#, "/accepted"));
+        assert!(acknowledged(r#"{"result": {"id": "r-17"}}"#, "/result/id"));
+        assert!(!acknowledged(r#"{"accepted": false}"#, "/accepted"));
+        assert!(!acknowledged(r#"{"result": {"id": ""}}"#, "/result/id"));
+        assert!(!acknowledged("queued", "/accepted"));
+        assert_eq!(receipt(r#"{"result": {"id": 17}}"#, "/result/id").as_deref(), Some("17"));
+        assert_eq!(receipt(r#"{"result": {}}"#, "/result/id"), None);
+    }
+
+    #[test]
+    fn test_gateway_failover() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port() as usize;
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0u8; 4096]).unwrap();
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+        });
+        let config = Config {
+            openclaw_gateways: serde_json::from_value(serde_json::json!([
+                {"gateway": "127.0.0.1", "port": 1},
+                {"gateway": "127.0.0.1", "port": port, "priority": 1},
+            ]))
+            .unwrap(),
+            ..Config::default()
+        };
+        let failover = Failover::new(&config);
+        assert_eq!(failover.order(Instant::now()), [0, 1]);
+
+        failover.deliver(&serde_json::json!({"message": "one"})).unwrap();
+        // The primary is cooling down, so it is tried last.
+        assert_eq!(failover.order(Instant::now()), [1, 0]);
+        assert_eq!(failover.order(Instant::now() + MAX_COOLDOWN), [0, 1]);
+        failover.deliver(&serde_json::json!({"message": "two"})).unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+//! Text messages through HTTP SMS gateways.
+//!
+//! An `sms` sink texts each number in `to`, for matches urgent enough to
+//! interrupt someone. `provider` picks the request: `twilio` (account SID
+//! as `account`, auth token as `token`), `vonage` (API key and secret),
+//! `messagebird` (access key as `token`), or `generic`, which POSTs
+//! `{"to", "from", "text"}` as JSON, or the sink's `template` with
+//! `{{to}}`, `{{from}}` and `{{text}}` filled in, to `url` with `headers`.
+//! `url` also replaces a provider's endpoint, e.g. for a regional one.
+//!
+//! The text is the payload's `text` if a rule template sets one, else
+//! the sender and subject, cut to `max_chars`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::http::{self, encode_component};
+use crate::metrics;
+use crate::mime::encode_base64;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+
+pub const DEFAULT_MAX_CHARS: usize = 160;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmsProvider {
+    #[default]
+    Generic,
+    Twilio,
+    Vonage,
+    Messagebird,
+}
+
+/// `sinks` entry of type `sms`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SmsConfig {
+    #[serde(default)]
+    pub provider: SmsProvider,
+    /// Numbers to text, in international format.
+    pub to: Vec<String>,
+    /// Sender number or name.
+    pub from: Option<String>,
+    /// Account SID or API key, as the provider calls it.
+    pub account: Option<String>,
+    /// Auth token, API secret or access key.
+    pub token: Option<SecretString>,
+    /// Endpoint of the generic provider, or a replacement for another's.
+    pub url: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, SecretString>,
+    /// Request body of the generic provider.
+    pub template: Option<Value>,
+    #[serde(default = "default_max_chars")]
+    pub max_chars: usize,
+}
+
+fn default_max_chars() -> usize {
+    DEFAULT_MAX_CHARS
+}
+
+impl SmsConfig {
+    /// Check the provider has what it needs.
+    pub fn validate(&self) -> Result<(), String> {
+        let needs: &[(&str, bool)] = match self.provider {
+            SmsProvider::Generic => &[("url", self.url.is_some())],
+            SmsProvider::Twilio | SmsProvider::Vonage => &[
+                ("account", self.account.is_some()),
+                ("token", self.token.is_some()),
+                ("from", self.from.is_some()),
+            ],
+            SmsProvider::Messagebird => &[("token", self.token.is_some()), ("from", self.from.is_some())],
+        };
+        if let Some((key, _)) = needs.iter().find(|(_, set)| !set) {
+            return Err(format!("{:?} needs {}", self.provider, key).to_lowercase());
+        }
+        if self.to.is_empty() {
+            return Err("no numbers in to".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// One HTTP request to the gateway.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+pub struct SmsSink {
+    pub config: SmsConfig,
+}
+
+impl SmsSink {
+    /// The text sent for `payload`.
+    pub fn text(&self, payload: &Value) -> String {
+        let text = match (&payload["text"], &payload["headers"]["From"], &payload["headers"]["Subject"]) {
+            (Value::String(text), _, _) => text.clone(),
+            (_, Value::String(from), Value::String(subject)) => format!("{}: {}", from, subject),
+            _ => payload["message"].as_str().unwrap_or_default().split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        if text.chars().count() <= self.config.max_chars {
+            return text;
+        }
+        let cut: String = text.chars().take(self.config.max_chars.saturating_sub(1)).collect();
+        format!("{}…", cut)
+    }
+
+    /// The requests that send `text` to every number.
+    pub fn requests(&self, text: &str) -> Vec<Request> {
+        let config = &self.config;
+        let account = config.account.as_deref().unwrap_or("");
+        let token = config.token.as_ref().map_or("", SecretString::expose);
+        let from = config.from.as_deref().unwrap_or("");
+        let url = |default: String| config.url.clone().unwrap_or(default);
+        let form = |fields: &[(&str, &str)]| {
+            fields.iter().map(|(k, v)| format!("{}={}", k, encode_component(v))).collect::<Vec<_>>().join("&")
+        };
+        let form_type = ("Content-Type".to_string(), "application/x-www-form-urlencoded".to_string());
+        let json_type = ("Content-Type".to_string(), "application/json".to_string());
+        match config.provider {
+            SmsProvider::Twilio => {
+                let basic = format!("Basic {}", encode_base64(format!("{}:{}", account, token).as_bytes()));
+                let endpoint = url(format!("https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json", account));
+                let headers = vec![form_type, ("Authorization".to_string(), basic)];
+                (config.to.iter())
+                    .map(|to| Request {
+                        url: endpoint.clone(),
+                        headers: headers.clone(),
+                        body: form(&[("To", to), ("From", from), ("Body", text)]),
+                    })
+                    .collect()
+            }
+            SmsProvider::Vonage => {
+                let endpoint = url("https://rest.nexmo.com/sms/json".to_string());
+                (config.to.iter())
+                    .map(|to| Request {
+                        url: endpoint.clone(),
+                        headers: vec![form_type.clone()],
+                        body: form(&[("api_key", account), ("api_secret", token), ("from", from), ("to", to), ("text", text)]),
+                    })
+                    .collect()
+            }
+            SmsProvider::Messagebird => vec![Request {
+                url: url("https://rest.messagebird.com/messages".to_string()),
+                headers: vec![json_type, ("Authorization".to_string(), format!("AccessKey {}", token))],
+                body: json!({"recipients": config.to, "originator": from, "body": text}).to_string(),
+            }],
+            SmsProvider::Generic => {
+                let mut headers = vec![json_type];
+                headers.extend(config.headers.iter().map(|(k, v)| (k.clone(), v.expose().to_string())));
+                (config.to.iter())
+                    .map(|to| {
+                        let vars = [("to", to.as_str()), ("from", from), ("text", text)];
+                        let body = match &config.template {
+                            Some(template) => fill(template, &vars),
+                            None => json!({"to": to, "from": config.from, "text": text}),
+                        };
+                        Request { url: url(String::new()), headers: headers.clone(), body: body.to_string() }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// `template` with `{{name}}` replaced in its string leaves.
+fn fill(template: &Value, vars: &[(&str, &str)]) -> Value {
+    match template {
+        Value::String(text) => {
+            let filled = vars.iter().fold(text.clone(), |text, (name, value)| text.replace(&format!("{{{{{}}}}}", name), value));
+            Value::String(filled)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| fill(item, vars)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), fill(v, vars))).collect()),
+        other => other.clone(),
+    }
+}
+
+impl Sink for SmsSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        let text = self.text(payload);
+        for request in self.requests(&text) {
+            let headers: Vec<(&str, &str)> = request.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            metrics::add(metrics::GATEWAY_BYTES_UPLOADED, request.body.len() as u64);
+            let response = http::request("POST", &request.url, &headers, Some(&request.body), http::DEFAULT_TIMEOUT)?;
+            if !response.is_success() {
+                return Err(format!("{} returned HTTP {}", request.url, response.status).into());
+            }
+            // Vonage reports failures in a 200 response.
+            if self.config.provider == SmsProvider::Vonage {
+                let body: Value = serde_json::from_str(&response.body).unwrap_or_default();
+                if body.pointer("/messages/0/status").and_then(Value::as_str) != Some("0") {
+                    let error = body.pointer("/messages/0/error-text").and_then(Value::as_str).unwrap_or("no status");
+                    return Err(format!("{} refused the message: {}", request.url, error).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_requests() {
+        let config: SmsConfig = serde_json::from_value(json!({
+            "provider": "twilio", "to": ["+4915100000"], "from": "+4930000", "account": "AC1", "token": "t", "max_chars": 20,
+        }))
+        .unwrap();
+        let sink = SmsSink { config };
+        let text = sink.text(&json!({"headers": {"From": "alice@example.com", "Subject": "Server down"}}));
+        assert_eq!(text, "alice@example.com: …");
+        let requests = sink.requests("Server & down");
+        assert_eq!(requests[0].url, "https://api.twilio.com/2010-04-01/Accounts/AC1/Messages.json");
+        assert_eq!(requests[0].body, "To=%2B4915100000&From=%2B4930000&Body=Server%20%26%20down");
+        assert!(requests[0].headers.contains(&("Authorization".to_string(), "Basic QUMxOnQ=".to_string())));
+
+        let generic = SmsConfig {
+            provider: SmsProvider::Generic,
+            url: Some("https://sms.local/send".to_string()),
+            template: Some(json!({"number": "{{to}}", "message": {"body": "[mail] {{text}}"}})),
+            ..sink.config.clone()
+        };
+        assert!(generic.validate().is_ok());
+        let requests = SmsSink { config: generic }.requests("hi");
+        assert_eq!(requests[0].body, r#"{"message":{"body":"[mail] hi"},"number":"+4915100000"}"#);
+        let missing = SmsConfig { provider: SmsProvider::Messagebird, token: None, ..sink.config };
+        assert_eq!(missing.validate().unwrap_err(), "messagebird needs token");
+    }
+}
@@ -0,0 +1,233 @@
+//! Forwarding events as email over SMTP.
+//!
+//! An `smtp` sink sends each payload as a plain-text message to its `to`
+//! addresses through Mailcow's submission port, logged in with the
+//! account's credentials. The subject is the payload's `subject`, else
+//! `Fwd:` and the original subject; the body is its `message`, else the
+//! payload as JSON, so a rule template decides what is sent. Port 465
+//! uses implicit TLS, any other STARTTLS.
+//!
+//! Every message carries `Auto-Submitted: auto-forwarded` and
+//! [`LOOP_HEADER`] with the account name. Mail arriving with that header
+//! for the same account is not forwarded by email again, so pointing a
+//! sink at a polled address cannot loop.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::digest::{hex, sha256};
+use crate::dns::{self, DnsConfig};
+use crate::metrics;
+use crate::mime::encode_base64;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+use crate::transport::{self, TlsConfig};
+
+pub const DEFAULT_PORT: usize = 587;
+pub const IMPLICIT_TLS_PORT: usize = 465;
+pub const LOOP_HEADER: &str = "X-Email-Checker-Forwarded";
+
+pub struct SmtpSink {
+    pub to: Vec<String>,
+    pub from: String,
+    host: String,
+    port: usize,
+    /// The account the sink sends as, named in [`LOOP_HEADER`].
+    username: String,
+    password: SecretString,
+    tls: TlsConfig,
+    dns: DnsConfig,
+}
+
+impl SmtpSink {
+    /// Unset options fall back to the account: its address, IMAP host
+    /// and the submission port.
+    pub fn new(to: &[String], from: Option<&str>, host: Option<&str>, port: Option<usize>, config: &Config) -> Self {
+        Self {
+            to: to.to_vec(),
+            from: from.unwrap_or(&config.mailcow_username).to_string(),
+            host: host.unwrap_or(&config.mailcow_imap_host).to_string(),
+            port: port.unwrap_or(DEFAULT_PORT),
+            username: config.mailcow_username.clone(),
+            password: config.mailcow_password.clone(),
+            tls: config.tls.clone(),
+            dns: config.dns.clone(),
+        }
+    }
+
+    /// The message for `payload`. With `key`, its `Message-ID` is derived
+    /// from it, so receivers can drop a retried copy.
+    fn message(&self, payload: &Value, key: Option<&str>) -> String {
+        let subject = match (&payload["subject"], &payload["headers"]["Subject"]) {
+            (Value::String(subject), _) => subject.clone(),
+            (_, Value::String(original)) => format!("Fwd: {}", original),
+            _ => "Forwarded by email_checker".to_string(),
+        };
+        let body = match &payload["message"] {
+            Value::String(message) => message.clone(),
+            _ => serde_json::to_string_pretty(payload).unwrap_or_default(),
+        };
+        let id = match key {
+            Some(key) => key.to_string(),
+            None => hex(&sha256(format!("{}{}", payload, chrono::Utc::now()).as_bytes()))[..32].to_string(),
+        };
+        let domain = self.from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+        let headers = [
+            ("Date", chrono::Local::now().to_rfc2822()),
+            ("From", self.from.clone()),
+            ("To", self.to.join(", ")),
+            ("Subject", encode_header(&subject)),
+            ("Message-ID", format!("<{}@{}>", id, domain)),
+            ("MIME-Version", "1.0".to_string()),
+            ("Content-Type", "text/plain; charset=utf-8".to_string()),
+            ("Content-Transfer-Encoding", "base64".to_string()),
+            ("Auto-Submitted", "auto-forwarded".to_string()),
+            (LOOP_HEADER, self.username.clone()),
+        ];
+        let mut message: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+        message.push_str("\r\n");
+        let encoded = encode_base64(body.replace("\r\n", "\n").replace('\n', "\r\n").as_bytes());
+        for line in encoded.as_bytes().chunks(76) {
+            message.push_str(&format!("{}\r\n", String::from_utf8_lossy(line)));
+        }
+        message
+    }
+
+    fn send(&self, payload: &Value, key: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.send_message(&self.from, &self.message(payload, key))
+    }
+
+    /// Submit a complete message with envelope sender `sender`; `""` is
+    /// the null sender, for mail that must not be bounced.
+    pub fn send_message(&self, sender: &str, message: &str) -> Result<(), Box<dyn Error>> {
+        metrics::add(metrics::GATEWAY_BYTES_UPLOADED, message.len() as u64);
+        let addr = dns::resolve(&self.dns, &self.host, self.port)?;
+        let mut session = if self.port == IMPLICIT_TLS_PORT {
+            Session::greeted(transport::connect_tls(&self.host, addr, &self.tls)?)?
+        } else {
+            // s_client has read the greeting and sent EHLO before STARTTLS.
+            Session { stream: BufReader::new(transport::connect_smtp(&self.host, addr, &self.tls)?) }
+        };
+        session
+            .send(&self.username, self.password.expose(), sender, &self.to, message)
+            .map_err(|e| format!("{}:{}: {}", self.host, self.port, e).into())
+    }
+}
+
+impl Sink for SmtpSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.send(payload, None)
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.send(payload, Some(key)).map(|()| None)
+    }
+}
+
+/// `text` as a header value, base64-encoded if it is not plain ASCII.
+pub(crate) fn encode_header(text: &str) -> String {
+    if text.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        return text.to_string();
+    }
+    format!("=?UTF-8?B?{}?=", encode_base64(text.as_bytes()))
+}
+
+/// An SMTP submission session.
+pub struct Session<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Session<S> {
+    /// Start on a fresh stream by reading the server's greeting.
+    pub fn greeted(stream: S) -> Result<Self, String> {
+        let mut session = Self { stream: BufReader::new(stream) };
+        session.reply(220)?;
+        Ok(session)
+    }
+
+    /// Log in and submit one message.
+    pub fn send(&mut self, username: &str, password: &str, from: &str, to: &[String], message: &str) -> Result<(), String> {
+        self.command("EHLO email-checker", 250)?;
+        let credentials = encode_base64(format!("\0{}\0{}", username, password).as_bytes());
+        self.command(&format!("AUTH PLAIN {}", credentials), 235)
+            .map_err(|e| format!("authentication failed: {}", e))?;
+        self.command(&format!("MAIL FROM:<{}>", from), 250)?;
+        for recipient in to {
+            self.command(&format!("RCPT TO:<{}>", recipient), 250)?;
+        }
+        self.command("DATA", 354)?;
+        let mut data = String::new();
+        for line in message.split_terminator("\r\n") {
+            // Dot-stuffing, so no line ends the data early.
+            let dot = if line.starts_with('.') { "." } else { "" };
+            data.push_str(&format!("{}{}\r\n", dot, line));
+        }
+        data.push('.');
+        self.command(&data, 250)?;
+        let _ = self.command("QUIT", 221);
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str, expected: u16) -> Result<(), String> {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .and_then(|()| stream.flush())
+            .map_err(|e| e.to_string())?;
+        self.reply(expected)
+    }
+
+    /// Read a possibly multi-line reply and check its code.
+    fn reply(&mut self, expected: u16) -> Result<(), String> {
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                return Err("connection closed".to_string());
+            }
+            let line = line.trim_end();
+            // `250-` continues the reply, `250 ` ends it.
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            return match line.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+                Some(code) if code == expected => Ok(()),
+                _ => Err(format!("server replied '{}'", line)),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imap::tests::Script;
+    use serde_json::json;
+
+    #[test]
+    fn test_submit_message() {
+        let config = Config {
+            mailcow_username: "bot@example.com".to_string(),
+            ..Config::default()
+        };
+        let sink = SmtpSink::new(&["me@example.org".to_string()], None, None, None, &config);
+        let payload = json!({"message": "📧 New Email\n.hidden\n", "headers": {"Subject": "Übung"}});
+        let message = sink.message(&payload, Some("k1"));
+        assert!(message.contains("From: bot@example.com\r\nTo: me@example.org\r\nSubject: =?UTF-8?B?RndkOiDDnGJ1bmc=?=\r\n"));
+        assert!(message.contains("Message-ID: <k1@example.com>\r\n"));
+        assert!(message.contains("Auto-Submitted: auto-forwarded\r\nX-Email-Checker-Forwarded: bot@example.com\r\n\r\n"));
+
+        let server = "220 mail ready\r\n250-mail\r\n250 AUTH PLAIN\r\n235 ok\r\n250 ok\r\n250 ok\r\n354 go\r\n250 queued\r\n221 bye\r\n";
+        let mut session = Session::greeted(Script::new(server)).unwrap();
+        session.send("bot", "pw", "bot@example.com", &sink.to, "Subject: x\r\n\r\n.hidden\r\n").unwrap();
+        let sent = String::from_utf8(session.stream.into_inner().output).unwrap();
+        assert!(sent.starts_with("EHLO email-checker\r\nAUTH PLAIN AGJvdABwdw==\r\nMAIL FROM:<bot@example.com>\r\n"));
+        assert!(sent.ends_with("DATA\r\nSubject: x\r\n\r\n..hidden\r\n.\r\nQUIT\r\n"));
+
+        let refused = "220 ready\r\n250 mail\r\n535 5.7.8 bad credentials\r\n";
+        let error = Session::greeted(Script::new(refused)).unwrap().send("bot", "pw", "a@b", &sink.to, "").unwrap_err();
+        assert_eq!(error, "authentication failed: server replied '535 5.7.8 bad credentials'");
+    }
+}
@@ -0,0 +1,266 @@
+//! A sink inserting events into a SQL database.
+//!
+//! A `sql` sink writes one row per event into `email_checker_events`
+//! through the database's command line client, `sqlite3`, `psql` or
+//! `mysql`, which must be installed. Before the first insert the sink
+//! brings the schema up to date: [`MIGRATIONS`] are applied in order and
+//! recorded in `email_checker_migrations`, so upgrades migrate existing
+//! databases. The columns are described in the README.
+
+use std::error::Error;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::process;
+use crate::secrets::SecretString;
+use crate::sink::Sink;
+
+pub const EVENTS_TABLE: &str = "email_checker_events";
+pub const MIGRATIONS_TABLE: &str = "email_checker_migrations";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Driver {
+    Sqlite,
+    Postgres,
+    Mysql,
+}
+
+/// Schema changes by version, as (SQLite, PostgreSQL, MySQL) statements.
+/// Append new versions; never edit an applied one.
+pub const MIGRATIONS: &[(u32, [&str; 3])] = &[(
+    1,
+    [
+        "CREATE TABLE email_checker_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            delivery_key TEXT UNIQUE,
+            created_at TEXT NOT NULL,
+            event_type TEXT,
+            sender TEXT,
+            subject TEXT,
+            message_id TEXT,
+            payload TEXT NOT NULL
+        );
+        CREATE INDEX email_checker_events_created_at ON email_checker_events (created_at);",
+        "CREATE TABLE email_checker_events (
+            id BIGSERIAL PRIMARY KEY,
+            delivery_key VARCHAR(128) UNIQUE,
+            created_at TIMESTAMP NOT NULL,
+            event_type VARCHAR(64),
+            sender TEXT,
+            subject TEXT,
+            message_id TEXT,
+            payload JSONB NOT NULL
+        );
+        CREATE INDEX email_checker_events_created_at ON email_checker_events (created_at);",
+        "CREATE TABLE email_checker_events (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            delivery_key VARCHAR(128) UNIQUE,
+            created_at DATETIME NOT NULL,
+            event_type VARCHAR(64),
+            sender TEXT,
+            subject TEXT,
+            message_id TEXT,
+            payload JSON NOT NULL,
+            INDEX email_checker_events_created_at (created_at)
+        );",
+    ],
+)];
+
+/// `sinks` entry of type `sql`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SqlConfig {
+    pub driver: Driver,
+    /// File for SQLite, database name otherwise.
+    pub database: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<SecretString>,
+}
+
+pub struct SqlSink {
+    pub config: SqlConfig,
+    /// Whether the schema has been brought up to date.
+    migrated: Mutex<bool>,
+}
+
+impl SqlSink {
+    pub fn new(config: &SqlConfig) -> Self {
+        Self { config: config.clone(), migrated: Mutex::new(false) }
+    }
+
+    /// Statements creating the migrations table and reading the version.
+    pub fn version_query(&self) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (version INTEGER PRIMARY KEY, applied_at VARCHAR(32) NOT NULL);\n\
+             SELECT COALESCE(MAX(version), 0) FROM {};\n",
+            MIGRATIONS_TABLE, MIGRATIONS_TABLE
+        )
+    }
+
+    /// A script applying the migrations after `version`, or `None` when
+    /// the schema is current.
+    pub fn migration_script(&self, version: u32) -> Option<String> {
+        let index = self.config.driver as usize;
+        let pending: Vec<_> = MIGRATIONS.iter().filter(|(v, _)| *v > version).collect();
+        if pending.is_empty() {
+            return None;
+        }
+        // MySQL commits DDL implicitly, so a transaction would not help.
+        let transaction = self.config.driver != Driver::Mysql;
+        let mut script = String::from(if transaction { "BEGIN;\n" } else { "" });
+        for (version, statements) in pending {
+            script.push_str(statements[index]);
+            script.push('\n');
+            let now = self.literal(&chrono::Utc::now().to_rfc3339());
+            script.push_str(&format!("INSERT INTO {} (version, applied_at) VALUES ({}, {});\n", MIGRATIONS_TABLE, version, now));
+        }
+        if transaction {
+            script.push_str("COMMIT;\n");
+        }
+        Some(script)
+    }
+
+    /// The statement storing `payload`.
+    pub fn insert(&self, payload: &Value, key: Option<&str>) -> String {
+        let header = |name: &str| payload["headers"][name].as_str().map_or("NULL".to_string(), |v| self.literal(v));
+        let values = [
+            key.map_or("NULL".to_string(), |k| self.literal(k)),
+            self.literal(&chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()),
+            payload["event_type"].as_str().map_or("NULL".to_string(), |t| self.literal(t)),
+            header("From"),
+            header("Subject"),
+            header("Message-ID"),
+            self.literal(&payload.to_string()),
+        ];
+        let columns = "delivery_key, created_at, event_type, sender, subject, message_id, payload";
+        let (verb, conflict) = match self.config.driver {
+            Driver::Sqlite => ("INSERT OR IGNORE", ""),
+            Driver::Postgres => ("INSERT", " ON CONFLICT (delivery_key) DO NOTHING"),
+            Driver::Mysql => ("INSERT IGNORE", ""),
+        };
+        format!("{} INTO {} ({}) VALUES ({}){};\n", verb, EVENTS_TABLE, columns, values.join(", "), conflict)
+    }
+
+    /// `text` as a string literal.
+    fn literal(&self, text: &str) -> String {
+        let escaped = text.replace('\'', "''");
+        match self.config.driver {
+            // Unless NO_BACKSLASH_ESCAPES is set, MySQL reads backslashes
+            // in literals as escapes.
+            Driver::Mysql => format!("'{}'", escaped.replace('\\', "\\\\")),
+            Driver::Sqlite | Driver::Postgres => format!("'{}'", escaped),
+        }
+    }
+
+    /// Run `script` with the client, returning what it printed.
+    fn run(&self, script: &str) -> Result<String, Box<dyn Error>> {
+        let config = &self.config;
+        let (program, mut command) = match config.driver {
+            Driver::Sqlite => {
+                let mut command = process::command("sqlite3", &[]);
+                command.args(["-batch", "-bail", &config.database]);
+                ("sqlite3", command)
+            }
+            Driver::Postgres => {
+                let mut command = process::command("psql", &["PG*"]);
+                command.args(["-X", "-q", "-t", "-A", "-v", "ON_ERROR_STOP=1", "-d", &config.database]);
+                command.args(config.host.iter().flat_map(|host| ["-h", host]));
+                command.args(config.port.iter().flat_map(|port| ["-p".to_string(), port.to_string()]));
+                command.args(config.user.iter().flat_map(|user| ["-U", user]));
+                command.args(["-w"]);
+                if let Some(password) = &config.password {
+                    command.env("PGPASSWORD", password.expose());
+                }
+                ("psql", command)
+            }
+            Driver::Mysql => {
+                let mut command = process::command("mysql", &["MYSQL_*"]);
+                command.args(["-N", "-B"]);
+                command.args(config.host.iter().map(|host| format!("--host={}", host)));
+                command.args(config.port.iter().map(|port| format!("--port={}", port)));
+                command.args(config.user.iter().map(|user| format!("--user={}", user)));
+                if let Some(password) = &config.password {
+                    command.env("MYSQL_PWD", password.expose());
+                }
+                command.arg(&config.database);
+                ("mysql", command)
+            }
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run {}: {}", program, e))?;
+        child.stdin.take().ok_or("stdin unavailable")?.write_all(script.as_bytes())?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()).into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn migrate(&self) -> Result<(), Box<dyn Error>> {
+        let mut migrated = self.migrated.lock().unwrap_or_else(|e| e.into_inner());
+        if *migrated {
+            return Ok(());
+        }
+        let output = self.run(&self.version_query())?;
+        let version = output
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .next_back()
+            .ok_or_else(|| format!("unexpected schema version '{}'", output.trim()))?;
+        if let Some(script) = self.migration_script(version) {
+            self.run(&script)?;
+        }
+        *migrated = true;
+        Ok(())
+    }
+
+    fn send(&self, payload: &Value, key: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.migrate()?;
+        self.run(&self.insert(payload, key)).map(|_| ())
+    }
+}
+
+impl Sink for SqlSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        self.send(payload, None)
+    }
+
+    fn deliver_keyed(&self, payload: &Value, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.send(payload, Some(key)).map(|()| None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_statements() {
+        let config: SqlConfig = serde_json::from_value(json!({"driver": "postgres", "database": "mail"})).unwrap();
+        let postgres = SqlSink::new(&config);
+        let payload = json!({"event_type": "message", "headers": {"Subject": "It's here"}});
+        let insert = postgres.insert(&payload, Some("k1"));
+        assert!(insert.starts_with("INSERT INTO email_checker_events (delivery_key, created_at, "));
+        assert!(insert.contains("VALUES ('k1', '"));
+        assert!(insert.contains("'message', NULL, 'It''s here', NULL, '{\"event_type\""));
+        assert!(insert.ends_with(" ON CONFLICT (delivery_key) DO NOTHING;\n"));
+
+        let mysql = SqlSink::new(&SqlConfig { driver: Driver::Mysql, ..config });
+        assert!(mysql.insert(&json!({"message": "a\\b"}), None).contains("'{\"message\":\"a\\\\\\\\b\"}'"));
+        let script = mysql.migration_script(0).unwrap();
+        assert!(script.contains("payload JSON NOT NULL") && !script.contains("BEGIN;"));
+        assert!(script.contains("INSERT INTO email_checker_migrations (version, applied_at) VALUES (1, '"));
+        assert_eq!(mysql.migration_script(MIGRATIONS.last().unwrap().0), None);
+    }
+}
@@ -0,0 +1,601 @@
+//! Local record of what has been delivered.
+//!
+//! Delivered UIDs are kept per mailbox and `UIDVALIDITY`, so a message is
+//! not forwarded twice if marking it `\Seen` failed, and delivered
+//! `Message-ID`s catch the same mail arriving twice. The state is a JSON
+//! file, written atomically after each delivery.
+//!
+//! State migrated from the Python checker only carries its last check
+//! time; it becomes `since`, which limits the search the same way the
+//! Python checker did.
+//!
+//! Traffic is also tallied here per account and day, for `stats`, and
+//! messages that could not be parsed in time are quarantined here so
+//! they are not retried every cycle. Mail held back on purpose, from
+//! senders outside the contacts allowlist, is kept here the same way.
+//!
+//! With `ack.reconcile`, the highest UID present when reconciliation
+//! started is kept per mailbox; `\Seen` mail above it without a delivery
+//! record was marked by something other than the checker.
+//!
+//! Mail a rule holds back is kept per mailbox with when it is due.
+//!
+//! Lists unsubscribed from, or that a dry run would have, are kept so each
+//! is only asked once.
+//!
+//! Bursts of near-identical mail being collapsed are kept by key until
+//! their window has passed.
+//!
+//! With `diff.enabled`, the last body forwarded per thread is kept to
+//! diff the next one against.
+//!
+//! Shared folders are not marked `\Seen`, so the highest UID up to which
+//! all their mail was handled is kept per mailbox instead.
+//!
+//! With `ack.receipt`, deliveries are also noted as in flight before they
+//! are sent, so a retry can tell that an earlier attempt's outcome is
+//! unknown, and the gateway's receipt is kept with the delivered UID.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub version: u32,
+    pub mailboxes: BTreeMap<String, MailboxState>,
+    /// Delivered `Message-ID`s and when (Unix seconds).
+    pub delivered: BTreeMap<String, i64>,
+    /// Only mail since this date is searched, e.g. `2026-10-14`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<NaiveDate>,
+    /// Quarantined UIDs per mailbox and when (Unix seconds).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub quarantine: BTreeMap<String, MailboxState>,
+    /// UIDs per mailbox left unseen and unforwarded on purpose, and when.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub skipped: BTreeMap<String, MailboxState>,
+    /// Traffic per account and day.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bandwidth: BTreeMap<String, BTreeMap<NaiveDate, Bandwidth>>,
+    /// Per mailbox, the UID after which seen mail is reconciled.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub reconcile_after: BTreeMap<String, Watermark>,
+    /// UIDs per mailbox whose delivery was sent but not yet acknowledged,
+    /// and since when (Unix seconds).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub inflight: BTreeMap<String, MailboxState>,
+    /// Deliveries per routing rule, by rule name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub rule_hits: BTreeMap<String, RuleHits>,
+    /// UIDs per mailbox held back by their rule, and when they are due
+    /// (Unix seconds).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub snoozed: BTreeMap<String, MailboxState>,
+    /// Per mailbox tracked by UID instead of `\Seen`, the UID up to which
+    /// all mail was handled.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub handled_through: BTreeMap<String, Watermark>,
+    /// Lists unsubscribed from, by `List-Id` or sender.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unsubscribed: BTreeMap<String, Unsubscribed>,
+    /// Bursts being collapsed, by [`crate::collapse::key`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bursts: BTreeMap<String, Burst>,
+    /// Last body forwarded per thread, by [`crate::diff::thread`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub threads: BTreeMap<String, ThreadBody>,
+    /// Arrivals per mailbox, for [`crate::growth`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub growth: BTreeMap<String, Growth>,
+    /// When each watchdog last matched (Unix seconds), by name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub watchdogs: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Growth {
+    /// When the mailbox was first looked at (Unix seconds).
+    pub since: i64,
+    pub uid_validity: u32,
+    /// `UIDNEXT` when last looked at.
+    pub uid_next: u32,
+    /// Arrivals per hour, by the hour's start (Unix seconds).
+    pub hours: BTreeMap<i64, u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_arrival: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThreadBody {
+    /// When it was forwarded (Unix seconds).
+    pub at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Burst {
+    /// When its first message was forwarded (Unix seconds).
+    pub started: i64,
+    pub subject: String,
+    pub from: String,
+    /// Sink the first message went to.
+    pub sink: String,
+    /// Copies held back since.
+    pub collapsed: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Unsubscribed {
+    /// When (Unix seconds).
+    pub at: i64,
+    /// E.g. `one-click https://example.com/u/1`.
+    pub via: String,
+    /// Only noted by a dry run.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    pub uid_validity: u32,
+    pub uid: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MailboxState {
+    pub uid_validity: u32,
+    /// Delivered UIDs and when (Unix seconds).
+    pub uids: BTreeMap<u32, i64>,
+    /// Receipt IDs the sink returned for delivered UIDs.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub receipts: BTreeMap<u32, String>,
+}
+
+/// Bytes downloaded from IMAP and uploaded to sinks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bandwidth {
+    pub downloaded: u64,
+    pub uploaded: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleHits {
+    pub matches: u64,
+    /// When the rule was first seen (Unix seconds).
+    pub since: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_match: Option<i64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            version: STATE_VERSION,
+            mailboxes: BTreeMap::new(),
+            delivered: BTreeMap::new(),
+            since: None,
+            quarantine: BTreeMap::new(),
+            skipped: BTreeMap::new(),
+            bandwidth: BTreeMap::new(),
+            reconcile_after: BTreeMap::new(),
+            inflight: BTreeMap::new(),
+            rule_hits: BTreeMap::new(),
+            snoozed: BTreeMap::new(),
+            handled_through: BTreeMap::new(),
+            unsubscribed: BTreeMap::new(),
+            bursts: BTreeMap::new(),
+            threads: BTreeMap::new(),
+            growth: BTreeMap::new(),
+            watchdogs: BTreeMap::new(),
+        }
+    }
+}
+
+impl State {
+    /// Load the state file; a missing file is an empty state.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path, e).into()),
+        }
+    }
+
+    /// Write the state file via a temporary file and rename.
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn is_delivered(&self, mailbox: &str, uid_validity: u32, uid: u32) -> bool {
+        self.mailboxes
+            .get(mailbox)
+            .is_some_and(|m| m.uid_validity == uid_validity && m.uids.contains_key(&uid))
+    }
+
+    pub fn is_duplicate(&self, message_id: &str) -> bool {
+        self.delivered.contains_key(message_id)
+    }
+
+    /// Record a delivery. A changed `UIDVALIDITY` invalidates the
+    /// mailbox's old UIDs.
+    pub fn record(&mut self, mailbox: &str, uid_validity: u32, uid: u32, message_id: Option<&str>, now: i64) {
+        mailbox_entry(&mut self.mailboxes, mailbox, uid_validity).uids.insert(uid, now);
+        if let Some(id) = message_id {
+            self.delivered.insert(id.to_string(), now);
+        }
+        if let Some(inflight) = self.inflight.get_mut(mailbox) {
+            inflight.uids.remove(&uid);
+        }
+        if let Some(snoozed) = self.snoozed.get_mut(mailbox) {
+            snoozed.uids.remove(&uid);
+        }
+    }
+
+    pub fn is_skipped(&self, mailbox: &str, uid_validity: u32, uid: u32) -> bool {
+        self.skipped
+            .get(mailbox)
+            .is_some_and(|m| m.uid_validity == uid_validity && m.uids.contains_key(&uid))
+    }
+
+    /// Hold a message back; it stays unseen on the server.
+    pub fn skip(&mut self, mailbox: &str, uid_validity: u32, uid: u32, now: i64) {
+        mailbox_entry(&mut self.skipped, mailbox, uid_validity).uids.insert(uid, now);
+    }
+
+    /// When a held-back message is due, if it is held.
+    pub fn snoozed_until(&self, mailbox: &str, uid_validity: u32, uid: u32) -> Option<i64> {
+        self.snoozed
+            .get(mailbox)
+            .filter(|m| m.uid_validity == uid_validity)
+            .and_then(|m| m.uids.get(&uid).copied())
+    }
+
+    /// Hold a message back until `due`; it stays unseen on the server.
+    pub fn snooze(&mut self, mailbox: &str, uid_validity: u32, uid: u32, due: i64) {
+        mailbox_entry(&mut self.snoozed, mailbox, uid_validity).uids.insert(uid, due);
+    }
+
+    /// Note that a delivery is about to be sent. Returns whether an
+    /// earlier attempt was sent without an answer.
+    pub fn begin_delivery(&mut self, mailbox: &str, uid_validity: u32, uid: u32, now: i64) -> bool {
+        let entry = mailbox_entry(&mut self.inflight, mailbox, uid_validity);
+        entry.uids.insert(uid, now).is_some()
+    }
+
+    /// Keep the receipt for a delivered UID.
+    pub fn record_receipt(&mut self, mailbox: &str, uid_validity: u32, uid: u32, receipt: &str) {
+        mailbox_entry(&mut self.mailboxes, mailbox, uid_validity).receipts.insert(uid, receipt.to_string());
+    }
+
+    pub fn receipt(&self, mailbox: &str, uid_validity: u32, uid: u32) -> Option<&str> {
+        self.mailboxes
+            .get(mailbox)
+            .filter(|m| m.uid_validity == uid_validity)
+            .and_then(|m| m.receipts.get(&uid))
+            .map(String::as_str)
+    }
+
+    pub fn is_quarantined(&self, mailbox: &str, uid_validity: u32, uid: u32) -> bool {
+        self.quarantine
+            .get(mailbox)
+            .is_some_and(|m| m.uid_validity == uid_validity && m.uids.contains_key(&uid))
+    }
+
+    /// Stop retrying a message; it stays unseen on the server.
+    pub fn quarantine(&mut self, mailbox: &str, uid_validity: u32, uid: u32, now: i64) {
+        mailbox_entry(&mut self.quarantine, mailbox, uid_validity).uids.insert(uid, now);
+    }
+
+    /// The UID after which `mailbox`'s seen mail is reconciled, unless
+    /// reconciliation has not started under this `UIDVALIDITY`.
+    pub fn reconcile_after(&self, mailbox: &str, uid_validity: u32) -> Option<u32> {
+        self.reconcile_after
+            .get(mailbox)
+            .filter(|w| w.uid_validity == uid_validity)
+            .map(|w| w.uid)
+    }
+
+    pub fn start_reconcile(&mut self, mailbox: &str, uid_validity: u32, uid: u32) {
+        self.reconcile_after.insert(mailbox.to_string(), Watermark { uid_validity, uid });
+    }
+
+    /// The UID up to which `mailbox`'s mail was handled, unless it has
+    /// not been tracked under this `UIDVALIDITY`.
+    pub fn handled_through(&self, mailbox: &str, uid_validity: u32) -> Option<u32> {
+        self.handled_through
+            .get(mailbox)
+            .filter(|w| w.uid_validity == uid_validity)
+            .map(|w| w.uid)
+    }
+
+    pub fn set_handled_through(&mut self, mailbox: &str, uid_validity: u32, uid: u32) {
+        self.handled_through.insert(mailbox.to_string(), Watermark { uid_validity, uid });
+    }
+
+    /// Record a delivery known only by `Message-ID`, as reported by the
+    /// Python backend.
+    pub fn record_message(&mut self, message_id: &str, now: i64) {
+        self.delivered.insert(message_id.to_string(), now);
+    }
+
+    /// Add a cycle's traffic to `account`'s total for `day`.
+    pub fn record_bandwidth(&mut self, account: &str, day: NaiveDate, traffic: Bandwidth) {
+        let entry = self.bandwidth.entry(account.to_string()).or_default().entry(day).or_default();
+        entry.downloaded += traffic.downloaded;
+        entry.uploaded += traffic.uploaded;
+    }
+
+    /// Count a message delivered by `rule`.
+    pub fn record_rule_hit(&mut self, rule: &str, now: i64) {
+        let hits = self.rule_hits.entry(rule.to_string()).or_insert(RuleHits { since: now, ..RuleHits::default() });
+        hits.matches += 1;
+        hits.last_match = Some(now);
+    }
+
+    /// Start counting for rules not seen before and forget rules that are
+    /// no longer configured.
+    pub fn track_rules(&mut self, rules: &[&str], now: i64) {
+        self.rule_hits.retain(|name, _| rules.contains(&name.as_str()));
+        for rule in rules {
+            self.rule_hits.entry(rule.to_string()).or_insert(RuleHits { since: now, ..RuleHits::default() });
+        }
+    }
+
+    /// Rules that have not matched since `cutoff`, counting a rule never
+    /// matched from when it was first seen.
+    pub fn unused_rules(&self, cutoff: i64) -> Vec<&str> {
+        self.rule_hits
+            .iter()
+            .filter(|(_, hits)| hits.last_match.unwrap_or(hits.since) < cutoff)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Merge an imported state into this one. Mailboxes with a different
+    /// `UIDVALIDITY` are taken from `other`.
+    pub fn merge(&mut self, other: State) {
+        merge_mailboxes(&mut self.mailboxes, other.mailboxes);
+        merge_mailboxes(&mut self.quarantine, other.quarantine);
+        merge_mailboxes(&mut self.skipped, other.skipped);
+        merge_mailboxes(&mut self.inflight, other.inflight);
+        merge_mailboxes(&mut self.snoozed, other.snoozed);
+        for (id, at) in other.delivered {
+            let entry = self.delivered.entry(id).or_insert(at);
+            *entry = (*entry).max(at);
+        }
+        self.since = self.since.max(other.since);
+        for (mailbox, watermark) in other.reconcile_after {
+            self.reconcile_after.entry(mailbox).or_insert(watermark);
+        }
+        for (mailbox, watermark) in other.handled_through {
+            let entry = self.handled_through.entry(mailbox).or_insert(watermark);
+            if entry.uid_validity == watermark.uid_validity {
+                entry.uid = entry.uid.max(watermark.uid);
+            }
+        }
+        for (list, unsubscribed) in other.unsubscribed {
+            let entry = self.unsubscribed.entry(list).or_insert_with(|| unsubscribed.clone());
+            if entry.dry_run && !unsubscribed.dry_run {
+                *entry = unsubscribed;
+            }
+        }
+        for (thread, body) in other.threads {
+            let entry = self.threads.entry(thread).or_insert_with(|| body.clone());
+            if body.at > entry.at {
+                *entry = body;
+            }
+        }
+        for (key, burst) in other.bursts {
+            self.bursts.entry(key).or_insert(burst);
+        }
+        for (rule, hits) in other.rule_hits {
+            self.rule_hits.entry(rule).or_insert(hits);
+        }
+        for (account, days) in other.bandwidth {
+            for (day, traffic) in days {
+                self.bandwidth.entry(account.clone()).or_default().entry(day).or_insert(traffic);
+            }
+        }
+    }
+
+    /// `UID SEARCH` criteria for unseen mail, honouring `since`.
+    pub fn search_criteria(&self) -> String {
+        match self.since {
+            Some(date) => format!("UNSEEN SINCE {}", date.format("%d-%b-%Y")),
+            None => "UNSEEN".to_string(),
+        }
+    }
+
+    /// Drop entries recorded before `cutoff`. Returns how many went.
+    pub fn prune(&mut self, cutoff: i64) -> usize {
+        let before = self.len();
+        // Held mail counts from when it is due, so an entry left by mail
+        // deleted while held goes a retention period after that.
+        let mailboxes = [
+            &mut self.mailboxes,
+            &mut self.quarantine,
+            &mut self.skipped,
+            &mut self.inflight,
+            &mut self.snoozed,
+        ];
+        for mailbox in mailboxes.into_iter().flat_map(|m| m.values_mut()) {
+            mailbox.uids.retain(|_, at| *at >= cutoff);
+            let uids = &mailbox.uids;
+            mailbox.receipts.retain(|uid, _| uids.contains_key(uid));
+        }
+        self.delivered.retain(|_, at| *at >= cutoff);
+        self.threads.retain(|_, body| body.at >= cutoff);
+        if let Some(cutoff) = DateTime::from_timestamp(cutoff, 0) {
+            for days in self.bandwidth.values_mut() {
+                days.retain(|day, _| *day >= cutoff.date_naive());
+            }
+            self.bandwidth.retain(|_, days| !days.is_empty());
+        }
+        before - self.len()
+    }
+
+    fn len(&self) -> usize {
+        self.delivered.len()
+            + self.threads.len()
+            + [&self.mailboxes, &self.quarantine, &self.skipped, &self.inflight, &self.snoozed]
+                .into_iter()
+                .flat_map(|m| m.values())
+                .map(|m| m.uids.len())
+                .sum::<usize>()
+            + self.bandwidth.values().map(BTreeMap::len).sum::<usize>()
+    }
+}
+
+/// `mailbox`'s entry in `map`, emptied if its `UIDVALIDITY` changed.
+fn mailbox_entry<'a>(map: &'a mut BTreeMap<String, MailboxState>, mailbox: &str, uid_validity: u32) -> &'a mut MailboxState {
+    let entry = map.entry(mailbox.to_string()).or_default();
+    if entry.uid_validity != uid_validity {
+        *entry = MailboxState {
+            uid_validity,
+            ..MailboxState::default()
+        };
+    }
+    entry
+}
+
+fn merge_mailboxes(into: &mut BTreeMap<String, MailboxState>, from: BTreeMap<String, MailboxState>) {
+    for (name, imported) in from {
+        match into.get_mut(&name) {
+            Some(existing) if existing.uid_validity == imported.uid_validity => {
+                existing.uids.extend(imported.uids);
+                existing.receipts.extend(imported.receipts);
+            }
+            _ => {
+                into.insert(name, imported);
+            }
+        }
+    }
+}
+
+/// Read the Python checker's `LAST_CHECK_FILE` (or the directory holding
+/// `.last_email_check`) and return the date of its last check.
+pub fn python_last_check(path: &Path) -> Result<NaiveDate, Box<dyn Error>> {
+    let file = if path.is_dir() { path.join(".last_email_check") } else { path.to_path_buf() };
+    let text = fs::read_to_string(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    let text = text.trim();
+    // `datetime.now().isoformat()`, naive unless the clock carried an offset.
+    let parsed = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| DateTime::parse_from_rfc3339(text).map(|dt| dt.naive_local()))
+        .map_err(|_| format!("{}: not an ISO timestamp: {}", file.display(), text))?;
+    Ok(parsed.date())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_uid_validity() {
+        let mut state = State::default();
+        state.record("INBOX", 7, 42, Some("<a@example.com>"), 100);
+        assert!(state.is_delivered("INBOX", 7, 42));
+        assert!(!state.is_delivered("INBOX", 8, 42));
+        assert!(state.is_duplicate("<a@example.com>"));
+
+        state.quarantine("INBOX", 7, 43, 100);
+        assert!(state.is_quarantined("INBOX", 7, 43) && !state.is_quarantined("INBOX", 7, 42));
+        state.skip("INBOX", 7, 45, 100);
+        assert!(state.is_skipped("INBOX", 7, 45) && !state.is_skipped("INBOX", 7, 43));
+
+        assert!(!state.begin_delivery("INBOX", 7, 44, 100));
+        assert!(state.begin_delivery("INBOX", 7, 44, 110), "a retry of an unanswered delivery");
+        state.record("INBOX", 7, 44, None, 120);
+        state.record_receipt("INBOX", 7, 44, "r-1");
+        assert!(!state.begin_delivery("INBOX", 7, 44, 130) && state.receipt("INBOX", 7, 44) == Some("r-1"));
+        state.record("INBOX", 7, 44, None, 130);
+
+        state.record("INBOX", 8, 1, None, 200);
+        assert!(!state.is_delivered("INBOX", 7, 42));
+        assert_eq!(state.prune(150), 3);
+        assert!(!state.is_duplicate("<a@example.com>"));
+
+        let day = NaiveDate::from_ymd_opt(2026, 3, 7).unwrap();
+        let traffic = Bandwidth {
+            downloaded: 1000,
+            uploaded: 10,
+        };
+        state.record_bandwidth("bot@example.com", day, traffic);
+        state.record_bandwidth("bot@example.com", day, traffic);
+        assert_eq!(state.bandwidth["bot@example.com"][&day].downloaded, 2000);
+        assert_eq!(state.prune(day.succ_opt().unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp()), 2);
+        assert!(state.bandwidth.is_empty());
+    }
+
+    #[test]
+    fn test_save_load_and_merge() {
+        let path = std::env::temp_dir().join(format!("email_checker_state_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut state = State::default();
+        state.record("INBOX", 7, 1, Some("<a@x>"), 100);
+        state.save(path).unwrap();
+        assert_eq!(State::load(path).unwrap(), state);
+
+        let mut other = State::default();
+        other.record("INBOX", 7, 2, Some("<a@x>"), 300);
+        state.merge(other);
+        assert!(state.is_delivered("INBOX", 7, 1) && state.is_delivered("INBOX", 7, 2));
+        assert_eq!(state.delivered["<a@x>"], 300);
+        fs::remove_file(path).unwrap();
+        assert_eq!(State::load(path).unwrap(), State::default());
+    }
+
+    #[test]
+    fn test_rule_hits_and_unused_rules() {
+        let mut state = State::default();
+        state.track_rules(&["ci", "invoices"], 100);
+        state.record_rule_hit("ci", 500);
+        state.record_rule_hit("ci", 900);
+        assert_eq!(state.rule_hits["ci"], RuleHits { matches: 2, since: 100, last_match: Some(900) });
+        assert_eq!(state.unused_rules(800), vec!["invoices"]);
+        assert_eq!(state.unused_rules(1000), vec!["ci", "invoices"]);
+        // A rule added later counts from when it was first seen.
+        state.track_rules(&["ci", "newsletters"], 2000);
+        assert_eq!(state.rule_hits.keys().collect::<Vec<_>>(), ["ci", "newsletters"]);
+        assert_eq!(state.unused_rules(1500), vec!["ci"]);
+    }
+
+    #[test]
+    fn test_python_last_check() {
+        let dir = std::env::temp_dir().join(format!("email_checker_python_state_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(".last_email_check"), "2026-03-07T18:04:05.123456\n").unwrap();
+        let date = python_last_check(&dir).unwrap();
+        let state = State {
+            since: Some(date),
+            ..State::default()
+        };
+        assert_eq!(state.search_criteria(), "UNSEEN SINCE 07-Mar-2026");
+        fs::write(dir.join(".last_email_check"), "2026-03-08T09:00:00").unwrap();
+        assert_eq!(python_last_check(&dir.join(".last_email_check")).unwrap().to_string(), "2026-03-08");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+//! Digest sinks: many events collected into one periodic summary.
+//!
+//! A `digest` sink accepts events without passing them on. Once `window`
+//! seconds have passed since the first one it holds, it sends `sink`
+//! one `digest` event with their count, senders and subjects, so
+//! high-volume, low-priority mail costs one notification per window.
+//! Accepted events count as delivered, so they are kept in `file` until
+//! the summary went out and a restart loses none. Without `file`, they
+//! are kept next to `state_file`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::email::OPENCLAW_CHANNEL;
+use crate::sink::{Sink, SinkConfig, DEFAULT_SINK};
+
+pub const DEFAULT_WINDOW: u64 = 3600;
+pub const DEFAULT_MAX_SUBJECTS: usize = 20;
+
+/// A `digest` entry in `sinks`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DigestConfig {
+    /// Sink the summaries are sent to.
+    pub sink: String,
+    #[serde(default = "default_window")]
+    pub window: u64,
+    /// Subjects listed in the summary's message; all are in its data.
+    #[serde(default = "default_max_subjects")]
+    pub max_subjects: usize,
+    pub file: Option<String>,
+}
+
+impl DigestConfig {
+    pub fn validate(&self, sinks: &BTreeMap<String, SinkConfig>) -> Result<(), String> {
+        if self.window == 0 {
+            return Err("window must be positive".to_string());
+        }
+        match sinks.get(&self.sink) {
+            None if self.sink != DEFAULT_SINK => Err(format!("unknown sink '{}'", self.sink)),
+            Some(SinkConfig::Digest(_)) => Err(format!("sink '{}' is itself a digest", self.sink)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn default_window() -> u64 {
+    DEFAULT_WINDOW
+}
+
+fn default_max_subjects() -> usize {
+    DEFAULT_MAX_SUBJECTS
+}
+
+/// One collected event.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    pub at: i64,
+    pub from: String,
+    pub subject: String,
+    pub event_type: String,
+}
+
+impl Entry {
+    /// The parts of `payload` a summary lists, from the built-in payload's
+    /// headers or a template's `from` and `subject`.
+    pub fn from_payload(payload: &Value, at: i64) -> Self {
+        let field = |header: &str, key: &str| {
+            payload["headers"][header]
+                .as_str()
+                .or_else(|| payload[key].as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Self {
+            at,
+            from: field("From", "from"),
+            subject: field("Subject", "subject"),
+            event_type: payload["event_type"].as_str().unwrap_or("message").to_string(),
+        }
+    }
+}
+
+/// The summary event for `entries`.
+pub fn summarize(entries: &[Entry], max_subjects: usize) -> Value {
+    let mut senders: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in entries {
+        *senders.entry(entry.from.as_str()).or_default() += 1;
+    }
+    let mut message = format!("📬 Digest: {} emails from {} senders\n", entries.len(), senders.len());
+    for entry in entries.iter().take(max_subjects) {
+        message.push_str(&format!("\n• {} ({})", entry.subject, entry.from));
+    }
+    if entries.len() > max_subjects {
+        message.push_str(&format!("\n… and {} more", entries.len() - max_subjects));
+    }
+    let time = |at: Option<i64>| at.and_then(|at| chrono::DateTime::from_timestamp(at, 0)).map(|at| at.to_rfc3339());
+    json!({
+        "channel": OPENCLAW_CHANNEL,
+        "event_type": "digest",
+        "message": message,
+        "digest": {
+            "count": entries.len(),
+            "since": time(entries.first().map(|e| e.at)),
+            "until": time(entries.last().map(|e| e.at)),
+            "senders": senders,
+            "subjects": entries.iter().map(|e| &e.subject).collect::<Vec<_>>(),
+        },
+    })
+}
+
+pub struct DigestSink {
+    target: Box<dyn Sink>,
+    window: u64,
+    max_subjects: usize,
+    file: Option<String>,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl DigestSink {
+    /// A digest sending to `target`, resuming the events kept in `file`.
+    pub fn new(config: &DigestConfig, file: Option<String>, target: Box<dyn Sink>) -> Self {
+        let entries = file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self {
+            target,
+            window: config.window,
+            max_subjects: config.max_subjects,
+            file,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn save(&self, entries: &[Entry]) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, serde_json::to_string(entries)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl Sink for DigestSink {
+    fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.push(Entry::from_payload(payload, chrono::Utc::now().timestamp()));
+        if let Err(e) = self.save(&entries) {
+            entries.pop();
+            return Err(format!("could not keep the event for the digest: {}", e).into());
+        }
+        Ok(())
+    }
+
+    /// Send the summary once the window since the first event has passed.
+    fn tick(&self) -> Result<(), Box<dyn Error>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(first) = entries.first() else {
+            return Ok(());
+        };
+        if chrono::Utc::now().timestamp() - first.at < self.window as i64 {
+            return Ok(());
+        }
+        self.target.deliver(&summarize(&entries, self.max_subjects))?;
+        entries.clear();
+        self.save(&entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Recorder(Rc<RefCell<Vec<Value>>>);
+
+    impl Sink for Recorder {
+        fn deliver(&self, payload: &Value) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(payload.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_digest_collects_and_summarizes() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let config = DigestConfig { sink: "openclaw".to_string(), window: 0, max_subjects: 1, file: None };
+        let digest = DigestSink::new(&config, None, Box::new(Recorder(sent.clone())));
+        digest.tick().unwrap();
+        assert!(sent.borrow().is_empty(), "nothing collected, nothing sent");
+        for subject in ["Sale", "New arrivals"] {
+            let payload = json!({"event_type": "message", "headers": {"From": "shop@example.com", "Subject": subject}});
+            digest.deliver(&payload).unwrap();
+        }
+        digest.deliver(&json!({"event_type": "newsletter", "from": "blog@example.org", "subject": "Weekly"})).unwrap();
+        digest.tick().unwrap();
+        let summary = &sent.borrow()[0];
+        assert_eq!(summary["event_type"], "digest");
+        assert_eq!(summary["digest"]["count"], 3);
+        assert_eq!(summary["digest"]["senders"]["shop@example.com"], 2);
+        assert_eq!(summary["message"], "📬 Digest: 3 emails from 2 senders\n\n• Sale (shop@example.com)\n… and 2 more");
+        digest.tick().unwrap();
+        assert_eq!(sent.borrow().len(), 1);
+    }
+}
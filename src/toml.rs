@@ -0,0 +1,137 @@
+//! Writing JSON values as TOML, for `config show --format toml`.
+//!
+//! Objects become tables and arrays of objects arrays of tables; objects
+//! inside other arrays are written inline. TOML has no null, so keys
+//! whose value is null are left out, which reads the same to a
+//! `#[serde(default)]` config.
+
+use serde_json::{Map, Value};
+
+/// `value`, which must be an object, as a TOML document.
+pub fn to_string(value: &Value) -> Result<String, String> {
+    let Value::Object(map) = value else {
+        return Err("only an object can be written as TOML".to_string());
+    };
+    let mut out = String::new();
+    table(&mut out, &mut Vec::new(), map);
+    Ok(out.trim_start().to_string())
+}
+
+fn table(out: &mut String, path: &mut Vec<String>, map: &Map<String, Value>) {
+    for (name, value) in map {
+        if !value.is_null() && !value.is_object() && !is_table_array(value) {
+            out.push_str(&format!("{} = {}\n", key(name), inline(value)));
+        }
+    }
+    for (name, value) in map {
+        path.push(key(name));
+        match value {
+            Value::Object(map) => {
+                out.push_str(&format!("\n[{}]\n", path.join(".")));
+                table(out, path, map);
+            }
+            Value::Array(items) if is_table_array(value) => {
+                for map in items.iter().filter_map(Value::as_object) {
+                    out.push_str(&format!("\n[[{}]]\n", path.join(".")));
+                    table(out, path, map);
+                }
+            }
+            _ => {}
+        }
+        path.pop();
+    }
+}
+
+fn is_table_array(value: &Value) -> bool {
+    matches!(value, Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object))
+}
+
+/// A bare key where TOML allows one, else a quoted one.
+fn key(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        name.to_string()
+    } else {
+        string(name)
+    }
+}
+
+fn inline(value: &Value) -> String {
+    match value {
+        Value::String(s) => string(s),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().filter(|v| !v.is_null()).map(inline).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(map) => {
+            let fields: Vec<String> =
+                map.iter().filter(|(_, v)| !v.is_null()).map(|(k, v)| format!("{} = {}", key(k), inline(v))).collect();
+            if fields.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", fields.join(", "))
+            }
+        }
+        other => other.to_string(),
+    }
+}
+
+/// A basic string, with the escapes TOML requires.
+fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tables_and_arrays() {
+        let value = json!({
+            "check_interval": 300,
+            "profile": null,
+            "folders": ["INBOX", "Entwürfe"],
+            "tls": {"verify": true, "ca_file": null},
+            "rules": [{"name": "ci", "match": {"from": "ci@\"x\""}}, {"name": "all"}],
+            "sinks": {"ops.team": {"type": "webhook", "headers": {}}},
+        });
+        let expected = r#"check_interval = 300
+folders = ["INBOX", "Entwürfe"]
+
+[[rules]]
+name = "ci"
+
+[rules.match]
+from = "ci@\"x\""
+
+[[rules]]
+name = "all"
+
+[sinks]
+
+[sinks."ops.team"]
+type = "webhook"
+
+[sinks."ops.team".headers]
+
+[tls]
+verify = true
+"#;
+        assert_eq!(to_string(&value).unwrap(), expected);
+        assert!(to_string(&json!([1])).is_err());
+    }
+}
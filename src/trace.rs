@@ -0,0 +1,232 @@
+//! OpenTelemetry spans for check cycles, exported over OTLP/HTTP.
+//!
+//! Each cycle is one trace: a `cycle` span with a `message` span per
+//! fetched message, which in turn holds `fetch`, `parse`, `filter` and
+//! `deliver`. Spans are collected during the cycle and posted as OTLP
+//! JSON to `{tracing.otlp_endpoint}/v1/traces` when it ends, so any
+//! collector (or Jaeger, Tempo, Honeycomb, ...) can show them. Errors
+//! and attributes are redacted like log lines on the way out.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::digest::hex;
+use crate::http;
+use crate::redact;
+use crate::secrets::SecretString;
+
+pub const DEFAULT_SERVICE_NAME: &str = "email_checker";
+
+/// `tracing` section of the config file.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// OTLP/HTTP base URL, e.g. `http://localhost:4318`; tracing is off
+    /// without one.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Extra request headers, e.g. an API key for a hosted backend.
+    pub headers: BTreeMap<String, SecretString>,
+}
+
+/// The `headers` values, which carry the collector's API key, print
+/// masked.
+impl fmt::Debug for TracingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        redact::debug_masked("TracingConfig", self, f)
+    }
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: DEFAULT_SERVICE_NAME.to_string(),
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    id: [u8; 8],
+    parent: Option<[u8; 8]>,
+    name: &'static str,
+    start: u128,
+    end: u128,
+    attributes: Vec<(&'static str, String)>,
+    error: Option<String>,
+}
+
+/// Spans of the current cycle. Spans nest: a new span's parent is the
+/// innermost one still open.
+#[derive(Debug, Default)]
+pub struct Tracer {
+    trace_id: [u8; 16],
+    spans: Vec<Span>,
+    open: Vec<usize>,
+}
+
+impl Tracer {
+    /// Start a new trace, dropping anything not exported.
+    pub fn begin(&mut self) {
+        self.trace_id = random_id();
+        self.spans.clear();
+        self.open.clear();
+    }
+
+    pub fn start(&mut self, name: &'static str) -> usize {
+        let parent = self.open.last().map(|&i| self.spans[i].id);
+        self.spans.push(Span {
+            id: random_id(),
+            parent,
+            name,
+            start: now_nanos(),
+            end: 0,
+            attributes: Vec::new(),
+            error: None,
+        });
+        self.open.push(self.spans.len() - 1);
+        self.spans.len() - 1
+    }
+
+    pub fn attr(&mut self, span: usize, key: &'static str, value: impl ToString) {
+        if let Some(span) = self.spans.get_mut(span) {
+            span.attributes.push((key, value.to_string()));
+        }
+    }
+
+    /// End `span` and any spans still open inside it, marking it failed
+    /// if `error` is set.
+    pub fn end(&mut self, span: usize, error: Option<String>) {
+        let Some(position) = self.open.iter().rposition(|&i| i == span) else {
+            return;
+        };
+        let now = now_nanos();
+        for i in self.open.drain(position..) {
+            self.spans[i].end = now;
+        }
+        self.spans[span].error = error;
+    }
+
+    /// The collected spans as an OTLP `ExportTraceServiceRequest`.
+    pub fn to_otlp(&self, service_name: &str) -> Value {
+        let spans: Vec<Value> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let attributes: Vec<Value> =
+                    span.attributes.iter().map(|(k, v)| attribute(k, &redact::redact(v))).collect();
+                let mut value = json!({
+                    "traceId": hex(&self.trace_id),
+                    "spanId": hex(&span.id),
+                    "name": span.name,
+                    "kind": 1,
+                    "startTimeUnixNano": span.start.to_string(),
+                    "endTimeUnixNano": span.end.max(span.start).to_string(),
+                    "attributes": attributes,
+                });
+                if let Some(parent) = span.parent {
+                    value["parentSpanId"] = json!(hex(&parent));
+                }
+                if let Some(error) = &span.error {
+                    value["status"] = json!({"code": 2, "message": redact::redact(error)});
+                }
+                value
+            })
+            .collect();
+        json!({
+            "resourceSpans": [{
+                "resource": {"attributes": [attribute("service.name", service_name)]},
+                "scopeSpans": [{"scope": {"name": DEFAULT_SERVICE_NAME}, "spans": spans}],
+            }]
+        })
+    }
+
+    /// Post the spans to the configured collector, if any, and clear them.
+    pub fn export(&mut self, config: &TracingConfig) -> Result<(), Box<dyn Error>> {
+        let Some(endpoint) = &config.otlp_endpoint else {
+            return Ok(());
+        };
+        if self.spans.is_empty() {
+            return Ok(());
+        }
+        let body = self.to_otlp(&config.service_name).to_string();
+        self.spans.clear();
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let mut headers: Vec<(&str, &str)> = vec![("Content-Type", "application/json")];
+        headers.extend(config.headers.iter().map(|(k, v)| (k.as_str(), v.expose())));
+        let response = http::request("POST", &url, &headers, Some(&body), http::DEFAULT_TIMEOUT)?;
+        if !response.is_success() {
+            return Err(format!("{} returned HTTP {}", url, response.status).into());
+        }
+        Ok(())
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+/// Random bytes for trace, span and event ids.
+pub fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0u8; N];
+    if File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut id)).is_err() {
+        let nanos = now_nanos().to_le_bytes();
+        id.copy_from_slice(&nanos[..N]);
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_spans_to_otlp() {
+        let mut tracer = Tracer::default();
+        tracer.begin();
+        let cycle = tracer.start("cycle");
+        let message = tracer.start("message");
+        tracer.attr(message, "imap.uid", 7);
+        tracer.start("fetch");
+        tracer.end(cycle, Some("connection reset".to_string()));
+
+        let otlp = tracer.to_otlp("mail-bot");
+        let resource = &otlp["resourceSpans"][0];
+        assert_eq!(resource["resource"]["attributes"][0]["value"]["stringValue"], "mail-bot");
+        let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 3);
+        assert!(spans[0].get("parentSpanId").is_none());
+        assert_eq!(spans[0]["status"]["code"], 2);
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(spans[2]["parentSpanId"], spans[1]["spanId"]);
+        assert_eq!(spans[1]["attributes"][0]["value"]["stringValue"], "7");
+        assert_eq!(spans[0]["traceId"].as_str().unwrap().len(), 32);
+        assert!(tracer.open.is_empty());
+    }
+
+    #[test]
+    fn test_export_is_redacted() {
+        let mut tracer = Tracer::default();
+        tracer.begin();
+        let span = tracer.start("deliver");
+        tracer.attr(span, "sink", "https://hooks.example.com/?token=abc123");
+        tracer.end(span, Some("login failed: password=hunter22".to_string()));
+        let otlp = tracer.to_otlp("mail-bot");
+        let span = &otlp["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["attributes"][0]["value"]["stringValue"], "https://hooks.example.com/?token=[REDACTED]");
+        assert_eq!(span["status"]["message"], "login failed: password=[REDACTED]");
+    }
+}
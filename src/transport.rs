@@ -0,0 +1,216 @@
+//! Byte streams to the IMAP server.
+//!
+//! TLS is provided by an `openssl s_client` child process whose stdin
+//! and stdout form the stream, keeping TLS out of the dependency tree.
+//! Like the Python checker, the certificate is not verified unless
+//! `tls.verify` or `tls.verify_hostname` is set.
+//!
+//! Servers that only listen on 143 are reached with STARTTLS, which
+//! `s_client -starttls imap` negotiates before handing over the stream;
+//! the greeting is consumed in the process.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metrics;
+use crate::process;
+
+pub const STARTTLS_PORT: usize = 143;
+
+/// `tls` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Use STARTTLS instead of implicit TLS; defaults to on for port 143.
+    pub starttls: Option<bool>,
+    /// Refuse to continue in plaintext if STARTTLS fails.
+    pub require_starttls: bool,
+    /// Verify the certificate chain and that it matches the host.
+    pub verify: bool,
+    /// Name the certificate must match, for servers reached by IP or an
+    /// internal alias. Implies `verify`.
+    pub verify_hostname: Option<String>,
+    /// Server name sent in the TLS handshake instead of the host.
+    pub sni: Option<String>,
+    /// CA bundle to verify against instead of the system store.
+    pub ca_file: Option<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            starttls: None,
+            require_starttls: true,
+            verify: false,
+            verify_hostname: None,
+            sni: None,
+            ca_file: None,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn use_starttls(&self, port: usize) -> bool {
+        self.starttls.unwrap_or(port == STARTTLS_PORT)
+    }
+
+    /// `s_client` options for SNI and verification when connecting to `host`.
+    fn s_client_args(&self, host: &str) -> Vec<String> {
+        let server_name = self.sni.as_deref().unwrap_or(host);
+        let mut args = vec!["-servername".to_string(), server_name.to_string()];
+        if self.verify || self.verify_hostname.is_some() {
+            let expected = self.verify_hostname.as_deref().unwrap_or(server_name);
+            args.extend(["-verify_return_error".to_string(), "-verify_hostname".to_string(), expected.to_string()]);
+        }
+        if let Some(ca_file) = &self.ca_file {
+            args.extend(["-CAfile".to_string(), ca_file.clone()]);
+        }
+        args
+    }
+}
+
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Open an implicit-TLS connection to `host`, resolved to `addr`.
+pub fn connect_tls(host: &str, addr: SocketAddr, tls: &TlsConfig) -> io::Result<Box<dyn Stream>> {
+    spawn_s_client(host, addr, tls, &[])
+}
+
+/// Connect in plaintext and upgrade with STARTTLS. The server greeting
+/// has already been read when this returns.
+pub fn connect_starttls(host: &str, addr: SocketAddr, tls: &TlsConfig) -> io::Result<Box<dyn Stream>> {
+    spawn_s_client(host, addr, tls, &["-starttls", "imap"])
+}
+
+/// Connect to a ManageSieve server and upgrade with STARTTLS. The
+/// capabilities it sends after the handshake are left to be read.
+pub fn connect_sieve(host: &str, addr: SocketAddr, tls: &TlsConfig) -> io::Result<Box<dyn Stream>> {
+    spawn_s_client(host, addr, tls, &["-starttls", "sieve"])
+}
+
+/// Connect to an SMTP submission server and upgrade with STARTTLS. The
+/// greeting has been read and `EHLO` sent when this returns.
+pub fn connect_smtp(host: &str, addr: SocketAddr, tls: &TlsConfig) -> io::Result<Box<dyn Stream>> {
+    spawn_s_client(host, addr, tls, &["-starttls", "smtp"])
+}
+
+/// Unencrypted connection, only used when STARTTLS is not required.
+pub fn connect_plain(addr: SocketAddr) -> io::Result<Box<dyn Stream>> {
+    Ok(Box::new(Metered(TcpStream::connect(addr)?)))
+}
+
+fn spawn_s_client(host: &str, addr: SocketAddr, tls: &TlsConfig, extra: &[&str]) -> io::Result<Box<dyn Stream>> {
+    let args: Vec<String> = tls.s_client_args(host).into_iter().chain(extra.iter().map(|a| a.to_string())).collect();
+    Ok(Box::new(Metered(s_client(addr, &args)?)))
+}
+
+/// An `openssl s_client` connection to `addr` with the given options.
+pub fn s_client(addr: SocketAddr, args: &[String]) -> io::Result<ChildStream> {
+    let mut child = process::command("openssl", process::TLS_ENV)
+        .args(["s_client", "-quiet"])
+        .arg("-connect")
+        .arg(addr.to_string())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run openssl: {}", e)))?;
+    let stdin = child.stdin.take().ok_or_else(|| io::Error::other("openssl stdin unavailable"))?;
+    let stdout = child.stdout.take().ok_or_else(|| io::Error::other("openssl stdout unavailable"))?;
+    Ok(ChildStream { child, stdin, stdout })
+}
+
+/// Counts the bytes read from the server into the download metric.
+pub struct Metered<S>(pub S);
+
+impl<S: Read> Read for Metered<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        metrics::add(metrics::IMAP_BYTES_DOWNLOADED, n as u64);
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Metered<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// A child process used as a bidirectional pipe; killed on drop.
+pub struct ChildStream {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl Read for ChildStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for ChildStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl Drop for ChildStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_starttls_default() {
+        let tls = TlsConfig::default();
+        assert!(tls.use_starttls(143));
+        assert!(!tls.use_starttls(993));
+        let forced = TlsConfig {
+            starttls: Some(true),
+            ..TlsConfig::default()
+        };
+        assert!(forced.use_starttls(10143) && forced.require_starttls);
+    }
+
+    #[test]
+    fn test_sni_and_verify_hostname() {
+        let tls = TlsConfig::default();
+        assert_eq!(tls.s_client_args("10.0.0.5"), ["-servername", "10.0.0.5"]);
+        let tls = TlsConfig {
+            sni: Some("mail.example.com".to_string()),
+            verify_hostname: Some("mail.internal".to_string()),
+            ..TlsConfig::default()
+        };
+        assert_eq!(
+            tls.s_client_args("10.0.0.5"),
+            ["-servername", "mail.example.com", "-verify_return_error", "-verify_hostname", "mail.internal"]
+        );
+        let tls = TlsConfig {
+            verify: true,
+            sni: Some("mail.example.com".to_string()),
+            ..TlsConfig::default()
+        };
+        assert_eq!(tls.s_client_args("10.0.0.5")[4], "mail.example.com");
+    }
+}
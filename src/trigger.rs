@@ -0,0 +1,84 @@
+//! Forwarding on a flag instead of on arrival.
+//!
+//! With `trigger.flag` set, e.g. to `\Flagged`, the checker forwards the
+//! messages that carry the flag or keyword rather than the unseen ones,
+//! so starring a mail in any client sends it to OpenClaw. Forwarded
+//! messages get `trigger.keyword` instead of `\Seen`, which leaves the
+//! user's read state alone and keeps them from being sent twice.
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_KEYWORD: &str = "$Forwarded";
+
+/// `trigger` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TriggerConfig {
+    /// Flag or keyword that sends a message, e.g. `\Flagged` or `$OpenClaw`.
+    pub flag: Option<String>,
+    /// Keyword set on a message once it was forwarded.
+    pub keyword: String,
+}
+
+impl Default for TriggerConfig {
+    fn default() -> Self {
+        Self {
+            flag: None,
+            keyword: DEFAULT_KEYWORD.to_string(),
+        }
+    }
+}
+
+impl TriggerConfig {
+    /// `UID SEARCH` criteria for the messages to forward, unless mail is
+    /// forwarded on arrival.
+    pub fn search_criteria(&self) -> Option<String> {
+        let flag = self.flag.as_deref()?;
+        let key = match flag.to_ascii_lowercase().as_str() {
+            "\\answered" => "ANSWERED".to_string(),
+            "\\deleted" => "DELETED".to_string(),
+            "\\draft" => "DRAFT".to_string(),
+            "\\flagged" => "FLAGGED".to_string(),
+            "\\seen" => "SEEN".to_string(),
+            _ => format!("KEYWORD {}", flag),
+        };
+        Some(format!("{} UNKEYWORD {}", key, self.keyword))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(flag) = &self.flag {
+            let system = ["\\answered", "\\deleted", "\\draft", "\\flagged", "\\seen"];
+            if flag.starts_with('\\') && !system.contains(&flag.to_ascii_lowercase().as_str()) {
+                return Err(format!("trigger.flag: '{}' is not a system flag", flag));
+            }
+            if !flag.starts_with('\\') && !is_atom(flag) {
+                return Err(format!("trigger.flag: '{}' is not a valid keyword", flag));
+            }
+        }
+        if !is_atom(&self.keyword) {
+            return Err(format!("trigger.keyword: '{}' is not a valid keyword", self.keyword));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `word` can be sent as an IMAP keyword.
+fn is_atom(word: &str) -> bool {
+    !word.is_empty() && word.bytes().all(|b| b.is_ascii_graphic() && !b"(){%*\"\\]".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_criteria_and_validation() {
+        let trigger = |flag: &str| TriggerConfig { flag: Some(flag.to_string()), ..TriggerConfig::default() };
+        assert_eq!(TriggerConfig::default().search_criteria(), None);
+        assert_eq!(trigger("\\Flagged").search_criteria().unwrap(), "FLAGGED UNKEYWORD $Forwarded");
+        assert_eq!(trigger("$OpenClaw").search_criteria().unwrap(), "KEYWORD $OpenClaw UNKEYWORD $Forwarded");
+        assert!(trigger("\\Recent").validate().is_err());
+        assert!(trigger("to openclaw").validate().is_err());
+        assert!(trigger("\\flagged").validate().is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+//! Unsubscribing from newsletters through `List-Unsubscribe`.
+//!
+//! A rule with `"unsubscribe": true` unsubscribes the account from the
+//! lists of the mail it matches, once per list. One-click HTTPS (RFC
+//! 8058, announced by `List-Unsubscribe-Post`) is preferred, then a
+//! `mailto:` request sent through the submission port. A plain link is
+//! never visited, since it may need a person to confirm. With
+//! `unsubscribe.dry_run` nothing is sent; each list is only noted in the
+//! state file, and `unsubscribe report` shows what was or would be done.
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::email::EmailData;
+use crate::http;
+use crate::redact;
+use crate::smtp::SmtpSink;
+
+/// `unsubscribe` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UnsubscribeConfig {
+    /// Only note which lists would be unsubscribed from.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Method {
+    /// POST `List-Unsubscribe=One-Click` to the URL.
+    OneClick(String),
+    Mailto { to: String, subject: String, body: String },
+}
+
+impl Method {
+    /// How the request is made, for the log and report.
+    pub fn describe(&self) -> String {
+        match self {
+            Method::OneClick(url) => format!("one-click {}", url),
+            Method::Mailto { to, .. } => format!("mail to {}", to),
+        }
+    }
+}
+
+/// The list `email` came from: its `List-Id`, else the sender.
+pub fn list(email: &EmailData) -> String {
+    match email.header("List-Id") {
+        Some(id) => id.rsplit_once('<').map_or(id, |(_, id)| id.trim_end_matches('>')).trim().to_string(),
+        None => redact::addresses(&email.from).first().map_or(email.from.clone(), |a| a.to_lowercase()),
+    }
+}
+
+/// The way to unsubscribe `email`'s recipient, if it offers a usable one.
+pub fn method(email: &EmailData) -> Option<Method> {
+    let header = email.header("List-Unsubscribe")?;
+    let uris: Vec<&str> = header.split(',').map(|uri| uri.trim().trim_start_matches('<').trim_end_matches('>')).collect();
+    let one_click = email
+        .header("List-Unsubscribe-Post")
+        .is_some_and(|post| post.split_whitespace().collect::<String>().eq_ignore_ascii_case("List-Unsubscribe=One-Click"));
+    if let Some(url) = uris.iter().find(|uri| one_click && uri.starts_with("https://")) {
+        return Some(Method::OneClick(url.to_string()));
+    }
+    let mailto = uris.iter().find_map(|uri| uri.strip_prefix("mailto:"))?;
+    let (to, query) = mailto.split_once('?').unwrap_or((mailto, ""));
+    let param = |name: &str| {
+        query
+            .split('&')
+            .find_map(|pair| pair.split_once('=').filter(|(key, _)| key.eq_ignore_ascii_case(name)))
+            .map(|(_, value)| percent_decode(value))
+    };
+    Some(Method::Mailto {
+        to: percent_decode(to),
+        subject: param("subject").unwrap_or_else(|| "unsubscribe".to_string()),
+        body: param("body").unwrap_or_else(|| "unsubscribe".to_string()),
+    })
+}
+
+/// Make the unsubscribe request as the account in `config`.
+pub fn send(method: &Method, config: &Config) -> Result<(), Box<dyn Error>> {
+    match method {
+        Method::OneClick(url) => {
+            let headers = [("Content-Type", "application/x-www-form-urlencoded")];
+            let response = http::request("POST", url, &headers, Some("List-Unsubscribe=One-Click"), http::DEFAULT_TIMEOUT)?;
+            if !response.is_success() {
+                return Err(format!("{} returned HTTP {}", url, response.status).into());
+            }
+            Ok(())
+        }
+        Method::Mailto { to, subject, body } => {
+            let smtp = SmtpSink::new(std::slice::from_ref(to), None, None, None, config);
+            let message = format!(
+                "Date: {}\r\nFrom: {}\r\nTo: {}\r\nSubject: {}\r\nAuto-Submitted: auto-generated\r\n\r\n{}\r\n",
+                chrono::Local::now().to_rfc2822(),
+                smtp.from,
+                to,
+                crate::smtp::encode_header(subject),
+                body.replace("\r\n", "\n").replace('\n', "\r\n")
+            );
+            smtp.send_message(&smtp.from, &message)
+        }
+    }
+}
+
+/// `%XX` escapes in a URI part decoded.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_methods() {
+        let email = |headers: &str| EmailData::from_raw(format!("From: News <news@shop.example>\r\n{}\r\n\r\nbody", headers).as_bytes());
+        let both = "List-Unsubscribe: <mailto:leave@shop.example?subject=Leave%20me>, <https://shop.example/u/1>";
+        assert_eq!(
+            method(&email(&format!("{}\r\nList-Unsubscribe-Post: List-Unsubscribe=One-Click", both))),
+            Some(Method::OneClick("https://shop.example/u/1".to_string()))
+        );
+        let Some(Method::Mailto { to, subject, body }) = method(&email(both)) else { panic!("expected mailto") };
+        assert_eq!((to.as_str(), subject.as_str(), body.as_str()), ("leave@shop.example", "Leave me", "unsubscribe"));
+        assert_eq!(method(&email("List-Unsubscribe: <https://shop.example/u/1>")), None);
+        assert_eq!(list(&email("List-Id: Shop News <news.shop.example>")), "news.shop.example");
+        assert_eq!(list(&email("")), "news@shop.example");
+    }
+}
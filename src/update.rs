@@ -0,0 +1,247 @@
+//! Replacing the running binary with the latest release.
+//!
+//! `self-update` asks `update.url` for the latest release, by default
+//! this project's GitHub releases; Gitea and Forgejo answer in the same
+//! format. The release must carry the binary for this platform as
+//! `update.asset`, a manifest naming its version, asset and SHA-256 as
+//! `{asset}.manifest`, and a base64 signature of the manifest as
+//! `{asset}.manifest.sig`, made like the remote config's with the key
+//! whose public half is `update.public_key`. Without a key nothing is
+//! installed, and neither is a version that is not newer than the running
+//! one, so an old signed release cannot be replayed. The binary is
+//! downloaded next to the current one, checked against the manifest and
+//! then renamed over it, so an interrupted update leaves the old binary
+//! in place.
+
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::digest::{hex, sha256};
+use crate::http;
+use crate::mime::decode_base64;
+use crate::process;
+use crate::remote;
+
+pub const DEFAULT_URL: &str = "https://api.github.com/repos/hijirii/rust-tools/releases/latest";
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// `update` section of the config file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UpdateConfig {
+    /// Release endpoint answering like GitHub's `releases/latest`.
+    pub url: String,
+    /// PEM file with the key releases are signed with.
+    pub public_key: Option<String>,
+    /// Name of the release asset to install.
+    pub asset: String,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_URL.to_string(),
+            public_key: None,
+            asset: format!("email_checker-{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+        }
+    }
+}
+
+/// The latest release's version and the download URLs of its asset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Release {
+    pub version: String,
+    pub binary: String,
+    pub manifest: String,
+    pub signature: String,
+}
+
+/// The signed `{asset}.manifest` of a release.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Manifest {
+    pub version: String,
+    pub asset: String,
+    /// Hex SHA-256 of the asset.
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Whether this manifest may install `release` as `asset` over
+    /// `current`. The version compared is the signed one, not the tag.
+    pub fn check(&self, release: &Release, asset: &str, current: &str) -> Result<(), String> {
+        if self.asset != asset {
+            return Err(format!("manifest is for '{}', not '{}'", self.asset, asset));
+        }
+        if self.version != release.version {
+            return Err(format!("release {} has a manifest for {}", release.version, self.version));
+        }
+        if !is_newer(&self.version, current) {
+            return Err(format!("refusing to install {} over {}", self.version, current));
+        }
+        Ok(())
+    }
+
+    /// Whether `binary` is the asset this manifest describes.
+    pub fn matches(&self, binary: &[u8]) -> bool {
+        self.sha256.eq_ignore_ascii_case(&hex(&sha256(binary)))
+    }
+}
+
+/// Pick `asset`, its manifest and the manifest's signature out of a
+/// release document.
+pub fn release(document: &Value, asset: &str) -> Result<Release, String> {
+    let version = document["tag_name"].as_str().ok_or("release has no tag_name")?;
+    let url = |name: &str| {
+        document["assets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|a| a["name"] == name)
+            .and_then(|a| a["browser_download_url"].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| format!("release {} has no asset '{}'", version, name))
+    };
+    Ok(Release {
+        version: version.trim_start_matches('v').to_string(),
+        binary: url(asset)?,
+        manifest: url(&format!("{}.manifest", asset))?,
+        signature: url(&format!("{}.manifest.sig", asset))?,
+    })
+}
+
+/// Whether `version` is later than `current`, comparing dotted numbers.
+pub fn is_newer(version: &str, current: &str) -> bool {
+    let parts = |v: &str| -> Vec<u64> { v.split(['.', '-']).map_while(|p| p.parse().ok()).collect() };
+    parts(version) > parts(current)
+}
+
+/// Fetch the latest release's description.
+pub fn latest(config: &UpdateConfig) -> Result<Release, Box<dyn Error>> {
+    let headers = [("Accept", "application/vnd.github+json"), ("User-Agent", "email_checker")];
+    let response = http::request("GET", &config.url, &headers, None, http::DEFAULT_TIMEOUT)?;
+    if !response.is_success() {
+        return Err(format!("{} returned HTTP {}", config.url, response.status).into());
+    }
+    let document: Value = serde_json::from_str(&response.body)?;
+    Ok(release(&document, &config.asset)?)
+}
+
+/// Download, verify and install `release` over `exe`, which must be
+/// running `current`.
+pub fn install(config: &UpdateConfig, release: &Release, exe: &Path, current: &str) -> Result<(), Box<dyn Error>> {
+    let key = config.public_key.as_deref().ok_or("update.public_key is not set; refusing to install an unsigned binary")?;
+    let manifest = download(&release.manifest)?;
+    let signature = decode_base64(&download(&release.signature)?);
+    remote::verify(&manifest, &signature, key).map_err(|e| format!("{}: {}", release.manifest, e))?;
+    let manifest: Manifest = serde_json::from_slice(&manifest).map_err(|e| format!("{}: {}", release.manifest, e))?;
+    manifest.check(release, &config.asset, current)?;
+    let staged = sibling(exe, "new");
+    let result = fs::write(&staged, download(&release.binary)?).map_err(Box::from).and_then(|()| {
+        if !manifest.matches(&fs::read(&staged)?) {
+            return Err(format!("{} does not match its manifest", release.binary).into());
+        }
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+        fs::rename(&staged, exe)?;
+        Ok(())
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    result
+}
+
+/// `exe` with `.{suffix}` appended, in the same directory so the final
+/// rename does not cross filesystems.
+fn sibling(exe: &Path, suffix: &str) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", suffix));
+    exe.with_file_name(name)
+}
+
+/// Fetch `url`, following the redirects release hosts use.
+fn download(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = process::network_command("curl", &[])
+        .args(["-fsSL", "--max-time", "300"])
+        .arg(url)
+        .output()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("downloading {} failed: {}", url, String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::process::Command;
+
+    #[test]
+    fn test_release_selection() {
+        let document = json!({
+            "tag_name": "v0.3.1",
+            "assets": [
+                {"name": "email_checker-x86_64-linux", "browser_download_url": "https://example.com/bin"},
+                {"name": "email_checker-x86_64-linux.manifest", "browser_download_url": "https://example.com/bin.manifest"},
+                {"name": "email_checker-x86_64-linux.manifest.sig", "browser_download_url": "https://example.com/bin.manifest.sig"},
+                {"name": "email_checker-aarch64-linux", "browser_download_url": "https://example.com/arm"},
+                {"name": "email_checker-aarch64-linux.manifest", "browser_download_url": "https://example.com/arm.manifest"},
+            ]
+        });
+        let release = release(&document, "email_checker-x86_64-linux").unwrap();
+        assert_eq!((release.version.as_str(), release.signature.as_str()), ("0.3.1", "https://example.com/bin.manifest.sig"));
+        let unsigned = super::release(&document, "email_checker-aarch64-linux").unwrap_err();
+        assert_eq!(unsigned, "release v0.3.1 has no asset 'email_checker-aarch64-linux.manifest.sig'");
+        assert!(is_newer("0.10.0", "0.2.0") && !is_newer("0.2.0", "0.2.0") && !is_newer("0.1.9", "0.2.0"));
+        assert_eq!(sibling(Path::new("/usr/local/bin/email_checker"), "new"), Path::new("/usr/local/bin/email_checker.new"));
+    }
+
+    #[test]
+    fn test_install_refuses_a_rollback() {
+        let dir = std::env::temp_dir().join(format!("email_checker_update_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let openssl = |args: &[&str]| assert!(Command::new("openssl").args(args).current_dir(&dir).output().unwrap().status.success());
+        openssl(&["genpkey", "-algorithm", "RSA", "-pkeyopt", "rsa_keygen_bits:2048", "-out", "key.pem"]);
+        openssl(&["pkey", "-in", "key.pem", "-pubout", "-out", "public.pem"]);
+        let config = UpdateConfig {
+            public_key: Some(dir.join("public.pem").to_string_lossy().into_owned()),
+            asset: "email_checker-test".to_string(),
+            ..UpdateConfig::default()
+        };
+        let exe = dir.join("email_checker");
+        let url = |name: &str| format!("file://{}", dir.join(name).display());
+        let publish = |version: &str, binary: &[u8]| {
+            fs::write(dir.join("binary"), binary).unwrap();
+            let manifest = Manifest { version: version.to_string(), asset: config.asset.clone(), sha256: hex(&sha256(binary)) };
+            fs::write(dir.join("manifest"), serde_json::to_vec(&manifest).unwrap()).unwrap();
+            openssl(&["dgst", "-sha256", "-sign", "key.pem", "-out", "manifest.raw", "manifest"]);
+            let signature = crate::mime::encode_base64(&fs::read(dir.join("manifest.raw")).unwrap());
+            fs::write(dir.join("manifest.sig"), signature).unwrap();
+            Release { version: version.to_string(), binary: url("binary"), manifest: url("manifest"), signature: url("manifest.sig") }
+        };
+
+        fs::write(&exe, "old").unwrap();
+        install(&config, &publish("0.3.0", b"new"), &exe, "0.2.0").unwrap();
+        assert_eq!(fs::read(&exe).unwrap(), b"new");
+        for version in ["0.3.0", "0.2.9"] {
+            let e = install(&config, &publish(version, b"older"), &exe, "0.3.0").unwrap_err();
+            assert_eq!(e.to_string(), format!("refusing to install {} over 0.3.0", version));
+        }
+        let mut retagged = publish("0.2.9", b"older");
+        retagged.version = "0.4.0".to_string();
+        let e = install(&config, &retagged, &exe, "0.3.0").unwrap_err();
+        assert_eq!(e.to_string(), "release 0.4.0 has a manifest for 0.2.9");
+        let swapped = publish("0.4.0", b"newer");
+        fs::write(dir.join("binary"), "tampered").unwrap();
+        assert!(install(&config, &swapped, &exe, "0.3.0").unwrap_err().to_string().ends_with("does not match its manifest"));
+        assert_eq!(fs::read(&exe).unwrap(), b"new");
+        assert!(!sibling(&exe, "new").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
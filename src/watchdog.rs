@@ -0,0 +1,80 @@
+//! Alerts on mail that should have arrived and did not.
+//!
+//! Each of `watchdogs` names mail expected regularly, such as a nightly
+//! backup report, with the same `match` conditions as routing rules, and
+//! how long may pass between two such messages. Once `within` passes
+//! without one, the `watchdog` health alert fires; the next match
+//! resolves it. When each last matched is kept in the state file, so a
+//! restart neither loses nor resets the clock; a new watchdog's clock
+//! starts when it is first loaded.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::email::EmailData;
+use crate::rules::Conditions;
+
+/// An entry of `watchdogs`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Watchdog {
+    pub name: String,
+    #[serde(default, rename = "match")]
+    pub conditions: Conditions,
+    /// Seconds, or a duration such as `26h`, that may pass between matches.
+    #[serde(deserialize_with = "config::seconds")]
+    pub within: usize,
+}
+
+/// Note that `email` arrived at `now`, for the watchdogs it matches.
+pub fn observe(watchdogs: &[Watchdog], last_seen: &mut BTreeMap<String, i64>, email: &EmailData, now: i64) {
+    for watchdog in watchdogs.iter().filter(|watchdog| watchdog.conditions.matches(email)) {
+        last_seen.insert(watchdog.name.clone(), now);
+    }
+}
+
+/// For each watchdog, by name, what is overdue at `now`, if anything.
+/// Watchdogs that are no longer configured are forgotten.
+pub fn overdue(
+    watchdogs: &[Watchdog],
+    last_seen: &mut BTreeMap<String, i64>,
+    now: i64,
+) -> BTreeMap<String, Option<String>> {
+    last_seen.retain(|name, _| watchdogs.iter().any(|watchdog| watchdog.name == *name));
+    let mut overdue = BTreeMap::new();
+    for watchdog in watchdogs {
+        let since = *last_seen.entry(watchdog.name.clone()).or_insert(now);
+        let message = (now - since >= watchdog.within as i64).then(|| {
+            let since = chrono::DateTime::from_timestamp(since, 0).map_or(since.to_string(), |at| at.to_rfc3339());
+            let hours = watchdog.within as f64 / 3600.0;
+            format!("No mail for '{}' since {}; it is expected every {} hours", watchdog.name, since, hours)
+        });
+        overdue.insert(watchdog.name.clone(), message);
+    }
+    overdue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overdue() {
+        let watchdog: Watchdog =
+            serde_json::from_str(r#"{"name": "backup", "match": {"from": "backup@host"}, "within": "26h"}"#).unwrap();
+        let watchdogs = [watchdog];
+        let mut last_seen = BTreeMap::from([("removed".to_string(), 0)]);
+        let start = 1_700_000_000;
+        assert_eq!(overdue(&watchdogs, &mut last_seen, start)["backup"], None);
+        assert!(!last_seen.contains_key("removed"));
+
+        let late = start + 26 * 3600;
+        let message = overdue(&watchdogs, &mut last_seen, late)["backup"].clone().unwrap();
+        assert_eq!(message, "No mail for 'backup' since 2023-11-14T22:13:20+00:00; it is expected every 26 hours");
+
+        let email = EmailData::from_raw(b"From: Backup <backup@host>\r\nSubject: done\r\n\r\nok");
+        observe(&watchdogs, &mut last_seen, &email, late);
+        assert_eq!(overdue(&watchdogs, &mut last_seen, late + 60)["backup"], None);
+    }
+}